@@ -0,0 +1,90 @@
+#![allow(non_camel_case_types)]
+
+use ascent::ascent;
+use crepe::crepe;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use datalog_rule_macro::program;
+use datalog_syntax::*;
+use micro_datalog::engine::datalog::MicroRuntime;
+
+// TC benchmark
+crepe! {
+    @input
+    struct e(usize, usize);
+
+    @output
+    struct tc(usize, usize);
+
+    tc(x, y) <- e(x, y);
+    tc(x, z) <- e(x, y), tc(y, z);
+}
+
+ascent! {
+    relation e(usize, usize);
+    relation tc(usize, usize);
+
+    tc(x, y) <-- e(x, y);
+    tc(x, z) <-- e(x, y), tc(y, z);
+}
+
+fn graph_dense() -> Vec<(usize, usize)> {
+    include_str!("../data/graph_dense.txt")
+        .lines()
+        .map(|line| {
+            let triple: Vec<_> = line.split(' ').collect();
+
+            (triple[0].parse().unwrap(), triple[1].parse().unwrap())
+        })
+        .collect()
+}
+
+fn transitive_closure(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tc/graph_dense");
+    group.sample_size(10);
+    let edges = graph_dense();
+
+    group.bench_function("micro", |b| {
+        b.iter_batched(
+            || {
+                let mut runtime = MicroRuntime::new(program! {
+                    tc(?x, ?y) <- [e(?x, ?y)],
+                    tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)]
+                });
+                edges.iter().for_each(|(from, to)| {
+                    runtime
+                        .insert("e", vec![(*from).into(), (*to).into()])
+                        .unwrap();
+                });
+                runtime
+            },
+            |mut runtime| runtime.poll(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("crepe", |b| {
+        b.iter_batched(
+            || {
+                let mut runtime = Crepe::new();
+                runtime.extend(edges.iter().map(|(from, to)| e(*from, *to)));
+                runtime
+            },
+            |runtime| runtime.run(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("ascent", |b| {
+        b.iter_batched(
+            || AscentProgram {
+                e: edges.clone(),
+                ..Default::default()
+            },
+            |mut runtime| runtime.run(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, transitive_closure);
+criterion_main!(benches);
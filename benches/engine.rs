@@ -0,0 +1,128 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use datalog_syntax::*;
+use micro_datalog::bench::{
+    chain, grid, nonlinear_transitive_closure, points_to, points_to_statements, random_graph,
+    same_generation, transitive_closure, tree,
+};
+use micro_datalog::engine::datalog::MicroRuntime;
+
+fn runtime_with(program: Program, facts: &[(&str, AnonymousGroundAtom)]) -> MicroRuntime {
+    let mut runtime = MicroRuntime::new(program);
+    facts.iter().for_each(|(relation, fact)| {
+        runtime.insert(relation, fact.clone()).unwrap();
+    });
+
+    runtime
+}
+
+fn edges(
+    relation: &'static str,
+    graph: Vec<AnonymousGroundAtom>,
+) -> Vec<(&'static str, AnonymousGroundAtom)> {
+    graph.into_iter().map(|fact| (relation, fact)).collect()
+}
+
+fn poll(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll");
+    group.sample_size(10);
+
+    let workloads = [
+        (
+            "tc/random",
+            transitive_closure(),
+            edges("e", random_graph(300, 600, 7)),
+        ),
+        ("tc/chain", transitive_closure(), edges("e", chain(150))),
+        ("tc/grid", transitive_closure(), edges("e", grid(15, 15))),
+        (
+            "tc/nonlinear/grid",
+            nonlinear_transitive_closure(),
+            edges("e", grid(15, 15)),
+        ),
+        ("sg/tree", same_generation(), edges("parent", tree(400, 3))),
+        (
+            "pt/random",
+            points_to(),
+            points_to_statements(1000, 1200, 7),
+        ),
+    ];
+    for (name, program, facts) in workloads {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || runtime_with(program.clone(), &facts),
+                |mut runtime| runtime.poll(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn incremental(c: &mut Criterion) {
+    let mut group = c.benchmark_group("incremental");
+    group.sample_size(10);
+
+    let graph = random_graph(300, 660, 7);
+    let (base, update) = graph.split_at(600);
+    let materialized = || {
+        let mut runtime = runtime_with(transitive_closure(), &edges("e", base.to_vec()));
+        runtime.poll();
+        runtime
+    };
+
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            materialized,
+            |mut runtime| {
+                runtime.insert_many("e", update.iter().cloned()).unwrap();
+                runtime.poll();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("delete", |b| {
+        b.iter_batched(
+            materialized,
+            |mut runtime| {
+                base.iter().take(60).for_each(|fact| {
+                    let (from, to) = (fact[0].clone(), fact[1].clone());
+                    runtime.remove(&build_query!(e(from, to)));
+                });
+                runtime.poll();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn bound_queries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bound_queries");
+
+    let mut runtime = runtime_with(transitive_closure(), &edges("e", random_graph(300, 600, 7)));
+    runtime.poll();
+    let reachable_from = prepare_query!(tc(?1, _));
+    runtime.prepare(&reachable_from).unwrap();
+
+    group.bench_function("query", |b| {
+        b.iter(|| runtime.query(&build_query!(tc(5usize, _))).unwrap().count())
+    });
+    group.bench_function("execute", |b| {
+        b.iter(|| {
+            runtime
+                .execute(&reachable_from, &[5usize.into()])
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("exists", |b| {
+        b.iter(|| runtime.exists(&build_query!(tc(5usize, 6usize))).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, poll, incremental, bound_queries);
+criterion_main!(benches);
@@ -5,27 +5,30 @@ use std::collections::HashMap;
 
 type RuleGraph<'a> = GraphMap<&'a Rule, bool, Directed>;
 
-pub fn generate_rule_dependency_graph<'a>(program: &Vec<Rule>) -> RuleGraph {
+pub fn generate_rule_dependency_graph(program: &[Rule]) -> RuleGraph<'_> {
     let mut output = DiGraphMap::new();
-    let mut idb_relations = HashMap::new();
+    let mut idb_relations: HashMap<&str, Vec<&Rule>> = HashMap::new();
     for rule in program {
-        idb_relations.insert(rule.clone().head.symbol, rule);
+        idb_relations
+            .entry(rule.head.symbol.as_str())
+            .or_default()
+            .push(rule);
         output.add_node(rule);
     }
     for rule in program {
         for body_atom in &rule.body {
-            if let Some(body_atom_rule) = idb_relations.get(&body_atom.symbol) {
-                output.add_edge(body_atom_rule, rule, true);
+            if let Some(body_atom_rules) = idb_relations.get(body_atom.symbol.as_str()) {
+                for body_atom_rule in body_atom_rules {
+                    output.add_edge(body_atom_rule, rule, true);
+                }
             }
         }
     }
-    return output;
+    output
 }
 
 pub fn stratify<'a>(rule_graph: &'a RuleGraph) -> Vec<Vec<&'a Rule>> {
-    let sccs = algo::kosaraju_scc(&rule_graph);
-
-    return sccs;
+    algo::kosaraju_scc(&rule_graph)
 }
 
 pub fn sort_program(program: &Program) -> Program {
@@ -33,11 +36,11 @@ pub fn sort_program(program: &Program) -> Program {
     let stratification = stratify(&rule_graph)
         .into_iter()
         .rev()
-        .flat_map(|program| program)
+        .flatten()
         .cloned()
         .collect();
 
-    return Program {
+    Program {
         inner: stratification,
-    };
+    }
 }
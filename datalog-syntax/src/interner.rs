@@ -0,0 +1,102 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Deref;
+use std::sync::{OnceLock, RwLock};
+
+// Interned strings are never freed, so that resolving one can hand out a plain reference.
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    strings: Vec<&'static str>,
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+
+    INTERNER.get_or_init(Default::default)
+}
+
+// A string that is stored once per process, and is copied, compared and hashed as an id. It is
+// still ordered as the string it stands for.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternedString(u32);
+
+impl InternedString {
+    pub fn new(value: &str) -> Self {
+        if let Some(id) = interner().read().unwrap().ids.get(value) {
+            return Self(*id);
+        }
+
+        let mut interner = interner().write().unwrap();
+        // Someone else may have interned it while the lock was being upgraded.
+        if let Some(id) = interner.ids.get(value) {
+            return Self(*id);
+        }
+        let string: &'static str = Box::leak(value.into());
+        let id = interner.strings.len() as u32;
+        interner.strings.push(string);
+        interner.ids.insert(string, id);
+
+        Self(id)
+    }
+    pub fn id(self) -> u32 {
+        self.0
+    }
+    pub fn as_str(self) -> &'static str {
+        interner().read().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl Deref for InternedString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl PartialOrd for InternedString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InternedString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.0 == other.0 {
+            return Ordering::Equal;
+        }
+
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Debug for InternedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for InternedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+// Ids are only meaningful within a process, so the string itself is what gets serialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for InternedString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for InternedString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        Ok(Self::new(&value))
+    }
+}
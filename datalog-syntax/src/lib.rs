@@ -1,10 +1,18 @@
+mod interner;
+
+pub use interner::InternedString;
+pub use ordered_float::OrderedFloat;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 
 #[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypedValue {
-    Str(String),
+    Str(InternedString),
     Int(usize),
     Bool(bool),
+    SignedInt(i64),
+    Float(OrderedFloat<f64>),
 }
 
 impl Debug for TypedValue {
@@ -13,19 +21,21 @@ impl Debug for TypedValue {
             TypedValue::Str(x) => x.fmt(f),
             TypedValue::Int(x) => x.fmt(f),
             TypedValue::Bool(x) => x.fmt(f),
+            TypedValue::SignedInt(x) => x.fmt(f),
+            TypedValue::Float(x) => x.0.fmt(f),
         }
     }
 }
 
 impl From<String> for TypedValue {
     fn from(value: String) -> Self {
-        TypedValue::Str(value)
+        TypedValue::Str(InternedString::new(&value))
     }
 }
 
 impl From<&str> for TypedValue {
     fn from(value: &str) -> Self {
-        TypedValue::Str(value.to_string())
+        TypedValue::Str(InternedString::new(value))
     }
 }
 
@@ -35,18 +45,197 @@ impl From<usize> for TypedValue {
     }
 }
 
+// How spliced values, the fields of relations and the elements of tuples become TypedValues. It is
+// its own trait because TypedValue converting from i64 as well as usize would leave unsuffixed
+// integer literals ambiguous.
+pub trait IntoTypedValue {
+    fn into_typed_value(self) -> TypedValue;
+}
+
+impl<T: Into<TypedValue>> IntoTypedValue for T {
+    fn into_typed_value(self) -> TypedValue {
+        self.into()
+    }
+}
+
+impl IntoTypedValue for i64 {
+    fn into_typed_value(self) -> TypedValue {
+        TypedValue::SignedInt(self)
+    }
+}
+
+impl From<f64> for TypedValue {
+    fn from(value: f64) -> Self {
+        TypedValue::Float(OrderedFloat(value))
+    }
+}
+
 impl From<bool> for TypedValue {
     fn from(value: bool) -> Self {
         TypedValue::Bool(value)
     }
 }
 
+macro_rules! try_from_typed_value {
+    ($target:ty, $variant:ident, $description:literal, $inner:ident => $conversion:expr) => {
+        impl TryFrom<&TypedValue> for $target {
+            type Error = String;
+
+            fn try_from(value: &TypedValue) -> Result<Self, Self::Error> {
+                match value {
+                    TypedValue::$variant($inner) => Ok($conversion),
+                    other => Err(format!("{:?} is not {}", other, $description)),
+                }
+            }
+        }
+    };
+}
+
+try_from_typed_value!(String, Str, "a string", inner => inner.to_string());
+try_from_typed_value!(usize, Int, "an unsigned integer", inner => *inner);
+try_from_typed_value!(i64, SignedInt, "a signed integer", inner => *inner);
+try_from_typed_value!(f64, Float, "a float", inner => inner.0);
+try_from_typed_value!(bool, Bool, "a boolean", inner => *inner);
+
 pub type Variable = String;
 
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Min,
+    Max,
+    // The value of the variable in one fact of the group: the one that the rule's first min or max
+    // is taken from, or else the least one, so that the choice is the same on every run.
+    Choice,
+}
+
+impl Debug for Aggregation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::Count => write!(f, "count"),
+            Aggregation::Sum => write!(f, "sum"),
+            Aggregation::Min => write!(f, "min"),
+            Aggregation::Max => write!(f, "max"),
+            Aggregation::Choice => write!(f, "choice"),
+        }
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+impl Debug for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Subtract => write!(f, "-"),
+            Operator::Multiply => write!(f, "*"),
+        }
+    }
+}
+
+// Arithmetic over the values that the body binds variables to, as computed for a head term.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expression {
+    Variable(Variable),
+    Constant(TypedValue),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    pub fn variables(&self) -> Vec<&Variable> {
+        match self {
+            Expression::Variable(name) => vec![name],
+            Expression::Constant(_) => vec![],
+            Expression::Binary(_, left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+        }
+    }
+    pub fn rename(&self, rename: &mut impl FnMut(&Variable) -> Variable) -> Expression {
+        match self {
+            Expression::Variable(name) => Expression::Variable(rename(name)),
+            Expression::Constant(value) => Expression::Constant(value.clone()),
+            Expression::Binary(operator, left, right) => Expression::Binary(
+                *operator,
+                Box::new(left.rename(rename)),
+                Box::new(right.rename(rename)),
+            ),
+        }
+    }
+    // Operands have to be numbers of the same type, and the result has to fit in it, for there to
+    // be one. Unsigned integers do not go below zero.
+    pub fn evaluate(
+        &self,
+        value_of: &impl Fn(&Variable) -> Option<TypedValue>,
+    ) -> Option<TypedValue> {
+        match self {
+            Expression::Variable(name) => value_of(name),
+            Expression::Constant(value) => Some(value.clone()),
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(value_of)?, right.evaluate(value_of)?);
+
+                match (left, right) {
+                    (TypedValue::Int(left), TypedValue::Int(right)) => match operator {
+                        Operator::Add => left.checked_add(right),
+                        Operator::Subtract => left.checked_sub(right),
+                        Operator::Multiply => left.checked_mul(right),
+                    }
+                    .map(TypedValue::Int),
+                    (TypedValue::SignedInt(left), TypedValue::SignedInt(right)) => match operator {
+                        Operator::Add => left.checked_add(right),
+                        Operator::Subtract => left.checked_sub(right),
+                        Operator::Multiply => left.checked_mul(right),
+                    }
+                    .map(TypedValue::SignedInt),
+                    (TypedValue::Float(left), TypedValue::Float(right)) => {
+                        let value = match operator {
+                            Operator::Add => left.0 + right.0,
+                            Operator::Subtract => left.0 - right.0,
+                            Operator::Multiply => left.0 * right.0,
+                        };
+
+                        value.is_finite().then(|| TypedValue::from(value))
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl Debug for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Variable(x) => x.fmt(f),
+            Expression::Constant(x) => x.fmt(f),
+            Expression::Binary(operator, left, right) => {
+                write!(f, "({:?} {:?} {:?})", left, operator, right)
+            }
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Term {
     Variable(String),
     Constant(TypedValue),
+    // Only allowed in rule heads, where it ranges over every way the body can be satisfied.
+    Aggregate(Aggregation, Variable),
+    // Only allowed in rule heads, over variables that the body binds. Bindings for which it has no
+    // value derive nothing.
+    Expression(Expression),
 }
 
 impl Debug for Term {
@@ -54,13 +243,77 @@ impl Debug for Term {
         match self {
             Term::Variable(x) => x.fmt(f),
             Term::Constant(x) => x.fmt(f),
+            Term::Aggregate(aggregation, x) => write!(f, "{:?}({:?})", aggregation, x),
+            Term::Expression(x) => x.fmt(f),
         }
     }
 }
 
 pub type AnonymousGroundAtom = Vec<TypedValue>;
 
+// A Rust type whose values are the facts of a relation, as derived by `#[derive(Relation)]`.
+pub trait Relation: Sized {
+    const SYMBOL: &'static str;
+
+    fn into_fact(self) -> AnonymousGroundAtom;
+    fn from_fact(fact: &AnonymousGroundAtom) -> Result<Self, String>;
+}
+
+// Plain tuples of values, such as (String, usize), read out of facts of the same arity.
+pub trait FromFact: Sized {
+    fn from_fact(fact: &AnonymousGroundAtom) -> Result<Self, String>;
+}
+
+// Plain tuples of anything that converts into values, such as ("a", 1usize), made into facts.
+pub trait IntoFact {
+    fn into_fact(self) -> AnonymousGroundAtom;
+}
+
+macro_rules! fact_for_tuple {
+    ($arity:literal; $($element:ident $index:tt),+) => {
+        impl<$($element),+> IntoFact for ($($element,)+)
+        where
+            $($element: IntoTypedValue),+
+        {
+            fn into_fact(self) -> AnonymousGroundAtom {
+                vec![$(self.$index.into_typed_value()),+]
+            }
+        }
+
+        impl<$($element),+> FromFact for ($($element,)+)
+        where
+            $($element: for<'a> TryFrom<&'a TypedValue, Error = String>),+
+        {
+            fn from_fact(fact: &AnonymousGroundAtom) -> Result<Self, String> {
+                if fact.len() != $arity {
+                    return Err(format!(
+                        "a fact with {} terms was given, but {} were expected",
+                        fact.len(),
+                        $arity
+                    ));
+                }
+
+                Ok(($(<$element>::try_from(&fact[$index])?,)+))
+            }
+        }
+    };
+}
+
+fact_for_tuple!(1; A 0);
+fact_for_tuple!(2; A 0, B 1);
+fact_for_tuple!(3; A 0, B 1, C 2);
+fact_for_tuple!(4; A 0, B 1, C 2, D 3);
+fact_for_tuple!(5; A 0, B 1, C 2, D 3, E 4);
+fact_for_tuple!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+fact_for_tuple!(7; A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+fact_for_tuple!(8; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+fact_for_tuple!(9; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+fact_for_tuple!(10; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+fact_for_tuple!(11; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+fact_for_tuple!(12; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Atom {
     pub terms: Vec<Term>,
     pub symbol: String,
@@ -83,14 +336,46 @@ impl Debug for Atom {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Matcher {
     Any,
     Constant(TypedValue),
+    // Anything but the value.
+    Not(TypedValue),
+    // Values of the same type that are at least, or at most, the bound.
+    Ge(TypedValue),
+    Le(TypedValue),
 }
 
+pub type Timestamp = u64;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Query<'a> {
     pub matchers: Vec<Matcher>,
     pub symbol: &'a str,
+    // Only facts inserted with metadata can pass these, derived facts having none.
+    pub since: Option<Timestamp>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub source: Option<&'a str>,
+}
+
+impl<'a> Query<'a> {
+    pub fn since(mut self, timestamp: Timestamp) -> Self {
+        self.since = Some(timestamp);
+        self
+    }
+    pub fn from_source(mut self, source: &'a str) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+// Which way the answers to a query are sorted by one of their columns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    Ascending,
+    Descending,
 }
 
 pub struct QueryBuilder<'a> {
@@ -103,6 +388,8 @@ impl<'a> QueryBuilder<'a> {
             query: Query {
                 matchers: vec![],
                 symbol: relation,
+                since: None,
+                source: None,
             },
         }
     }
@@ -112,6 +399,15 @@ impl<'a> QueryBuilder<'a> {
     pub fn with_constant(&mut self, value: TypedValue) {
         self.query.matchers.push(Matcher::Constant(value))
     }
+    pub fn with_not(&mut self, value: TypedValue) {
+        self.query.matchers.push(Matcher::Not(value))
+    }
+    pub fn with_ge(&mut self, value: TypedValue) {
+        self.query.matchers.push(Matcher::Ge(value))
+    }
+    pub fn with_le(&mut self, value: TypedValue) {
+        self.query.matchers.push(Matcher::Le(value))
+    }
 }
 
 impl<'a> From<QueryBuilder<'a>> for Query<'a> {
@@ -120,28 +416,276 @@ impl<'a> From<QueryBuilder<'a>> for Query<'a> {
     }
 }
 
+// Every column is matched by `_`, a constant, or a constant preceded by `!`, `>=` or `<=`.
 #[macro_export]
 macro_rules! build_query {
-    ($relation:ident ( $( $matcher:tt ),* $(,)? )) => {{
+    ($relation:ident ( $( $matchers:tt )* )) => {{
         let mut builder = QueryBuilder::new(stringify!($relation));
-        $(
-            build_query!(@matcher builder, $matcher);
-        )*
+        build_query!(@matchers builder; $( $matchers )*);
         builder.query
     }};
-    (@matcher $builder:expr, _) => {{
+    (@matchers $builder:ident;) => {};
+    (@matchers $builder:ident; _ $(, $( $rest:tt )* )?) => {{
         $builder.with_any();
+        build_query!(@matchers $builder; $( $( $rest )* )?);
+    }};
+    (@matchers $builder:ident; ! $value:tt $(, $( $rest:tt )* )?) => {{
+        $builder.with_not($value.into());
+        build_query!(@matchers $builder; $( $( $rest )* )?);
+    }};
+    (@matchers $builder:ident; >= $value:tt $(, $( $rest:tt )* )?) => {{
+        $builder.with_ge($value.into());
+        build_query!(@matchers $builder; $( $( $rest )* )?);
     }};
-    (@matcher $builder:expr, $value:expr) => {{
+    (@matchers $builder:ident; <= $value:tt $(, $( $rest:tt )* )?) => {{
+        $builder.with_le($value.into());
+        build_query!(@matchers $builder; $( $( $rest )* )?);
+    }};
+    (@matchers $builder:ident; $value:tt $(, $( $rest:tt )* )?) => {{
         $builder.with_constant($value.into());
+        build_query!(@matchers $builder; $( $( $rest )* )?);
+    }};
+}
+
+// A column of a prepared query, which may be left to a value given every time the query is run.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Parameter {
+    Any,
+    Constant(TypedValue),
+    // The n-th of the values that the query is run with, counting from 1.
+    Placeholder(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PreparedQuery {
+    pub parameters: Vec<Parameter>,
+    pub symbol: String,
+}
+
+impl PreparedQuery {
+    pub fn new(relation: &str) -> Self {
+        PreparedQuery {
+            parameters: vec![],
+            symbol: relation.to_string(),
+        }
+    }
+    pub fn with_any(&mut self) {
+        self.parameters.push(Parameter::Any);
+    }
+    pub fn with_constant(&mut self, value: TypedValue) {
+        self.parameters.push(Parameter::Constant(value));
+    }
+    pub fn with_placeholder(&mut self, position: usize) {
+        self.parameters.push(Parameter::Placeholder(position));
+    }
+    // The columns whose values are known once the placeholders are given.
+    pub fn bound_columns(&self) -> Vec<usize> {
+        self.parameters
+            .iter()
+            .enumerate()
+            .filter(|(_, parameter)| !matches!(parameter, Parameter::Any))
+            .map(|(column, _)| column)
+            .collect()
+    }
+    // The query that the placeholders stand for when given these values.
+    pub fn bind(&self, values: &[TypedValue]) -> Result<Query<'_>, String> {
+        let placeholders = self
+            .parameters
+            .iter()
+            .filter_map(|parameter| match parameter {
+                Parameter::Placeholder(position) => Some(*position),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        if values.len() != placeholders {
+            return Err(format!(
+                "{} values were given, but the query has {} placeholders",
+                values.len(),
+                placeholders
+            ));
+        }
+
+        let matchers = self
+            .parameters
+            .iter()
+            .map(|parameter| match parameter {
+                Parameter::Any => Ok(Matcher::Any),
+                Parameter::Constant(value) => Ok(Matcher::Constant(value.clone())),
+                Parameter::Placeholder(0) => Err("placeholders are counted from 1".to_string()),
+                Parameter::Placeholder(position) => {
+                    Ok(Matcher::Constant(values[position - 1].clone()))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Query {
+            matchers,
+            symbol: &self.symbol,
+            since: None,
+            source: None,
+        })
+    }
+}
+
+// Like build_query, with ?n standing for the n-th value that the query is run with.
+#[macro_export]
+macro_rules! prepare_query {
+    ($relation:ident ( $( $parameters:tt )* )) => {{
+        let mut prepared = PreparedQuery::new(stringify!($relation));
+        prepare_query!(@parameters prepared, $( $parameters )*);
+        prepared
     }};
+    (@parameters $prepared:expr, ) => {};
+    (@parameters $prepared:expr, ? $position:literal $(, $( $rest:tt )* )?) => {{
+        $prepared.with_placeholder($position);
+        prepare_query!(@parameters $prepared, $( $( $rest )* )?);
+    }};
+    (@parameters $prepared:expr, _ $(, $( $rest:tt )* )?) => {{
+        $prepared.with_any();
+        prepare_query!(@parameters $prepared, $( $( $rest )* )?);
+    }};
+    (@parameters $prepared:expr, $value:expr $(, $( $rest:tt )* )?) => {{
+        $prepared.with_constant($value.into());
+        prepare_query!(@parameters $prepared, $( $( $rest )* )?);
+    }};
+}
+
+// How a rule is to be evaluated, overriding the planner where it gets a rule wrong.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanHints {
+    // Whether the body atoms are joined in the order that they are written in, rather than in the
+    // one the planner picks.
+    pub no_reorder: bool,
+    // Whether the joins of the rule hash what they read every time, rather than keeping indices
+    // over the relations and probing those.
+    pub no_index: bool,
+    // The columns of relations to keep indices over, whether or not the joins would.
+    pub indices: Vec<(String, Vec<usize>)>,
 }
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rule {
     pub head: Atom,
     pub body: Vec<Atom>,
     pub id: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hints: PlanHints,
+}
+
+impl Rule {
+    pub fn is_aggregate(&self) -> bool {
+        self.head
+            .terms
+            .iter()
+            .any(|term| matches!(term, Term::Aggregate(_, _)))
+    }
+    // The variables of the head and of negated atoms that no positive body atom binds, in the order
+    // that they occur. Wildcards are never bound, and stand for any value.
+    pub fn unsafe_variables(&self) -> Vec<&Variable> {
+        let bound: HashSet<&Variable> = self
+            .body
+            .iter()
+            .filter(|atom| atom.sign)
+            .flat_map(|atom| atom.terms.iter())
+            .filter_map(|term| match term {
+                Term::Variable(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let negated = self.body.iter().filter(|atom| !atom.sign);
+
+        let mut unsafe_variables = vec![];
+        for term in self
+            .head
+            .terms
+            .iter()
+            .chain(negated.flat_map(|atom| &atom.terms))
+        {
+            let variables = match term {
+                Term::Variable(name) | Term::Aggregate(_, name) => vec![name],
+                Term::Expression(expression) => expression.variables(),
+                Term::Constant(_) => vec![],
+            };
+            for name in variables {
+                if name != "_" && !bound.contains(name) && !unsafe_variables.contains(&name) {
+                    unsafe_variables.push(name);
+                }
+            }
+        }
+
+        unsafe_variables
+    }
+    // The rule with its variables named after the order in which they first occur, so that rules
+    // that only differ in the names of their variables look the same.
+    fn canonical(&self) -> (Atom, Vec<Atom>) {
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut rename = |atom: &Atom| Atom {
+            terms: atom
+                .terms
+                .iter()
+                .map(|term| match term {
+                    Term::Variable(name) if name != "_" => {
+                        let next = format!("?{}", names.len());
+                        Term::Variable(names.entry(name.clone()).or_insert(next).clone())
+                    }
+                    Term::Aggregate(aggregation, name) => {
+                        let next = format!("?{}", names.len());
+                        Term::Aggregate(
+                            *aggregation,
+                            names.entry(name.clone()).or_insert(next).clone(),
+                        )
+                    }
+                    Term::Expression(expression) => {
+                        Term::Expression(expression.rename(&mut |name| {
+                            let next = format!("?{}", names.len());
+                            names.entry(name.clone()).or_insert(next).clone()
+                        }))
+                    }
+                    other => other.clone(),
+                })
+                .collect(),
+            symbol: atom.symbol.clone(),
+            sign: atom.sign,
+        };
+        let head = rename(&self.head);
+
+        (head, self.body.iter().map(rename).collect())
+    }
+    // Whether every fact that the other rule derives is also derived by this one, since they have
+    // the same head and the other's body has every atom of this one's.
+    fn subsumes(&self, other: &Rule) -> bool {
+        let bound: HashSet<&str> = self
+            .body
+            .iter()
+            .filter(|atom| atom.sign)
+            .flat_map(|atom| atom.terms.iter())
+            .filter_map(|term| match term {
+                Term::Variable(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        // Variables that only occur in the head stand for new values, which are not the same as
+        // those that the other rule binds them to.
+        let range_restricted = self.head.terms.iter().all(|term| match term {
+            Term::Variable(name) => bound.contains(name.as_str()),
+            Term::Expression(expression) => expression
+                .variables()
+                .iter()
+                .all(|name| bound.contains(name.as_str())),
+            _ => true,
+        });
+
+        range_restricted
+            && !self.is_aggregate()
+            && !other.is_aggregate()
+            && self.head == other.head
+            && self.body.iter().all(|atom| other.body.contains(atom))
+    }
 }
 
 impl Debug for Rule {
@@ -160,6 +704,7 @@ impl Debug for Rule {
 }
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub inner: Vec<Rule>,
 }
@@ -168,11 +713,48 @@ impl From<Vec<Rule>> for Program {
     fn from(value: Vec<Rule>) -> Self {
         let mut val = value;
         val.sort();
+        let mut seen = HashSet::new();
+        val.retain(|rule| seen.insert(rule.canonical()));
+        // Rules with fewer body atoms are looked at first, so that of two rules that subsume each
+        // other, only the first is kept.
+        let mut by_body_length: Vec<usize> = (0..val.len()).collect();
+        by_body_length.sort_by_key(|&idx| val[idx].body.len());
+        let mut kept: Vec<usize> = vec![];
+        for idx in by_body_length {
+            if !kept.iter().any(|&other| val[other].subsumes(&val[idx])) {
+                kept.push(idx);
+            }
+        }
+        let mut idx = 0;
+        val.retain(|_| {
+            idx += 1;
+            kept.contains(&(idx - 1))
+        });
         // Questionable, I know :)
         for (id, rule) in val.iter_mut().enumerate() {
-            (*rule).id = id;
+            rule.id = id;
         }
 
         Self { inner: val }
     }
 }
+
+impl Program {
+    // Fails on the first rule that is not safe, which is one whose variables are not all bound by
+    // its positive body atoms, or that has none to begin with.
+    pub fn validate(&self) -> Result<(), String> {
+        for rule in &self.inner {
+            if !rule.body.iter().any(|atom| atom.sign) {
+                return Err(format!("{:?} has no positive body atom", rule));
+            }
+            if let Some(variable) = rule.unsafe_variables().first() {
+                return Err(format!(
+                    "{:?} has variable {} that no positive body atom binds",
+                    rule, variable
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
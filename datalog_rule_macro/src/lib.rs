@@ -2,27 +2,56 @@ extern crate common;
 extern crate proc_macro;
 
 use common::program_transformations::dependency_graph::{generate_rule_dependency_graph, stratify};
-use datalog_syntax::{Atom, Rule, Term, TypedValue};
+use datalog_syntax::{Aggregation, Atom, Expression, Operator, Rule, Term, TypedValue};
 use proc_macro::TokenStream;
 use quote::quote;
 use std::collections::{HashMap, HashSet};
 use syn::parse::{Parse, ParseStream};
-use syn::{bracketed, parenthesized, Expr, Ident, Result, Token};
+use syn::{
+    bracketed, parenthesized, Expr, ExprLit, ExprUnary, Ident, Lit, LitInt, Result, Token, UnOp,
+};
 
+#[derive(Clone)]
 enum TermArg {
     Variable(Ident),
+    // A variable that occurs nowhere else, not even where another wildcard is.
+    Wildcard(Token![_]),
     Constant(Expr),
+    // A Rust value from where the rule is made, as in #threshold or #(config.threshold), that is
+    // evaluated once as the rule is built.
+    Splice(Expr),
+    Aggregate(Aggregation, Ident),
+    // What a variable assigned after the body, as in ?z = ?y + 1, is computed as.
+    Expression(ExpressionArg),
 }
 
+#[derive(Clone)]
+enum ExpressionArg {
+    Variable(Ident),
+    Constant(Expr),
+    Splice(Expr),
+    Binary(Operator, Box<ExpressionArg>, Box<ExpressionArg>),
+}
+
+#[derive(Clone)]
 struct AtomArgs {
     name: Ident,
     args: Vec<TermArg>,
     sign: bool,
 }
 
+// What the attributes before a rule, as in #[no_reorder] or #[index(e(0))], ask of the planner.
+#[derive(Clone, Default)]
+struct HintArgs {
+    no_reorder: bool,
+    no_index: bool,
+    indices: Vec<(Ident, Vec<usize>)>,
+}
+
 struct RuleMacroInput {
     head: AtomArgs,
     body: Vec<AtomArgs>,
+    hints: HintArgs,
 }
 
 impl Parse for TermArg {
@@ -31,6 +60,25 @@ impl Parse for TermArg {
             input.parse::<Token![?]>()?;
             let ident: Ident = input.parse()?;
             Ok(TermArg::Variable(ident))
+        } else if input.peek(Token![_]) {
+            Ok(TermArg::Wildcard(input.parse()?))
+        } else if input.peek(Token![#]) {
+            input.parse::<Token![#]>()?;
+            if input.peek(syn::token::Paren) {
+                let content;
+                parenthesized!(content in input);
+                Ok(TermArg::Splice(content.parse()?))
+            } else {
+                let ident: Ident = input.parse()?;
+                Ok(TermArg::Splice(syn::parse_quote!(#ident)))
+            }
+        } else if let Some(aggregation) = peek_aggregation(input) {
+            input.parse::<Ident>()?;
+            let content;
+            parenthesized!(content in input);
+            content.parse::<Token![?]>()?;
+            let ident: Ident = content.parse()?;
+            Ok(TermArg::Aggregate(aggregation, ident))
         } else {
             let expr: Expr = input.parse()?;
             Ok(TermArg::Constant(expr))
@@ -38,26 +86,337 @@ impl Parse for TermArg {
     }
 }
 
+// Multiplication binds tighter than addition and subtraction, which associate to the left.
+impl Parse for ExpressionArg {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut left = parse_product(input)?;
+        loop {
+            let operator = if input.peek(Token![+]) {
+                input.parse::<Token![+]>()?;
+                Operator::Add
+            } else if input.peek(Token![-]) {
+                input.parse::<Token![-]>()?;
+                Operator::Subtract
+            } else {
+                return Ok(left);
+            };
+            left = ExpressionArg::Binary(operator, Box::new(left), Box::new(parse_product(input)?));
+        }
+    }
+}
+
+fn parse_product(input: ParseStream) -> Result<ExpressionArg> {
+    let mut left = parse_operand(input)?;
+    while input.peek(Token![*]) {
+        input.parse::<Token![*]>()?;
+        left = ExpressionArg::Binary(
+            Operator::Multiply,
+            Box::new(left),
+            Box::new(parse_operand(input)?),
+        );
+    }
+
+    Ok(left)
+}
+
+fn parse_operand(input: ParseStream) -> Result<ExpressionArg> {
+    if input.peek(syn::token::Paren) {
+        let content;
+        parenthesized!(content in input);
+        return content.parse();
+    }
+
+    // Constants are single literals, since anything longer would swallow the operators after them.
+    if input.peek(Token![-]) {
+        input.parse::<Token![-]>()?;
+        let lit: Lit = input.parse()?;
+        return Ok(ExpressionArg::Constant(syn::parse_quote!(-#lit)));
+    }
+    if input.peek(Lit) {
+        let lit: Lit = input.parse()?;
+        return Ok(ExpressionArg::Constant(syn::parse_quote!(#lit)));
+    }
+
+    if !input.peek(Token![?]) && !input.peek(Token![#]) {
+        return Err(input.error("expected a variable, a constant or a parenthesized expression"));
+    }
+    match input.parse::<TermArg>()? {
+        TermArg::Variable(ident) => Ok(ExpressionArg::Variable(ident)),
+        TermArg::Splice(expr) => Ok(ExpressionArg::Splice(expr)),
+        _ => unreachable!(),
+    }
+}
+
+impl ExpressionArg {
+    fn variables(&self) -> Vec<&Ident> {
+        match self {
+            ExpressionArg::Variable(ident) => vec![ident],
+            ExpressionArg::Constant(_) | ExpressionArg::Splice(_) => vec![],
+            ExpressionArg::Binary(_, left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+        }
+    }
+    // Variables assigned before are replaced by what they are computed as.
+    fn substitute(self, assignments: &HashMap<String, ExpressionArg>) -> ExpressionArg {
+        match self {
+            ExpressionArg::Variable(ident) => assignments
+                .get(&ident.to_string())
+                .cloned()
+                .unwrap_or(ExpressionArg::Variable(ident)),
+            ExpressionArg::Binary(operator, left, right) => ExpressionArg::Binary(
+                operator,
+                Box::new(left.substitute(assignments)),
+                Box::new(right.substitute(assignments)),
+            ),
+            other => other,
+        }
+    }
+}
+
+// Calls to anything but an aggregation are left alone, since they are valid constant expressions.
+fn peek_aggregation(input: ParseStream) -> Option<Aggregation> {
+    if !input.peek2(syn::token::Paren) {
+        return None;
+    }
+
+    let ident: Ident = input.fork().parse().ok()?;
+    match ident.to_string().as_str() {
+        "count" => Some(Aggregation::Count),
+        "sum" => Some(Aggregation::Sum),
+        "min" => Some(Aggregation::Min),
+        "max" => Some(Aggregation::Max),
+        "choice" => Some(Aggregation::Choice),
+        _ => None,
+    }
+}
+
 impl Parse for RuleMacroInput {
     fn parse(input: ParseStream) -> Result<Self> {
-        let head = input.parse::<AtomArgs>()?;
-        let mut distinguished_variables: HashMap<String, (&Ident, bool)> = head
-            .args
-            .iter()
-            .filter(|term| matches!(term, TermArg::Variable(_)))
-            .map(|variable| match variable {
-                TermArg::Variable(ident) => (ident.to_string(), (ident, false)),
-                _ => unreachable!(),
-            })
-            .collect();
+        let mut rules = parse_rules(input)?;
+        if rules.len() > 1 {
+            return Err(syn::Error::new(
+                rules[1].head.name.span(),
+                "rule! makes a single rule, use program! for rules with several heads or disjuncts",
+            ));
+        }
+
+        Ok(rules.remove(0))
+    }
+}
+
+// A rule may have several heads, as in a(?x), b(?x) <- [c(?x)], which stands for one rule per head
+// with the same body. Rules with the same body share their joins within an iteration. Likewise, a
+// body may be a disjunction of conjunctions, as in a(?x) <- [b(?x), c(?x); d(?x)], which stands for
+// one rule per disjunct. Head variables may be computed from those that the body binds, by
+// assignments after it, as in next(?x, ?z) <- [node(?x, ?y)], ?z = ?y + 1. Attributes before a
+// rule, as in #[no_reorder], #[no_index] or #[index(e(0))], override how it is planned.
+fn parse_rules(input: ParseStream) -> Result<Vec<RuleMacroInput>> {
+    let hints = parse_hints(&input.call(syn::Attribute::parse_outer)?)?;
+    let mut heads = vec![input.parse::<AtomArgs>()?];
+    while input.peek(Token![,]) {
+        input.parse::<Token![,]>()?;
+        heads.push(input.parse::<AtomArgs>()?);
+    }
+
+    input.parse::<Token![<-]>()?;
+    let content2;
+    bracketed!(content2 in input);
+    let mut disjuncts: Vec<Vec<AtomArgs>> = vec![vec![]];
+    while !content2.is_empty() {
+        disjuncts.last_mut().unwrap().push(content2.parse()?);
+        if content2.is_empty() {
+            break;
+        }
+        if content2.peek(Token![;]) {
+            content2.parse::<Token![;]>()?;
+            disjuncts.push(vec![]);
+        } else {
+            content2.parse::<Token![,]>()?;
+        }
+    }
+
+    let mut assignments: HashMap<String, ExpressionArg> = HashMap::new();
+    while input.peek(Token![,]) && input.peek2(Token![?]) {
+        input.parse::<Token![,]>()?;
+        input.parse::<Token![?]>()?;
+        let ident: Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let expression = input.parse::<ExpressionArg>()?.substitute(&assignments);
 
-        input.parse::<Token![<-]>()?;
-        let content2;
-        bracketed!(content2 in input);
-        let body: syn::punctuated::Punctuated<AtomArgs, Token![,]> =
-            content2.parse_terminated(AtomArgs::parse)?;
-        let body_vec: Vec<AtomArgs> = body.into_iter().collect();
-        body_vec.iter().for_each(|body_atom| {
+        let is_bound = disjuncts.iter().flatten().any(|body_atom| {
+            body_atom
+                .args
+                .iter()
+                .any(|term| matches!(term, TermArg::Variable(bound) if *bound == ident))
+        });
+        if is_bound {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!(
+                    "variable {} is bound by the body, so it can not be assigned",
+                    ident
+                ),
+            ));
+        }
+        if assignments.insert(ident.to_string(), expression).is_some() {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!("variable {} is assigned more than once", ident),
+            ));
+        }
+    }
+    for term in heads.iter_mut().flat_map(|head| &mut head.args) {
+        if let TermArg::Variable(ident) = term {
+            if let Some(expression) = assignments.get(&ident.to_string()) {
+                *term = TermArg::Expression(expression.clone());
+            }
+        }
+    }
+
+    let mut rules = vec![];
+    for body_vec in disjuncts {
+        for body_atom in &body_vec {
+            for term in &body_atom.args {
+                if let TermArg::Aggregate(_, ident) = term {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        "aggregations are only allowed in the head",
+                    ));
+                }
+            }
+        }
+        if !body_vec.iter().any(|body_atom| body_atom.sign) {
+            return Err(syn::Error::new(
+                heads[0].name.span(),
+                "a rule needs at least one positive body atom",
+            ));
+        }
+
+        check_negation(&body_vec)?;
+        for head in &heads {
+            check_head(head, &body_vec)?;
+
+            rules.push(RuleMacroInput {
+                head: head.clone(),
+                body: body_vec.clone(),
+                hints: hints.clone(),
+            });
+        }
+    }
+
+    Ok(rules)
+}
+
+// Hints apply to every rule that the heads and disjuncts after them make.
+fn parse_hints(attributes: &[syn::Attribute]) -> Result<HintArgs> {
+    let mut hints = HintArgs::default();
+    for attribute in attributes {
+        match attribute.parse_meta()? {
+            syn::Meta::Path(path) if path.is_ident("no_reorder") => hints.no_reorder = true,
+            syn::Meta::Path(path) if path.is_ident("no_index") => hints.no_index = true,
+            syn::Meta::List(list) if list.path.is_ident("index") => {
+                for nested in &list.nested {
+                    let relation = match nested {
+                        syn::NestedMeta::Meta(syn::Meta::List(relation))
+                            if relation.path.get_ident().is_some()
+                                && !relation.nested.is_empty() =>
+                        {
+                            relation
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                nested,
+                                "expected a relation and the columns to index, as in e(0, 1)",
+                            ))
+                        }
+                    };
+                    let columns = relation
+                        .nested
+                        .iter()
+                        .map(|column| match column {
+                            syn::NestedMeta::Lit(Lit::Int(column)) => column.base10_parse(),
+                            _ => Err(syn::Error::new_spanned(column, "expected a column")),
+                        })
+                        .collect::<Result<_>>()?;
+
+                    hints
+                        .indices
+                        .push((relation.path.get_ident().unwrap().clone(), columns));
+                }
+            }
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected no_reorder, no_index or index(...)",
+                ))
+            }
+        }
+    }
+
+    Ok(hints)
+}
+
+// Every variable of a negated atom has to be bound by a positive one, or the negation would range
+// over every value there is.
+fn check_negation(body_vec: &[AtomArgs]) -> Result<()> {
+    let bound: HashSet<String> = body_vec
+        .iter()
+        .filter(|body_atom| body_atom.sign)
+        .flat_map(|body_atom| &body_atom.args)
+        .filter_map(|term| match term {
+            TermArg::Variable(ident) => Some(ident.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    for term in body_vec
+        .iter()
+        .filter(|body_atom| !body_atom.sign)
+        .flat_map(|body_atom| &body_atom.args)
+    {
+        if let TermArg::Variable(ident) = term {
+            if !bound.contains(&ident.to_string()) {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!(
+                        "variable {} of a negated atom is not bound by a positive one",
+                        ident
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_head(head: &AtomArgs, body_vec: &[AtomArgs]) -> Result<()> {
+    for term in &head.args {
+        if let TermArg::Wildcard(wildcard) = term {
+            return Err(syn::Error::new(
+                wildcard.span,
+                "wildcards are only allowed in the body",
+            ));
+        }
+    }
+    let mut distinguished_variables: HashMap<String, (&Ident, bool)> = head
+        .args
+        .iter()
+        .flat_map(|term| match term {
+            TermArg::Variable(ident) | TermArg::Aggregate(_, ident) => vec![ident],
+            TermArg::Expression(expression) => expression.variables(),
+            _ => vec![],
+        })
+        .map(|ident| (ident.to_string(), (ident, false)))
+        .collect();
+
+    body_vec
+        .iter()
+        .filter(|body_atom| body_atom.sign)
+        .for_each(|body_atom| {
             body_atom
                 .args
                 .iter()
@@ -74,20 +433,16 @@ impl Parse for RuleMacroInput {
                 });
         });
 
-        for (key, value) in distinguished_variables {
-            if !value.1 {
-                return Err(syn::Error::new(
-                    value.0.span(),
-                    format!("variable {} not found in the body", key),
-                ));
-            }
+    for (key, value) in distinguished_variables {
+        if !value.1 {
+            return Err(syn::Error::new(
+                value.0.span(),
+                format!("variable {} not found in the body", key),
+            ));
         }
-
-        Ok(RuleMacroInput {
-            head,
-            body: body_vec,
-        })
     }
+
+    Ok(())
 }
 
 impl Parse for AtomArgs {
@@ -111,6 +466,24 @@ impl Parse for AtomArgs {
     }
 }
 
+/// Rejects rules without a positive body atom, since negated atoms alone bind nothing.
+///
+/// ```compile_fail
+/// use datalog_rule_macro::rule;
+/// use datalog_syntax::*;
+///
+/// rule! { bad(?x) <- [!good(?x)] };
+/// ```
+///
+/// Rejects rules with a variable that only negated atoms have, as the negation would range over
+/// every value there is.
+///
+/// ```compile_fail
+/// use datalog_rule_macro::rule;
+/// use datalog_syntax::*;
+///
+/// rule! { s(?y) <- [r(?y), !t(?x)] };
+/// ```
 #[proc_macro]
 pub fn rule(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as RuleMacroInput);
@@ -122,7 +495,11 @@ pub fn rule(input: TokenStream) -> TokenStream {
         .iter()
         .map(|arg| match arg {
             TermArg::Variable(ident) => quote! { Term::Variable(stringify!(#ident).to_string()) },
-            TermArg::Constant(expr) => quote! { Term::Constant(TypedValue::from(#expr)) },
+            TermArg::Constant(expr) => constant_term_tokens(expr),
+            TermArg::Splice(expr) => splice_term_tokens(expr),
+            TermArg::Wildcard(_) => wildcard_term_tokens(),
+            TermArg::Aggregate(aggregation, ident) => aggregate_term_tokens(aggregation, ident),
+            TermArg::Expression(expression) => expression_term_tokens(expression),
         })
         .collect();
 
@@ -137,8 +514,12 @@ pub fn rule(input: TokenStream) -> TokenStream {
                         TermArg::Variable(ident) => {
                             quote! { Term::Variable(stringify!(#ident).to_string()) }
                         }
-                        TermArg::Constant(expr) =>
-                            quote! { Term::Constant(TypedValue::from(#expr)) },
+                        TermArg::Constant(expr) => constant_term_tokens(expr),
+                        TermArg::Splice(expr) => splice_term_tokens(expr),
+                        TermArg::Wildcard(_) => wildcard_term_tokens(),
+                        TermArg::Aggregate(aggregation, ident) =>
+                            aggregate_term_tokens(aggregation, ident),
+                        TermArg::Expression(expression) => expression_term_tokens(expression),
                     }
                 })
                 .collect();
@@ -147,11 +528,13 @@ pub fn rule(input: TokenStream) -> TokenStream {
         })
         .collect();
 
+    let hints = hints_tokens(&input.hints);
     let expanded = quote! {
         Rule {
             head: Atom { terms: vec![#(#head_terms),*], symbol: stringify!(#head_name).to_string(), sign: true },
             body: vec![#(#body_atoms),*],
-            id: 0
+            id: 0,
+            hints: #hints
         }
     };
 
@@ -159,20 +542,48 @@ pub fn rule(input: TokenStream) -> TokenStream {
 }
 
 struct ProgramMacroInput {
-    rules: syn::punctuated::Punctuated<RuleMacroInput, Token![,]>,
+    rules: Vec<RuleMacroInput>,
 }
 
 impl Parse for ProgramMacroInput {
     fn parse(input: ParseStream) -> Result<Self> {
-        let rules = input.parse_terminated(RuleMacroInput::parse)?;
+        let mut rules = vec![];
+        while !input.is_empty() {
+            rules.extend(parse_rules(input)?);
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+
         Ok(ProgramMacroInput { rules })
     }
 }
 
+/// Rejects programs in which an aggregate depends on itself. Negated atoms are kept as they are,
+/// leaving it to the runtime to find out whether they can be stratified.
+///
+/// ```compile_fail
+/// use datalog_rule_macro::program;
+/// use datalog_syntax::*;
+///
+/// program! {
+///     tc(?x, ?y) <- [e(?x, ?y)],
+///     e(?x, count(?y)) <- [tc(?x, ?y)]
+/// };
+/// ```
 #[proc_macro]
 pub fn program(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as ProgramMacroInput);
 
+    if let Err(error) = check_aggregation(&input) {
+        return error.to_compile_error().into();
+    }
+
+    program_tokens(input)
+}
+
+fn program_tokens(input: ProgramMacroInput) -> TokenStream {
     let rules: Vec<_> = input.rules
         .into_iter()
         .map(|rule_input| {
@@ -183,8 +594,12 @@ pub fn program(input: TokenStream) -> TokenStream {
                     match arg {
                         TermArg::Variable(ident) =>
                             quote! { Term::Variable(stringify!(#ident).to_string()) },
-                        TermArg::Constant(expr) =>
-                            quote! { Term::Constant(TypedValue::from(#expr)) },
+                        TermArg::Constant(expr) => constant_term_tokens(expr),
+                        TermArg::Splice(expr) => splice_term_tokens(expr),
+                        TermArg::Wildcard(_) => wildcard_term_tokens(),
+                        TermArg::Aggregate(aggregation, ident) =>
+                            aggregate_term_tokens(aggregation, ident),
+                        TermArg::Expression(expression) => expression_term_tokens(expression),
                     }
                 })
                 .collect();
@@ -200,20 +615,27 @@ pub fn program(input: TokenStream) -> TokenStream {
                                 TermArg::Variable(ident) => {
                                     quote! { Term::Variable(stringify!(#ident).to_string()) }
                                 }
-                                TermArg::Constant(expr) =>
-                                    quote! { Term::Constant(TypedValue::from(#expr)) },
+                                TermArg::Constant(expr) => constant_term_tokens(expr),
+                                TermArg::Splice(expr) => splice_term_tokens(expr),
+                                TermArg::Wildcard(_) => wildcard_term_tokens(),
+                                TermArg::Aggregate(aggregation, ident) =>
+                                    aggregate_term_tokens(aggregation, ident),
+                                TermArg::Expression(expression) => expression_term_tokens(expression),
                             }
                         })
                         .collect();
-                    quote! { Atom { terms: vec![#(#terms),*], symbol: stringify!(#name).to_string(), sign: true } }
+                    let sign = atom.sign;
+                    quote! { Atom { terms: vec![#(#terms),*], symbol: stringify!(#name).to_string(), sign: #sign } }
                 })
                 .collect();
 
+            let hints = hints_tokens(&rule_input.hints);
             quote! {
             Rule {
                 head: Atom { terms: vec![#(#head_terms),*], symbol: stringify!(#head_name).to_string(), sign: true },
                 body: vec![#(#body_atoms),*],
-                id: 0
+                id: 0,
+                hints: #hints
             }
         }
         })
@@ -226,10 +648,25 @@ pub fn program(input: TokenStream) -> TokenStream {
     expanded.into()
 }
 
+/// Rejects programs in which a negated atom is also the head of some rule.
+///
+/// ```compile_fail
+/// use datalog_rule_macro::semipositive_program;
+/// use datalog_syntax::*;
+///
+/// semipositive_program! {
+///     tc(?x, ?y) <- [e(?x, ?y)],
+///     tc(?x, ?z) <- [e(?x, ?y), !tc(?y, ?z)]
+/// };
+/// ```
 #[proc_macro]
 pub fn semipositive_program(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as ProgramMacroInput);
 
+    if let Err(error) = check_aggregation(&input) {
+        return error.to_compile_error().into();
+    }
+
     let mut heads = HashSet::new();
     for rule in &input.rules {
         heads.insert(&rule.head.name);
@@ -249,127 +686,315 @@ pub fn semipositive_program(input: TokenStream) -> TokenStream {
         }
     }
 
-    let rules: Vec<_> = input.rules
-        .into_iter()
-        .map(|rule_input| {
-            let head_name = &rule_input.head.name;
-            let head_terms: Vec<_> = rule_input.head.args
-                .iter()
-                .map(|arg| {
-                    match arg {
-                        TermArg::Variable(ident) =>
-                            quote! { Term::Variable(stringify!(#ident).to_string()) },
-                        TermArg::Constant(expr) =>
-                            quote! { Term::Constant(TypedValue::from(#expr)) },
-                    }
-                })
-                .collect();
+    program_tokens(input)
+}
 
-            let body_atoms: Vec<_> = rule_input.body
-                .iter()
-                .map(|atom| {
-                    let name = &atom.name;
-                    let terms: Vec<_> = atom.args
-                        .iter()
-                        .map(|arg| {
-                            match arg {
-                                TermArg::Variable(ident) => {
-                                    quote! { Term::Variable(stringify!(#ident).to_string()) }
-                                }
-                                TermArg::Constant(expr) =>
-                                    quote! { Term::Constant(TypedValue::from(#expr)) },
-                            }
-                        })
-                        .collect();
-                    let sign = atom.sign;
-                    quote! { Atom { terms: vec![#(#terms),*], symbol: stringify!(#name).to_string(), sign: #sign } }
-                })
-                .collect();
+fn string_to_ident_with_span(symbol: &str, span: syn::__private::Span) -> Ident {
+    Ident::new(symbol, span)
+}
 
-            quote! {
-            Rule {
-                head: Atom { terms: vec![#(#head_terms),*], symbol: stringify!(#head_name).to_string(), sign: true },
-                body: vec![#(#body_atoms),*],
-                id: 0
+// Unsuffixed integer literals are taken as usize when positive and as signed integers when
+// negated. TypedValue only converts from usize, so signed literals are given their variant here.
+fn constant_term_tokens(expr: &Expr) -> proc_macro2::TokenStream {
+    let value = constant_value_tokens(expr);
+    quote! { Term::Constant(#value) }
+}
+
+fn constant_value_tokens(expr: &Expr) -> proc_macro2::TokenStream {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) if lit_int.suffix().is_empty() => {
+            let lit_usize =
+                LitInt::new(&format!("{}usize", lit_int.base10_digits()), lit_int.span());
+            quote! { TypedValue::from(#lit_usize) }
+        }
+        Expr::Unary(ExprUnary {
+            op: UnOp::Neg(_),
+            expr: inner,
+            ..
+        }) => match inner.as_ref() {
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(lit_int),
+                ..
+            }) if lit_int.suffix().is_empty() => {
+                let lit_i64 =
+                    LitInt::new(&format!("{}i64", lit_int.base10_digits()), lit_int.span());
+                quote! { TypedValue::SignedInt(-#lit_i64) }
+            }
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(lit_int),
+                ..
+            }) if lit_int.suffix().starts_with('i') => {
+                quote! { TypedValue::SignedInt(i64::from(#expr)) }
             }
+            _ => quote! { TypedValue::from(#expr) },
+        },
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) if lit_int.suffix().starts_with('i') => {
+            quote! { TypedValue::SignedInt(i64::from(#expr)) }
         }
-        })
-        .collect();
+        _ => quote! { TypedValue::from(#expr) },
+    }
+}
 
-    let expanded = quote! {
-        Program::from( vec![#(#rules),*] )
+fn wildcard_term_tokens() -> proc_macro2::TokenStream {
+    quote! { Term::Variable("_".to_string()) }
+}
+
+// The value is cloned, so that splicing a String or anything else that is not Copy leaves it to be
+// used again.
+fn splice_term_tokens(expr: &Expr) -> proc_macro2::TokenStream {
+    let value = splice_value_tokens(expr);
+    quote! { Term::Constant(#value) }
+}
+
+fn splice_value_tokens(expr: &Expr) -> proc_macro2::TokenStream {
+    quote! { IntoTypedValue::into_typed_value(::std::clone::Clone::clone(&(#expr))) }
+}
+
+fn expression_term_tokens(expression: &ExpressionArg) -> proc_macro2::TokenStream {
+    let expression = expression_tokens(expression);
+    quote! { Term::Expression(#expression) }
+}
+
+fn expression_tokens(expression: &ExpressionArg) -> proc_macro2::TokenStream {
+    match expression {
+        ExpressionArg::Variable(ident) => {
+            quote! { Expression::Variable(stringify!(#ident).to_string()) }
+        }
+        ExpressionArg::Constant(expr) => {
+            let value = constant_value_tokens(expr);
+            quote! { Expression::Constant(#value) }
+        }
+        ExpressionArg::Splice(expr) => {
+            let value = splice_value_tokens(expr);
+            quote! { Expression::Constant(#value) }
+        }
+        ExpressionArg::Binary(operator, left, right) => {
+            let operator = match operator {
+                Operator::Add => quote! { Operator::Add },
+                Operator::Subtract => quote! { Operator::Subtract },
+                Operator::Multiply => quote! { Operator::Multiply },
+            };
+            let (left, right) = (expression_tokens(left), expression_tokens(right));
+            quote! { Expression::Binary(#operator, Box::new(#left), Box::new(#right)) }
+        }
+    }
+}
+
+fn hints_tokens(hints: &HintArgs) -> proc_macro2::TokenStream {
+    let no_reorder = hints.no_reorder;
+    let no_index = hints.no_index;
+    let symbols = hints.indices.iter().map(|(symbol, _)| symbol);
+    let columns = hints.indices.iter().map(|(_, columns)| columns);
+
+    quote! {
+        PlanHints {
+            no_reorder: #no_reorder,
+            no_index: #no_index,
+            indices: vec![#((stringify!(#symbols).to_string(), vec![#(#columns),*])),*],
+        }
+    }
+}
+
+fn aggregate_term_tokens(aggregation: &Aggregation, ident: &Ident) -> proc_macro2::TokenStream {
+    let aggregation = match aggregation {
+        Aggregation::Count => quote! { Aggregation::Count },
+        Aggregation::Sum => quote! { Aggregation::Sum },
+        Aggregation::Min => quote! { Aggregation::Min },
+        Aggregation::Max => quote! { Aggregation::Max },
+        Aggregation::Choice => quote! { Aggregation::Choice },
     };
 
-    expanded.into()
+    quote! { Term::Aggregate(#aggregation, stringify!(#ident).to_string()) }
 }
 
-fn string_to_ident_with_span(symbol: &str, span: syn::__private::Span) -> Ident {
-    Ident::new(symbol, span)
+// An aggregate can only be computed once everything it ranges over is known, so no relation in its
+// body may depend on its head. A single min or max is the exception, as it only ever improves.
+fn check_aggregation(input: &ProgramMacroInput) -> Result<()> {
+    let mut dependencies: HashMap<String, HashSet<String>> = HashMap::new();
+    for rule in &input.rules {
+        dependencies
+            .entry(rule.head.name.to_string())
+            .or_default()
+            .extend(rule.body.iter().map(|atom| atom.name.to_string()));
+    }
+
+    for rule in &input.rules {
+        let aggregations: Vec<_> = rule
+            .head
+            .args
+            .iter()
+            .filter_map(|term| match term {
+                TermArg::Aggregate(aggregation, _) => Some(aggregation),
+                _ => None,
+            })
+            .collect();
+        let extrema = aggregations
+            .iter()
+            .filter(|aggregation| matches!(aggregation, Aggregation::Min | Aggregation::Max))
+            .count();
+        let is_monotonic = extrema == 1
+            && aggregations
+                .iter()
+                .all(|aggregation| !matches!(aggregation, Aggregation::Count | Aggregation::Sum));
+        if aggregations.is_empty() || is_monotonic {
+            continue;
+        }
+
+        let head = rule.head.name.to_string();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<String> = rule.body.iter().map(|atom| atom.name.to_string()).collect();
+        while let Some(symbol) = stack.pop() {
+            if symbol == head {
+                let message = format!("Aggregate '{}' depends on itself!", head);
+                return Err(syn::Error::new(rule.head.name.span(), message));
+            }
+
+            if seen.insert(symbol.clone()) {
+                if let Some(next) = dependencies.get(&symbol) {
+                    stack.extend(next.iter().cloned());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn lit_to_typed_value(lit: &Lit, negated: bool) -> Result<TypedValue> {
+    match lit {
+        Lit::Str(lit_str) if !negated => Ok(TypedValue::from(lit_str.value())),
+        Lit::Bool(lit_bool) if !negated => Ok(TypedValue::from(lit_bool.value)),
+        Lit::Int(lit_int) if negated || lit_int.suffix().starts_with('i') => {
+            // Parsed wider than i64, as the digits of i64::MIN only fit once they are negated.
+            let value = lit_int.base10_parse::<i128>()?;
+            let value = if negated { -value } else { value };
+            i64::try_from(value)
+                .map(TypedValue::SignedInt)
+                .map_err(|_| {
+                    let message = format!("{} does not fit in an i64", value);
+                    syn::Error::new(lit_int.span(), message)
+                })
+        }
+        Lit::Int(lit_int) => Ok(TypedValue::from(lit_int.base10_parse::<usize>()?)),
+        Lit::Float(lit_float) => {
+            let value = lit_float.base10_parse::<f64>()?;
+            Ok(TypedValue::from(if negated { -value } else { value }))
+        }
+        _ => Err(syn::Error::new(lit.span(), "Unsupported literal type")),
+    }
 }
 
-fn expr_to_typed_value(expr: &Expr) -> TypedValue {
+fn expr_to_typed_value(expr: &Expr) -> Result<TypedValue> {
     match expr {
-        Expr::Lit(expr_lit) => match &expr_lit.lit {
-            syn::Lit::Str(lit_str) => TypedValue::from(lit_str.value()),
-            syn::Lit::Int(lit_int) => TypedValue::from(lit_int.base10_parse::<usize>().unwrap()),
-            syn::Lit::Bool(lit_bool) => TypedValue::from(lit_bool.value),
-            _ => panic!("Unsupported literal type"),
+        Expr::Lit(expr_lit) => lit_to_typed_value(&expr_lit.lit, false),
+        Expr::Unary(ExprUnary {
+            op: UnOp::Neg(_),
+            expr: inner,
+            ..
+        }) => match inner.as_ref() {
+            Expr::Lit(expr_lit) => lit_to_typed_value(&expr_lit.lit, true),
+            _ => Err(syn::Error::new_spanned(expr, "Unsupported expression type")),
         },
-        _ => panic!("Unsupported expression type"),
+        _ => Err(syn::Error::new_spanned(expr, "Unsupported expression type")),
     }
 }
 
-#[proc_macro]
-pub fn stratified_program(input: TokenStream) -> TokenStream {
-    let input_clone = input.clone();
-    let parsed_input = syn::parse_macro_input!(input as ProgramMacroInput);
+fn expression_value(expression: &ExpressionArg) -> Result<Expression> {
+    Ok(match expression {
+        ExpressionArg::Variable(ident) => Expression::Variable(ident.to_string()),
+        ExpressionArg::Constant(expr) => Expression::Constant(expr_to_typed_value(expr)?),
+        ExpressionArg::Splice(_) => Expression::Constant(spliced_placeholder()),
+        ExpressionArg::Binary(operator, left, right) => Expression::Binary(
+            *operator,
+            Box::new(expression_value(left)?),
+            Box::new(expression_value(right)?),
+        ),
+    })
+}
 
+// The rules as the engine sees them, for stratifying.
+fn program_rules(input: &ProgramMacroInput) -> Result<Vec<Rule>> {
     let mut program_rules: Vec<_> = vec![];
 
-    for rule in parsed_input.rules {
-        // let head_name = &rule.head.name;
-        let head_terms: Vec<_> = rule
+    for rule in &input.rules {
+        let head_terms = rule
             .head
             .args
             .iter()
-            .map(|arg| match arg {
-                TermArg::Variable(ident) => Term::Variable(ident.to_string()),
-                TermArg::Constant(expr) => Term::Constant(expr_to_typed_value(expr)),
-            })
-            .collect();
+            .map(term_value)
+            .collect::<Result<Vec<_>>>()?;
 
-        let body_atoms: Vec<_> = rule
-            .body
-            .iter()
-            .map(|atom| {
-                let atom_name = &atom.name;
-                let atom_terms: Vec<_> = atom
-                    .args
-                    .iter()
-                    .map(|arg| match arg {
-                        TermArg::Variable(ident) => Term::Variable(ident.to_string()),
-                        TermArg::Constant(expr) => Term::Constant(expr_to_typed_value(expr)),
-                    })
-                    .collect();
-                Atom {
-                    terms: atom_terms,
-                    symbol: atom_name.to_string(),
-                    sign: atom.sign,
-                }
-            })
-            .collect();
+        let mut body_atoms: Vec<_> = vec![];
+        for atom in &rule.body {
+            body_atoms.push(Atom {
+                terms: atom.args.iter().map(term_value).collect::<Result<_>>()?,
+                symbol: atom.name.to_string(),
+                sign: atom.sign,
+            });
+        }
 
         program_rules.push(Rule {
             head: Atom {
                 terms: head_terms,
-                symbol: stringify!(head_name).to_string(),
+                symbol: rule.head.name.to_string(),
                 sign: true,
             },
             body: body_atoms,
             id: 0,
+            hints: Default::default(),
         });
     }
 
+    Ok(program_rules)
+}
+
+fn term_value(arg: &TermArg) -> Result<Term> {
+    Ok(match arg {
+        TermArg::Variable(ident) => Term::Variable(ident.to_string()),
+        TermArg::Constant(expr) => Term::Constant(expr_to_typed_value(expr)?),
+        TermArg::Splice(_) => Term::Constant(spliced_placeholder()),
+        TermArg::Wildcard(_) => Term::Variable("_".to_string()),
+        TermArg::Aggregate(aggregation, ident) => Term::Aggregate(*aggregation, ident.to_string()),
+        TermArg::Expression(expression) => Term::Expression(expression_value(expression)?),
+    })
+}
+
+// Spliced values are only known once the program runs, and stratifying does not look at
+// constants anyway.
+fn spliced_placeholder() -> TypedValue {
+    TypedValue::from(false)
+}
+
+/// Rejects programs in which negated dependencies form a cycle.
+///
+/// ```compile_fail
+/// use datalog_rule_macro::stratified_program;
+/// use datalog_syntax::*;
+///
+/// stratified_program! {
+///     tc(?x, ?y) <- [e(?x, ?y)],
+///     e(?x, ?y) <- [s(?x, ?y); t(?x, ?y), !tc(?x, ?y)]
+/// };
+/// ```
+#[proc_macro]
+pub fn stratified_program(input: TokenStream) -> TokenStream {
+    let parsed_input = syn::parse_macro_input!(input as ProgramMacroInput);
+
+    if let Err(error) = check_aggregation(&parsed_input) {
+        return error.to_compile_error().into();
+    }
+
+    let program_rules = match program_rules(&parsed_input) {
+        Ok(program_rules) => program_rules,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
     let rule_graph = generate_rule_dependency_graph(&program_rules);
     let stratification = stratify(&rule_graph);
 
@@ -389,5 +1014,125 @@ pub fn stratified_program(input: TokenStream) -> TokenStream {
         }
     }
 
-    semipositive_program(input_clone)
+    program_tokens(parsed_input)
+}
+
+// The relation a struct stands for is named after it, unless given as in #[relation(name = "e")].
+fn relation_symbol(input: &syn::DeriveInput) -> Result<String> {
+    for attribute in input
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path.is_ident("relation"))
+    {
+        if let syn::Meta::List(list) = attribute.parse_meta()? {
+            let name = list.nested.iter().find_map(|nested| match nested {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(name_value))
+                    if name_value.path.is_ident("name") =>
+                {
+                    match &name_value.lit {
+                        Lit::Str(name) => Some(name.value()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            });
+
+            return name.ok_or_else(|| syn::Error::new_spanned(&list, "expected name = \"...\""));
+        }
+    }
+
+    Ok(input.ident.to_string())
+}
+
+/// Implements `Relation` for a struct, whose fields in order are the terms of a fact.
+///
+/// ```
+/// use datalog_rule_macro::Relation;
+/// use datalog_syntax::*;
+///
+/// #[derive(Relation, Debug, PartialEq)]
+/// #[relation(name = "e")]
+/// struct Edge {
+///     from: String,
+///     to: usize,
+/// }
+///
+/// let fact = Edge { from: "a".to_string(), to: 1 }.into_fact();
+/// assert_eq!(vec![TypedValue::from("a"), TypedValue::from(1usize)], fact);
+/// assert_eq!(Edge { from: "a".to_string(), to: 1 }, Edge::from_fact(&fact).unwrap());
+/// assert_eq!("e", Edge::SYMBOL);
+/// ```
+#[proc_macro_derive(Relation, attributes(relation))]
+pub fn derive_relation(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let fields = match &input.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new(input.ident.span(), "only structs can be relations")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let symbol = match relation_symbol(&input) {
+        Ok(symbol) => symbol,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let arity = fields.len();
+    let indices = 0..arity;
+    let types = fields.iter().map(|field| &field.ty);
+
+    let (into_values, from_fact) = match fields {
+        syn::Fields::Named(named) => {
+            let idents: Vec<_> = named.named.iter().map(|field| &field.ident).collect();
+
+            (
+                quote! { #(::datalog_syntax::IntoTypedValue::into_typed_value(self.#idents)),* },
+                quote! { Self { #(#idents: <#types as ::std::convert::TryFrom<&::datalog_syntax::TypedValue>>::try_from(
+                    &fact[#indices],
+                )?),* } },
+            )
+        }
+        syn::Fields::Unnamed(_) => {
+            let members = (0..arity).map(syn::Index::from);
+
+            (
+                quote! { #(::datalog_syntax::IntoTypedValue::into_typed_value(self.#members)),* },
+                quote! { Self(#(<#types as ::std::convert::TryFrom<&::datalog_syntax::TypedValue>>::try_from(
+                    &fact[#indices],
+                )?),*) },
+            )
+        }
+        syn::Fields::Unit => (quote! {}, quote! { Self }),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics ::datalog_syntax::Relation for #name #type_generics #where_clause {
+            const SYMBOL: &'static str = #symbol;
+
+            fn into_fact(self) -> ::datalog_syntax::AnonymousGroundAtom {
+                vec![#into_values]
+            }
+
+            fn from_fact(
+                fact: &::datalog_syntax::AnonymousGroundAtom,
+            ) -> ::std::result::Result<Self, ::std::string::String> {
+                if fact.len() != #arity {
+                    return Err(format!(
+                        "{} has arity {}, but a fact with {} terms was given",
+                        #symbol,
+                        #arity,
+                        fact.len()
+                    ));
+                }
+
+                Ok(#from_fact)
+            }
+        }
+    };
+
+    expanded.into()
 }
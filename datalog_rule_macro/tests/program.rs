@@ -21,15 +21,117 @@ mod tests {
         assert_eq!(expected_program, actual_program);
     }
 
+    #[test]
+    fn test_program_with_negation() {
+        let expected_program = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { unreached(?x) <- [node(?x), !tc(1usize, ?x)] },
+        ]);
+        let actual_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            unreached(?x) <- [node(?x), !tc(1usize, ?x)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+        let unreached = actual_program
+            .inner
+            .iter()
+            .find(|rule| rule.head.symbol == "unreached")
+            .unwrap();
+        assert!(!unreached.body[1].sign);
+    }
+
+    #[test]
+    fn test_program_with_several_heads() {
+        let expected_program = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { a(?x) <- [e(?x, ?y), !tc(?y, ?x)] },
+            rule! { b(?y, 1usize) <- [e(?x, ?y), !tc(?y, ?x)] },
+        ]);
+        let actual_program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            a(?x), b(?y, 1usize) <- [e(?x, ?y), !tc(?y, ?x)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+    }
+
+    #[test]
+    fn test_program_with_disjunction() {
+        let expected_program = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { near(?x) <- [tc(1usize, ?x)] },
+            rule! { near(?x) <- [node(?x), !tc(?x, _)] },
+            rule! { far(?x) <- [node(?x), !tc(1usize, ?x)] },
+        ]);
+        let actual_program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            near(?x) <- [tc(1usize, ?x); node(?x), !tc(?x, _)],
+            far(?x) <- [node(?x), !tc(1usize, ?x)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+    }
+
+    #[test]
+    fn test_program_with_plan_hints() {
+        let program = program! {
+            #[no_reorder]
+            #[index(e(0), e(1, 0))]
+            near(?x) <- [e(1usize, ?x); e(?x, 1usize)],
+            #[no_index]
+            tc(?x, ?y) <- [e(?x, ?y)]
+        };
+
+        let hints = |symbol: &str| -> Vec<&PlanHints> {
+            program
+                .inner
+                .iter()
+                .filter(|rule| rule.head.symbol == symbol)
+                .map(|rule| &rule.hints)
+                .collect()
+        };
+        let pinned = PlanHints {
+            no_reorder: true,
+            no_index: false,
+            indices: vec![("e".to_string(), vec![0]), ("e".to_string(), vec![1, 0])],
+        };
+        assert_eq!(vec![&pinned, &pinned], hints("near"));
+        let unindexed = PlanHints {
+            no_index: true,
+            ..Default::default()
+        };
+        assert_eq!(vec![&unindexed], hints("tc"));
+    }
+
+    #[test]
+    fn test_redundant_rules_are_dropped() {
+        let expected_program = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)] },
+            rule! { lonely(?x) <- [node(?x), !e(?x, _)] },
+        ]);
+        let actual_program = semipositive_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?xx, ?zz) <- [e(?xx, ?yy), tc(?yy, ?zz)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            tc(?x, ?y) <- [e(?x, ?y), e(?y, ?x)],
+            lonely(?x) <- [node(?x), !e(?x, _), !e(_, ?x)],
+            lonely(?x) <- [node(?x), !e(?x, _)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+    }
+
     #[test]
     fn test_semipositive_program() {
         let expected_program = Program::from(vec![
             rule! { tc(?x, ?y) <- [e(?x, ?y)] },
-            rule! { tc(?x, ?z) <- [!e(?x, ?y), tc(?y, ?z)] },
+            rule! { tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z), !blocked(?x, ?y)] },
         ]);
         let actual_program = semipositive_program! {
                 tc(?x, ?y) <- [e(?x, ?y)],
-                tc(?x, ?z) <- [!e(?x, ?y), tc(?y, ?z)]
+                tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z), !blocked(?x, ?y)]
         };
 
         assert_eq!(expected_program, actual_program);
@@ -49,17 +151,67 @@ mod tests {
         assert_eq!(expected_program, actual_program);
     }
 
+    #[test]
+    fn test_stratified_program_with_spliced_constants() {
+        let root = 1usize;
+        let expected_program = Program::from(vec![
+            rule! { reachable(?y) <- [e(1usize, ?y)] },
+            rule! { unreachable(?x) <- [node(?x), !reachable(?x)] },
+        ]);
+        let actual_program = stratified_program! {
+            reachable(?y) <- [e(#root, ?y)],
+            unreachable(?x) <- [node(?x), !reachable(?x)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+    }
+
+    #[test]
+    fn test_stratified_program_with_signed_constants() {
+        let offset = -1i64;
+        let expected_program = Program::from(vec![
+            rule! { floor(?x) <- [reading(?x, -9223372036854775808)] },
+            rule! { shifted(?x) <- [reading(?x, -1i64)] },
+        ]);
+        let actual_program = stratified_program! {
+            floor(?x) <- [reading(?x, -9223372036854775808)],
+            shifted(?x) <- [reading(?x, #offset)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+        assert_eq!(
+            Term::Constant(TypedValue::SignedInt(i64::MIN)),
+            actual_program.inner[0].body[0].terms[1]
+        );
+    }
+
     #[test]
     fn test_valid_stratified_program_with_negation() {
         let expected_program = Program::from(vec![
             rule! { tc(?x, ?y) <- [e(?x, ?y)] },
             rule! { tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)] },
-            rule! { d(?x, ?z) <- [tc(?x, ?y), !e(?y, ?z)] },
+            rule! { d(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z), !e(?y, ?z)] },
+        ]);
+        let actual_program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            d(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z), !e(?y, ?z)]
+        };
+
+        assert_eq!(expected_program, actual_program);
+    }
+
+    #[test]
+    fn test_stratified_program_negating_derived_relation() {
+        let expected_program = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)] },
+            rule! { acyclic(?x) <- [node(?x), !tc(?x, ?x)] },
         ]);
         let actual_program = stratified_program! {
             tc(?x, ?y) <- [e(?x, ?y)],
             tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
-            d(?x, ?z) <- [tc(?x, ?y), !e(?y, ?z)]
+            acyclic(?x) <- [node(?x), !tc(?x, ?x)]
         };
 
         assert_eq!(expected_program, actual_program);
@@ -0,0 +1,37 @@
+#[cfg(test)]
+mod tests {
+    use datalog_rule_macro::Relation;
+    use datalog_syntax::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Relation, Debug, PartialEq)]
+    struct Reading(String, i64, f64, bool);
+
+    #[test]
+    fn test_tuple_struct_relation() {
+        let reading = Reading("sensor".to_string(), -3, 0.5, true);
+        let expected_fact: AnonymousGroundAtom = vec![
+            "sensor".into(),
+            TypedValue::SignedInt(-3),
+            0.5.into(),
+            true.into(),
+        ];
+
+        assert_eq!("Reading", Reading::SYMBOL);
+        assert_eq!(
+            expected_fact,
+            Reading("sensor".to_string(), -3, 0.5, true).into_fact()
+        );
+        assert_eq!(reading, Reading::from_fact(&expected_fact).unwrap());
+    }
+
+    #[test]
+    fn test_relation_from_mismatching_fact() {
+        let too_short: AnonymousGroundAtom = vec!["sensor".into()];
+        let mistyped: AnonymousGroundAtom =
+            vec!["sensor".into(), 3usize.into(), 0.5.into(), true.into()];
+
+        assert!(Reading::from_fact(&too_short).is_err());
+        assert!(Reading::from_fact(&mistyped).is_err());
+    }
+}
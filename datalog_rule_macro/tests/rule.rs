@@ -36,6 +36,7 @@ mod tests {
                 },
             ],
             id: 0,
+            hints: Default::default(),
         };
 
         assert_eq!(rule_output, expected_output);
@@ -73,6 +74,186 @@ mod tests {
                 },
             ],
             id: 0,
+            hints: Default::default(),
+        };
+
+        assert_eq!(rule_output, expected_output);
+    }
+
+    #[test]
+    fn test_numeric_constants() {
+        let rule_output = rule! { temperature(?x, -3) <- [reading(?x, 2.5, -0.5, 7i64)] };
+
+        let expected_output = Rule {
+            head: Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Constant(TypedValue::SignedInt(-3)),
+                ],
+                symbol: "temperature".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Constant(TypedValue::Float(OrderedFloat(2.5))),
+                    Term::Constant(TypedValue::Float(OrderedFloat(-0.5))),
+                    Term::Constant(TypedValue::SignedInt(7)),
+                ],
+                symbol: "reading".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
+
+        assert_eq!(rule_output, expected_output);
+    }
+
+    #[test]
+    fn test_aggregate_rule() {
+        let rule_output = rule! { path_count(?x, count(?y)) <- [tc(?x, ?y)] };
+
+        let expected_output = Rule {
+            head: Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Aggregate(Aggregation::Count, "y".to_string()),
+                ],
+                symbol: "path_count".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Variable("y".to_string()),
+                ],
+                symbol: "tc".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
+
+        assert_eq!(rule_output, expected_output);
+    }
+
+    #[test]
+    fn test_wildcard_rule() {
+        let rule_output = rule! { alive(?x) <- [person(?x, _, _)] };
+
+        let expected_output = Rule {
+            head: Atom {
+                terms: vec![Term::Variable("x".to_string())],
+                symbol: "alive".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Variable("_".to_string()),
+                    Term::Variable("_".to_string()),
+                ],
+                symbol: "person".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
+
+        assert_eq!(rule_output, expected_output);
+    }
+
+    #[test]
+    fn test_spliced_constants() {
+        struct Config {
+            threshold: usize,
+        }
+        let config = Config { threshold: 10 };
+        let unit = "kb".to_string();
+
+        let rule_output =
+            rule! { big(?x, #unit) <- [size(?x, #(config.threshold * 2)), unit(?x, #unit)] };
+
+        let expected_output = Rule {
+            head: Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Constant(TypedValue::from("kb")),
+                ],
+                symbol: "big".to_string(),
+                sign: true,
+            },
+            body: vec![
+                Atom {
+                    terms: vec![
+                        Term::Variable("x".to_string()),
+                        Term::Constant(TypedValue::from(20usize)),
+                    ],
+                    symbol: "size".to_string(),
+                    sign: true,
+                },
+                Atom {
+                    terms: vec![
+                        Term::Variable("x".to_string()),
+                        Term::Constant(TypedValue::from("kb")),
+                    ],
+                    symbol: "unit".to_string(),
+                    sign: true,
+                },
+            ],
+            id: 0,
+            hints: Default::default(),
+        };
+
+        assert_eq!(rule_output, expected_output);
+        // Splicing a value does not take it away.
+        assert_eq!("kb", unit);
+    }
+
+    #[test]
+    fn test_assigned_head_variables() {
+        let step = 2usize;
+
+        let rule_output = rule! {
+            next(?x, ?z) <- [node(?x, ?y)], ?w = ?y * #step, ?z = (?w - 1) + ?y * 3
+        };
+
+        let variable = |name: &str| Box::new(Expression::Variable(name.to_string()));
+        let w = Expression::Binary(
+            Operator::Multiply,
+            variable("y"),
+            Box::new(Expression::Constant(TypedValue::from(2usize))),
+        );
+        let z = Expression::Binary(
+            Operator::Add,
+            Box::new(Expression::Binary(
+                Operator::Subtract,
+                Box::new(w),
+                Box::new(Expression::Constant(TypedValue::from(1usize))),
+            )),
+            Box::new(Expression::Binary(
+                Operator::Multiply,
+                variable("y"),
+                Box::new(Expression::Constant(TypedValue::from(3usize))),
+            )),
+        );
+        let expected_output = Rule {
+            head: Atom {
+                terms: vec![Term::Variable("x".to_string()), Term::Expression(z)],
+                symbol: "next".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Variable("y".to_string()),
+                ],
+                symbol: "node".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
         };
 
         assert_eq!(rule_output, expected_output);
@@ -0,0 +1,131 @@
+// Datasets and programs for benchmarking the engine. Every generator is deterministic, so that
+// runs can be compared with each other.
+use datalog_rule_macro::program;
+use datalog_syntax::*;
+
+// A xorshift generator, which is plenty for laying out edges.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        (self.0 % bound as u64) as usize
+    }
+}
+
+fn edge(from: usize, to: usize) -> AnonymousGroundAtom {
+    vec![from.into(), to.into()]
+}
+
+// As many distinct edges as asked for between the nodes, without self loops.
+pub fn random_graph(nodes: usize, edges: usize, seed: u64) -> Vec<AnonymousGroundAtom> {
+    assert!(
+        edges <= nodes * (nodes - 1),
+        "{} nodes can not have {} edges",
+        nodes,
+        edges
+    );
+
+    let mut random = Random::new(seed);
+    let mut graph = indexmap::IndexSet::new();
+    while graph.len() < edges {
+        let (from, to) = (random.below(nodes), random.below(nodes));
+        if from != to {
+            graph.insert(edge(from, to));
+        }
+    }
+
+    graph.into_iter().collect()
+}
+
+// 0 -> 1 -> ... -> length, the worst case for the number of iterations of transitive closure.
+pub fn chain(length: usize) -> Vec<AnonymousGroundAtom> {
+    (0..length).map(|node| edge(node, node + 1)).collect()
+}
+
+// Nodes numbered row by row, with edges to the right and downwards.
+pub fn grid(width: usize, height: usize) -> Vec<AnonymousGroundAtom> {
+    let node = |column: usize, row: usize| row * width + column;
+
+    (0..height)
+        .flat_map(|row| (0..width).map(move |column| (column, row)))
+        .flat_map(|(column, row)| {
+            let right =
+                (column + 1 < width).then(|| edge(node(column, row), node(column + 1, row)));
+            let down = (row + 1 < height).then(|| edge(node(column, row), node(column, row + 1)));
+
+            right.into_iter().chain(down)
+        })
+        .collect()
+}
+
+// A tree where every node but the root has a parent, as read by same_generation.
+pub fn tree(nodes: usize, branching: usize) -> Vec<AnonymousGroundAtom> {
+    (1..nodes)
+        .map(|node| edge(node, (node - 1) / branching))
+        .collect()
+}
+
+// The statements of a program for points_to to analyse, along with the relations they go in.
+pub fn points_to_statements(
+    variables: usize,
+    statements: usize,
+    seed: u64,
+) -> Vec<(&'static str, AnonymousGroundAtom)> {
+    let mut random = Random::new(seed);
+
+    (0..statements)
+        .map(|statement| {
+            let (left, right) = (random.below(variables), random.below(variables));
+            let relation = match random.below(10) {
+                0..=2 => return ("new", edge(left, statement)),
+                3..=6 => "assign",
+                7..=8 => "load",
+                _ => "store",
+            };
+
+            (relation, edge(left, right))
+        })
+        .collect()
+}
+
+// Over e(from, to).
+pub fn transitive_closure() -> Program {
+    program! {
+        tc(?x, ?y) <- [e(?x, ?y)],
+        tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+    }
+}
+
+// Over e(from, to), joining the closure with itself, which doubles the length of the paths it
+// knows of on every iteration.
+pub fn nonlinear_transitive_closure() -> Program {
+    program! {
+        tc(?x, ?y) <- [e(?x, ?y)],
+        tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+    }
+}
+
+// Over parent(child, parent), as made by tree.
+pub fn same_generation() -> Program {
+    program! {
+        sg(?x, ?y) <- [parent(?x, ?p), parent(?y, ?p)],
+        sg(?x, ?y) <- [parent(?x, ?a), sg(?a, ?b), parent(?y, ?b)],
+    }
+}
+
+// Andersen's analysis over the statements made by points_to_statements.
+pub fn points_to() -> Program {
+    program! {
+        pt(?p, ?o) <- [new(?p, ?o)],
+        pt(?p, ?o) <- [assign(?p, ?q), pt(?q, ?o)],
+        pt(?p, ?o) <- [load(?p, ?q), pt(?q, ?r), pt(?r, ?o)],
+        pt(?r, ?o) <- [store(?p, ?q), pt(?p, ?r), pt(?q, ?o)],
+    }
+}
@@ -1,3 +1,20 @@
 pub mod datalog;
+pub mod delimited;
+pub(crate) mod epoch_storage;
+pub mod equivalence;
+pub mod explain;
+pub(crate) mod fact_interner;
+pub(crate) mod hash_index;
 pub(crate) mod index_storage;
+pub(crate) mod journal;
+pub mod metadata;
+pub mod observer;
+pub mod rdf;
+pub mod reader;
+#[cfg(feature = "server")]
+pub mod server;
+pub(crate) mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sql;
 pub(crate) mod storage;
+pub mod transaction;
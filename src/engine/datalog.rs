@@ -1,487 +1,6664 @@
-use crate::engine::storage::RelationStorage;
+use crate::engine::delimited::{read_delimited, write_delimited, ColumnType};
+use crate::engine::epoch_storage::{Epoch, EpochStorage};
+use crate::engine::equivalence::Equivalence;
+use crate::engine::explain::{
+    discrepancy, plan_rule, relation_statistics, Discrepancy, Explanation, RelationSchema,
+    RelationStatistics, RulePlan, Strategy, StrategyComparison,
+};
+use crate::engine::fact_interner::FactInterner;
+use crate::engine::hash_index::HashIndex;
+use crate::engine::index_storage::IndexStorage;
+use crate::engine::metadata::{FactMetadata, MetadataStorage};
+use crate::engine::observer::{
+    DepthRecorder, EvaluationObserver, MemoryUsage, PollStatistics, RelationDelta, RuleStatistics,
+    Stopwatch, Tee,
+};
+use crate::engine::rdf::read_triples;
+use crate::engine::reader::{Published, Reader};
+use crate::engine::snapshot::{
+    read_header, read_storage, read_u64, write_header, write_storage, write_u64,
+};
+#[cfg(feature = "sqlite")]
+use crate::engine::sql::{read_rows, write_rows, Connection};
+use crate::engine::storage::{FactStorage, RelationStorage};
+use crate::engine::transaction::Transaction;
+use crate::evaluation::counting::{Delta, DerivationCounts};
+use crate::evaluation::lattice::Lattice;
 use crate::evaluation::query::pattern_match;
-use crate::evaluation::semi_naive::semi_naive_evaluation;
+use crate::evaluation::semi_naive::{semi_naive_evaluation, semi_naive_evaluation_with_delta};
+use crate::evaluation::spj_processor::{
+    get_existential_variables, get_join_indices, get_selection_indices, RuleEvaluator, StackCache,
+};
+use crate::evaluation::top_down::TopDown;
 use crate::helpers::helpers::{
-    add_prefix, split_program, OVERDELETION_PREFIX, REDERIVATION_PREFIX,
+    add_prefix, is_internal, split_program, OVERDELETION_PREFIX, REDERIVATION_PREFIX,
+};
+use crate::program_transformations::dependency_graph::{
+    sort_program, split_components, stratify_program,
 };
-use crate::program_transformations::dependency_graph::sort_program;
 use crate::program_transformations::dred::{make_overdeletion_program, make_rederivation_program};
+use crate::program_transformations::specialization::{is_bound_by, specialize_program};
+use crate::program_transformations::typecheck::TypeSignature;
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
 use datalog_syntax::*;
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Duration;
+type Subscription = Box<dyn FnMut(&RelationDelta)>;
+type FactSet = IndexSet<Arc<AnonymousGroundAtom>>;
+type Group = (Vec<TypedValue>, Vec<Arc<AnonymousGroundAtom>>);
+
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeOptions {
+    // Whether head variables that the body does not bind are given a fresh constant for every
+    // binding of the other head variables, rather than the program being rejected.
+    pub skolemize: bool,
+    // Whether queries made while changes are pending are answered by evaluating them on demand
+    // against those changes, rather than failing until the next poll.
+    pub on_demand: bool,
+    // Whether derived facts are deleted once nothing derives them anymore, going by how many
+    // derivations every one of them has, rather than by overdeleting and rederiving them. Only
+    // programs that neither recurse, negate nor aggregate can count their derivations.
+    pub count_derivations: bool,
+    // Whether rules whose positive atoms join in a cycle are evaluated by one worst-case optimal
+    // join over all of them, rather than by a chain of binary joins.
+    pub generic_joins: bool,
+    // Whether querying a relation that neither the program nor any insertion mentions fails,
+    // rather than finding nothing.
+    pub strict_relations: bool,
+    // Whether the iteration that first derived every fact is kept, for query_with_depth to hand
+    // out. Derivations that are counted are not evaluated in iterations, so it rules that out.
+    pub record_depths: bool,
+    // Whether the poll that every fact first appeared in is kept, for query_new_since and
+    // take_delta to go by.
+    pub record_epochs: bool,
+    // How many bytes the intermediate results of an evaluation, its join results and the facts it
+    // derived in its last iteration, may take, past which the poll fails rather than exhausting
+    // memory. A transaction whose commit runs past it fails with it too.
+    pub intermediate_memory_limit: Option<usize>,
+    // Whether equal facts share one allocation, however many relations hold them, at the cost of
+    // looking every stored fact up once more.
+    pub share_facts: bool,
+}
+
+// How far a query answered top-down may go, none of the limits being set meaning that it goes as
+// far as it takes. Goals are as deep as the chain of rules that they were met through is long.
+#[derive(Clone, Debug, Default)]
+pub struct QueryBudget {
+    pub max_depth: Option<usize>,
+    pub max_subqueries: Option<usize>,
+    pub max_answers: Option<usize>,
+}
+
+// The answers that were found within a budget, and whether it kept any from being found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BudgetedAnswers {
+    pub facts: Vec<AnonymousGroundAtom>,
+    pub truncated: bool,
+}
+
 pub struct MicroRuntime {
     processed: RelationStorage,
     unprocessed_insertions: RelationStorage,
     unprocessed_deletions: RelationStorage,
     program: Program,
-    nonrecursive_program: Program,
-    recursive_program: Program,
+    // The id that the next rule added to the program is given.
+    next_rule_id: usize,
+    // The nonrecursive and recursive rules of every strongly connected component of every stratum,
+    // in the order that they are evaluated in.
+    strata: Vec<(Program, Program)>,
     nonrecursive_overdeletion_program: Program,
     recursive_overdeletion_program: Program,
     nonrecursive_rederivation_program: Program,
     recursive_rederivation_program: Program,
+    aggregate_program: Program,
+    // The aggregates that range over relations that depend on them, which are evaluated with the
+    // rules of those relations.
+    lattice: Lattice,
+    // Every rule deriving a relation that some rule derives through negation, as needed to tell
+    // which facts a negation no longer allows.
+    negating_program: Program,
+    // The facts that were inserted into the relations of the negating program, which no negation
+    // takes away, since no rule derived them.
+    asserted: RelationStorage,
+    negated_relations: HashSet<String>,
+    // Whether a negated relation has grown since the last check.
+    negations_stale: bool,
+    stacks: StackCache,
+    epoch: Epoch,
+    // The epochs that the facts first appeared in, when the options ask for them.
+    epochs: Option<EpochStorage>,
+    metadata: MetadataStorage,
+    observer: Box<dyn EvaluationObserver>,
+    statistics: PollStatistics,
+    // The depths of the derived facts, when the options ask for them.
+    depths: Option<DepthRecorder>,
+    subscriptions: HashMap<String, Vec<Subscription>>,
+    options: RuntimeOptions,
+    // Whether the derived relations were emptied, and have to be derived again by the next poll.
+    rematerialize: bool,
+    // The facts of subscribed relations that the current poll has inserted and deleted so far, in
+    // the order the relations changed in, which is the order their subscribers are told in.
+    deltas: IndexMap<String, (FactSet, FactSet)>,
+    // How many terms the facts of every relation have, as used by the program or, for relations
+    // that it does not mention, as first inserted.
+    arities: HashMap<String, usize>,
+    // Where the relations are published at the end of every poll, once something asked to read
+    // them from elsewhere.
+    reader: Option<Reader>,
+    // The derivations of every derived fact, when the options ask for them to be counted.
+    counts: Option<DerivationCounts>,
+    // The types of the columns that the program ties to constants, which inserted facts must have.
+    types: TypeSignature,
+    // The relations whose new facts were taken since the last poll.
+    taken_deltas: HashSet<String>,
+    // The indices that were asked for, or that prepared queries probe, which are kept until they
+    // are dropped.
+    created_indices: IndexSet<(String, Vec<usize>)>,
+    // The facts that only hold under some scenario, by the name of the scenario.
+    scenarios: HashMap<String, RelationStorage>,
+    // The equivalences as they were before the pending changes were applied to them, while the
+    // state is saved.
+    saved_equivalences: HashMap<String, Equivalence>,
+}
+
+// Everything about the runtime that staging changes and polling them changes, as kept to go back
+// to when a poll fails halfway through. The facts, along with their epochs, depths and metadata,
+// are journaled instead. The statistics are left as the failed poll had them.
+pub(crate) struct PollState {
+    epoch: Epoch,
+    deltas: IndexMap<String, (FactSet, FactSet)>,
+    arities: HashMap<String, usize>,
+    negations_stale: bool,
+    rematerialize: bool,
+    taken_deltas: HashSet<String>,
 }
 
 impl MicroRuntime {
-    pub fn insert(&mut self, relation: &str, ground_atom: AnonymousGroundAtom) -> bool {
-        self.unprocessed_insertions.insert(relation, ground_atom)
+    fn check_arity(&mut self, relation: &str, arity: usize) -> Result<(), String> {
+        self.check_counted_insertion(relation)?;
+        let expected_arity = *self.arities.entry(relation.to_string()).or_insert(arity);
+        self.processed
+            .inner
+            .entry(relation.to_string())
+            .or_default();
+
+        if arity != expected_arity {
+            return Err(format!(
+                "{} has arity {}, but a fact with {} terms was given",
+                relation, expected_arity, arity
+            ));
+        }
+
+        Ok(())
     }
-    pub fn remove(&mut self, query: &Query) {
-        let deletion_targets: Vec<_> = self
-            .processed
-            .get_relation(query.symbol)
-            .iter()
-            .map(|hash| hash)
-            .filter(|fact| pattern_match(query, fact))
-            .cloned()
+    pub fn insert(
+        &mut self,
+        relation: &str,
+        ground_atom: AnonymousGroundAtom,
+    ) -> Result<bool, String> {
+        self.check_arity(relation, ground_atom.len())?;
+        self.types.check(relation, &ground_atom)?;
+
+        Ok(self.unprocessed_insertions.insert(relation, ground_atom))
+    }
+    pub fn insert_with_metadata(
+        &mut self,
+        relation: &str,
+        ground_atom: AnonymousGroundAtom,
+        metadata: FactMetadata,
+    ) -> Result<bool, String> {
+        self.check_arity(relation, ground_atom.len())?;
+        self.types.check(relation, &ground_atom)?;
+        self.metadata.set(relation, ground_atom.clone(), metadata);
+
+        Ok(self.unprocessed_insertions.insert(relation, ground_atom))
+    }
+    // Inserts a fact that stops holding once the time to live is over, counted in the milliseconds
+    // of FactMetadata::now.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn insert_with_ttl(
+        &mut self,
+        relation: &str,
+        ground_atom: AnonymousGroundAtom,
+        ttl: Duration,
+    ) -> Result<bool, String> {
+        let metadata = FactMetadata::now().with_ttl(ttl.as_millis() as Timestamp);
+
+        self.insert_with_metadata(relation, ground_atom, metadata)
+    }
+    // Removes every fact whose metadata is older than the timestamp, returning how many there are.
+    // What was derived from them goes away with the next poll.
+    pub fn expire(&mut self, before: Timestamp) -> usize {
+        let expired: Vec<_> = self
+            .metadata
+            .older_than(before)
+            .map(|(relation_symbol, fact)| (relation_symbol.clone(), fact.clone()))
             .collect();
 
-        self.unprocessed_deletions
-            .insert_registered(query.symbol, deletion_targets.into_iter());
+        self.remove_expired(expired)
     }
-    pub fn contains(
+    // Removes every fact whose time to live is over by now, returning how many there are. Like
+    // expire, what was derived from them goes away with the next poll.
+    pub fn expire_due(&mut self, now: Timestamp) -> usize {
+        let expired: Vec<_> = self
+            .metadata
+            .due(now)
+            .map(|(relation_symbol, fact)| (relation_symbol.clone(), fact.clone()))
+            .collect();
+
+        self.remove_expired(expired)
+    }
+    fn remove_expired(&mut self, expired: Vec<(String, AnonymousGroundAtom)>) -> usize {
+        let expired: Vec<_> = expired
+            .into_iter()
+            .filter(|(relation_symbol, fact)| self.processed.contains(relation_symbol, fact))
+            .map(|(relation_symbol, fact)| (relation_symbol, Arc::new(fact)))
+            .collect();
+        let expired_count = expired.len();
+
+        expired.into_iter().for_each(|(relation_symbol, fact)| {
+            self.remove_facts(&relation_symbol, vec![fact]);
+        });
+
+        expired_count
+    }
+    pub fn metadata(
         &self,
         relation: &str,
         ground_atom: &AnonymousGroundAtom,
-    ) -> Result<bool, String> {
-        if !self.safe() {
-            return Err("poll needed to obtain correct results".to_string());
+    ) -> Option<&FactMetadata> {
+        self.metadata.get(relation, ground_atom)
+    }
+    pub fn insert_typed<R: Relation>(&mut self, fact: R) -> Result<bool, String> {
+        self.insert(R::SYMBOL, fact.into_fact())
+    }
+    pub fn insert_tuple<T: IntoFact>(&mut self, relation: &str, fact: T) -> Result<bool, String> {
+        self.insert(relation, fact.into_fact())
+    }
+    // Either all facts are inserted, or none is if any of them has the wrong arity or types.
+    pub fn insert_many(
+        &mut self,
+        relation: &str,
+        ground_atoms: impl IntoIterator<Item = AnonymousGroundAtom>,
+    ) -> Result<(), String> {
+        let ground_atoms: Vec<_> = ground_atoms.into_iter().map(Arc::new).collect();
+        for ground_atom in &ground_atoms {
+            self.check_arity(relation, ground_atom.len())?;
+            self.types.check(relation, ground_atom)?;
         }
 
-        if !self.processed.contains(relation, ground_atom) {
-            return Ok(self.unprocessed_insertions.contains(relation, ground_atom));
-        }
+        self.unprocessed_insertions
+            .reserve(relation, ground_atoms.len());
+        self.unprocessed_insertions
+            .insert_all(relation, ground_atoms.into_iter());
 
-        Ok(true)
+        Ok(())
     }
-    pub fn query<'a>(
-        &'a self,
-        query: &'a Query,
-    ) -> Result<impl Iterator<Item = AnonymousGroundAtom> + 'a, String> {
-        if !self.safe() {
-            return Err("poll needed to obtain correct results".to_string());
+    // The i-th fact is made out of the i-th value of every column.
+    pub fn insert_columns(
+        &mut self,
+        relation: &str,
+        columns: Vec<Vec<TypedValue>>,
+    ) -> Result<(), String> {
+        let length = columns.first().map_or(0, |column| column.len());
+        if columns.iter().any(|column| column.len() != length) {
+            return Err("all columns must have the same length".to_string());
         }
+        self.check_arity(relation, columns.len())?;
 
-        return Ok(self
-            .processed
-            .get_relation(query.symbol)
-            .iter()
-            .filter(|fact| pattern_match(query, fact))
-            .map(|fact| (**fact).clone()));
+        let mut columns: Vec<_> = columns
+            .into_iter()
+            .map(|column| column.into_iter())
+            .collect();
+        self.insert_many(
+            relation,
+            (0..length).map(|_| {
+                columns
+                    .iter_mut()
+                    .map(|column| column.next().unwrap())
+                    .collect()
+            }),
+        )
+    }
+    // Every line of the file is a fact, with its fields read as the types in the schema.
+    pub fn load_csv(
+        &mut self,
+        relation: &str,
+        path: impl AsRef<Path>,
+        schema: &[ColumnType],
+    ) -> Result<(), String> {
+        self.load_delimited(relation, path, schema, ',')
     }
+    pub fn load_tsv(
+        &mut self,
+        relation: &str,
+        path: impl AsRef<Path>,
+        schema: &[ColumnType],
+    ) -> Result<(), String> {
+        self.load_delimited(relation, path, schema, '\t')
+    }
+    fn load_delimited(
+        &mut self,
+        relation: &str,
+        path: impl AsRef<Path>,
+        schema: &[ColumnType],
+        delimiter: char,
+    ) -> Result<(), String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let facts = read_delimited(BufReader::new(file), schema, delimiter)?;
 
-    pub fn poll(&mut self) {
-        if !self.unprocessed_deletions.is_empty() {
-            self.unprocessed_deletions.drain_all_relations().for_each(
-                |(relation_symbol, unprocessed_facts)| {
-                    let mut overdeletion_symbol = relation_symbol.clone();
-                    add_prefix(&mut overdeletion_symbol, OVERDELETION_PREFIX);
+        self.insert_many(relation, facts)
+    }
+    // Every triple of an N-Triples or Turtle file is a fact of the ternary relation.
+    pub fn load_triples(&mut self, relation: &str, path: impl AsRef<Path>) -> Result<(), String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let facts = read_triples(BufReader::new(file))?;
 
-                    self.processed.insert_all(
-                        &overdeletion_symbol,
-                        unprocessed_facts.into_iter().map(|fact| fact),
-                    );
-                },
-            );
+        self.insert_many(relation, facts)
+    }
+    // Every row that the SQL query returns is a fact, with its values read as the types that the
+    // program gives the columns of the relation.
+    #[cfg(feature = "sqlite")]
+    pub fn load_sql(
+        &mut self,
+        connection: &Connection,
+        query: &str,
+        relation: &str,
+    ) -> Result<(), String> {
+        let facts = read_rows(connection, query, |column| {
+            self.types.column_type(relation, column)
+        })?;
 
-            semi_naive_evaluation(
-                &mut self.processed,
-                &self.nonrecursive_overdeletion_program,
-                &self.recursive_overdeletion_program,
-            );
-            self.processed.overdelete();
+        self.insert_many(relation, facts)
+    }
+    // Replaces the rows of the table with the answers to the query.
+    #[cfg(feature = "sqlite")]
+    pub fn write_sql(
+        &self,
+        query: &Query,
+        connection: &Connection,
+        table: &str,
+    ) -> Result<(), String> {
+        let facts = self.query_ref(query)?;
 
-            semi_naive_evaluation(
-                &mut self.processed,
-                &self.nonrecursive_rederivation_program,
-                &self.recursive_rederivation_program,
-            );
-            self.processed.rederive();
+        write_rows(connection, table, query.matchers.len(), facts)
+    }
+    pub fn write_csv(&self, query: &Query, path: impl AsRef<Path>) -> Result<(), String> {
+        self.write_delimited(query, path, ',')
+    }
+    pub fn write_tsv(&self, query: &Query, path: impl AsRef<Path>) -> Result<(), String> {
+        self.write_delimited(query, path, '\t')
+    }
+    fn write_delimited(
+        &self,
+        query: &Query,
+        path: impl AsRef<Path>,
+        delimiter: char,
+    ) -> Result<(), String> {
+        let facts = self.query_ref(query)?;
+        let file = File::create(path).map_err(|error| error.to_string())?;
+        let mut writer = BufWriter::new(file);
 
-            self.processed.clear_prefix(OVERDELETION_PREFIX);
-            self.processed.clear_prefix(REDERIVATION_PREFIX);
+        write_delimited(&mut writer, facts, delimiter)?;
+
+        writer.flush().map_err(|error| error.to_string())
+    }
+    pub fn remove(&mut self, query: &Query) {
+        let deletion_targets = self.matching_facts(query);
+
+        self.remove_facts(query.symbol, deletion_targets);
+    }
+    pub(crate) fn matching_facts(&self, query: &Query) -> Vec<Arc<AnonymousGroundAtom>> {
+        self.processed
+            .facts(query.symbol)
+            .filter(|fact| self.is_answer(query, fact))
+            .cloned()
+            .collect()
+    }
+    pub(crate) fn remove_facts(&mut self, relation: &str, facts: Vec<Arc<AnonymousGroundAtom>>) {
+        self.unprocessed_deletions
+            .insert_registered(relation, facts.into_iter());
+    }
+    // A fact inserted into a derived relation would have no derivation to count.
+    pub(crate) fn check_counted_insertion(&self, relation: &str) -> Result<(), String> {
+        match &self.counts {
+            Some(counts) if counts.derives(relation) => Err(format!(
+                "{} is derived by counting its derivations, so facts can not be inserted into it",
+                relation
+            )),
+            _ => Ok(()),
         }
-        if !self.unprocessed_insertions.is_empty() {
-            // Additions
-            self.unprocessed_insertions.drain_all_relations().for_each(
-                |(relation_symbol, unprocessed_facts)| {
-                    // And in their respective place
-                    self.processed
-                        .insert_registered(&relation_symbol, unprocessed_facts.into_iter());
-                },
-            );
+    }
+    pub fn types(&self) -> &TypeSignature {
+        &self.types
+    }
+    pub(crate) fn arity(&self, relation: &str) -> Option<usize> {
+        self.arities.get(relation).copied()
+    }
+    // Matchers beyond the arity of the relation would otherwise be ignored, and missing ones would
+    // match anything.
+    fn check_query(&self, query: &Query) -> Result<(), String> {
+        match self.arities.get(query.symbol) {
+            None if self.options.strict_relations => Err(format!(
+                "{} is not a relation of the program, nor was anything inserted into it",
+                query.symbol
+            )),
+            Some(arity) if *arity != query.matchers.len() => Err(format!(
+                "{} has arity {}, but the query has {} matchers",
+                query.symbol,
+                arity,
+                query.matchers.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+    // Whether every one of the columns is one that the relation has, if its arity is known yet.
+    fn check_columns(
+        &self,
+        relation: &str,
+        mut columns: impl Iterator<Item = usize>,
+    ) -> Result<(), String> {
+        let Some(arity) = self.arities.get(relation) else {
+            return Ok(());
+        };
 
-            semi_naive_evaluation(
-                &mut self.processed,
-                &self.nonrecursive_program,
-                &self.recursive_program,
-            );
+        match columns.find(|column| column >= arity) {
+            Some(column) => Err(format!(
+                "{} has arity {}, but column {} was asked for",
+                relation, arity, column
+            )),
+            None => Ok(()),
         }
     }
+    // Facts of the relations that the current program derives are dropped, and those of the new
+    // program are derived by the next poll. All other facts, pending changes to them included, are
+    // kept as they are.
+    pub fn replace_program(&mut self, program: Program) -> Result<(), String> {
+        let derived_relations: HashSet<String> = self
+            .program
+            .inner
+            .iter()
+            .map(|rule| rule.head.symbol.clone())
+            .collect();
+        let program = self.keep_rule_ids(program);
 
-    pub fn new(program: Program) -> Self {
-        let mut processed: RelationStorage = Default::default();
-        let mut unprocessed_insertions: RelationStorage = Default::default();
-        let mut unprocessed_deletions: RelationStorage = Default::default();
+        self.swap_program(program, &derived_relations)
+    }
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+    // Only the relation that the rule derives, and those that depend on it, are derived again.
+    // Returns the id that the rule is given, which it keeps for as long as it is in the program.
+    pub fn add_rule(&mut self, rule: Rule) -> Result<usize, String> {
+        let mut rules = self.program.inner.clone();
+        rules.push(rule.clone());
+        let program = self.keep_rule_ids(Program::from(rules));
+        // Program::from drops rules that others already imply.
+        let Some(id) = program
+            .inner
+            .iter()
+            .find(|added| added.head == rule.head && added.body == rule.body)
+            .map(|added| added.id)
+        else {
+            return Err(format!("{:?} is already implied by the program", rule));
+        };
+        let affected_relations = downstream_relations(&program, &rule.head.symbol);
 
-        let mut relations = IndexSet::new();
-        let mut overdeletion_relations = IndexSet::new();
-        let mut rederive_relations = IndexSet::new();
+        self.swap_program(program, &affected_relations)?;
 
-        program.inner.iter().for_each(|rule| {
-            relations.insert(&rule.head.symbol);
-            overdeletion_relations.insert(format!("{}{}", OVERDELETION_PREFIX, rule.head.symbol));
-            rederive_relations.insert(format!("{}{}", REDERIVATION_PREFIX, rule.head.symbol));
-            rule.body.iter().for_each(|body_atom| {
-                relations.insert(&body_atom.symbol);
-                overdeletion_relations
-                    .insert(format!("{}{}", OVERDELETION_PREFIX, body_atom.symbol));
+        Ok(id)
+    }
+    // A view is a relation derived by a rule that matches the query, with one column for every
+    // column that the query leaves open. Returns the id of that rule, which remove_rule drops it by.
+    pub fn create_view(&mut self, name: &str, query: &Query) -> Result<usize, String> {
+        if query.since.is_some() || query.source.is_some() {
+            return Err("views can not filter facts by their metadata".to_string());
+        }
+        self.check_query(query)?;
+        let is_taken = self.arities.contains_key(name)
+            || self.processed.inner.contains_key(name)
+            || self
+                .program
+                .inner
+                .iter()
+                .any(|rule| rule.head.symbol == name);
+        if is_taken {
+            return Err(format!("{} is already a relation", name));
+        }
+
+        let body_terms: Vec<Term> = query
+            .matchers
+            .iter()
+            .enumerate()
+            .map(|(column, matcher)| match matcher {
+                Matcher::Any => Ok(Term::Variable(format!("x{}", column))),
+                Matcher::Constant(value) => Ok(Term::Constant(value.clone())),
+                _ => Err("views can only select by constants".to_string()),
             })
-        });
+            .collect::<Result<_, String>>()?;
+        let head_terms = body_terms
+            .iter()
+            .filter(|term| matches!(term, Term::Variable(_)))
+            .cloned()
+            .collect();
+        let rule = Rule {
+            head: Atom {
+                terms: head_terms,
+                symbol: name.to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: body_terms,
+                symbol: query.symbol.to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
 
-        relations.iter().for_each(|relation_symbol| {
-            processed
-                .inner
-                .entry(relation_symbol.to_string())
-                .or_default();
+        self.add_rule(rule)
+    }
+    // Removes the rule with the given id, returning it. As with add_rule, only the relations that
+    // depend on it are derived again.
+    pub fn remove_rule(&mut self, id: usize) -> Result<Rule, String> {
+        let Some(position) = self.program.inner.iter().position(|rule| rule.id == id) else {
+            return Err(format!("there is no rule with id {}", id));
+        };
+        let affected_relations =
+            downstream_relations(&self.program, &self.program.inner[position].head.symbol);
 
-            unprocessed_insertions
-                .inner
-                .entry(relation_symbol.to_string())
-                .or_default();
+        let mut rules = self.program.inner.clone();
+        let rule = rules.remove(position);
+        let program = self.keep_rule_ids(Program::from(rules));
+        self.swap_program(program, &affected_relations)?;
 
-            unprocessed_deletions
-                .inner
-                .entry(relation_symbol.to_string())
-                .or_default();
-        });
+        Ok(rule)
+    }
+    // Program::from numbers rules by their position, which shifts whenever the program changes.
+    // Rules that were already in the program get their id back, and new ones are given fresh ids.
+    fn keep_rule_ids(&mut self, mut program: Program) -> Program {
+        for rule in program.inner.iter_mut() {
+            let previous = self.program.inner.iter().find(|previous| {
+                previous.head == rule.head
+                    && previous.body == rule.body
+                    && previous.hints == rule.hints
+            });
+            rule.id = match previous {
+                Some(previous) => previous.id,
+                None => {
+                    self.next_rule_id += 1;
+                    self.next_rule_id - 1
+                }
+            };
+        }
 
-        overdeletion_relations.iter().for_each(|relation_symbol| {
-            processed
-                .inner
-                .entry(relation_symbol.to_string())
-                .or_default();
-        });
+        program
+    }
+    // Swaps the program for another one, dropping the facts of the given relations for the next
+    // poll to derive again.
+    fn swap_program(
+        &mut self,
+        program: Program,
+        dropped_relations: &HashSet<String>,
+    ) -> Result<(), String> {
+        let is_kept = |relation_symbol: &str| {
+            !dropped_relations.contains(relation_symbol) && !is_internal(relation_symbol)
+        };
 
-        rederive_relations.iter().for_each(|relation_symbol| {
-            processed
+        if let Some(rule) = program
+            .inner
+            .iter()
+            .find(|rule| self.processed.equivalences.contains_key(&rule.head.symbol))
+        {
+            return Err(format!(
+                "{} is an equivalence, hence {:?} can not derive it",
+                rule.head.symbol, rule
+            ));
+        }
+        let mut runtime = Self::try_with_options(program, self.options.clone())?;
+        for (relation_symbol, arity) in &self.arities {
+            if !is_kept(relation_symbol) {
+                continue;
+            }
+            let expected_arity = *runtime
+                .arities
+                .entry(relation_symbol.clone())
+                .or_insert(*arity);
+            if expected_arity != *arity {
+                return Err(format!(
+                    "{} has arity {}, but the program uses it with {} terms",
+                    relation_symbol, arity, expected_arity
+                ));
+            }
+        }
+        // The kept facts have to be of the types that the new program expects.
+        let kept_relations = self
+            .processed
+            .inner
+            .iter()
+            .chain(self.unprocessed_insertions.inner.iter())
+            .filter(|(relation_symbol, _)| is_kept(relation_symbol));
+        for (relation_symbol, facts) in kept_relations {
+            for fact in facts {
+                runtime.types.check(relation_symbol, fact)?;
+            }
+        }
+
+        self.processed
+            .inner
+            .iter()
+            .for_each(|(relation_symbol, facts)| {
+                if is_kept(relation_symbol) {
+                    runtime
+                        .processed
+                        .insert_registered(relation_symbol, facts.iter().cloned());
+                } else {
+                    if let Some(epochs) = &mut self.epochs {
+                        epochs.forget(relation_symbol, facts.iter());
+                    }
+                    if let Some(depths) = &mut self.depths {
+                        depths.forget(relation_symbol, facts.iter());
+                    }
+                    if self.subscriptions.contains_key(relation_symbol) {
+                        let (insertions, deletions) =
+                            self.deltas.entry(relation_symbol.clone()).or_default();
+                        facts.iter().for_each(|fact| {
+                            if !insertions.swap_remove(fact) {
+                                deletions.insert(fact.clone());
+                            }
+                        });
+                    }
+                }
+            });
+        self.unprocessed_insertions.drain_all_relations().for_each(
+            |(relation_symbol, unprocessed_facts)| {
+                runtime
+                    .unprocessed_insertions
+                    .insert_registered(&relation_symbol, unprocessed_facts.into_iter());
+            },
+        );
+        self.unprocessed_deletions.drain_all_relations().for_each(
+            |(relation_symbol, unprocessed_facts)| {
+                if is_kept(&relation_symbol) {
+                    runtime
+                        .unprocessed_deletions
+                        .insert_registered(&relation_symbol, unprocessed_facts.into_iter());
+                }
+            },
+        );
+
+        // Whatever was kept of a relation that the previous program did not derive was inserted.
+        let was_derived = |relation_symbol: &str| {
+            self.program
                 .inner
-                .entry(relation_symbol.to_string())
-                .or_default();
+                .iter()
+                .any(|rule| rule.head.symbol == relation_symbol)
+        };
+        let asserted: Vec<_> = runtime
+            .processed
+            .inner
+            .iter()
+            .filter(|(relation_symbol, _)| {
+                runtime
+                    .negating_program
+                    .inner
+                    .iter()
+                    .any(|rule| rule.head.symbol == **relation_symbol)
+            })
+            .map(|(relation_symbol, facts)| {
+                let facts: Vec<_> = facts
+                    .iter()
+                    .filter(|fact| {
+                        !was_derived(relation_symbol)
+                            || self.asserted.contains(relation_symbol, fact)
+                    })
+                    .cloned()
+                    .collect();
+
+                (relation_symbol.clone(), facts)
+            })
+            .collect();
+        asserted.into_iter().for_each(|(relation_symbol, facts)| {
+            runtime
+                .asserted
+                .insert_all(&relation_symbol, facts.into_iter());
         });
 
-        let (nonrecursive_program, recursive_program) = split_program(program.clone());
+        runtime.next_rule_id = self.next_rule_id;
+        runtime.epoch = self.epoch;
+        if runtime.epochs.is_some() {
+            runtime.epochs = self.epochs.take();
+        }
+        if runtime.depths.is_some() {
+            runtime.depths = self.depths.take();
+        }
+        runtime.metadata = std::mem::take(&mut self.metadata);
+        runtime.observer = std::mem::replace(&mut self.observer, Box::new(()));
+        runtime.subscriptions = std::mem::take(&mut self.subscriptions);
+        runtime.deltas = std::mem::take(&mut self.deltas);
+        runtime.reader = self.reader.take();
+        runtime.taken_deltas = std::mem::take(&mut self.taken_deltas);
+        runtime.created_indices = std::mem::take(&mut self.created_indices);
+        runtime.scenarios = std::mem::take(&mut self.scenarios);
+        runtime.processed.equivalences = std::mem::take(&mut self.processed.equivalences);
+        runtime.register_indices();
+        runtime.rematerialize = true;
+        *self = runtime;
 
-        let overdeletion_program = make_overdeletion_program(&program);
-        let (nonrecursive_overdeletion_program, recursive_overdeletion_program) =
-            split_program(overdeletion_program);
+        Ok(())
+    }
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+    pub fn contains(
+        &self,
+        relation: &str,
+        ground_atom: &AnonymousGroundAtom,
+    ) -> Result<bool, String> {
+        if !self.safe() {
+            if !self.options.on_demand {
+                return Err("poll needed to obtain correct results".to_string());
+            }
 
-        let rederivation_program = make_rederivation_program(&program);
-        let (nonrecursive_rederivation_program, recursive_rederivation_program) =
-            split_program(rederivation_program);
+            let query = Query {
+                matchers: ground_atom.iter().cloned().map(Matcher::Constant).collect(),
+                symbol: relation,
+                since: None,
+                source: None,
+            };
 
-        let nonrecursive_program = sort_program(&nonrecursive_program);
-        let nonrecursive_overdeletion_program = sort_program(&nonrecursive_overdeletion_program);
-        let nonrecursive_rederivation_program = sort_program(&nonrecursive_rederivation_program);
+            return Ok(self.evaluate_on_demand()?.exists(&query));
+        }
 
-        Self {
-            processed,
-            unprocessed_insertions,
-            unprocessed_deletions,
-            program,
-            nonrecursive_program,
-            recursive_program,
-            nonrecursive_overdeletion_program,
-            recursive_overdeletion_program,
-            nonrecursive_rederivation_program,
-            recursive_rederivation_program,
+        if !self.processed.contains(relation, ground_atom) {
+            return Ok(self.unprocessed_insertions.contains(relation, ground_atom));
         }
+
+        Ok(true)
     }
-    pub fn safe(&self) -> bool {
-        self.unprocessed_insertions.is_empty() && self.unprocessed_deletions.is_empty()
-    }
-}
+    // A solver for the facts that queries would have after the next poll, which only evaluates the
+    // rules they depend on. The derived relations are evaluated from the base ones, with the pending
+    // changes applied, since the facts they were last derived with may have been deleted since.
+    fn evaluate_on_demand(&self) -> Result<TopDown<'_>, String> {
+        if let Some(rule) = self
+            .program
+            .inner
+            .iter()
+            .find(|rule| !get_existential_variables(rule).is_empty())
+        {
+            return Err(format!("{:?} can not be evaluated on demand", rule));
+        }
 
-#[cfg(test)]
-mod tests {
-    use crate::engine::datalog::MicroRuntime;
-    use datalog_rule_macro::program;
-    use datalog_syntax::*;
-    use std::collections::HashSet;
+        let base = move |relation_symbol: &str| self.base_facts(relation_symbol);
 
-    #[test]
-    fn integration_test_insertions_only() {
-        let tc_program = program! {
-            tc(?x, ?y) <- [e(?x, ?y)],
-            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        TopDown::new(&self.program, base)
+    }
+    // The facts that a relation would have after the next poll, leaving aside those that the
+    // program would derive.
+    fn base_facts(&self, relation_symbol: &str) -> Vec<Arc<AnonymousGroundAtom>> {
+        let pending = self.unprocessed_insertions.facts(relation_symbol);
+        let is_derived = self
+            .program
+            .inner
+            .iter()
+            .any(|rule| rule.head.symbol == relation_symbol);
+        if is_derived {
+            return pending.cloned().collect();
+        }
+
+        let deleted = self.unprocessed_deletions.inner.get(relation_symbol);
+        self.processed
+            .facts(relation_symbol)
+            .filter(|fact| !deleted.is_some_and(|deleted| deleted.contains(*fact)))
+            .chain(pending)
+            .cloned()
+            .collect()
+    }
+    // Queries that bind constants of relations that are derived without recursion are answered on
+    // demand bottom-up, by the rules they depend on specialized to those constants, which only
+    // derive the facts that the query can match.
+    fn evaluate_specialized(
+        &self,
+        query: &Query,
+    ) -> Result<Option<Vec<Arc<AnonymousGroundAtom>>>, String> {
+        let Some(specialization) = specialize_program(&self.program, query) else {
+            return Ok(None);
         };
 
-        let mut runtime = MicroRuntime::new(tc_program);
+        let mut storage = RelationStorage::default();
+        for rule in &specialization.program.inner {
+            for atom in std::iter::once(&rule.head).chain(&rule.body) {
+                if storage.inner.contains_key(&atom.symbol) {
+                    continue;
+                }
+
+                let facts = match specialization.origins.get(&atom.symbol) {
+                    Some((relation_symbol, binding)) => self
+                        .base_facts(relation_symbol)
+                        .into_iter()
+                        .filter(|fact| is_bound_by(binding, fact))
+                        .collect(),
+                    None => self.base_facts(&atom.symbol),
+                };
+                storage.insert_all(&atom.symbol, facts.into_iter());
+            }
+        }
+
+        let components = split_components(&specialization.program);
+        let stacks = StackCache::new(
+            components
+                .iter()
+                .flat_map(|(nonrecursive, recursive)| [nonrecursive, recursive]),
+            false,
+        );
+        for (nonrecursive_program, recursive_program) in &components {
+            semi_naive_evaluation(
+                &mut storage,
+                &stacks,
+                nonrecursive_program,
+                recursive_program,
+                self.options.intermediate_memory_limit,
+                &mut (),
+            )?;
+        }
+
+        Ok(Some(
+            storage.facts(&specialization.answers).cloned().collect(),
+        ))
+    }
+    // Whether the query has any answer, without looking for the rest once one is found.
+    pub fn exists(&self, query: &Query) -> Result<bool, String> {
+        self.check_query(query)?;
+        let filters_metadata = query.since.is_some() || query.source.is_some();
+        if !self.safe() && self.options.on_demand && !filters_metadata {
+            return Ok(self.evaluate_on_demand()?.exists(query));
+        }
+
+        Ok(self.query_ref(query)?.next().is_some())
+    }
+    pub fn query<'a>(
+        &'a self,
+        query: &'a Query,
+    ) -> Result<impl Iterator<Item = AnonymousGroundAtom> + 'a, String> {
+        Ok(self.query_ref(query)?.map(|fact| (*fact).clone()))
+    }
+    // Same as query, but hands out the stored facts themselves rather than deep copies of them.
+    pub fn query_ref<'a>(
+        &'a self,
+        query: &'a Query,
+    ) -> Result<impl Iterator<Item = Arc<AnonymousGroundAtom>> + 'a, String> {
+        self.check_query(query)?;
+        let facts: Box<dyn Iterator<Item = Arc<AnonymousGroundAtom>> + 'a> = if self.safe() {
+            Box::new(self.processed.facts(query.symbol).cloned())
+        } else if self.options.on_demand {
+            match self.evaluate_specialized(query)? {
+                Some(facts) => Box::new(facts.into_iter()),
+                None => Box::new(self.evaluate_on_demand()?.query(query)),
+            }
+        } else {
+            return Err("poll needed to obtain correct results".to_string());
+        };
+
+        Ok(facts.filter(|fact| self.is_answer(query, fact)))
+    }
+    // Answers the query top-down from the facts as they would be after the next poll, going no
+    // further than the budget allows. The answers found are answers, though there may be more.
+    pub fn query_within(
+        &self,
+        query: &Query,
+        budget: &QueryBudget,
+    ) -> Result<BudgetedAnswers, String> {
+        self.check_query(query)?;
+        let mut answers = self.evaluate_on_demand()?.with_budget(budget).query(query);
+        let mut matching = answers.by_ref().filter(|fact| self.is_answer(query, fact));
+
+        let facts = matching
+            .by_ref()
+            .take(budget.max_answers.unwrap_or(usize::MAX))
+            .map(|fact| (*fact).clone())
+            .collect();
+        let more = matching.next().is_some();
+
+        Ok(BudgetedAnswers {
+            facts,
+            truncated: more || answers.truncated(),
+        })
+    }
+    fn is_answer(&self, query: &Query, fact: &AnonymousGroundAtom) -> bool {
+        let filters_metadata = query.since.is_some() || query.source.is_some();
+
+        pattern_match(query, fact)
+            && (!filters_metadata
+                || self
+                    .metadata
+                    .get(query.symbol, fact)
+                    .is_some_and(|metadata| metadata.satisfies(query)))
+    }
+    // Answers every query as query would, in the order they are given. The facts of every relation
+    // are gone through once for all of its queries and, when they are evaluated on demand, what
+    // evaluating one of them derives is reused by the rest.
+    pub fn query_many(&self, queries: &[Query]) -> Result<Vec<Vec<AnonymousGroundAtom>>, String> {
+        for query in queries {
+            self.check_query(query)?;
+        }
+        let mut answers = vec![vec![]; queries.len()];
+
+        if !self.safe() {
+            if !self.options.on_demand {
+                return Err("poll needed to obtain correct results".to_string());
+            }
+
+            let mut solver = self.evaluate_on_demand()?;
+            for (query, answers) in queries.iter().zip(&mut answers) {
+                *answers = solver
+                    .answers(query)
+                    .into_iter()
+                    .filter(|fact| self.is_answer(query, fact))
+                    .map(|fact| (*fact).clone())
+                    .collect();
+            }
+
+            return Ok(answers);
+        }
+
+        let mut queries_by_relation: IndexMap<&str, Vec<usize>> = IndexMap::new();
+        for (idx, query) in queries.iter().enumerate() {
+            queries_by_relation
+                .entry(query.symbol)
+                .or_default()
+                .push(idx);
+        }
+        for (relation_symbol, idxs) in queries_by_relation {
+            for fact in self.processed.facts(relation_symbol) {
+                for &idx in &idxs {
+                    if self.is_answer(&queries[idx], fact) {
+                        answers[idx].push((**fact).clone());
+                    }
+                }
+            }
+        }
+
+        Ok(answers)
+    }
+    // Same as query, along with the iteration that first derived every answer, which is zero for
+    // facts that were inserted rather than derived.
+    pub fn query_with_depth(
+        &self,
+        query: &Query,
+    ) -> Result<Vec<(AnonymousGroundAtom, usize)>, String> {
+        let Some(depths) = &self.depths else {
+            return Err("depths are only recorded when the options ask for them".to_string());
+        };
+        if !self.safe() {
+            return Err("poll needed to obtain correct results".to_string());
+        }
+
+        Ok(self
+            .query_ref(query)?
+            .map(|fact| {
+                let depth = depths.get(query.symbol, &fact).unwrap_or_default();
+                ((*fact).clone(), depth)
+            })
+            .collect())
+    }
+    // Inserts a fact that only holds under the scenario, for queries that enable it. Scenarios are
+    // kept apart from the relations of the program, which polls never see them in.
+    pub fn insert_under(
+        &mut self,
+        scenario: &str,
+        relation: &str,
+        ground_atom: AnonymousGroundAtom,
+    ) -> Result<bool, String> {
+        self.check_arity(relation, ground_atom.len())?;
+        self.types.check(relation, &ground_atom)?;
+        if self.processed.get_equivalence(relation).is_some() {
+            return Err(format!(
+                "{} is an equivalence, whose facts can not be put under a scenario",
+                relation
+            ));
+        }
+
+        Ok(self
+            .scenarios
+            .entry(scenario.to_string())
+            .or_default()
+            .insert(relation, ground_atom))
+    }
+    // Forgets every fact of the scenario, returning whether it had any.
+    pub fn drop_scenario(&mut self, scenario: &str) -> bool {
+        self.scenarios.remove(scenario).is_some()
+    }
+    // Answers the query as if the facts of the enabled scenarios had been inserted, without the
+    // runtime changing. Unless the program negates, what the last poll derived is evaluated onwards
+    // rather than from scratch, so that only what the scenarios add is derived anew.
+    pub fn query_under(
+        &self,
+        query: &Query,
+        scenarios: &[&str],
+    ) -> Result<Vec<AnonymousGroundAtom>, String> {
+        self.check_query(query)?;
+        if !self.safe() {
+            return Err("poll needed to obtain correct results".to_string());
+        }
+        if let Some(scenario) = scenarios
+            .iter()
+            .find(|scenario| !self.scenarios.contains_key(**scenario))
+        {
+            return Err(format!("{} is not a scenario", scenario));
+        }
+        if !self.aggregate_program.inner.is_empty() {
+            return Err("scenarios can not be evaluated for programs that aggregate".to_string());
+        }
+
+        let is_derived = |relation_symbol: &str| {
+            self.program
+                .inner
+                .iter()
+                .any(|rule| rule.head.symbol == relation_symbol)
+        };
+        let mut storage = RelationStorage::default();
+        for (relation_symbol, facts) in &self.processed.inner {
+            if is_internal(relation_symbol) {
+                continue;
+            }
+
+            let is_kept = self.negated_relations.is_empty() || !is_derived(relation_symbol);
+            storage.insert_all(relation_symbol, facts.iter().filter(|_| is_kept).cloned());
+        }
+        for scenario in scenarios {
+            for (relation_symbol, facts) in &self.scenarios[*scenario].inner {
+                storage.insert_all(relation_symbol, facts.iter().cloned());
+            }
+        }
+
+        for (nonrecursive_program, recursive_program) in &self.strata {
+            semi_naive_evaluation(
+                &mut storage,
+                &self.stacks,
+                nonrecursive_program,
+                recursive_program,
+                self.options.intermediate_memory_limit,
+                &mut (),
+            )?;
+        }
+
+        Ok(storage
+            .facts(query.symbol)
+            .filter(|fact| self.is_answer(query, fact))
+            .map(|fact| (**fact).clone())
+            .collect())
+    }
+    // Indexes the relation of the query by the columns that it binds, so that running it does not
+    // scan the whole relation every time. The index is kept like a created one.
+    pub fn prepare(&mut self, query: &PreparedQuery) -> Result<(), String> {
+        if let Some(arity) = self.arities.get(&query.symbol) {
+            if query.parameters.len() != *arity {
+                return Err(format!(
+                    "{} has arity {}, but the query has {} parameters",
+                    query.symbol,
+                    arity,
+                    query.parameters.len()
+                ));
+            }
+        }
+
+        let columns = query.bound_columns();
+        if !columns.is_empty() {
+            self.processed
+                .register_index(&query.symbol, columns.clone());
+            self.created_indices.insert((query.symbol.clone(), columns));
+        }
+
+        Ok(())
+    }
+    // The facts matching the query once its placeholders are given these values. Queries that
+    // were not prepared, or are run while changes are pending, are answered like any other.
+    pub fn execute(
+        &self,
+        query: &PreparedQuery,
+        values: &[TypedValue],
+    ) -> Result<Vec<AnonymousGroundAtom>, String> {
+        let bound_query = query.bind(values)?;
+        let columns = query.bound_columns();
+
+        match self.processed.get_index(&query.symbol, &columns) {
+            Some(index) if self.safe() => {
+                let key: Vec<_> = bound_query
+                    .matchers
+                    .iter()
+                    .filter_map(|matcher| match matcher {
+                        Matcher::Constant(value) => Some(value),
+                        _ => None,
+                    })
+                    .collect();
+
+                Ok(index.get(&key).map(|fact| (**fact).clone()).collect())
+            }
+            _ => Ok(self.query(&bound_query)?.collect()),
+        }
+    }
+    // Every fact of the relation that R stands for, failing if any does not fit R.
+    pub fn query_typed<R: Relation>(&self) -> Result<Vec<R>, String> {
+        let arity = self.arity(R::SYMBOL).unwrap_or_default();
+        let query = Query {
+            matchers: (0..arity).map(|_| Matcher::Any).collect(),
+            symbol: R::SYMBOL,
+            since: None,
+            source: None,
+        };
+
+        let facts = self
+            .query_ref(&query)?
+            .map(|fact| R::from_fact(&fact))
+            .collect();
+
+        facts
+    }
+    // The facts matching the query as tuples, failing if any does not fit T.
+    pub fn query_as<T: FromFact>(&self, query: &Query) -> Result<Vec<T>, String> {
+        self.query_ref(query)?
+            .map(|fact| T::from_fact(&fact))
+            .collect()
+    }
+    // Only the given columns of the facts matching the query, with every distinct combination of
+    // their values yielded once.
+    pub fn query_project<'a>(
+        &'a self,
+        query: &'a Query,
+        columns: &'a [usize],
+    ) -> Result<impl Iterator<Item = AnonymousGroundAtom> + 'a, String> {
+        if !self.safe() {
+            return Err("poll needed to obtain correct results".to_string());
+        }
+        self.check_query(query)?;
+        self.check_columns(query.symbol, columns.iter().copied())?;
+
+        let mut seen: HashSet<Vec<&TypedValue>> = HashSet::new();
+        Ok(self
+            .processed
+            .facts(query.symbol)
+            .filter(|fact| self.is_answer(query, fact))
+            .filter_map(move |fact| {
+                let projection: Vec<_> = columns.iter().map(|column| &fact[*column]).collect();
+
+                if seen.insert(projection.clone()) {
+                    return Some(projection.into_iter().cloned().collect());
+                }
+
+                None
+            }))
+    }
+    // The facts matching the query, grouped by their values at the given columns. Once polled, the
+    // index that joins keep over those columns is gone through rather than the facts hashed anew.
+    pub fn query_grouped(
+        &self,
+        query: &Query,
+        columns: &[usize],
+    ) -> Result<HashMap<Vec<TypedValue>, Vec<AnonymousGroundAtom>>, String> {
+        Ok(self
+            .groups(query, columns)?
+            .into_iter()
+            .map(|(key, facts)| (key, facts.into_iter().map(|fact| (*fact).clone()).collect()))
+            .collect())
+    }
+    // How many facts matching the query every group has, grouped as by query_grouped.
+    pub fn query_group_counts(
+        &self,
+        query: &Query,
+        columns: &[usize],
+    ) -> Result<HashMap<Vec<TypedValue>, usize>, String> {
+        Ok(self
+            .groups(query, columns)?
+            .into_iter()
+            .map(|(key, facts)| (key, facts.len()))
+            .collect())
+    }
+    fn groups(&self, query: &Query, columns: &[usize]) -> Result<Vec<Group>, String> {
+        self.check_query(query)?;
+        self.check_columns(query.symbol, columns.iter().copied())?;
+
+        let built;
+        let index = match self
+            .safe()
+            .then(|| self.processed.get_index(query.symbol, columns))
+            .flatten()
+        {
+            Some(index) => index,
+            None => {
+                built = HashIndex::from_facts(columns.to_vec(), self.query_ref(query)?);
+                &built
+            }
+        };
+
+        Ok(index
+            .groups()
+            .filter_map(|(key, facts)| {
+                let facts: Vec<_> = facts
+                    .into_iter()
+                    .filter(|fact| self.is_answer(query, fact))
+                    .cloned()
+                    .collect();
+
+                (!facts.is_empty()).then(|| (key.into_iter().cloned().collect(), facts))
+            })
+            .collect())
+    }
+    // The facts matching the query that no other one with the same values at the key columns is
+    // better than, as told by better(a, b) for a better than b, e.g. the shortest paths between
+    // every two nodes out of all their paths. Which facts are better than which need only be a
+    // partial order, so that incomparable facts are all kept.
+    pub fn query_minimal(
+        &self,
+        query: &Query,
+        key: &[usize],
+        better: impl Fn(&AnonymousGroundAtom, &AnonymousGroundAtom) -> bool,
+    ) -> Result<Vec<AnonymousGroundAtom>, String> {
+        self.check_columns(query.symbol, key.iter().copied())?;
+
+        let mut groups: IndexMap<Vec<TypedValue>, Vec<Arc<AnonymousGroundAtom>>> = IndexMap::new();
+        self.query_ref(query)?.for_each(|fact| {
+            let key_values = key.iter().map(|column| fact[*column].clone()).collect();
+            groups.entry(key_values).or_default().push(fact);
+        });
+
+        Ok(groups
+            .into_values()
+            .flat_map(|group| {
+                group
+                    .iter()
+                    .filter(|fact| !group.iter().any(|other| better(other, fact)))
+                    .map(|fact| (**fact).clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+    // A page of the facts matching the query, sorted by the given columns in turn and then by the
+    // whole fact, so that pages never overlap. Only the facts up to the end of the page are put in
+    // order, rather than every one that matches.
+    pub fn query_sorted(
+        &self,
+        query: &Query,
+        ordering: &[(usize, Direction)],
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AnonymousGroundAtom>, String> {
+        self.check_columns(query.symbol, ordering.iter().map(|(column, _)| *column))?;
+
+        let compare = |left: &Arc<AnonymousGroundAtom>, right: &Arc<AnonymousGroundAtom>| {
+            ordering
+                .iter()
+                .map(|(column, direction)| {
+                    let order = left[*column].cmp(&right[*column]);
+                    match direction {
+                        Direction::Ascending => order,
+                        Direction::Descending => order.reverse(),
+                    }
+                })
+                .find(|order| order.is_ne())
+                .unwrap_or_else(|| left.cmp(right))
+        };
+
+        let mut facts: Vec<_> = self.query_ref(query)?.collect();
+        let end = offset.saturating_add(limit).min(facts.len());
+        if end == 0 {
+            return Ok(vec![]);
+        }
+        if end < facts.len() {
+            facts.select_nth_unstable_by(end - 1, compare);
+            facts.truncate(end);
+        }
+        facts.sort_unstable_by(compare);
+
+        Ok(facts
+            .into_iter()
+            .skip(offset)
+            .map(|fact| (*fact).clone())
+            .collect())
+    }
+    // Facts matching the query that first appeared after the given epoch, i.e. everything that is
+    // new to a consumer that last read at that epoch.
+    pub fn query_new_since<'a>(
+        &'a self,
+        query: &'a Query,
+        epoch: Epoch,
+    ) -> Result<impl Iterator<Item = AnonymousGroundAtom> + 'a, String> {
+        let Some(epochs) = &self.epochs else {
+            return Err("epochs are only recorded when the options ask for them".to_string());
+        };
+
+        Ok(self
+            .query_ref(query)?
+            .filter(move |fact| {
+                epochs
+                    .get(query.symbol, fact)
+                    .is_some_and(|fact_epoch| fact_epoch > epoch)
+            })
+            .map(|fact| (*fact).clone()))
+    }
+    // Makes the relation hold the reflexive, symmetric and transitive closure of the pairs inserted
+    // into it, as kept by a union-find, rather than leaving the rules to spell the closure out. It
+    // has to be declared before any fact goes in, and no rule may derive it.
+    pub fn declare_equivalence(&mut self, relation: &str) -> Result<(), String> {
+        if self
+            .program
+            .inner
+            .iter()
+            .any(|rule| rule.head.symbol == relation)
+        {
+            return Err(format!(
+                "{} is derived by the program, hence it can not be an equivalence",
+                relation
+            ));
+        }
+        if self.processed.equivalences.contains_key(relation) {
+            return Ok(());
+        }
+        let holds_facts = [&self.processed, &self.unprocessed_insertions]
+            .iter()
+            .any(|storage| {
+                storage
+                    .inner
+                    .get(relation)
+                    .is_some_and(|facts| !facts.is_empty())
+            });
+        if holds_facts {
+            return Err(format!(
+                "{} already holds facts, hence it can not become an equivalence",
+                relation
+            ));
+        }
+        self.check_arity(relation, 2)?;
+
+        self.processed
+            .equivalences
+            .insert(relation.to_string(), Equivalence::default());
+
+        Ok(())
+    }
+    // The classes of the equivalence as of the last poll.
+    pub fn equivalence(&self, relation: &str) -> Option<&Equivalence> {
+        self.processed.get_equivalence(relation)
+    }
+    // Keeps a hash index over the columns of the relation, which joins and selections on exactly
+    // those columns, in that order, read instead of going through the whole relation.
+    pub fn create_index(&mut self, relation: &str, columns: &[usize]) -> Result<(), String> {
+        if columns.is_empty() {
+            return Err("an index needs at least one column".to_string());
+        }
+        self.check_columns(relation, columns.iter().copied())?;
+
+        self.processed.register_index(relation, columns.to_vec());
+        self.created_indices
+            .insert((relation.to_string(), columns.to_vec()));
+
+        Ok(())
+    }
+    // Returns whether the index had been created. Indices that the program needs are kept anyway.
+    pub fn drop_index(&mut self, relation: &str, columns: &[usize]) -> bool {
+        if !self
+            .created_indices
+            .swap_remove(&(relation.to_string(), columns.to_vec()))
+        {
+            return false;
+        }
+        let needed = self
+            .needed_indices()
+            .any(|(relation_symbol, needed_columns)| {
+                relation_symbol == relation && needed_columns == columns
+            });
+        if !needed {
+            self.processed.drop_index(relation, columns);
+        }
+
+        true
+    }
+    // The indices that the selections of the program would read rather than scan the relations
+    // they select from, and that are not there yet.
+    pub fn advise_indices(&self) -> Vec<(String, Vec<usize>)> {
+        let advice: IndexSet<_> = self
+            .program
+            .inner
+            .iter()
+            .flat_map(get_selection_indices)
+            .filter(|(relation_symbol, columns)| {
+                self.processed.get_index(relation_symbol, columns).is_none()
+            })
+            .collect();
+
+        advice.into_iter().collect()
+    }
+    // The facts that the last poll added to the relation, handed out once per poll, so that a
+    // consumer polling along with the runtime sees every new fact exactly once.
+    pub fn take_delta(&mut self, relation: &str) -> Result<Vec<Arc<AnonymousGroundAtom>>, String> {
+        let Some(epochs) = &self.epochs else {
+            return Err("epochs are only recorded when the options ask for them".to_string());
+        };
+        if !self.taken_deltas.insert(relation.to_string()) {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .processed
+            .inner
+            .get(relation)
+            .into_iter()
+            .flatten()
+            .filter(|fact| epochs.get(relation, fact) == Some(self.epoch))
+            .cloned()
+            .collect())
+    }
+    pub fn relation_statistics(&self, relation: &str) -> Option<RelationStatistics> {
+        self.processed.inner.get(relation).map(relation_statistics)
+    }
+    // Every relation that the runtime holds, with how many facts it has, in order of their names.
+    pub fn relations(&self) -> impl Iterator<Item = (&str, usize)> {
+        let mut relations: Vec<_> = self
+            .processed
+            .inner
+            .iter()
+            .filter(|(relation_symbol, _)| !is_internal(relation_symbol))
+            .map(|(relation_symbol, facts)| (relation_symbol.as_str(), facts.len()))
+            .collect();
+        relations.sort();
+
+        relations.into_iter()
+    }
+    pub fn relation_schema(&self, relation: &str) -> Option<RelationSchema> {
+        if is_internal(relation) {
+            return None;
+        }
+        let facts = self.processed.inner.get(relation)?;
+
+        Some(RelationSchema {
+            arity: self.arity(relation),
+            derived: self
+                .program
+                .inner
+                .iter()
+                .any(|rule| rule.head.symbol == relation),
+            size: facts.len(),
+            indices: self
+                .processed
+                .indices
+                .get(relation)
+                .into_iter()
+                .flatten()
+                .map(|index| index.columns.clone())
+                .collect(),
+        })
+    }
+    // Answers the query by every other strategy that can answer it, and reports those whose answers
+    // differ from what the last poll derived, so that strategies can be tested against each other.
+    pub fn query_check(&self, query: &Query) -> Result<Vec<Discrepancy>, String> {
+        Ok(self
+            .run_strategies(query, false)?
+            .into_iter()
+            .filter_map(|comparison| comparison.discrepancy)
+            .collect())
+    }
+    // Same as query_check, along with how long every strategy took, and with the whole program
+    // derived anew from the base relations as one more strategy, so that strategies can be weighed
+    // against each other.
+    pub fn compare_strategies(&self, query: &Query) -> Result<Vec<StrategyComparison>, String> {
+        self.run_strategies(query, true)
+    }
+    fn run_strategies(
+        &self,
+        query: &Query,
+        derive_anew: bool,
+    ) -> Result<Vec<StrategyComparison>, String> {
+        if !self.safe() {
+            return Err("poll needed to obtain correct results".to_string());
+        }
+        let expected: BTreeSet<_> = self.query(query)?.collect();
+
+        let answers = |facts: &mut dyn Iterator<Item = Arc<AnonymousGroundAtom>>| {
+            facts
+                .filter(|fact| self.is_answer(query, fact))
+                .map(|fact| (*fact).clone())
+                .collect::<BTreeSet<_>>()
+        };
+        let mut strategies = vec![];
+
+        let stopwatch = Stopwatch::start();
+        let actual = answers(&mut self.evaluate_on_demand()?.query(query));
+        strategies.push((Strategy::TopDown, stopwatch.elapsed(), actual));
+
+        let stopwatch = Stopwatch::start();
+        if let Some(facts) = self.evaluate_specialized(query)? {
+            let actual = answers(&mut facts.into_iter());
+            strategies.push((Strategy::Specialized, stopwatch.elapsed(), actual));
+        }
+
+        if derive_anew {
+            let stopwatch = Stopwatch::start();
+            let runtime = self.derive_anew()?;
+            let actual = answers(&mut runtime.processed.facts(query.symbol).cloned());
+            strategies.push((Strategy::Rematerialized, stopwatch.elapsed(), actual));
+        }
+
+        Ok(strategies
+            .into_iter()
+            .map(|(strategy, time, actual)| StrategyComparison {
+                strategy,
+                time,
+                discrepancy: discrepancy(strategy, &expected, &actual),
+            })
+            .collect())
+    }
+    // A runtime of the same program and options that has derived everything anew from the facts of
+    // the relations that no rule derives.
+    fn derive_anew(&self) -> Result<MicroRuntime, String> {
+        let mut runtime = Self::try_with_options(self.program.clone(), self.options.clone())?;
+        for (relation_symbol, facts) in &self.processed.inner {
+            let is_derived = self
+                .program
+                .inner
+                .iter()
+                .any(|rule| rule.head.symbol == *relation_symbol);
+            if is_internal(relation_symbol) || is_derived {
+                continue;
+            }
+
+            runtime.insert_many(relation_symbol, facts.iter().map(|fact| (**fact).clone()))?;
+        }
+        runtime.try_poll()?;
+
+        Ok(runtime)
+    }
+    // How every rule that the queried relation depends on is evaluated, along with how much work it
+    // took during the last poll.
+    pub fn explain(&self, query: &Query) -> Result<Explanation, String> {
+        if !self.safe() {
+            return Err("poll needed to obtain correct results".to_string());
+        }
+
+        let mut relevant_relations: IndexSet<&str> = IndexSet::from([query.symbol]);
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            self.program
+                .inner
+                .iter()
+                .filter(|rule| relevant_relations.contains(rule.head.symbol.as_str()))
+                .flat_map(|rule| rule.body.iter())
+                .collect::<Vec<_>>()
+                .into_iter()
+                .for_each(|body_atom| {
+                    changed |= relevant_relations.insert(&body_atom.symbol);
+                });
+        }
+
+        let rules = self
+            .program
+            .inner
+            .iter()
+            .filter(|rule| relevant_relations.contains(rule.head.symbol.as_str()))
+            .map(|rule| {
+                let (evaluations, new_facts, _) = self
+                    .statistics
+                    .rules
+                    .get(&format!("{:?}", rule))
+                    .copied()
+                    .unwrap_or_default();
+
+                Ok(RulePlan {
+                    rule: rule.clone(),
+                    operations: plan_rule(&self.processed, rule, self.options.generic_joins)?,
+                    evaluations,
+                    new_facts,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Explanation {
+            relation: query.symbol.to_string(),
+            rules,
+            iterations: self.statistics.iterations,
+        })
+    }
+    // What evaluating every rule of the program took over the last poll, the slowest first.
+    // Overdeleting and rederiving facts is done by rules of their own, which are left out.
+    pub fn stats(&self) -> Vec<RuleStatistics> {
+        let mut statistics: Vec<_> = self
+            .program
+            .inner
+            .iter()
+            .map(|rule| {
+                let (evaluations, new_facts, time) = self
+                    .statistics
+                    .rules
+                    .get(&format!("{:?}", rule))
+                    .copied()
+                    .unwrap_or_default();
+
+                RuleStatistics {
+                    rule: rule.clone(),
+                    evaluations,
+                    new_facts,
+                    time,
+                }
+            })
+            .collect();
+        statistics.sort_by_key(|rule_statistics| std::cmp::Reverse(rule_statistics.time));
+
+        statistics
+    }
+    // Roughly how many bytes the facts, the indices over them and the intermediate results of the
+    // last poll take.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (facts, indices) = [
+            &self.processed,
+            &self.unprocessed_insertions,
+            &self.unprocessed_deletions,
+        ]
+        .iter()
+        .map(|storage| storage.bytes())
+        .fold((0, 0), |(facts, indices), (more_facts, more_indices)| {
+            (facts + more_facts, indices + more_indices)
+        });
+
+        MemoryUsage {
+            facts,
+            indices,
+            intermediate_peak: self.statistics.intermediate_peak,
+        }
+    }
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    // Snapshots hold every relation along with the pending updates and the epoch of each fact, if
+    // it was recorded, and can only be loaded back into a runtime of the same program.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let file = File::create(path).map_err(|error| error.to_string())?;
+        let mut writer = BufWriter::new(file);
+        let untagged = EpochStorage::default();
+        let epochs = self.epochs.as_ref().unwrap_or(&untagged);
+
+        write_header(&mut writer, &self.program)?;
+        write_u64(&mut writer, self.epoch as u64)?;
+        write_storage(&mut writer, &self.processed, epochs)?;
+        write_storage(&mut writer, &self.unprocessed_insertions, epochs)?;
+        write_storage(&mut writer, &self.unprocessed_deletions, epochs)?;
+
+        writer.flush().map_err(|error| error.to_string())
+    }
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let mut reader = BufReader::new(file);
+
+        read_header(&mut reader, &self.program)?;
+        let epoch = read_u64(&mut reader)? as Epoch;
+        let mut epochs = EpochStorage::default();
+        let mut processed = RelationStorage {
+            interner: self.options.share_facts.then(FactInterner::default),
+            ..Default::default()
+        };
+        let mut unprocessed_insertions = RelationStorage::default();
+        let mut unprocessed_deletions = RelationStorage::default();
+        read_storage(&mut reader, &mut processed, &mut epochs)?;
+        read_storage(&mut reader, &mut unprocessed_insertions, &mut epochs)?;
+        read_storage(&mut reader, &mut unprocessed_deletions, &mut epochs)?;
+        // Snapshots only hold the closures of equivalences, which stand in for the pairs that were
+        // put in.
+        let equivalences = self
+            .processed
+            .equivalences
+            .keys()
+            .map(|relation_symbol| {
+                let mut equivalence = Equivalence::default();
+                processed
+                    .inner
+                    .get(relation_symbol)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|fact| {
+                        equivalence.insert(&fact[0], &fact[1]);
+                    });
+
+                (relation_symbol.clone(), equivalence)
+            })
+            .collect();
+        processed.equivalences = equivalences;
+
+        // Nothing is replaced until the whole snapshot has been read, so a failed load leaves the
+        // runtime as it was.
+        self.epoch = epoch;
+        if self.epochs.is_some() {
+            self.epochs = Some(epochs);
+        }
+        // Snapshots do not hold metadata, nor which facts were inserted rather than derived.
+        self.metadata = Default::default();
+        self.asserted = Default::default();
+        self.processed = processed;
+        self.unprocessed_insertions = unprocessed_insertions;
+        self.unprocessed_deletions = unprocessed_deletions;
+        if let Some(counts) = &mut self.counts {
+            counts.clear();
+        }
+        self.register_indices();
+        self.publish();
+
+        Ok(())
+    }
+
+    pub fn poll(&mut self) {
+        self.try_poll().unwrap_or_else(|error| panic!("{}", error))
+    }
+    // Journals every change to the facts from then on, until the state is either restored or
+    // kept, which costs as much as the changes.
+    pub(crate) fn save_state(&mut self) -> PollState {
+        self.processed.open_journal();
+        self.unprocessed_insertions.open_journal();
+        self.unprocessed_deletions.open_journal();
+        self.asserted.open_journal();
+        if let Some(epochs) = &mut self.epochs {
+            epochs.journal.open();
+        }
+        self.metadata.journal.open();
+        if let Some(depths) = &mut self.depths {
+            depths.journal.open();
+        }
+
+        PollState {
+            epoch: self.epoch,
+            deltas: self.deltas.clone(),
+            arities: self.arities.clone(),
+            negations_stale: self.negations_stale,
+            rematerialize: self.rematerialize,
+            taken_deltas: self.taken_deltas.clone(),
+        }
+    }
+    // The derivation counts are not journaled, hence the next poll counts them all over again.
+    pub(crate) fn restore_state(&mut self, state: PollState) {
+        self.processed.roll_back();
+        self.unprocessed_insertions.roll_back();
+        self.unprocessed_deletions.roll_back();
+        self.asserted.roll_back();
+        if let Some(epochs) = &mut self.epochs {
+            epochs.roll_back();
+        }
+        self.metadata.roll_back();
+        if let Some(depths) = &mut self.depths {
+            depths.roll_back();
+        }
+        if let Some(counts) = &mut self.counts {
+            counts.clear();
+        }
+        self.processed
+            .equivalences
+            .extend(std::mem::take(&mut self.saved_equivalences));
+        self.epoch = state.epoch;
+        self.deltas = state.deltas;
+        self.arities = state.arities;
+        self.negations_stale = state.negations_stale;
+        self.rematerialize = state.rematerialize;
+        self.taken_deltas = state.taken_deltas;
+    }
+    pub(crate) fn keep_state(&mut self) {
+        self.processed.close_journal();
+        self.unprocessed_insertions.close_journal();
+        self.unprocessed_deletions.close_journal();
+        self.asserted.close_journal();
+        if let Some(epochs) = &mut self.epochs {
+            epochs.journal.close();
+        }
+        self.metadata.journal.close();
+        if let Some(depths) = &mut self.depths {
+            depths.journal.close();
+        }
+        self.saved_equivalences.clear();
+    }
+    // As poll, but an evaluation that fails is returned rather than panicked on. Whatever the
+    // failed poll derived so far is kept, and derived once more by the next poll.
+    pub fn try_poll(&mut self) -> Result<(), String> {
+        self.epoch += 1;
+        self.statistics = Default::default();
+        self.taken_deltas.clear();
+        self.close_equivalences();
+
+        if let Err(error) = self.evaluate() {
+            self.processed.clear_prefix(OVERDELETION_PREFIX);
+            self.processed.clear_prefix(REDERIVATION_PREFIX);
+            self.rematerialize = true;
+
+            return Err(error);
+        }
+
+        self.notify_subscriptions();
+
+        self.processed.compact();
+        self.unprocessed_insertions.compact();
+        self.unprocessed_deletions.compact();
+        self.publish();
+
+        Ok(())
+    }
+    // Aggregates are only recomputed once everything they range over has settled, and any change
+    // to them is then processed like an update to a base relation, until none is left. The same
+    // goes for facts that a negation no longer allows.
+    fn evaluate(&mut self) -> Result<(), String> {
+        loop {
+            if self.counts.is_some() {
+                self.process_counted_changes();
+            } else {
+                self.process_deletions()?;
+                self.process_insertions()?;
+            }
+
+            let aggregates_changed = self.stage_aggregates()?;
+            let negations_changed = self.stage_negations()?;
+            if !aggregates_changed && !negations_changed {
+                return Ok(());
+            }
+        }
+    }
+    // A handle for other threads to query what the runtime held at the end of its last poll, while
+    // it goes on polling. Every poll copies the relations for readers from then on.
+    pub fn reader(&mut self) -> Reader {
+        if self.reader.is_none() {
+            self.reader = Some(Reader::default());
+            self.publish();
+        }
+
+        self.reader.clone().unwrap()
+    }
+    fn publish(&self) {
+        let Some(reader) = &self.reader else {
+            return;
+        };
+
+        reader.publish(Published {
+            epoch: self.epoch,
+            relations: self
+                .processed
+                .inner
+                .iter()
+                .filter(|(relation_symbol, _)| !is_internal(relation_symbol))
+                .map(|(relation_symbol, facts)| (relation_symbol.clone(), Arc::new(facts.clone())))
+                .collect(),
+            arities: self.arities.clone(),
+        });
+    }
+
+    // Turns the pairs inserted into and removed from every equivalence into changes to its closure,
+    // which the rest of the poll then processes like changes to any other relation.
+    fn close_equivalences(&mut self) {
+        let is_saved = self.processed.journal.is_open();
+        self.processed
+            .equivalences
+            .iter_mut()
+            .for_each(|(relation_symbol, equivalence)| {
+                let is_pending = |storage: &RelationStorage| {
+                    storage
+                        .inner
+                        .get(relation_symbol)
+                        .is_some_and(|facts| !facts.is_empty())
+                };
+                if is_saved
+                    && (is_pending(&self.unprocessed_insertions)
+                        || is_pending(&self.unprocessed_deletions))
+                {
+                    self.saved_equivalences
+                        .entry(relation_symbol.clone())
+                        .or_insert_with(|| equivalence.clone());
+                }
+                let removed_facts: IndexSet<AnonymousGroundAtom> = self
+                    .unprocessed_deletions
+                    .drain_relation(relation_symbol)
+                    .into_iter()
+                    .flat_map(|pair| equivalence.remove(&pair[0], &pair[1]))
+                    .collect();
+                let added_facts: IndexSet<AnonymousGroundAtom> = self
+                    .unprocessed_insertions
+                    .drain_relation(relation_symbol)
+                    .into_iter()
+                    .flat_map(|pair| equivalence.insert(&pair[0], &pair[1]))
+                    .collect();
+
+                let held_facts = self.processed.inner.get(relation_symbol);
+                let is_held = |fact: &AnonymousGroundAtom| {
+                    held_facts.is_some_and(|facts| facts.contains(fact))
+                };
+                let deletions: Vec<_> = removed_facts
+                    .iter()
+                    .filter(|fact| is_held(fact) && !added_facts.contains(*fact))
+                    .map(|fact| Arc::new(fact.clone()))
+                    .collect();
+                let insertions: Vec<_> = added_facts
+                    .into_iter()
+                    .filter(|fact| !is_held(fact))
+                    .map(Arc::new)
+                    .collect();
+
+                self.unprocessed_deletions
+                    .insert_registered(relation_symbol, deletions.into_iter());
+                self.unprocessed_insertions
+                    .insert_registered(relation_symbol, insertions.into_iter());
+            });
+    }
+
+    // Deletions that a failed evaluation did not get through are put back, to be processed by the
+    // next poll.
+    fn process_deletions(&mut self) -> Result<(), String> {
+        if !self.unprocessed_deletions.is_empty() {
+            let deletions: Vec<_> = self.unprocessed_deletions.drain_all_relations().collect();
+            deletions
+                .iter()
+                .for_each(|(relation_symbol, unprocessed_facts)| {
+                    unprocessed_facts.iter().for_each(|fact| {
+                        self.asserted.remove(relation_symbol, fact);
+                    });
+                    let mut overdeletion_symbol = relation_symbol.clone();
+                    add_prefix(&mut overdeletion_symbol, OVERDELETION_PREFIX);
+
+                    self.processed
+                        .insert_all(&overdeletion_symbol, unprocessed_facts.iter().cloned());
+                });
+
+            if let Err(error) = self.retract_overdeleted() {
+                deletions
+                    .into_iter()
+                    .for_each(|(relation_symbol, unprocessed_facts)| {
+                        self.unprocessed_deletions
+                            .insert_registered(&relation_symbol, unprocessed_facts.into_iter());
+                    });
+
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+    // Takes out everything that the facts in the overdeletion relations may have derived, and then
+    // puts back whatever can still be derived without them.
+    fn retract_overdeleted(&mut self) -> Result<(), String> {
+        semi_naive_evaluation(
+            &mut self.processed,
+            &self.stacks,
+            &self.nonrecursive_overdeletion_program,
+            &self.recursive_overdeletion_program,
+            self.options.intermediate_memory_limit,
+            &mut Tee(
+                &mut Tee(&mut self.statistics, &mut self.depths),
+                self.observer.as_mut(),
+            ),
+        )?;
+        self.processed.overdelete();
+
+        semi_naive_evaluation(
+            &mut self.processed,
+            &self.stacks,
+            &self.nonrecursive_rederivation_program,
+            &self.recursive_rederivation_program,
+            self.options.intermediate_memory_limit,
+            &mut Tee(
+                &mut Tee(&mut self.statistics, &mut self.depths),
+                self.observer.as_mut(),
+            ),
+        )?;
+        let rederived = self.processed.rederive();
+
+        // The rederivation program only looks one step ahead, hence facts whose remaining
+        // derivations go through other rederived facts are only recovered by the fixpoint.
+        let negated_relation_lengths = self.negated_relation_lengths();
+        self.materialize_strata_with_delta(&rederived)?;
+        self.check_negated_relation_growth(negated_relation_lengths);
+
+        // Overdeleted facts that were not rederived are gone for good.
+        self.processed
+            .inner
+            .iter()
+            .filter_map(|(symbol, facts)| {
+                symbol
+                    .strip_prefix(OVERDELETION_PREFIX)
+                    .map(|relation_symbol| (relation_symbol, facts))
+            })
+            .for_each(|(relation_symbol, overdeleted_facts)| {
+                let deleted_facts: Vec<_> = overdeleted_facts
+                    .iter()
+                    .filter(|fact| !self.processed.contains(relation_symbol, fact))
+                    .collect();
+
+                if let Some(epochs) = &mut self.epochs {
+                    epochs.forget(relation_symbol, deleted_facts.iter().copied());
+                }
+                if let Some(depths) = &mut self.depths {
+                    depths.forget(relation_symbol, deleted_facts.iter().copied());
+                }
+                self.metadata.forget(
+                    relation_symbol,
+                    deleted_facts.iter().map(|fact| fact.as_ref()),
+                );
+
+                if self.subscriptions.contains_key(relation_symbol) {
+                    let (insertions, deletions) =
+                        self.deltas.entry(relation_symbol.to_string()).or_default();
+                    deleted_facts.into_iter().for_each(|fact| {
+                        if !insertions.swap_remove(fact) {
+                            deletions.insert(fact.clone());
+                        }
+                    });
+                }
+            });
+
+        self.processed.clear_prefix(OVERDELETION_PREFIX);
+        self.processed.clear_prefix(REDERIVATION_PREFIX);
+
+        Ok(())
+    }
+
+    // The changes to the base relations are carried over to the derived ones in a single pass,
+    // and derived facts go once the last of their derivations does.
+    fn process_counted_changes(&mut self) {
+        let counts = self.counts.as_mut().unwrap();
+        let recount = std::mem::take(&mut self.rematerialize) || !counts.is_complete();
+        if !recount
+            && self.unprocessed_insertions.is_empty()
+            && self.unprocessed_deletions.is_empty()
+        {
+            return;
+        }
+
+        let insertions: HashMap<String, FactSet> = self
+            .unprocessed_insertions
+            .drain_all_relations()
+            .map(|(relation_symbol, facts)| (relation_symbol, facts.into_iter().collect()))
+            .collect();
+        let mut changes: HashMap<String, Delta> = HashMap::new();
+        // Facts that are both inserted and deleted stay, as they would with overdeletion, while
+        // deleting derived facts is left to their derivations.
+        self.unprocessed_deletions
+            .drain_all_relations()
+            .for_each(|(relation_symbol, facts)| {
+                if counts.derives(&relation_symbol) {
+                    return;
+                }
+                let reinserted = insertions.get(&relation_symbol);
+                let deletions: FactSet = facts
+                    .into_iter()
+                    .filter(|fact| self.processed.contains(&relation_symbol, fact))
+                    .filter(|fact| !reinserted.is_some_and(|facts| facts.contains(fact)))
+                    .collect();
+                changes.entry(relation_symbol).or_default().deletions = deletions;
+            });
+        insertions.into_iter().for_each(|(relation_symbol, facts)| {
+            let insertions: FactSet = facts
+                .into_iter()
+                .filter(|fact| !self.processed.contains(&relation_symbol, fact))
+                .collect();
+            changes.entry(relation_symbol).or_default().insertions = insertions;
+        });
+
+        if recount {
+            // Everything that the base relations are about to hold is new to counts that start
+            // from nothing.
+            counts.clear();
+            let mut base_relations: HashMap<String, Delta> = self
+                .processed
+                .inner
+                .iter()
+                .filter(|(relation_symbol, _)| {
+                    !is_internal(relation_symbol) && !counts.derives(relation_symbol)
+                })
+                .map(|(relation_symbol, facts)| {
+                    let change = changes.get(relation_symbol);
+                    let insertions = facts
+                        .iter()
+                        .filter(|fact| {
+                            !change.is_some_and(|change| change.deletions.contains(*fact))
+                        })
+                        .chain(
+                            change
+                                .into_iter()
+                                .flat_map(|change| change.insertions.iter()),
+                        )
+                        .cloned()
+                        .collect();
+
+                    (
+                        relation_symbol.clone(),
+                        Delta {
+                            insertions,
+                            deletions: Default::default(),
+                        },
+                    )
+                })
+                .collect();
+            counts.propagate(&RelationStorage::default(), &mut base_relations);
+
+            base_relations
+                .keys()
+                .filter(|relation_symbol| counts.derives(relation_symbol))
+                .for_each(|relation_symbol| {
+                    let facts = self
+                        .processed
+                        .inner
+                        .get(relation_symbol)
+                        .into_iter()
+                        .flatten();
+                    changes.insert(
+                        relation_symbol.clone(),
+                        Delta {
+                            insertions: counts
+                                .derived_facts(relation_symbol)
+                                .filter(|fact| !self.processed.contains(relation_symbol, fact))
+                                .cloned()
+                                .collect(),
+                            deletions: facts
+                                .filter(|fact| !counts.is_derived(relation_symbol, fact))
+                                .cloned()
+                                .collect(),
+                        },
+                    );
+                });
+        } else {
+            counts.propagate(&self.processed, &mut changes);
+        }
+
+        changes.into_iter().for_each(|(relation_symbol, change)| {
+            change.deletions.iter().for_each(|fact| {
+                self.processed.remove(&relation_symbol, fact);
+            });
+            self.processed
+                .insert_all(&relation_symbol, change.insertions.iter().cloned());
+
+            if let Some(epochs) = &mut self.epochs {
+                epochs.forget(&relation_symbol, change.deletions.iter());
+                epochs.tag(&relation_symbol, change.insertions.iter(), self.epoch);
+            }
+            self.metadata.forget(
+                &relation_symbol,
+                change.deletions.iter().map(|fact| fact.as_ref()),
+            );
+
+            if self.subscriptions.contains_key(&relation_symbol) {
+                let (insertions, deletions) = self.deltas.entry(relation_symbol).or_default();
+                change.deletions.into_iter().for_each(|fact| {
+                    if !insertions.swap_remove(&fact) {
+                        deletions.insert(fact);
+                    }
+                });
+                change.insertions.into_iter().for_each(|fact| {
+                    if !deletions.swap_remove(&fact) {
+                        insertions.insert(fact);
+                    }
+                });
+            }
+        });
+    }
+
+    // Every component only starts once all of those before it, which it may read or negate, are
+    // complete.
+    fn materialize_strata(&mut self) -> Result<(), String> {
+        self.materialize_strata_with_delta(&HashMap::new())
+    }
+
+    fn materialize_strata_with_delta(
+        &mut self,
+        delta: &HashMap<String, FactStorage>,
+    ) -> Result<(), String> {
+        self.strata
+            .iter()
+            .try_for_each(|(nonrecursive_program, recursive_program)| {
+                semi_naive_evaluation_with_delta(
+                    &mut self.processed,
+                    &self.stacks,
+                    nonrecursive_program,
+                    recursive_program,
+                    delta,
+                    self.options.intermediate_memory_limit,
+                    &mut Tee(
+                        &mut Tee(&mut self.statistics, &mut self.depths),
+                        self.observer.as_mut(),
+                    ),
+                )
+            })
+    }
+
+    fn process_insertions(&mut self) -> Result<(), String> {
+        if std::mem::take(&mut self.rematerialize) || !self.unprocessed_insertions.is_empty() {
+            // Insertions only ever append to the processed relations, so whatever lies past the
+            // previous length was derived during this poll.
+            let previous_lengths: Vec<_> = self
+                .processed
+                .inner
+                .iter()
+                .filter(|(symbol, _)| !is_internal(symbol))
+                .map(|(symbol, facts)| (symbol.clone(), facts.len()))
+                .collect();
+
+            let negated_relation_lengths = self.negated_relation_lengths();
+
+            // Additions
+            self.unprocessed_insertions.drain_all_relations().for_each(
+                |(relation_symbol, unprocessed_facts)| {
+                    let is_negating = self
+                        .negating_program
+                        .inner
+                        .iter()
+                        .any(|rule| rule.head.symbol == relation_symbol);
+                    if is_negating {
+                        self.asserted
+                            .insert_all(&relation_symbol, unprocessed_facts.iter().cloned());
+                    }
+                    // And in their respective place
+                    self.processed
+                        .insert_registered(&relation_symbol, unprocessed_facts.into_iter());
+                },
+            );
+
+            self.materialize_strata()?;
+            self.check_negated_relation_growth(negated_relation_lengths);
+
+            previous_lengths
+                .into_iter()
+                .for_each(|(relation_symbol, previous_length)| {
+                    let facts = self.processed.get_relation(&relation_symbol);
+
+                    if let Some(epochs) = &mut self.epochs {
+                        epochs.tag(
+                            &relation_symbol,
+                            facts.iter().skip(previous_length),
+                            self.epoch,
+                        );
+                    }
+
+                    if self.subscriptions.contains_key(&relation_symbol) {
+                        let (insertions, deletions) =
+                            self.deltas.entry(relation_symbol).or_default();
+                        facts.iter().skip(previous_length).for_each(|fact| {
+                            if !deletions.swap_remove(fact) {
+                                insertions.insert(fact.clone());
+                            }
+                        });
+                    }
+                });
+        }
+
+        Ok(())
+    }
+
+    // How many facts each negated relation holds, to tell later whether any has grown.
+    fn negated_relation_lengths(&self) -> Vec<(String, usize)> {
+        self.negated_relations
+            .iter()
+            .map(|relation_symbol| {
+                (
+                    relation_symbol.clone(),
+                    self.processed.get_relation(relation_symbol).len(),
+                )
+            })
+            .collect()
+    }
+    // Relations only ever grow by being appended to, for as long as nothing is deleted.
+    fn check_negated_relation_growth(&mut self, previous_lengths: Vec<(String, usize)>) {
+        self.negations_stale |=
+            previous_lengths
+                .into_iter()
+                .any(|(relation_symbol, previous_length)| {
+                    self.processed.get_relation(&relation_symbol).len() > previous_length
+                });
+    }
+    // Facts that were derived while a negated relation did not hold what it now does are deleted
+    // if their rules no longer derive them, and they were not inserted. Deleting them takes care of
+    // whatever was derived from them in turn.
+    fn stage_negations(&mut self) -> Result<bool, String> {
+        if !std::mem::take(&mut self.negations_stale) {
+            return Ok(false);
+        }
+
+        let mut derivable: IndexMap<String, HashSet<AnonymousGroundAtom>> = IndexMap::new();
+        for rule in &self.negating_program.inner {
+            derivable
+                .entry(rule.head.symbol.clone())
+                .or_default()
+                .extend(
+                    RuleEvaluator::new(&self.processed, rule, self.stacks.get(rule)?)
+                        .step(&mut IndexStorage::default())?,
+                );
+        }
+
+        let mut changed = false;
+        derivable.into_iter().for_each(|(relation_symbol, facts)| {
+            let stale: Vec<_> = self
+                .processed
+                .get_relation(&relation_symbol)
+                .iter()
+                .filter(|fact| {
+                    !facts.contains(&***fact) && !self.asserted.contains(&relation_symbol, fact)
+                })
+                .cloned()
+                .collect();
+
+            changed |= !stale.is_empty();
+            self.unprocessed_deletions
+                .insert_registered(&relation_symbol, stale.into_iter());
+        });
+
+        Ok(changed)
+    }
+    // Stages the difference between what the aggregate rules yield and what their heads hold,
+    // returning whether there was any.
+    fn stage_aggregates(&mut self) -> Result<bool, String> {
+        let mut aggregates: IndexMap<String, IndexSet<AnonymousGroundAtom>> = IndexMap::new();
+
+        for rule in &self.aggregate_program.inner {
+            if self.lattice.derives(&rule.head.symbol) {
+                continue;
+            }
+            let evaluation = RuleEvaluator::new(&self.processed, rule, self.stacks.get(rule)?)
+                .step(&mut IndexStorage::default())?;
+
+            aggregates
+                .entry(rule.head.symbol.clone())
+                .or_default()
+                .extend(evaluation);
+        }
+        aggregates.extend(self.lattice.evaluate(
+            &self.processed,
+            &self.stacks,
+            self.options.intermediate_memory_limit,
+        )?);
+
+        let mut changed = false;
+        aggregates.into_iter().for_each(|(relation_symbol, facts)| {
+            let current_relation = self.processed.get_relation(&relation_symbol);
+
+            let stale: Vec<_> = current_relation
+                .iter()
+                .filter(|fact| !facts.contains(&***fact))
+                .cloned()
+                .collect();
+            let fresh: Vec<_> = facts
+                .into_iter()
+                .filter(|fact| !current_relation.contains(fact))
+                .collect();
+
+            changed |= !stale.is_empty() || !fresh.is_empty();
+
+            self.unprocessed_deletions
+                .insert_registered(&relation_symbol, stale.into_iter());
+            fresh.into_iter().for_each(|fact| {
+                self.unprocessed_insertions.insert(&relation_symbol, fact);
+            });
+        });
+
+        Ok(changed)
+    }
+
+    pub fn new(program: Program) -> Self {
+        Self::with_options(program, RuntimeOptions::default())
+    }
+    pub fn try_new(program: Program) -> Result<Self, String> {
+        Self::try_with_options(program, RuntimeOptions::default())
+    }
+    pub fn with_options(program: Program, options: RuntimeOptions) -> Self {
+        Self::try_with_options(program, options).unwrap_or_else(|error| panic!("{}", error))
+    }
+    // As with_options, but a program that can not be evaluated is returned as an error rather
+    // than panicked on.
+    pub fn try_with_options(program: Program, options: RuntimeOptions) -> Result<Self, String> {
+        let mut processed = RelationStorage {
+            interner: options.share_facts.then(FactInterner::default),
+            ..Default::default()
+        };
+        let mut unprocessed_insertions: RelationStorage = Default::default();
+        let mut unprocessed_deletions: RelationStorage = Default::default();
+
+        let mut relations = IndexSet::new();
+        let mut overdeletion_relations = IndexSet::new();
+        let mut rederive_relations = IndexSet::new();
+        let mut arities = HashMap::new();
+
+        for rule in &program.inner {
+            // Negated atoms only rule facts out, so there has to be something for them to rule out.
+            if !rule.body.iter().any(|body_atom| body_atom.sign) {
+                return Err(format!("{:?} has no positive body atom", rule));
+            }
+            // Head variables that the body does not bind are existential, but those of negated
+            // atoms would have the negation range over every value there is.
+            let existential_variables = get_existential_variables(rule);
+            if let Some(variable) = rule
+                .unsafe_variables()
+                .into_iter()
+                .find(|variable| !existential_variables.contains(variable))
+            {
+                return Err(format!(
+                    "{:?} negates over variable {} that no positive body atom binds",
+                    rule, variable
+                ));
+            }
+            if rule
+                .body
+                .iter()
+                .flat_map(|body_atom| &body_atom.terms)
+                .any(|term| matches!(term, Term::Expression(_)))
+            {
+                return Err(format!("{:?} computes a term outside of its head", rule));
+            }
+            let is_computed = |variable: &&Variable| {
+                rule.head.terms.iter().any(|term| {
+                    matches!(term, Term::Expression(expression) if expression.variables().contains(variable))
+                })
+            };
+            if let Some(variable) = get_existential_variables(rule)
+                .into_iter()
+                .find(|variable| !options.skolemize || rule.is_aggregate() || is_computed(variable))
+            {
+                return Err(format!(
+                    "{:?} has head variable {} that its body does not bind",
+                    rule, variable
+                ));
+            }
+            // The planner only moves atoms that share no variable with those before them, which
+            // a rule pinned to its join order can not have.
+            if rule.hints.no_reorder {
+                let mut bound = HashSet::new();
+                for (position, body_atom) in rule
+                    .body
+                    .iter()
+                    .filter(|body_atom| body_atom.sign)
+                    .enumerate()
+                {
+                    let variables: Vec<_> = body_atom
+                        .terms
+                        .iter()
+                        .filter(|term| matches!(term, Term::Variable(name) if name != "_"))
+                        .collect();
+                    if position > 0 && !variables.iter().any(|term| bound.contains(term)) {
+                        return Err(format!(
+                            "{:?} can not keep its join order, as {} shares no variable with the \
+                             atoms before it",
+                            rule, body_atom.symbol
+                        ));
+                    }
+                    bound.extend(variables);
+                }
+            }
+            // Every column that a rule reads has to be there in every fact of the relation.
+            for atom in std::iter::once(&rule.head).chain(&rule.body) {
+                let arity = *arities
+                    .entry(atom.symbol.clone())
+                    .or_insert(atom.terms.len());
+                if arity != atom.terms.len() {
+                    return Err(format!(
+                        "{} is used with {} terms in {:?}, but with {} elsewhere",
+                        atom.symbol,
+                        atom.terms.len(),
+                        rule,
+                        arity
+                    ));
+                }
+            }
+
+            relations.insert(&rule.head.symbol);
+            overdeletion_relations.insert(format!("{}{}", OVERDELETION_PREFIX, rule.head.symbol));
+            rederive_relations.insert(format!("{}{}", REDERIVATION_PREFIX, rule.head.symbol));
+            rule.body.iter().for_each(|body_atom| {
+                relations.insert(&body_atom.symbol);
+                overdeletion_relations
+                    .insert(format!("{}{}", OVERDELETION_PREFIX, body_atom.symbol));
+            })
+        }
+
+        for rule in &program.inner {
+            for (relation_symbol, columns) in &rule.hints.indices {
+                let arity = arities.get(relation_symbol).copied().unwrap_or(usize::MAX);
+                if let Some(column) = columns.iter().find(|&&column| column >= arity) {
+                    return Err(format!(
+                        "{:?} asks for an index over column {} of {}, which has {} terms",
+                        rule, column, relation_symbol, arity
+                    ));
+                }
+            }
+        }
+
+        relations.iter().for_each(|relation_symbol| {
+            processed
+                .inner
+                .entry(relation_symbol.to_string())
+                .or_default();
+
+            unprocessed_insertions
+                .inner
+                .entry(relation_symbol.to_string())
+                .or_default();
+
+            unprocessed_deletions
+                .inner
+                .entry(relation_symbol.to_string())
+                .or_default();
+        });
+
+        overdeletion_relations.iter().for_each(|relation_symbol| {
+            processed
+                .inner
+                .entry(relation_symbol.to_string())
+                .or_default();
+        });
+
+        rederive_relations.iter().for_each(|relation_symbol| {
+            processed
+                .inner
+                .entry(relation_symbol.to_string())
+                .or_default();
+        });
+
+        // Aggregate rules, and those of relations that aggregates depend on them through, are
+        // evaluated in full, so the rest are the only ones to be maintained incrementally.
+        let lattice = Lattice::new(&program)?;
+        let (aggregate_rules, incremental_rules): (Vec<_>, Vec<_>) = program
+            .inner
+            .iter()
+            .cloned()
+            .partition(|rule| rule.is_aggregate() || lattice.derives(&rule.head.symbol));
+        let aggregate_program = sort_program(&Program::from(aggregate_rules));
+        let incremental_program = Program::from(incremental_rules);
+
+        let strata: Vec<(Program, Program)> = stratify_program(&incremental_program)
+            .ok_or("negation must be stratified")?
+            .iter()
+            .flat_map(split_components)
+            .collect();
+
+        let overdeletion_program = make_overdeletion_program(&incremental_program);
+        let (nonrecursive_overdeletion_program, recursive_overdeletion_program) =
+            split_program(overdeletion_program);
+
+        let rederivation_program = make_rederivation_program(&incremental_program);
+        let (nonrecursive_rederivation_program, recursive_rederivation_program) =
+            split_program(rederivation_program);
+
+        let nonrecursive_overdeletion_program = sort_program(&nonrecursive_overdeletion_program);
+        let nonrecursive_rederivation_program = sort_program(&nonrecursive_rederivation_program);
+
+        let negated_relations: HashSet<String> = incremental_program
+            .inner
+            .iter()
+            .flat_map(|rule| rule.body.iter())
+            .filter(|body_atom| !body_atom.sign)
+            .map(|body_atom| body_atom.symbol.clone())
+            .collect();
+        let negating_relations: HashSet<&String> = incremental_program
+            .inner
+            .iter()
+            .filter(|rule| rule.body.iter().any(|body_atom| !body_atom.sign))
+            .map(|rule| &rule.head.symbol)
+            .collect();
+        let negating_program = Program::from(
+            incremental_program
+                .inner
+                .iter()
+                .filter(|rule| negating_relations.contains(&rule.head.symbol))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+
+        let types = TypeSignature::infer(&program)?;
+        let counts = options
+            .count_derivations
+            .then(|| DerivationCounts::new(&program))
+            .transpose()?;
+        if counts.is_some() && options.record_depths {
+            return Err("depths can not be recorded while derivations are counted".to_string());
+        }
+
+        let stacks = StackCache::new(
+            strata
+                .iter()
+                .flat_map(|(nonrecursive_program, recursive_program)| {
+                    [nonrecursive_program, recursive_program]
+                })
+                .chain([
+                    &nonrecursive_overdeletion_program,
+                    &recursive_overdeletion_program,
+                    &nonrecursive_rederivation_program,
+                    &recursive_rederivation_program,
+                    &aggregate_program,
+                    &negating_program,
+                ])
+                .chain(lattice.programs()),
+            options.generic_joins,
+        );
+
+        let next_rule_id = program
+            .inner
+            .iter()
+            .map(|rule| rule.id + 1)
+            .max()
+            .unwrap_or(0);
+        let mut runtime = Self {
+            processed,
+            unprocessed_insertions,
+            unprocessed_deletions,
+            program,
+            next_rule_id,
+            strata,
+            nonrecursive_overdeletion_program,
+            recursive_overdeletion_program,
+            nonrecursive_rederivation_program,
+            recursive_rederivation_program,
+            aggregate_program,
+            lattice,
+            negating_program,
+            asserted: Default::default(),
+            negated_relations,
+            negations_stale: false,
+            stacks,
+            epoch: 0,
+            epochs: options.record_epochs.then(EpochStorage::default),
+            metadata: Default::default(),
+            observer: Box::new(()),
+            statistics: Default::default(),
+            depths: options.record_depths.then(DepthRecorder::default),
+            subscriptions: HashMap::new(),
+            deltas: IndexMap::new(),
+            options,
+            rematerialize: false,
+            arities,
+            reader: None,
+            counts,
+            types,
+            taken_deltas: HashSet::new(),
+            created_indices: IndexSet::new(),
+            scenarios: HashMap::new(),
+            saved_equivalences: HashMap::new(),
+        };
+        runtime.register_indices();
+
+        Ok(runtime)
+    }
+    // Joins probe these instead of hashing the relations they read all over again on every poll.
+    fn needed_indices(&self) -> impl Iterator<Item = (String, Vec<usize>)> + '_ {
+        let programs = self
+            .strata
+            .iter()
+            .flat_map(|(nonrecursive_program, recursive_program)| {
+                [nonrecursive_program, recursive_program]
+            })
+            .chain([
+                &self.nonrecursive_overdeletion_program,
+                &self.recursive_overdeletion_program,
+                &self.nonrecursive_rederivation_program,
+                &self.recursive_rederivation_program,
+                &self.aggregate_program,
+            ]);
+
+        programs
+            .flat_map(|program| program.inner.iter())
+            .flat_map(get_join_indices)
+            .chain(self.counts.iter().flat_map(DerivationCounts::lookups))
+    }
+    fn register_indices(&mut self) {
+        let indices: Vec<_> = self
+            .needed_indices()
+            .chain(self.created_indices.iter().cloned())
+            .collect();
+
+        indices.into_iter().for_each(|(relation_symbol, columns)| {
+            self.processed.register_index(&relation_symbol, columns)
+        });
+    }
+    // The callback gets told, after every poll that changed the relation, what the change was.
+    pub fn subscribe(&mut self, relation: &str, callback: impl FnMut(&RelationDelta) + 'static) {
+        self.subscriptions
+            .entry(relation.to_string())
+            .or_default()
+            .push(Box::new(callback));
+    }
+    fn notify_subscriptions(&mut self) {
+        self.deltas
+            .drain(..)
+            .for_each(|(relation_symbol, (insertions, deletions))| {
+                if insertions.is_empty() && deletions.is_empty() {
+                    return;
+                }
+
+                let delta = RelationDelta {
+                    insertions: insertions.into_iter().collect(),
+                    deletions: deletions.into_iter().collect(),
+                };
+                self.subscriptions
+                    .get_mut(&relation_symbol)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|callback| callback(&delta));
+            });
+    }
+    pub fn set_observer(&mut self, observer: impl EvaluationObserver + 'static) {
+        self.observer = Box::new(observer);
+    }
+    pub fn safe(&self) -> bool {
+        self.unprocessed_insertions.is_empty()
+            && self.unprocessed_deletions.is_empty()
+            && !self.rematerialize
+    }
+}
+
+// The relation along with every relation that some rule derives from it, however indirectly.
+fn downstream_relations(program: &Program, relation_symbol: &str) -> HashSet<String> {
+    let mut relations = HashSet::new();
+    relations.insert(relation_symbol.to_string());
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in &program.inner {
+            if rule
+                .body
+                .iter()
+                .any(|body_atom| relations.contains(&body_atom.symbol))
+            {
+                changed |= relations.insert(rule.head.symbol.clone());
+            }
+        }
+    }
+
+    relations
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::datalog::{MicroRuntime, QueryBudget, RuntimeOptions};
+    use crate::engine::delimited::ColumnType;
+    use crate::engine::explain::{Strategy, StrategyComparison};
+    use crate::engine::metadata::FactMetadata;
+    use crate::engine::observer::EvaluationObserver;
+    use crate::engine::rdf::{rdfs, ENTAILED, RDF_TYPE, TRIPLE};
+    use crate::evaluation::spj_processor::Instruction;
+    use crate::helpers::helpers::OVERDELETION_PREFIX;
+    use datalog_rule_macro::{program, rule, stratified_program, Relation};
+    use datalog_syntax::*;
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn integration_test_insertions_only() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+        ]
+        .into_iter()
+        .for_each(|edge| {
+            runtime.insert("e", edge).unwrap();
+        });
+
+        runtime.poll();
+
+        // This query reads as: "Get all in tc with any values in any positions"
+        let all = build_query!(tc(_, _));
+        // And this one as: "Get all in tc with the first term being a"
+        // There also is a QueryBuilder, if you do not want to use a macro.
+        let all_from_a = build_query!(tc("a", _));
+
+        let actual_all: HashSet<AnonymousGroundAtom> = runtime.query(&all).unwrap().collect();
+        let expected_all: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+            // Second iter
+            vec!["a".into(), "c".into()],
+            vec!["b".into(), "d".into()],
+            // Third iter
+            vec!["a".into(), "d".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all, actual_all);
+
+        let actual_all_from_a: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all_from_a).unwrap().collect();
+        let expected_all_from_a: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all_from_a, actual_all_from_a);
+
+        expected_all.iter().for_each(|fact| {
+            assert!(runtime.contains("tc", fact).unwrap());
+        });
+
+        expected_all_from_a.iter().for_each(|fact| {
+            assert!(runtime.contains("tc", fact).unwrap());
+        });
+
+        // Update
+        runtime.insert("e", vec!["d".into(), "e".into()]).unwrap();
+        assert!(!runtime.safe());
+        runtime.poll();
+        assert!(runtime.safe());
+
+        let actual_all_after_update: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all).unwrap().collect();
+        let expected_all_after_update: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+            // Second iter
+            vec!["a".into(), "c".into()],
+            vec!["b".into(), "d".into()],
+            // Third iter
+            vec!["a".into(), "d".into()],
+            // Update
+            vec!["d".into(), "e".into()],
+            vec!["c".into(), "e".into()],
+            vec!["b".into(), "e".into()],
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all_after_update, actual_all_after_update);
+
+        let actual_all_from_a_after_update: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all_from_a).unwrap().collect();
+        let expected_all_from_a_after_update: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            expected_all_from_a_after_update,
+            actual_all_from_a_after_update
+        );
+    }
+    #[test]
+    fn integration_test_insertions_nonlinear() {
+        let all = build_query!(tc(_, _));
+
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        (0..20usize).for_each(|node| {
+            runtime
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        });
+        runtime.poll();
+        assert_eq!(210, runtime.query(&all).unwrap().count());
+
+        // New edges have to be joined with the paths derived by the previous poll on both sides.
+        (20..25usize).for_each(|node| {
+            runtime
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        });
+        runtime
+            .insert("e", vec![100usize.into(), 0usize.into()])
+            .unwrap();
+        runtime.poll();
+        assert_eq!(351, runtime.query(&all).unwrap().count());
+        assert!(runtime
+            .contains("tc", &vec![100usize.into(), 25usize.into()])
+            .unwrap());
+    }
+    #[test]
+    fn integration_test_deletions() {
+        // Queries. The explanation is in the test above
+        let all = build_query!(tc(_, _));
+        let all_from_a = build_query!(tc("a", _));
+
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![
+            vec!["a".into(), "b".into()],
+            // this extra atom will help with testing that rederivation works
+            vec!["a".into(), "e".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+            vec!["d".into(), "e".into()],
+        ]
+        .into_iter()
+        .for_each(|edge| {
+            runtime.insert("e", edge).unwrap();
+        });
+
+        runtime.poll();
+
+        let actual_all: HashSet<AnonymousGroundAtom> = runtime.query(&all).unwrap().collect();
+        let expected_all: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "e".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+            // Second iter
+            vec!["a".into(), "c".into()],
+            vec!["b".into(), "d".into()],
+            // Third iter
+            vec!["a".into(), "d".into()],
+            // Fourth iter
+            vec!["d".into(), "e".into()],
+            vec!["c".into(), "e".into()],
+            vec!["b".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all, actual_all);
+
+        let actual_all_from_a: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all_from_a).unwrap().collect();
+        let expected_all_from_a: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all_from_a, actual_all_from_a);
+
+        // Update
+        // Point removals are a bit annoying, since they incur creating a query.
+        let d_to_e = build_query!(e("d", "e"));
+        runtime.remove(&d_to_e);
+        assert!(!runtime.safe());
+        runtime.poll();
+        assert!(runtime.safe());
+
+        let actual_all_after_update: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all).unwrap().collect();
+        let expected_all_after_update: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), "d".into()],
+            // Second iter
+            vec!["a".into(), "c".into()],
+            vec!["b".into(), "d".into()],
+            // Third iter
+            vec!["a".into(), "d".into()],
+            // This remains
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_all_after_update, actual_all_after_update);
+
+        let actual_all_from_a_after_update: HashSet<AnonymousGroundAtom> =
+            runtime.query(&all_from_a).unwrap().collect();
+        let expected_all_from_a_after_update: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            expected_all_from_a_after_update,
+            actual_all_from_a_after_update
+        );
+    }
+
+    #[test]
+    fn integration_test_stratified_evaluation() {
+        let stratified_program = program! {
+            // Stratum 1: Base rule
+            base(?x, ?y) <- [edge(?x, ?y)],
+
+            // Stratum 2: Derived rule depends on Stratum 1
+            derived(?x, ?y) <- [base(?x, ?y)],
+            derived(?x, ?z) <- [base(?x, ?y), derived(?y, ?z)],
+
+            // Stratum 3: Another level of derivation
+            top(?x, ?z) <- [derived(?x, ?y), base(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(stratified_program);
+
+        // Insert facts into the base layer (Stratum 1)
+        vec![vec!["a".into(), "b".into()], vec!["b".into(), "c".into()]]
+            .into_iter()
+            .for_each(|edge| {
+                runtime.insert("edge", edge).unwrap();
+            });
+
+        runtime.poll();
+
+        // Query and assert expectations for each stratum
+        // Expected results for Stratum 1: `base`
+        let base_query = build_query!(base(_, _));
+        let actual_base: HashSet<AnonymousGroundAtom> =
+            runtime.query(&base_query).unwrap().collect();
+        let expected_base: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into(), "b".into()], vec!["b".into(), "c".into()]]
+                .into_iter()
+                .collect();
+        assert_eq!(expected_base, actual_base);
+
+        // Expected results for Stratum 2: `derived`
+        let derived_query = build_query!(derived(_, _));
+        let actual_derived: HashSet<AnonymousGroundAtom> =
+            runtime.query(&derived_query).unwrap().collect();
+        let expected_derived: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["a".into(), "c".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_derived, actual_derived);
+
+        // Expected results for Stratum 3: `top`
+        let top_query = build_query!(top(_, _));
+        let actual_top: HashSet<AnonymousGroundAtom> = runtime.query(&top_query).unwrap().collect();
+        let expected_top: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into(), "c".into()]].into_iter().collect();
+        assert_eq!(expected_top, actual_top);
+
+        // Test deletions to check if stratified rederivation works correctly
+        let edge_b_to_c = build_query!(edge("b", "c"));
+        runtime.remove(&edge_b_to_c);
+        runtime.poll();
+
+        // After deletion, only certain derived facts should remain
+        let actual_derived_after_delete: HashSet<AnonymousGroundAtom> =
+            runtime.query(&derived_query).unwrap().collect();
+        let expected_derived_after_delete: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into(), "b".into()]].into_iter().collect();
+        assert_eq!(expected_derived_after_delete, actual_derived_after_delete);
+
+        let actual_top_after_delete: HashSet<AnonymousGroundAtom> =
+            runtime.query(&top_query).unwrap().collect();
+        let expected_top_after_delete: HashSet<AnonymousGroundAtom> = HashSet::new();
+        assert_eq!(expected_top_after_delete, actual_top_after_delete);
+    }
+
+    #[test]
+    fn integration_test_query_new_since() {
+        let all = build_query!(tc(_, _));
+
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            record_epochs: true,
+            ..Default::default()
+        };
+
+        // Epochs are only kept when asked for.
+        let mut runtime = MicroRuntime::new(tc_program.clone());
+        runtime.poll();
+        assert!(runtime.query_new_since(&all, 0).is_err());
+        assert!(runtime.take_delta("tc").is_err());
+
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+
+        let first_read = runtime.epoch();
+        let actual_new: HashSet<AnonymousGroundAtom> =
+            runtime.query_new_since(&all, 0).unwrap().collect();
+        let expected_new: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["a".into(), "c".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_new, actual_new);
+        assert_eq!(
+            0,
+            runtime.query_new_since(&all, first_read).unwrap().count()
+        );
+
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.poll();
+
+        let actual_new: HashSet<AnonymousGroundAtom> =
+            runtime.query_new_since(&all, first_read).unwrap().collect();
+        let expected_new: HashSet<AnonymousGroundAtom> = vec![
+            vec!["c".into(), "d".into()],
+            vec!["b".into(), "d".into()],
+            vec!["a".into(), "d".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_new, actual_new);
+
+        // Deleting and rederiving a fact does not make it new again, but a fact that is deleted
+        // and later derived anew does count as new.
+        let second_read = runtime.epoch();
+        runtime.insert("e", vec!["a".into(), "c".into()]).unwrap();
+        runtime.poll();
+        runtime.remove(&build_query!(e("a", "c")));
+        runtime.poll();
+        assert_eq!(
+            0,
+            runtime.query_new_since(&all, second_read).unwrap().count()
+        );
+
+        let third_read = runtime.epoch();
+        runtime.remove(&build_query!(e("c", "d")));
+        runtime.poll();
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.poll();
+        let actual_new: HashSet<AnonymousGroundAtom> =
+            runtime.query_new_since(&all, third_read).unwrap().collect();
+        let expected_new: HashSet<AnonymousGroundAtom> = vec![
+            vec!["c".into(), "d".into()],
+            vec!["b".into(), "d".into()],
+            vec!["a".into(), "d".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_new, actual_new);
+    }
+
+    #[test]
+    fn integration_test_compaction_between_polls() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        (0..100usize).for_each(|node| {
+            runtime
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        });
+        runtime.poll();
+
+        // The drained insertion buffer must not hold on to the capacity of the batch.
+        assert_eq!(
+            0,
+            runtime
+                .unprocessed_insertions
+                .inner
+                .get("e")
+                .unwrap()
+                .capacity()
+        );
+
+        runtime.remove(&build_query!(e(50usize, 51usize)));
+        runtime.poll();
+
+        runtime
+            .processed
+            .inner
+            .iter()
+            .filter(|(symbol, _)| symbol.starts_with(OVERDELETION_PREFIX))
+            .for_each(|(_, relation)| assert_eq!(0, relation.capacity()));
+        let tc = runtime.processed.get_relation("tc");
+        assert!(tc.capacity() <= tc.len() * 2);
+
+        // Nor must the indices hold on to the buckets of the keys that were taken away.
+        runtime.create_index("tc", &[0]).unwrap();
+        let buckets = |runtime: &MicroRuntime| {
+            let index = runtime.processed.get_index("tc", &[0]).unwrap();
+            (index.buckets.len(), index.buckets.capacity())
+        };
+        let (_, capacity_before) = buckets(&runtime);
+        (0..96usize).for_each(|node| {
+            let next = node + 1;
+            runtime.remove(&build_query!(e(node, next)));
+        });
+        runtime.poll();
+
+        let (len, capacity) = buckets(&runtime);
+        assert_eq!(4, len);
+        assert!(capacity < capacity_before);
+        assert!(capacity <= len * 2);
+    }
+
+    #[test]
+    fn integration_test_aggregation() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            reach_count(?x, count(?y)) <- [tc(?x, ?y)],
+            reaches_two(?x) <- [reach_count(?x, ?n), two(?n)],
+            weight_stats(?x, sum(?w), min(?w), max(?w)) <- [w(?x, ?w)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.insert("two", vec![2usize.into()]).unwrap();
+        runtime
+            .insert("w", vec!["a".into(), TypedValue::SignedInt(-3)])
+            .unwrap();
+        runtime
+            .insert("w", vec!["a".into(), TypedValue::SignedInt(5)])
+            .unwrap();
+        runtime.insert("w", vec!["b".into(), 1.5.into()]).unwrap();
+        runtime.poll();
+
+        let reach_count = build_query!(reach_count(_, _));
+        let reaches_two = build_query!(reaches_two(_));
+        let actual_counts: HashSet<_> = runtime.query(&reach_count).unwrap().collect();
+        let expected_counts: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), 2usize.into()],
+            vec!["b".into(), 1usize.into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_counts, actual_counts);
+        let actual_reaches_two: HashSet<_> = runtime.query(&reaches_two).unwrap().collect();
+        let expected_reaches_two: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into()]].into_iter().collect();
+        assert_eq!(expected_reaches_two, actual_reaches_two);
+
+        let actual_stats: HashSet<_> = runtime
+            .query(&build_query!(weight_stats(_, _, _, _)))
+            .unwrap()
+            .collect();
+        let expected_stats: HashSet<AnonymousGroundAtom> = vec![
+            vec![
+                "a".into(),
+                TypedValue::SignedInt(2),
+                TypedValue::SignedInt(-3),
+                TypedValue::SignedInt(5),
+            ],
+            vec!["b".into(), 1.5.into(), 1.5.into(), 1.5.into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_stats, actual_stats);
+
+        // Stale aggregates are retracted, along with whatever was derived from them.
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.poll();
+        let actual_counts: HashSet<_> = runtime.query(&reach_count).unwrap().collect();
+        let expected_counts: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), 3usize.into()],
+            vec!["b".into(), 2usize.into()],
+            vec!["c".into(), 1usize.into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_counts, actual_counts);
+        let actual_reaches_two: HashSet<_> = runtime.query(&reaches_two).unwrap().collect();
+        let expected_reaches_two: HashSet<AnonymousGroundAtom> =
+            vec![vec!["b".into()]].into_iter().collect();
+        assert_eq!(expected_reaches_two, actual_reaches_two);
+
+        runtime.remove(&build_query!(e("a", "b")));
+        runtime.poll();
+        let actual_counts: HashSet<_> = runtime.query(&reach_count).unwrap().collect();
+        let expected_counts: HashSet<AnonymousGroundAtom> = vec![
+            vec!["b".into(), 2usize.into()],
+            vec!["c".into(), 1usize.into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_counts, actual_counts);
+    }
+
+    #[test]
+    fn integration_test_recursive_aggregation() {
+        let program = program! {
+            shortest(?x, min(?d)) <- [source(?x, ?d)],
+            shortest(?y, min(?d)) <- [reachable(?y, ?d)],
+            reachable(?y, ?e) <- [shortest(?x, ?d), e(?x, ?y, ?w)], ?e = ?d + ?w,
+            close(?x) <- [shortest(?x, ?d), short(?d)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert("source", vec!["a".into(), 0usize.into()])
+            .unwrap();
+        runtime.insert("short", vec![1usize.into()]).unwrap();
+        for (from, to, weight) in [
+            ("a", "b", 5usize),
+            ("a", "c", 1),
+            ("c", "b", 1),
+            ("b", "d", 1),
+        ] {
+            runtime
+                .insert("e", vec![from.into(), to.into(), weight.into()])
+                .unwrap();
+        }
+        runtime.poll();
+
+        let shortest = |runtime: &MicroRuntime| {
+            let mut facts: Vec<_> = runtime
+                .query(&build_query!(shortest(_, _)))
+                .unwrap()
+                .collect();
+            facts.sort();
+            facts
+        };
+        let path = |node: &str, distance: usize| -> AnonymousGroundAtom {
+            vec![node.into(), distance.into()]
+        };
+        assert_eq!(
+            vec![path("a", 0), path("b", 2), path("c", 1), path("d", 3)],
+            shortest(&runtime)
+        );
+        let close: Vec<_> = runtime.query(&build_query!(close(_))).unwrap().collect();
+        assert_eq!(vec![vec![TypedValue::from("c")]], close);
+
+        // Shorter paths improve on the ones there were, and taking them away brings those back.
+        runtime
+            .insert("e", vec!["a".into(), "d".into(), 1usize.into()])
+            .unwrap();
+        runtime.poll();
+        assert_eq!(
+            vec![path("a", 0), path("b", 2), path("c", 1), path("d", 1)],
+            shortest(&runtime)
+        );
+
+        runtime.remove(&build_query!(e("a", "c", _)));
+        runtime.remove(&build_query!(e("a", "d", _)));
+        runtime.poll();
+        assert_eq!(
+            vec![path("a", 0), path("b", 5), path("d", 6)],
+            shortest(&runtime)
+        );
+        assert!(runtime
+            .query(&build_query!(reachable("d", 3usize)))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "which only a single min or max can")]
+    fn test_recursive_count() {
+        MicroRuntime::new(Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { tc(?x, count(?y)) <- [tc(?x, ?y)] },
+        ]));
+    }
+
+    #[test]
+    fn integration_test_choice() {
+        let program = program! {
+            reach(?x) <- [root(?x)],
+            reach(?y) <- [reach(?x), e(?x, ?y)],
+            parent(?y, choice(?x)) <- [reach(?x), e(?x, ?y)],
+            cheapest(?item, min(?price), choice(?shop)) <- [offer(?item, ?shop, ?price)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime.insert("root", vec![1usize.into()]).unwrap();
+        for (from, to) in [(1usize, 2usize), (1, 3), (2, 4), (3, 4)] {
+            runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+        }
+        for (shop, price) in [("b", 3usize), ("a", 3), ("c", 5)] {
+            runtime
+                .insert("offer", vec!["milk".into(), shop.into(), price.into()])
+                .unwrap();
+        }
+        runtime.poll();
+
+        let mut actual_parents: Vec<(usize, usize)> =
+            runtime.query_as(&build_query!(parent(_, _))).unwrap();
+        actual_parents.sort();
+        assert_eq!(vec![(2, 1), (3, 1), (4, 2)], actual_parents);
+        // Ties on the price go to the least shop.
+        let actual_cheapest: Vec<(String, usize, String)> =
+            runtime.query_as(&build_query!(cheapest(_, _, _))).unwrap();
+        assert_eq!(
+            vec![("milk".to_string(), 3, "a".to_string())],
+            actual_cheapest
+        );
+
+        runtime.remove(&build_query!(e(2usize, 4usize)));
+        runtime.poll();
+        let mut actual_parents: Vec<(usize, usize)> =
+            runtime.query_as(&build_query!(parent(_, _))).unwrap();
+        actual_parents.sort();
+        assert_eq!(vec![(2, 1), (3, 1), (4, 3)], actual_parents);
+    }
+
+    #[test]
+    fn integration_test_save_and_load() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let path =
+            std::env::temp_dir().join(format!("micro-datalog-{}.snapshot", std::process::id()));
+        let options = RuntimeOptions {
+            record_epochs: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program.clone(), options.clone());
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime
+            .insert("e", vec!["b".into(), TypedValue::SignedInt(-1)])
+            .unwrap();
+        runtime.poll();
+        let first_read = runtime.epoch();
+        runtime
+            .insert("e", vec![TypedValue::SignedInt(-1), 2.5.into()])
+            .unwrap();
+        runtime.poll();
+        // Pending updates are part of the snapshot too.
+        runtime.insert("e", vec![2.5.into(), true.into()]).unwrap();
+        runtime.save(&path).unwrap();
+
+        let mut restored = MicroRuntime::with_options(tc_program, options);
+        restored.load(&path).unwrap();
+        assert_eq!(runtime.epoch(), restored.epoch());
+        assert!(!restored.safe());
+        runtime.poll();
+        restored.poll();
+
+        let all = build_query!(tc(_, _));
+        let expected: HashSet<_> = runtime.query(&all).unwrap().collect();
+        let actual: HashSet<_> = restored.query(&all).unwrap().collect();
+        assert_eq!(expected, actual);
+        let expected_new: HashSet<_> = runtime.query_new_since(&all, first_read).unwrap().collect();
+        let actual_new: HashSet<_> = restored
+            .query_new_since(&all, first_read)
+            .unwrap()
+            .collect();
+        assert_eq!(expected_new, actual_new);
+
+        let mut other = MicroRuntime::new(program! { tc(?x, ?y) <- [e(?x, ?y)] });
+        assert!(other.load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn integration_test_bulk_insertions() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert_many(
+                "e",
+                vec![vec!["a".into(), "b".into()], vec!["b".into(), "c".into()]],
+            )
+            .unwrap();
+        runtime
+            .insert_columns(
+                "e",
+                vec![vec!["c".into(), "d".into()], vec!["d".into(), "e".into()]],
+            )
+            .unwrap();
+        assert!(runtime
+            .insert_columns("e", vec![vec!["e".into()], vec![]])
+            .is_err());
+        runtime.poll();
+
+        let all_from_a = build_query!(tc("a", _));
+        let actual: HashSet<_> = runtime.query(&all_from_a).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+            vec!["a".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn integration_test_deletions_with_chained_rederivation() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
         vec![
             vec!["a".into(), "b".into()],
             vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
+            vec!["c".into(), "d".into()],
+            vec!["b".into(), "x".into()],
+            vec!["x".into(), "c".into()],
+        ]
+        .into_iter()
+        .for_each(|edge| {
+            runtime.insert("e", edge).unwrap();
+        });
+        runtime.poll();
+
+        // tc(a, c) and tc(a, d) can only be rederived through tc(b, c) and tc(b, d), which are
+        // overdeleted themselves.
+        runtime.remove(&build_query!(e("b", "c")));
+        runtime.poll();
+
+        let all_from_a = build_query!(tc("a", _));
+        let actual: HashSet<_> = runtime.query(&all_from_a).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "x".into()],
+            vec!["a".into(), "c".into()],
+            vec!["a".into(), "d".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn integration_test_join_indices_across_polls() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+        runtime.remove(&build_query!(e("b", "c")));
+        runtime.poll();
+        runtime.insert("e", vec!["b".into(), "d".into()]).unwrap();
+        runtime.insert("e", vec!["d".into(), "e".into()]).unwrap();
+        runtime.poll();
+
+        let all = build_query!(tc(_, _));
+        let actual: HashSet<_> = runtime.query(&all).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "d".into()],
+            vec!["a".into(), "e".into()],
+            vec!["b".into(), "d".into()],
+            vec!["b".into(), "e".into()],
+            vec!["d".into(), "e".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+
+        // The index that the recursive join probes has followed every insertion and deletion.
+        let tc_index = runtime.processed.get_index("tc", &[0]).unwrap();
+        let indexed: HashSet<_> = tc_index
+            .buckets
+            .values()
+            .flatten()
+            .map(|fact| (**fact).clone())
+            .collect();
+        assert_eq!(expected, indexed);
+        assert_eq!(
+            expected.len(),
+            tc_index.buckets.values().map(Vec::len).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn integration_test_query_minimal() {
+        let path_program = program! {
+            path(?x, ?y, ?w) <- [e(?x, ?y, ?w)],
+            path(?x, ?z, ?w) <- [path(?x, ?y, ?v), e(?y, ?z, ?u), weights(?v, ?u, ?w)],
+        };
+
+        let mut runtime = MicroRuntime::new(path_program);
+        for (from, to, weight) in [
+            ("a", "b", 1usize),
+            ("b", "c", 1),
+            ("a", "c", 5),
+            ("c", "d", 2),
+        ] {
+            runtime
+                .insert("e", vec![from.into(), to.into(), weight.into()])
+                .unwrap();
+        }
+        for (first, second) in [(1usize, 1usize), (1, 2), (2, 2), (5, 2)] {
+            runtime
+                .insert(
+                    "weights",
+                    vec![first.into(), second.into(), (first + second).into()],
+                )
+                .unwrap();
+        }
+        runtime.poll();
+
+        let shorter = |left: &AnonymousGroundAtom, right: &AnonymousGroundAtom| left[2] < right[2];
+        let mut shortest = runtime
+            .query_minimal(&build_query!(path("a", _, _)), &[0, 1], shorter)
+            .unwrap();
+        shortest.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into(), 1usize.into()],
+            vec!["a".into(), "c".into(), 2usize.into()],
+            vec!["a".into(), "d".into(), 4usize.into()],
+        ];
+        assert_eq!(expected, shortest);
+
+        // Without a key, only the shortest path of all is left.
+        let shortest_of_all = runtime
+            .query_minimal(&build_query!(path(_, _, _)), &[], shorter)
+            .unwrap();
+        assert_eq!(2, shortest_of_all.len());
+        assert!(shortest_of_all.iter().all(|fact| fact[2] == 1usize.into()));
+
+        assert!(runtime
+            .query_minimal(&build_query!(path(_, _, _)), &[3], shorter)
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_query_sorted() {
+        let program = program! {
+            score(?player, ?points) <- [game(?player, ?points)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        for (player, points) in [("d", 7usize), ("a", 3), ("c", 7), ("b", 9), ("e", 1)] {
+            runtime
+                .insert("game", vec![player.into(), points.into()])
+                .unwrap();
+        }
+        runtime.poll();
+
+        let by_points = [(1, Direction::Descending)];
+        let page = |offset, limit| {
+            runtime
+                .query_sorted(&build_query!(score(_, _)), &by_points, offset, limit)
+                .unwrap()
+                .into_iter()
+                .map(|fact| fact[0].clone())
+                .collect::<Vec<_>>()
+        };
+        // Ties are broken by the whole fact.
+        let expected: Vec<TypedValue> = vec!["b".into(), "c".into()];
+        assert_eq!(expected, page(0, 2));
+        let expected: Vec<TypedValue> = vec!["d".into(), "a".into()];
+        assert_eq!(expected, page(2, 2));
+        let expected: Vec<TypedValue> = vec!["e".into()];
+        assert_eq!(expected, page(4, 2));
+        assert!(page(5, 2).is_empty());
+        assert!(page(0, 0).is_empty());
+
+        let by_name = [(0, Direction::Ascending)];
+        let sorted = runtime
+            .query_sorted(&build_query!(score(_, 7usize)), &by_name, 0, 10)
+            .unwrap();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec!["c".into(), 7usize.into()],
+            vec!["d".into(), 7usize.into()],
+        ];
+        assert_eq!(expected, sorted);
+
+        assert!(runtime
+            .query_sorted(
+                &build_query!(score(_, _)),
+                &[(2, Direction::Ascending)],
+                0,
+                1
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_query_project() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "d".into()]).unwrap();
+        runtime.poll();
+
+        let all = build_query!(tc(_, _));
+        let sources: Vec<_> = runtime.query_project(&all, &[0]).unwrap().collect();
+        assert_eq!(2, sources.len());
+        let sources: HashSet<_> = sources.into_iter().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![vec!["a".into()], vec!["b".into()]]
+            .into_iter()
+            .collect();
+        assert_eq!(expected, sources);
+
+        let from_b = build_query!(tc("b", _));
+        let swapped: HashSet<_> = runtime.query_project(&from_b, &[1, 0]).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> =
+            vec![vec!["c".into(), "b".into()], vec!["d".into(), "b".into()]]
+                .into_iter()
+                .collect();
+        assert_eq!(expected, swapped);
+
+        assert!(runtime.query_project(&all, &[2]).is_err());
+    }
+
+    #[test]
+    fn integration_test_delimited_files() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let edges_path =
+            std::env::temp_dir().join(format!("micro-datalog-{}-edges.csv", std::process::id()));
+        let tc_path =
+            std::env::temp_dir().join(format!("micro-datalog-{}-tc.tsv", std::process::id()));
+        std::fs::write(&edges_path, "a,1\n\"b,\"\"c\"\"\",-2\r\n\n1,x\n").unwrap();
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        let schema = [ColumnType::Str, ColumnType::SignedInt];
+        let error = runtime.load_csv("e", &edges_path, &schema).unwrap_err();
+        assert!(error.starts_with("line 4:"));
+
+        std::fs::write(&edges_path, "a,b\nb,\"c,\"\"d\"\"\"\n").unwrap();
+        let schema = [ColumnType::Str, ColumnType::Str];
+        runtime.load_csv("e", &edges_path, &schema).unwrap();
+        runtime.poll();
+        runtime
+            .write_tsv(&build_query!(tc("a", _)), &tc_path)
+            .unwrap();
+
+        let mut reread = MicroRuntime::new(program! { tc(?x, ?y) <- [e(?x, ?y)] });
+        reread.load_tsv("e", &tc_path, &schema).unwrap();
+        reread.poll();
+        let actual: HashSet<_> = reread.query(&build_query!(tc(_, _))).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["a".into(), "c,\"d\"".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+
+        std::fs::remove_file(edges_path).unwrap();
+        std::fs::remove_file(tc_path).unwrap();
+    }
+
+    #[test]
+    fn integration_test_rdfs() {
+        let path = std::env::temp_dir().join(format!("micro-datalog-{}.ttl", std::process::id()));
+        std::fs::write(
+            &path,
+            "@prefix ex: <http://example.org/> .
+            @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+            ex:Dog rdfs:subClassOf ex:Mammal .
+            ex:Mammal rdfs:subClassOf ex:Animal .
+            ex:hasPuppy rdfs:subPropertyOf ex:hasChild .
+            ex:hasChild rdfs:domain ex:Parent ; rdfs:range ex:Animal .
+            ex:rex a ex:Dog ; ex:hasPuppy ex:fido .
+            ",
+        )
+        .unwrap();
+
+        let mut runtime = MicroRuntime::new(rdfs());
+        runtime.load_triples(TRIPLE, &path).unwrap();
+        runtime.poll();
+        std::fs::remove_file(path).unwrap();
+
+        let types = |resource: &str| {
+            let query = Query {
+                matchers: vec![
+                    Matcher::Constant(format!("http://example.org/{}", resource).into()),
+                    Matcher::Constant(RDF_TYPE.into()),
+                    Matcher::Any,
+                ],
+                symbol: ENTAILED,
+                since: None,
+                source: None,
+            };
+            let mut types: Vec<_> = runtime
+                .query(&query)
+                .unwrap()
+                .map(|fact| fact[2].clone())
+                .collect();
+            types.sort();
+            types
+        };
+        let classes = |classes: &[&str]| -> Vec<TypedValue> {
+            classes
+                .iter()
+                .map(|class| format!("http://example.org/{}", class).into())
+                .collect()
+        };
+        assert_eq!(
+            classes(&["Animal", "Dog", "Mammal", "Parent"]),
+            types("rex")
+        );
+        assert_eq!(classes(&["Animal"]), types("fido"));
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn integration_test_sql() {
+        use crate::engine::sql::Connection;
+
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            heavy(?x) <- [w(?x, 2usize)],
+        };
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE edges (src TEXT, dst TEXT, weight INTEGER);
+                INSERT INTO edges VALUES ('a', 'b', 1), ('b', 'c', 2), ('c', NULL, 3);",
+            )
+            .unwrap();
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        let error = runtime
+            .load_sql(&connection, "SELECT src, dst FROM edges", "e")
+            .unwrap_err();
+        assert!(error.starts_with("row 3:"));
+        runtime
+            .load_sql(
+                &connection,
+                "SELECT src, dst FROM edges WHERE dst IS NOT NULL",
+                "e",
+            )
+            .unwrap();
+        // Integers are read as the type of their column, which is unsigned here.
+        runtime
+            .load_sql(&connection, "SELECT src, weight FROM edges", "w")
+            .unwrap();
+        runtime.poll();
+        let heavy: Vec<_> = runtime.query(&build_query!(heavy(_))).unwrap().collect();
+        assert_eq!(vec![vec![TypedValue::from("b")]], heavy);
+
+        runtime
+            .write_sql(&build_query!(tc(_, _)), &connection, "tc")
+            .unwrap();
+        runtime
+            .write_sql(&build_query!(tc("b", _)), &connection, "tc")
+            .unwrap();
+        let rows: Vec<(String, String)> = connection
+            .prepare("SELECT c0, c1 FROM tc")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec![("b".to_string(), "c".to_string())], rows);
+    }
+
+    #[test]
+    fn integration_test_relations() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.insert("label", vec!["a".into()]).unwrap();
+        runtime.poll();
+
+        let actual: Vec<_> = runtime.relations().collect();
+        assert_eq!(vec![("e", 2), ("label", 1), ("tc", 3)], actual);
+
+        let tc_schema = runtime.relation_schema("tc").unwrap();
+        assert_eq!(Some(2), tc_schema.arity);
+        assert!(tc_schema.derived);
+        assert_eq!(3, tc_schema.size);
+        let e_schema = runtime.relation_schema("e").unwrap();
+        assert!(!e_schema.derived);
+        // The recursive rule joins every edge with the paths that start where it ends.
+        assert!(tc_schema.indices.contains(&vec![0]));
+        assert!(runtime.relation_schema("delete_tc").is_none());
+        assert!(runtime.relation_schema("missing").is_none());
+    }
+
+    #[test]
+    fn integration_test_take_delta() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let sorted_delta = |runtime: &mut MicroRuntime| {
+            let mut delta: Vec<_> = runtime
+                .take_delta("tc")
+                .unwrap()
+                .into_iter()
+                .map(|fact| (*fact).clone())
+                .collect();
+            delta.sort();
+            delta
+        };
+
+        let options = RuntimeOptions {
+            record_epochs: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.poll();
+        assert_eq!(
+            vec![vec![TypedValue::from("a"), "b".into()]],
+            sorted_delta(&mut runtime)
+        );
+        // Taken once per poll.
+        assert!(sorted_delta(&mut runtime).is_empty());
+
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+        let expected: Vec<AnonymousGroundAtom> =
+            vec![vec!["a".into(), "c".into()], vec!["b".into(), "c".into()]];
+        assert_eq!(expected, sorted_delta(&mut runtime));
+
+        // A poll that derives nothing leaves nothing to take.
+        runtime.remove(&build_query!(e("a", "b")));
+        runtime.poll();
+        assert!(sorted_delta(&mut runtime).is_empty());
+        assert!(runtime.take_delta("e").unwrap().is_empty());
+    }
+
+    #[test]
+    fn integration_test_created_indices() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            from_a(?y) <- [f("a", ?y)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        assert_eq!(vec![("f".to_string(), vec![0])], runtime.advise_indices());
+        runtime.create_index("f", &[0]).unwrap();
+        assert!(runtime.advise_indices().is_empty());
+        assert!(runtime.create_index("f", &[2]).is_err());
+        assert!(runtime.create_index("f", &[]).is_err());
+
+        runtime.insert("f", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("f", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+        // The selection reads the index, which is kept in step with the relation.
+        let mut from_a: Vec<_> = runtime.query(&build_query!(from_a(_))).unwrap().collect();
+        from_a.sort();
+        assert_eq!(vec![vec![TypedValue::from("b")]], from_a);
+        runtime.insert("f", vec!["a".into(), "c".into()]).unwrap();
+        runtime.poll();
+        assert_eq!(2, runtime.query(&build_query!(from_a(_))).unwrap().count());
+
+        // Created indices survive changes to the program.
+        runtime
+            .add_rule(rule! { from_b(?y) <- [f("b", ?y)] })
+            .unwrap();
+        assert!(runtime
+            .relation_schema("f")
+            .unwrap()
+            .indices
+            .contains(&vec![0]));
+
+        assert!(runtime.drop_index("f", &[0]));
+        assert!(!runtime.drop_index("f", &[0]));
+        assert!(!runtime
+            .relation_schema("f")
+            .unwrap()
+            .indices
+            .contains(&vec![0]));
+        // Joins keep the indices they need.
+        runtime.create_index("tc", &[0]).unwrap();
+        assert!(runtime.drop_index("tc", &[0]));
+        assert!(runtime
+            .relation_schema("tc")
+            .unwrap()
+            .indices
+            .contains(&vec![0]));
+    }
+
+    #[test]
+    fn integration_test_equivalences() {
+        let program = program! {
+            shared(?x, ?z) <- [same(?x, ?y), owns(?y, ?z)],
+            linked("a", "c") <- [same("a", "c")],
+        };
+
+        let mut runtime = MicroRuntime::new(program.clone());
+        runtime.declare_equivalence("same").unwrap();
+        assert!(runtime.declare_equivalence("shared").is_err());
+        runtime
+            .insert("same", vec!["a".into(), "b".into()])
+            .unwrap();
+        runtime
+            .insert("same", vec!["c".into(), "b".into()])
+            .unwrap();
+        runtime
+            .insert("owns", vec!["a".into(), "car".into()])
+            .unwrap();
+        runtime.poll();
+
+        // Three values make up one class, which holds every pair of them.
+        assert_eq!(9, runtime.query(&build_query!(same(_, _))).unwrap().count());
+        assert!(runtime
+            .equivalence("same")
+            .unwrap()
+            .equivalent(&"c".into(), &"a".into()));
+        assert!(runtime
+            .contains("linked", &vec!["a".into(), "c".into()])
+            .unwrap());
+        for owner in ["a", "b", "c"] {
+            assert!(runtime
+                .contains("shared", &vec![owner.into(), "car".into()])
+                .unwrap());
+        }
+
+        // Taking one pair out splits the class, and what was derived through it goes. No pair is left
+        // for c to be in, hence it is not even equivalent to itself anymore.
+        runtime.remove(&build_query!(same("c", "b")));
+        runtime.poll();
+        assert_eq!(4, runtime.query(&build_query!(same(_, _))).unwrap().count());
+        assert!(!runtime
+            .contains("linked", &vec!["a".into(), "c".into()])
+            .unwrap());
+        assert!(!runtime
+            .contains("shared", &vec!["c".into(), "car".into()])
+            .unwrap());
+        assert!(runtime
+            .contains("shared", &vec!["b".into(), "car".into()])
+            .unwrap());
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert("same", vec!["a".into(), "b".into()])
+            .unwrap();
+        assert!(runtime.declare_equivalence("same").is_err());
+    }
+
+    #[test]
+    fn integration_test_deterministic_order() {
+        let run = |count_derivations: bool| {
+            let program = if count_derivations {
+                program! {
+                    two_hops(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+                }
+            } else {
+                program! {
+                    tc(?x, ?y) <- [e(?x, ?y)],
+                    tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+                    one_way(?x, ?y) <- [e(?x, ?y), !tc(?y, ?x)],
+                    out_degree(?x, count(?y)) <- [e(?x, ?y)],
+                }
+            };
+            let options = RuntimeOptions {
+                count_derivations,
+                ..Default::default()
+            };
+            let mut runtime = MicroRuntime::with_options(program.clone(), options);
+            (0..30usize).for_each(|node| {
+                runtime
+                    .insert("e", vec![node.into(), ((node * 7 + 3) % 30).into()])
+                    .unwrap();
+                runtime
+                    .insert("e", vec![node.into(), ((node * 11 + 5) % 30).into()])
+                    .unwrap();
+            });
+            runtime.poll();
+            runtime.remove(&build_query!(e(4usize, _)));
+            runtime.remove(&build_query!(e(_, 17usize)));
+            runtime.poll();
+            // Deriving everything again goes through other paths than keeping it up to date.
+            runtime.replace_program(program).unwrap();
+            runtime.poll();
+
+            ["tc", "one_way", "out_degree", "two_hops"]
+                .iter()
+                .filter(|relation| runtime.arity(relation).is_some())
+                .map(|relation| {
+                    let query = Query {
+                        matchers: vec![Matcher::Any, Matcher::Any],
+                        symbol: relation,
+                        since: None,
+                        source: None,
+                    };
+
+                    runtime.query(&query).unwrap().collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Every runtime hashes with different keys, so any order that depends on them would show.
+        for count_derivations in [false, true] {
+            let first_run = run(count_derivations);
+            assert!(first_run.iter().all(|facts| !facts.is_empty()));
+            (0..5).for_each(|_| assert_eq!(first_run, run(count_derivations)));
+        }
+    }
+
+    #[test]
+    fn integration_test_reader() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        let reader = runtime.reader();
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        // Nothing is published before the poll is over.
+        assert!(reader.query(&build_query!(tc(_, _))).unwrap().is_empty());
+        runtime.poll();
+
+        std::thread::scope(|scope| {
+            let thread_reader = reader.clone();
+            let read = scope.spawn(move || {
+                let facts = thread_reader.query(&build_query!(tc(_, _))).unwrap();
+                facts.len()
+            });
+            runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+            runtime.poll();
+
+            // Either from before the second poll, or from after it, but never from the middle.
+            let read_facts = read.join().unwrap();
+            assert!(read_facts == 3 || read_facts == 6);
+        });
+
+        assert_eq!(runtime.epoch(), reader.epoch());
+        assert_eq!(6, reader.query(&build_query!(tc(_, _))).unwrap().len());
+        assert!(reader.contains("tc", &vec!["a".into(), "d".into()]));
+        assert!(reader.query(&build_query!(tc(_))).is_err());
+    }
+
+    #[test]
+    fn integration_test_counted_derivations() {
+        let program = program! {
+            two_hops(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+            reach(?x, ?y) <- [e(?x, ?y)],
+            reach(?x, ?z) <- [two_hops(?x, ?z)],
+            shared(?y, ?z) <- [e(?x, ?y), e(?x, ?z), reach(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            count_derivations: true,
+            ..Default::default()
+        };
+
+        let mut counting = MicroRuntime::with_options(program.clone(), options);
+        let mut overdeleting = MicroRuntime::new(program);
+        let edge = |from: usize, to: usize| vec![from.into(), to.into()];
+        let relations = ["two_hops", "reach", "shared"];
+        let assert_same = |counting: &MicroRuntime, overdeleting: &MicroRuntime| {
+            for relation in relations {
+                let query = Query {
+                    matchers: vec![Matcher::Any, Matcher::Any],
+                    symbol: relation,
+                    since: None,
+                    source: None,
+                };
+                let mut expected: Vec<_> = overdeleting.query(&query).unwrap().collect();
+                expected.sort();
+                let mut actual: Vec<_> = counting.query(&query).unwrap().collect();
+                actual.sort();
+                assert_eq!(expected, actual, "{}", relation);
+            }
+        };
+
+        // Two ways from 1 to 4, so that either can go without taking 1 to 4 along.
+        for runtime in [&mut counting, &mut overdeleting] {
+            for (from, to) in [(1, 2), (2, 4), (1, 3), (3, 4), (4, 5), (1, 4)] {
+                runtime.insert("e", edge(from, to)).unwrap();
+            }
+            runtime.poll();
+        }
+        assert_same(&counting, &overdeleting);
+        assert!(counting.contains("two_hops", &edge(1, 4)).unwrap());
+
+        for runtime in [&mut counting, &mut overdeleting] {
+            runtime.remove(&build_query!(e(2usize, 4usize)));
+            runtime.poll();
+        }
+        assert_same(&counting, &overdeleting);
+        assert!(counting.contains("two_hops", &edge(1, 4)).unwrap());
+
+        for runtime in [&mut counting, &mut overdeleting] {
+            runtime.remove(&build_query!(e(3usize, 4usize)));
+            runtime.remove(&build_query!(e(1usize, 2usize)));
+            runtime.insert("e", edge(1, 2)).unwrap();
+            runtime.insert("e", edge(5, 1)).unwrap();
+            runtime.poll();
+        }
+        assert_same(&counting, &overdeleting);
+        assert!(!counting.contains("two_hops", &edge(1, 4)).unwrap());
+        assert!(counting.contains("reach", &edge(1, 4)).unwrap());
+
+        // Counts start over from what the runtime holds after rules change.
+        let rule = rule! { reach(?x, ?y) <- [e(?y, ?x)] };
+        counting.add_rule(rule.clone()).unwrap();
+        overdeleting.add_rule(rule).unwrap();
+        for runtime in [&mut counting, &mut overdeleting] {
+            runtime.poll();
+            runtime.remove(&build_query!(e(4usize, 5usize)));
+            runtime.poll();
+        }
+        assert_same(&counting, &overdeleting);
+
+        assert!(counting.insert("reach", edge(7, 8)).is_err());
+        assert!(counting
+            .add_rule(rule! { reach(?x, ?z) <- [reach(?x, ?y), e(?y, ?z)] })
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_explain() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            unrelated(?x) <- [f(?x)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.poll();
+
+        let tc_statistics = runtime.relation_statistics("tc").unwrap();
+        assert_eq!(6, tc_statistics.size);
+        assert_eq!(vec![3, 3], tc_statistics.distinct_values);
+
+        let explanation = runtime.explain(&build_query!(tc(_, _))).unwrap();
+        assert_eq!(2, explanation.rules.len());
+        assert!(explanation.iterations > 0);
+
+        let recursive_rule = explanation
+            .rules
+            .iter()
+            .find(|rule_plan| rule_plan.rule.body.len() == 2)
+            .unwrap();
+        assert!(recursive_rule.evaluations > 0);
+        assert_eq!(3, recursive_rule.new_facts);
+        let sizes: Vec<_> = recursive_rule
+            .operations
+            .iter()
+            .map(|operation| (operation.estimated_size, operation.actual_size))
+            .collect();
+        // Every edge is estimated to reach two of the six paths, as there are three sources.
+        assert_eq!(vec![(3, 3), (6, 6), (6, 3), (6, 3)], sizes);
+        assert!(explanation.to_string().contains("estimated 6, actual 3"));
+    }
+
+    #[test]
+    fn integration_test_rule_statistics() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            unrelated(?x) <- [f(?x)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let statistics = runtime.stats();
+        assert_eq!(3, statistics.len());
+        assert!(statistics
+            .windows(2)
+            .all(|pair| pair[0].time >= pair[1].time));
+        let recursive_rule = statistics
+            .iter()
+            .find(|rule_statistics| rule_statistics.rule.body.len() == 2)
+            .unwrap();
+        assert!(recursive_rule.evaluations > 1);
+        assert_eq!(3, recursive_rule.new_facts);
+        assert!(recursive_rule.time > Duration::ZERO);
+
+        // They are of the last poll alone.
+        runtime.insert("f", vec![1usize.into()]).unwrap();
+        runtime.poll();
+        let statistics = runtime.stats();
+        let new_facts = |head: &str| {
+            statistics
+                .iter()
+                .filter(|rule_statistics| rule_statistics.rule.head.symbol == head)
+                .map(|rule_statistics| rule_statistics.new_facts)
+                .sum::<usize>()
+        };
+        assert_eq!(0, new_facts("tc"));
+        assert_eq!(1, new_facts("unrelated"));
+    }
+
+    #[test]
+    fn integration_test_memory_usage() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let edges = || (0..20usize).map(|node| vec![node.into(), (node + 1).into()]);
+
+        let mut runtime = MicroRuntime::new(tc_program.clone());
+        assert_eq!(0, runtime.memory_usage().facts);
+        runtime.insert_many("e", edges()).unwrap();
+        let pending = runtime.memory_usage();
+        assert!(pending.facts > 0);
+        runtime.poll();
+        let usage = runtime.memory_usage();
+        assert!(usage.facts > pending.facts);
+        assert!(usage.indices > 0);
+        assert!(usage.intermediate_peak > 0);
+
+        let options = RuntimeOptions {
+            intermediate_memory_limit: Some(64),
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        runtime.insert_many("e", edges()).unwrap();
+        let error = runtime.try_poll().unwrap_err();
+        assert!(error.contains("bytes"), "{}", error);
+        // The failed poll still tells how far past the limit it went.
+        assert!(runtime.memory_usage().intermediate_peak > 64);
+    }
+
+    #[test]
+    fn integration_test_memory_limit_over_many_polls() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            intermediate_memory_limit: Some(4096),
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+
+        // Every poll derives a single fact, so however large the relations grow, what it holds on
+        // to in between stays small.
+        for node in (0..1000usize).step_by(2) {
+            runtime
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+            runtime.try_poll().unwrap();
+            assert!(runtime.memory_usage().intermediate_peak <= 4096);
+        }
+        assert_eq!(500, runtime.query(&build_query!(tc(_, _))).unwrap().count());
+    }
+
+    #[test]
+    fn integration_test_failed_poll_keeps_pending_deletions() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert("e", vec![0usize.into(), 1usize.into()])
+            .unwrap();
+        for node in 2..400usize {
+            runtime
+                .insert("e", vec![1usize.into(), node.into()])
+                .unwrap();
+        }
+        runtime.poll();
+
+        // Overdeleting what e(0, 1) derived joins it with everything that 1 reaches at once.
+        runtime.options.intermediate_memory_limit = Some(4096);
+        runtime.remove(&build_query!(e(0usize, 1usize)));
+        assert!(runtime.try_poll().is_err());
+
+        runtime.options.intermediate_memory_limit = None;
+        runtime.try_poll().unwrap();
+        assert!(!runtime
+            .contains("e", &vec![0usize.into(), 1usize.into()])
+            .unwrap());
+        assert_eq!(
+            0,
+            runtime.query(&build_query!(tc(0usize, _))).unwrap().count()
+        );
+        assert_eq!(398, runtime.query(&build_query!(tc(_, _))).unwrap().count());
+    }
+
+    #[test]
+    fn test_failed_commit_leaves_runtime_as_it_was() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            intermediate_memory_limit: Some(4096),
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        runtime
+            .insert("e", vec![0usize.into(), 1usize.into()])
+            .unwrap();
+        runtime.poll();
+        let all = build_query!(tc(_, _));
+        let before: HashSet<_> = runtime.query(&all).unwrap().collect();
+
+        let mut transaction = runtime.transaction();
+        for node in 1..100usize {
+            transaction
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        }
+        transaction.remove(&build_query!(e(0usize, 1usize)));
+        let error = transaction.commit().unwrap_err();
+        assert_eq!(
+            "intermediate results take more than the 4096 bytes that they may",
+            error
+        );
+
+        assert!(runtime.safe());
+        assert_eq!(before, runtime.query(&all).unwrap().collect());
+        runtime.poll();
+        assert_eq!(before, runtime.query(&all).unwrap().collect());
+    }
+
+    #[test]
+    fn test_failed_commit_takes_back_what_the_poll_changed() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            intermediate_memory_limit: Some(4096),
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        runtime.declare_equivalence("same").unwrap();
+        runtime
+            .insert("same", vec!["a".into(), "b".into()])
+            .unwrap();
+        let edge = vec![0usize.into(), 1usize.into()];
+        runtime
+            .insert_with_metadata("e", edge.clone(), FactMetadata::new(7))
+            .unwrap();
+        runtime.poll();
+        let epoch = runtime.epoch();
+
+        // The deletion is done with by the time the insertions outgrow the limit.
+        let mut transaction = runtime.transaction();
+        transaction
+            .insert("same", vec!["b".into(), "c".into()])
+            .unwrap();
+        for node in 1..100usize {
+            transaction
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        }
+        transaction.remove(&build_query!(e(0usize, 1usize)));
+        assert!(transaction.commit().is_err());
+
+        assert_eq!(epoch, runtime.epoch());
+        assert_eq!(
+            vec![edge.clone()],
+            runtime
+                .query(&build_query!(tc(_, _)))
+                .unwrap()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&FactMetadata::new(7)), runtime.metadata("e", &edge));
+        let same = runtime.equivalence("same").unwrap();
+        assert!(same.equivalent(&"a".into(), &"b".into()));
+        assert!(!same.equivalent(&"a".into(), &"c".into()));
+
+        // Nothing is journaled after a commit that went through.
+        let mut transaction = runtime.transaction();
+        transaction
+            .insert("same", vec!["b".into(), "c".into()])
+            .unwrap();
+        transaction.commit().unwrap();
+        assert!(!runtime.processed.journal.is_open());
+        assert!(runtime
+            .equivalence("same")
+            .unwrap()
+            .equivalent(&"a".into(), &"c".into()));
+    }
+
+    #[test]
+    fn integration_test_query_grouped() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let key = |node: usize| -> Vec<TypedValue> { vec![node.into()] };
+        let all_tc = build_query!(tc(_, _));
+        for column in [0, 1] {
+            let grouped = runtime.query_grouped(&all_tc, &[column]).unwrap();
+            assert_eq!(3, grouped.len());
+            grouped.iter().for_each(|(group_key, facts)| {
+                assert!(facts.iter().all(|fact| fact[column] == group_key[0]));
+            });
+        }
+
+        let counts = runtime.query_group_counts(&all_tc, &[0]).unwrap();
+        assert_eq!(3, counts.len());
+        assert_eq!(Some(&3), counts.get(&key(1)));
+        assert_eq!(Some(&2), counts.get(&key(2)));
+        assert_eq!(Some(&1), counts.get(&key(3)));
+
+        // Only the facts matching the query are grouped.
+        let counts = runtime
+            .query_group_counts(&build_query!(tc(_, >= 3usize)), &[1])
+            .unwrap();
+        assert_eq!(2, counts.len());
+        assert_eq!(Some(&2), counts.get(&key(3)));
+        assert_eq!(Some(&3), counts.get(&key(4)));
+
+        assert!(runtime.query_grouped(&all_tc, &[2]).is_err());
+    }
+
+    #[test]
+    fn integration_test_shared_facts() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let edges = || (0..10usize).map(|node| vec![node.into(), (node + 1).into()]);
+        let run = |share_facts| {
+            let options = RuntimeOptions {
+                share_facts,
+                ..Default::default()
+            };
+            let mut runtime = MicroRuntime::with_options(tc_program.clone(), options);
+            runtime.insert_many("e", edges()).unwrap();
+            runtime.poll();
+
+            runtime
+        };
+
+        let shared = run(true);
+        let edge = Arc::new(vec![3usize.into(), 4usize.into()]);
+        let stored = |relation| {
+            shared
+                .processed
+                .get_relation(relation)
+                .get(&edge)
+                .unwrap()
+                .clone()
+        };
+        assert!(Arc::ptr_eq(&stored("e"), &stored("tc")));
+
+        let unshared = run(false);
+        assert_eq!(
+            unshared.query(&build_query!(tc(_, _))).unwrap().count(),
+            shared.query(&build_query!(tc(_, _))).unwrap().count()
+        );
+        assert!(shared.memory_usage().facts < unshared.memory_usage().facts);
+    }
+
+    #[test]
+    fn integration_test_query_check() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            hop(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert_many(
+                "e",
+                (0..5usize).map(|node| vec![node.into(), (node + 1).into()]),
+            )
+            .unwrap();
+        runtime.poll();
+        assert!(runtime
+            .query_check(&build_query!(tc(1usize, _)))
+            .unwrap()
+            .is_empty());
+        assert!(runtime
+            .query_check(&build_query!(hop(1usize, _)))
+            .unwrap()
+            .is_empty());
+
+        // Facts that only the poll derived are missing from every other strategy.
+        let bogus: AnonymousGroundAtom = vec![1usize.into(), 9usize.into()];
+        runtime.processed.insert("hop", bogus.clone());
+        let discrepancies = runtime.query_check(&build_query!(hop(1usize, _))).unwrap();
+        assert_eq!(
+            vec![Strategy::TopDown, Strategy::Specialized],
+            discrepancies
+                .iter()
+                .map(|discrepancy| discrepancy.strategy)
+                .collect::<Vec<_>>()
+        );
+        assert!(discrepancies.iter().all(|discrepancy| {
+            discrepancy.missing == vec![bogus.clone()]
+                && discrepancy.unexpected.is_empty()
+                && discrepancy.witness == bogus
+        }));
+    }
+
+    #[test]
+    fn integration_test_compare_strategies() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            hop(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+        };
+        let strategies = |comparisons: &[StrategyComparison]| {
+            comparisons
+                .iter()
+                .map(|comparison| comparison.strategy)
+                .collect::<Vec<_>>()
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert_many(
+                "e",
+                (0..5usize).map(|node| vec![node.into(), (node + 1).into()]),
+            )
+            .unwrap();
+        assert!(runtime
+            .compare_strategies(&build_query!(tc(1usize, _)))
+            .is_err());
+        runtime.poll();
+
+        // Recursive relations can not be specialized.
+        let comparisons = runtime
+            .compare_strategies(&build_query!(tc(1usize, _)))
+            .unwrap();
+        assert_eq!(
+            vec![Strategy::TopDown, Strategy::Rematerialized],
+            strategies(&comparisons)
+        );
+        assert!(comparisons
+            .iter()
+            .all(|comparison| comparison.discrepancy.is_none()));
+
+        // Every strategy misses a fact that only the poll derived.
+        let bogus: AnonymousGroundAtom = vec![1usize.into(), 9usize.into()];
+        runtime.processed.insert("hop", bogus.clone());
+        let comparisons = runtime
+            .compare_strategies(&build_query!(hop(1usize, _)))
+            .unwrap();
+        assert_eq!(
+            vec![
+                Strategy::TopDown,
+                Strategy::Specialized,
+                Strategy::Rematerialized
+            ],
+            strategies(&comparisons)
+        );
+        assert!(comparisons.iter().all(|comparison| comparison
+            .discrepancy
+            .as_ref()
+            .is_some_and(|discrepancy| discrepancy.missing == vec![bogus.clone()])));
+    }
+
+    #[test]
+    fn integration_test_scenarios() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            unreachable(?x) <- [node(?x), !tc(0usize, ?x)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert_many("e", vec![vec![0usize.into(), 1usize.into()]])
+            .unwrap();
+        (1..4usize).for_each(|node| {
+            runtime.insert("node", vec![node.into()]).unwrap();
+        });
+        runtime
+            .insert_under("bridge", "e", vec![1usize.into(), 2usize.into()])
+            .unwrap();
+        runtime
+            .insert_under("shortcut", "e", vec![2usize.into(), 3usize.into()])
+            .unwrap();
+        runtime.poll();
+
+        let sorted = |mut facts: Vec<AnonymousGroundAtom>| {
+            facts.sort();
+            facts
+        };
+        let facts = |facts: &[&[usize]]| -> Vec<AnonymousGroundAtom> {
+            facts
+                .iter()
+                .map(|fact| fact.iter().map(|value| (*value).into()).collect())
+                .collect()
+        };
+        let reachable = build_query!(tc(0usize, _));
+        let unreachable = build_query!(unreachable(_));
+        assert_eq!(
+            facts(&[&[0, 1]]),
+            runtime.query_under(&reachable, &[]).unwrap()
+        );
+        assert_eq!(
+            facts(&[&[0, 1], &[0, 2], &[0, 3]]),
+            sorted(
+                runtime
+                    .query_under(&reachable, &["bridge", "shortcut"])
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            facts(&[&[3]]),
+            runtime.query_under(&unreachable, &["bridge"]).unwrap()
+        );
+        assert_eq!(
+            facts(&[&[2], &[3]]),
+            sorted(runtime.query_under(&unreachable, &["shortcut"]).unwrap())
+        );
+
+        // The runtime itself is left as it was.
+        assert_eq!(1, runtime.query(&reachable).unwrap().count());
+        assert!(runtime.query_under(&reachable, &["unknown"]).is_err());
+        assert!(runtime.drop_scenario("bridge"));
+        assert!(runtime.query_under(&reachable, &["bridge"]).is_err());
+    }
+
+    #[derive(Relation, Debug, PartialEq, Eq, Hash)]
+    #[relation(name = "e")]
+    struct Edge {
+        from: String,
+        to: String,
+    }
+
+    #[derive(Relation, Debug, PartialEq, Eq, Hash)]
+    #[relation(name = "tc")]
+    struct Path(String, String);
+
+    #[test]
+    fn integration_test_typed_relations() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert_typed(Edge {
+                from: "a".to_string(),
+                to: "b".to_string(),
+            })
+            .unwrap();
+        runtime
+            .insert_typed(Edge {
+                from: "b".to_string(),
+                to: "c".to_string(),
+            })
+            .unwrap();
+        runtime.poll();
+
+        let actual: HashSet<Path> = runtime.query_typed().unwrap().into_iter().collect();
+        let expected: HashSet<Path> = vec![
+            Path("a".to_string(), "b".to_string()),
+            Path("b".to_string(), "c".to_string()),
+            Path("a".to_string(), "c".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+
+        #[derive(Relation, Debug)]
+        #[relation(name = "tc")]
+        struct NumberedPath(usize, usize);
+        assert!(runtime.query_typed::<NumberedPath>().is_err());
+    }
+
+    #[test]
+    fn integration_test_query_as() {
+        let program = program! {
+            weighted(?x, ?w, ?ok) <- [e(?x, ?w), valid(?x, ?ok)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime.insert_tuple("e", ("a", 3usize)).unwrap();
+        runtime.insert_tuple("valid", ("a", true)).unwrap();
+        let wide = (
+            1usize, -2i64, 3.5, "d", true, 6usize, 7usize, 8usize, 9usize, 10usize,
+        );
+        runtime.insert_tuple("wide", wide).unwrap();
+        assert!(runtime.insert_tuple("e", ("b", 1usize, 2usize)).is_err());
+        runtime.poll();
+
+        let weighted = build_query!(weighted(_, _, _));
+        let actual: Vec<(String, usize, bool)> = runtime.query_as(&weighted).unwrap();
+        assert_eq!(vec![("a".to_string(), 3, true)], actual);
+
+        assert!(runtime.query_as::<(String, usize)>(&weighted).is_err());
+        assert!(runtime.query_as::<(String, i64, bool)>(&weighted).is_err());
+
+        type Wide = (
+            usize,
+            i64,
+            f64,
+            String,
+            bool,
+            usize,
+            usize,
+            usize,
+            usize,
+            usize,
+        );
+        let actual: Vec<Wide> = runtime
+            .query_as(&build_query!(wide(_, _, _, _, _, _, _, _, _, _)))
+            .unwrap();
+        assert_eq!(
+            vec![(1, -2, 3.5, "d".to_string(), true, 6, 7, 8, 9, 10)],
+            actual
+        );
+    }
+
+    #[test]
+    fn integration_test_subscriptions() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        let deltas = Rc::new(RefCell::new(vec![]));
+        let subscriber_deltas = deltas.clone();
+        runtime.subscribe("tc", move |delta| {
+            let facts = |facts: &Vec<Arc<AnonymousGroundAtom>>| -> HashSet<_> {
+                facts.iter().map(|fact| (**fact).clone()).collect()
+            };
+            subscriber_deltas
+                .borrow_mut()
+                .push((facts(&delta.insertions), facts(&delta.deletions)));
+        });
+
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+        let expected_insertions: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["a".into(), "c".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            vec![(expected_insertions, HashSet::new())],
+            *deltas.borrow()
+        );
+
+        // A poll that changes nothing is not reported.
+        runtime.poll();
+        assert_eq!(1, deltas.borrow().len());
+
+        // Facts that are deleted and rederived within the same poll are not reported.
+        runtime.remove(&build_query!(e("b", "c")));
+        runtime.insert("e", vec!["a".into(), "c".into()]).unwrap();
+        runtime.poll();
+        let expected_deletions: HashSet<AnonymousGroundAtom> =
+            vec![vec!["b".into(), "c".into()]].into_iter().collect();
+        assert_eq!((HashSet::new(), expected_deletions), deltas.borrow()[1]);
+    }
+
+    #[test]
+    fn integration_test_transactions() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.poll();
+        let all = build_query!(tc(_, _));
+        let before: HashSet<_> = runtime.query(&all).unwrap().collect();
+
+        let mut transaction = runtime.transaction();
+        transaction
+            .insert("e", vec!["b".into(), "c".into()])
+            .unwrap();
+        transaction.remove(&build_query!(e("a", "b")));
+        assert!(transaction.insert("e", vec!["c".into()]).is_err());
+        transaction.rollback();
+        assert!(runtime.safe());
+        assert_eq!(before, runtime.query(&all).unwrap().collect());
+
+        let mut transaction = runtime.transaction();
+        transaction
+            .insert("e", vec!["b".into(), "c".into()])
+            .unwrap();
+        transaction
+            .insert("e", vec!["c".into(), "d".into()])
+            .unwrap();
+        // Removals also apply to facts staged by the same transaction.
+        transaction.remove(&build_query!(e("c", _)));
+        transaction.commit().unwrap();
+        assert!(runtime.safe());
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["a".into(), "c".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, runtime.query(&all).unwrap().collect());
+    }
+
+    #[test]
+    fn integration_test_replace_program() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        runtime.poll();
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+
+        let reversed_program = program! {
+            reversed(?y, ?x) <- [e(?x, ?y)],
+        };
+        runtime.replace_program(reversed_program).unwrap();
+        assert!(!runtime.safe());
+        runtime.poll();
+
+        // The facts derived by the previous program are gone, while the base ones, pending
+        // insertions included, now feed the new program.
+        assert!(runtime
+            .query(&build_query!(tc(_, _)))
+            .unwrap()
+            .next()
+            .is_none());
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["b".into(), "a".into()],
+            vec!["c".into(), "b".into()],
+            vec!["d".into(), "c".into()],
+        ]
+        .into_iter()
+        .collect();
+        let actual: HashSet<_> = runtime
+            .query(&build_query!(reversed(_, _)))
+            .unwrap()
+            .collect();
+        assert_eq!(expected, actual);
+
+        let mismatched_program = program! {
+            single(?x) <- [e(?x)],
+        };
+        assert!(runtime.replace_program(mismatched_program).is_err());
+        assert_eq!(
+            3,
+            runtime
+                .query(&build_query!(reversed(_, _)))
+                .unwrap()
+                .count()
+        );
+    }
+
+    #[test]
+    fn integration_test_wildcards() {
+        let program = program! {
+            alive(?x) <- [person(?x, _, _)],
+            sender(?x) <- [message(?x, _), message(_, ?x)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert(
+                "person",
+                vec!["alice".into(), 30usize.into(), "paris".into()],
+            )
+            .unwrap();
+        runtime
+            .insert("message", vec!["alice".into(), "bob".into()])
+            .unwrap();
+        runtime
+            .insert("message", vec!["bob".into(), "carol".into()])
+            .unwrap();
+        runtime.poll();
+
+        // Both wildcards of a fact may take different values.
+        assert!(runtime.contains("alive", &vec!["alice".into()]).unwrap());
+        // And they do not join, so sender holds whoever both sent and received a message.
+        let actual: HashSet<_> = runtime.query(&build_query!(sender(_))).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![vec!["bob".into()]].into_iter().collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn integration_test_fact_metadata() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert_with_metadata(
+                "e",
+                vec!["a".into(), "b".into()],
+                FactMetadata::new(10).with_source("sensor"),
+            )
+            .unwrap();
+        runtime
+            .insert_with_metadata("e", vec!["b".into(), "c".into()], FactMetadata::new(20))
+            .unwrap();
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.poll();
+
+        let recent = build_query!(e(_, _)).since(15);
+        let expected: Vec<AnonymousGroundAtom> = vec![vec!["b".into(), "c".into()]];
+        assert_eq!(
+            expected,
+            runtime.query(&recent).unwrap().collect::<Vec<_>>()
+        );
+        let from_sensor = build_query!(e(_, _)).from_source("sensor");
+        let expected: Vec<AnonymousGroundAtom> = vec![vec!["a".into(), "b".into()]];
+        assert_eq!(
+            expected,
+            runtime.query(&from_sensor).unwrap().collect::<Vec<_>>()
+        );
+        // Derived facts have no metadata to filter by.
+        assert_eq!(
+            0,
+            runtime
+                .query(&build_query!(tc(_, _)).since(0))
+                .unwrap()
+                .count()
+        );
+
+        assert_eq!(1, runtime.expire(15));
+        runtime.poll();
+        assert!(!runtime
+            .contains("tc", &vec!["a".into(), "b".into()])
+            .unwrap());
+        assert!(runtime
+            .contains("tc", &vec!["b".into(), "d".into()])
+            .unwrap());
+        assert!(runtime
+            .metadata("e", &vec!["a".into(), "b".into()])
+            .is_none());
+        assert_eq!(
+            Some(&FactMetadata::new(20)),
+            runtime.metadata("e", &vec!["b".into(), "c".into()])
+        );
+    }
+
+    #[test]
+    fn test_remove_by_source() {
+        let mut runtime = MicroRuntime::new(program! { tc(?x, ?y) <- [e(?x, ?y)] });
+        for (from, source) in [("a", "sensor"), ("b", "camera")] {
+            runtime
+                .insert_with_metadata(
+                    "e",
+                    vec![from.into(), "c".into()],
+                    FactMetadata::new(10).with_source(source),
+                )
+                .unwrap();
+        }
+        runtime.insert("e", vec!["d".into(), "c".into()]).unwrap();
+        runtime.poll();
+
+        runtime.remove(&build_query!(e(_, "c")).from_source("sensor"));
+        runtime.poll();
+
+        let mut remaining: Vec<_> = runtime.query(&build_query!(tc(_, _))).unwrap().collect();
+        remaining.sort();
+        let expected: Vec<AnonymousGroundAtom> =
+            vec![vec!["b".into(), "c".into()], vec!["d".into(), "c".into()]];
+        assert_eq!(expected, remaining);
+        assert_eq!(
+            vec![vec![TypedValue::from("b")]],
+            runtime
+                .query_project(&build_query!(e(_, _)).from_source("camera"), &[0])
+                .unwrap()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn integration_test_fact_ttl() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert_with_metadata(
+                "e",
+                vec!["a".into(), "b".into()],
+                FactMetadata::new(10).with_ttl(5),
+            )
+            .unwrap();
+        runtime
+            .insert_with_metadata(
+                "e",
+                vec!["b".into(), "c".into()],
+                FactMetadata::new(10).with_ttl(50),
+            )
+            .unwrap();
+        runtime
+            .insert_with_ttl("e", vec!["c".into(), "d".into()], Duration::from_secs(60))
+            .unwrap();
+        runtime.poll();
+        assert!(runtime
+            .contains("tc", &vec!["a".into(), "d".into()])
+            .unwrap());
+
+        assert_eq!(0, runtime.expire_due(14));
+        assert_eq!(1, runtime.expire_due(15));
+        runtime.poll();
+        assert!(!runtime
+            .contains("tc", &vec!["a".into(), "d".into()])
+            .unwrap());
+        assert!(runtime
+            .contains("tc", &vec!["b".into(), "d".into()])
+            .unwrap());
+
+        assert_eq!(1, runtime.expire_due(60));
+        runtime.poll();
+        assert!(!runtime
+            .contains("tc", &vec!["b".into(), "d".into()])
+            .unwrap());
+        assert!(runtime
+            .contains("tc", &vec!["c".into(), "d".into()])
+            .unwrap());
+        assert!(runtime
+            .metadata("e", &vec!["c".into(), "d".into()])
+            .is_some_and(|metadata| metadata.expires_at.is_some()));
+    }
+
+    #[test]
+    fn integration_test_interned_strings() {
+        let program = program! {
+            first_name(?team, min(?name)) <- [member(?team, ?name)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        let zebra = InternedString::new("interned strings: zebra");
+        let aardvark = InternedString::new("interned strings: aardvark");
+        // Interned later, hence with a higher id, yet still the lesser string.
+        assert!(aardvark.id() > zebra.id());
+        runtime
+            .insert("member", vec!["t".into(), TypedValue::Str(zebra)])
+            .unwrap();
+        runtime
+            .insert("member", vec!["t".into(), TypedValue::Str(aardvark)])
+            .unwrap();
+        runtime.poll();
+
+        let actual: Vec<(String, String)> =
+            runtime.query_as(&build_query!(first_name(_, _))).unwrap();
+        assert_eq!(
+            vec![("t".to_string(), "interned strings: aardvark".to_string())],
+            actual
+        );
+        assert_eq!(aardvark, InternedString::new(aardvark.as_str()));
+    }
+
+    #[test]
+    fn integration_test_query_ref() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.insert("e", vec!["a".into(), "b".into()]).unwrap();
+        runtime.insert("e", vec!["b".into(), "c".into()]).unwrap();
+        let all = build_query!(tc(_, _));
+        assert!(runtime.query_ref(&all).is_err());
+        runtime.poll();
+
+        let first: Vec<_> = runtime.query_ref(&all).unwrap().collect();
+        let second: Vec<_> = runtime.query_ref(&all).unwrap().collect();
+        assert_eq!(3, first.len());
+        // Both reads share the stored facts instead of copying them.
+        first
+            .iter()
+            .zip(second.iter())
+            .for_each(|(left, right)| assert!(Arc::ptr_eq(left, right)));
+    }
+
+    #[test]
+    fn integration_test_stratified_negation() {
+        let program = stratified_program! {
+            reach(?y) <- [source(?x), e(?x, ?y)],
+            reach(?z) <- [reach(?y), e(?y, ?z)],
+            unreached(?x) <- [node(?x), !reach(?x)],
+            into_unreached(?x, ?y) <- [e(?x, ?y), node(?y), !reach(?y)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        vec!["a", "b", "c", "d", "e"].into_iter().for_each(|node| {
+            runtime.insert("node", vec![node.into()]).unwrap();
+        });
+        runtime.insert("source", vec!["a".into()]).unwrap();
+        vec![("a", "b"), ("b", "c"), ("d", "e")]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let unreached = build_query!(unreached(_));
+        let actual: HashSet<_> = runtime.query(&unreached).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into()], vec!["d".into()], vec!["e".into()]]
+                .into_iter()
+                .collect();
+        assert_eq!(expected, actual);
+
+        let actual: HashSet<_> = runtime
+            .query(&build_query!(into_unreached(_, _)))
+            .unwrap()
+            .collect();
+        let expected: HashSet<AnonymousGroundAtom> =
+            vec![vec!["d".into(), "e".into()]].into_iter().collect();
+        assert_eq!(expected, actual);
+
+        runtime.insert("node", vec!["f".into()]).unwrap();
+        runtime.poll();
+        let actual: HashSet<_> = runtime.query(&unreached).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![
+            vec!["a".into()],
+            vec!["d".into()],
+            vec!["e".into()],
+            vec!["f".into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn integration_test_stratified_negation_updates() {
+        let program = stratified_program! {
+            reach(?y) <- [source(?x), e(?x, ?y)],
+            reach(?z) <- [reach(?y), e(?y, ?z)],
+            unreached(?x) <- [node(?x), !reach(?x)],
+            linked(?x) <- [node(?x), !unreached(?x)],
+            quiet(?x) <- [node(?x), !alarm("on")],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        vec!["a", "b", "c", "d"].into_iter().for_each(|node| {
+            runtime.insert("node", vec![node.into()]).unwrap();
+        });
+        runtime.insert("source", vec!["a".into()]).unwrap();
+        vec![("a", "b"), ("b", "c")]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let nodes = |runtime: &MicroRuntime, query: &Query| -> HashSet<String> {
+            runtime
+                .query_as::<(String,)>(query)
+                .unwrap()
+                .into_iter()
+                .map(|(node,)| node)
+                .collect()
+        };
+        let set = |nodes: &[&str]| -> HashSet<String> {
+            nodes.iter().map(|node| node.to_string()).collect()
+        };
+        let unreached = build_query!(unreached(_));
+        let linked = build_query!(linked(_));
+        let quiet = build_query!(quiet(_));
+        assert_eq!(set(&["a", "d"]), nodes(&runtime, &unreached));
+        assert_eq!(set(&["b", "c"]), nodes(&runtime, &linked));
+
+        // Growing a negated relation retracts what it now rules out, across strata.
+        runtime.insert("e", vec!["c".into(), "d".into()]).unwrap();
+        runtime.insert("alarm", vec!["on".into()]).unwrap();
+        runtime.poll();
+        assert_eq!(set(&["a"]), nodes(&runtime, &unreached));
+        assert_eq!(set(&["b", "c", "d"]), nodes(&runtime, &linked));
+        assert_eq!(set(&[]), nodes(&runtime, &quiet));
+
+        // And shrinking it lets through what it used to rule out.
+        runtime.remove(&build_query!(e("a", "b")));
+        runtime.remove(&build_query!(alarm("on")));
+        runtime.poll();
+        assert_eq!(set(&["a", "b", "c", "d"]), nodes(&runtime, &unreached));
+        assert_eq!(set(&[]), nodes(&runtime, &linked));
+        assert_eq!(set(&["a", "b", "c", "d"]), nodes(&runtime, &quiet));
+    }
+
+    #[test]
+    fn integration_test_constants_in_single_and_negated_atoms() {
+        let program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            from_a(?y) <- [e("a", ?y)],
+            unreached(?x) <- [node(?x), !tc("a", ?x)],
+            quiet(?x) <- [node(?x), !alarm("on")],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        vec!["a", "b", "c", "d"].into_iter().for_each(|node| {
+            runtime.insert("node", vec![node.into()]).unwrap();
+        });
+        vec![("a", "b"), ("b", "c"), ("d", "a")]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.insert("alarm", vec!["off".into()]).unwrap();
+        runtime.poll();
+
+        let actual: HashSet<_> = runtime.query(&build_query!(from_a(_))).unwrap().collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![vec!["b".into()]].into_iter().collect();
+        assert_eq!(expected, actual);
+
+        let actual: HashSet<_> = runtime
+            .query(&build_query!(unreached(_)))
+            .unwrap()
+            .collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![vec!["a".into()], vec!["d".into()]]
+            .into_iter()
+            .collect();
+        assert_eq!(expected, actual);
+
+        assert_eq!(4, runtime.query(&build_query!(quiet(_))).unwrap().count());
+    }
+
+    #[test]
+    #[should_panic(expected = "has no positive body atom")]
+    fn test_rule_without_positive_body_atom() {
+        let rule = Rule {
+            head: Atom {
+                terms: vec![Term::Variable("x".to_string())],
+                symbol: "bad".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![Term::Variable("x".to_string())],
+                symbol: "good".to_string(),
+                sign: false,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
+
+        MicroRuntime::new(Program::from(vec![rule]));
+    }
+
+    // s(?y) <- [r(?y), !t(?x)], which the macros reject for ?x only being in a negated atom.
+    fn unsafe_negation_rule() -> Rule {
+        Rule {
+            head: Atom {
+                terms: vec![Term::Variable("y".to_string())],
+                symbol: "s".to_string(),
+                sign: true,
+            },
+            body: vec![
+                Atom {
+                    terms: vec![Term::Variable("y".to_string())],
+                    symbol: "r".to_string(),
+                    sign: true,
+                },
+                Atom {
+                    terms: vec![Term::Variable("x".to_string())],
+                    symbol: "t".to_string(),
+                    sign: false,
+                },
+            ],
+            id: 0,
+            hints: Default::default(),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "negates over variable x that no positive body atom binds")]
+    fn test_unsafe_negation() {
+        let program = Program::from(vec![unsafe_negation_rule()]);
+        assert!(program
+            .validate()
+            .unwrap_err()
+            .ends_with("has variable x that no positive body atom binds"));
+
+        MicroRuntime::new(program);
+    }
+
+    // parent(?x, ?p) <- [person(?x)], which the macros reject for ?p not being in the body.
+    fn existential_parent_rule() -> Rule {
+        Rule {
+            head: Atom {
+                terms: vec![
+                    Term::Variable("x".to_string()),
+                    Term::Variable("p".to_string()),
+                ],
+                symbol: "parent".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![Term::Variable("x".to_string())],
+                symbol: "person".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        }
+    }
+
+    #[test]
+    fn integration_test_skolemization() {
+        let program = Program::from(vec![
+            existential_parent_rule(),
+            rule! { founded_by(?p) <- [parent(?x, ?p), founder(?x)] },
+        ]);
+        let options = RuntimeOptions {
+            skolemize: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(program.clone(), options.clone());
+        runtime.insert("person", vec!["ada".into()]).unwrap();
+        runtime.insert("person", vec!["bob".into()]).unwrap();
+        runtime.insert("founder", vec!["ada".into()]).unwrap();
+        runtime.poll();
+
+        let parents: Vec<_> = runtime
+            .query(&build_query!(parent(_, _)))
+            .unwrap()
+            .collect();
+        assert_eq!(2, parents.len());
+        let founded_by: Vec<_> = runtime
+            .query(&build_query!(founded_by(_)))
+            .unwrap()
+            .collect();
+        assert_eq!(1, founded_by.len());
+        let parents_of_ada: Vec<_> = runtime
+            .query(&build_query!(parent("ada", _)))
+            .unwrap()
+            .collect();
+        assert_eq!(1, parents_of_ada.len());
+
+        // The same binding always yields the same constant.
+        let mut other_runtime = MicroRuntime::with_options(program, options);
+        other_runtime.insert("person", vec!["ada".into()]).unwrap();
+        other_runtime.poll();
+        let other_parents_of_ada: Vec<_> = other_runtime
+            .query(&build_query!(parent("ada", _)))
+            .unwrap()
+            .collect();
+        assert_eq!(parents_of_ada, other_parents_of_ada);
+    }
+
+    #[test]
+    fn integration_test_head_expressions() {
+        // Counting down stops at zero, since unsigned integers do not go below it.
+        let program = program! {
+            countdown(?x, ?y) <- [start(?x, ?y)],
+            countdown(?x, ?z) <- [countdown(?x, ?y)], ?z = ?y - 1,
+            scaled(?x, ?z) <- [countdown(?x, ?y)], ?z = ?y * ?y + 1,
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime
+            .insert("start", vec!["a".into(), 3usize.into()])
+            .unwrap();
+        runtime
+            .insert("start", vec!["b".into(), 1usize.into()])
+            .unwrap();
+        runtime.poll();
+
+        let facts = |runtime: &MicroRuntime, query: &Query| {
+            let mut facts: Vec<_> = runtime.query(query).unwrap().collect();
+            facts.sort();
+            facts
+        };
+        let fact =
+            |name: &str, value: usize| -> AnonymousGroundAtom { vec![name.into(), value.into()] };
+        assert_eq!(
+            vec![
+                fact("a", 0),
+                fact("a", 1),
+                fact("a", 2),
+                fact("a", 3),
+                fact("b", 0),
+                fact("b", 1)
+            ],
+            facts(&runtime, &build_query!(countdown(_, _)))
+        );
+        assert_eq!(
+            vec![
+                fact("a", 1),
+                fact("a", 2),
+                fact("a", 5),
+                fact("a", 10),
+                fact("b", 1),
+                fact("b", 2)
+            ],
+            facts(&runtime, &build_query!(scaled(_, _)))
+        );
+        assert!(runtime
+            .query_check(&build_query!(countdown("a", _)))
+            .unwrap()
+            .is_empty());
+
+        runtime.remove(&build_query!(start("a", _)));
+        runtime.poll();
+        assert_eq!(
+            vec![fact("b", 0), fact("b", 1)],
+            facts(&runtime, &build_query!(countdown(_, _)))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "that its body does not bind")]
+    fn test_head_expression_over_unbound_variable() {
+        let rule = Rule {
+            head: Atom {
+                terms: vec![Term::Expression(Expression::Variable("y".to_string()))],
+                symbol: "next".to_string(),
+                sign: true,
+            },
+            body: vec![Atom {
+                terms: vec![Term::Variable("x".to_string())],
+                symbol: "node".to_string(),
+                sign: true,
+            }],
+            id: 0,
+            hints: Default::default(),
+        };
+        let options = RuntimeOptions {
+            skolemize: true,
+            ..Default::default()
+        };
+
+        MicroRuntime::with_options(Program::from(vec![rule]), options);
+    }
+
+    #[test]
+    #[should_panic(expected = "that its body does not bind")]
+    fn test_existential_head_variable_without_skolemization() {
+        MicroRuntime::new(Program::from(vec![existential_parent_rule()]));
+    }
+
+    #[test]
+    fn test_programs_that_can_not_be_evaluated() {
+        let conflicting_arities = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            loop_(?x) <- [e(?x, ?x, ?x)],
+        };
+        assert!(MicroRuntime::try_new(conflicting_arities)
+            .err()
+            .unwrap()
+            .contains("e is used with"));
+
+        let unstratified = program! {
+            p(?x) <- [e(?x, ?x), !q(?x)],
+            q(?x) <- [e(?x, ?x), !p(?x)],
+        };
+        assert!(MicroRuntime::try_new(unstratified.clone()).is_err());
+
+        // A runtime that is given such a program keeps going with the one it had.
+        let mut runtime = MicroRuntime::new(program! { tc(?x, ?y) <- [e(?x, ?y)] });
+        runtime
+            .insert("e", vec![1usize.into(), 1usize.into()])
+            .unwrap();
+        assert!(runtime.replace_program(unstratified).is_err());
+        assert!(runtime.try_poll().is_ok());
+        assert!(runtime.exists(&build_query!(tc(1usize, 1usize))).unwrap());
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        new_facts_per_rule: Rc<RefCell<HashMap<String, usize>>>,
+        iterations: Rc<RefCell<usize>>,
+    }
+
+    impl EvaluationObserver for RecordingObserver {
+        fn rule_evaluated(&mut self, rule: &Rule, new_facts: usize) {
+            *self
+                .new_facts_per_rule
+                .borrow_mut()
+                .entry(format!("{:?}", rule))
+                .or_default() += new_facts;
+        }
+        fn iteration_finished(&mut self, _iteration: usize, _new_facts: usize) {
+            *self.iterations.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn integration_test_observer() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let observer = RecordingObserver::default();
+        let new_facts_per_rule = observer.new_facts_per_rule.clone();
+        let iterations = observer.iterations.clone();
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime.set_observer(observer);
+        vec![("a", "b"), ("b", "c"), ("c", "d")]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let expected: HashMap<String, usize> = vec![
+            (r#"tc("x", "y") <- [e("x", "y")]"#.to_string(), 3),
+            (
+                r#"tc("x", "z") <- [e("x", "y"), tc("y", "z")]"#.to_string(),
+                3,
+            ),
         ]
         .into_iter()
-        .for_each(|edge| {
-            runtime.insert("e", edge);
+        .collect();
+        assert_eq!(expected, *new_facts_per_rule.borrow());
+        // The nonrecursive pass, then tc(a, c) and tc(b, d), then tc(a, d), and then nothing.
+        assert_eq!(4, *iterations.borrow());
+    }
+
+    #[test]
+    fn integration_test_arity_validation() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        assert!(runtime
+            .insert("e", vec![1usize.into(), 2usize.into(), 3usize.into()])
+            .is_err());
+        assert!(runtime
+            .insert_many(
+                "e",
+                vec![vec![1usize.into(), 2usize.into()], vec![2usize.into()],],
+            )
+            .is_err());
+        assert!(runtime
+            .insert_columns("e", vec![vec![1usize.into()]])
+            .is_err());
+        // Nothing was inserted, not even the well formed fact of the batch.
+        assert!(runtime.safe());
+
+        // Relations that the program does not mention keep the arity they were first given.
+        assert!(runtime.insert("label", vec!["a".into()]).is_ok());
+        assert!(runtime
+            .insert("label", vec!["a".into(), "b".into()])
+            .is_err());
+
+        runtime.poll();
+        assert!(runtime.query(&build_query!(tc(_, _))).is_ok());
+        assert!(runtime.query(&build_query!(tc(_, _, _))).is_err());
+        assert!(runtime.query(&build_query!(tc(1usize))).is_err());
+        assert!(runtime.exists(&build_query!(e(_, _, _))).is_err());
+        assert!(runtime.query_project(&build_query!(tc(_)), &[0]).is_err());
+    }
+
+    #[test]
+    fn integration_test_type_validation() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            from_a(?y) <- [tc("a", ?y)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        assert_eq!(Some(ColumnType::Str), runtime.types().column_type("e", 1));
+        assert!(runtime
+            .insert("e", vec!["a".into(), 1usize.into()])
+            .is_err());
+        assert!(runtime
+            .insert_many(
+                "e",
+                vec![
+                    vec!["a".into(), "b".into()],
+                    vec![1usize.into(), "b".into()]
+                ],
+            )
+            .is_err());
+        assert!(runtime.safe());
+
+        let mut transaction = runtime.transaction();
+        assert!(transaction
+            .insert("e", vec![1usize.into(), 2usize.into()])
+            .is_err());
+        transaction.rollback();
+
+        // Rules that use a column as two types at once are rejected.
+        assert!(runtime
+            .add_rule(rule! { from_one(?y) <- [tc(1usize, ?y)] })
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_on_demand_queries() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            on_demand: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program.clone(), options);
+        runtime
+            .insert("e", vec![1usize.into(), 2usize.into()])
+            .unwrap();
+        runtime
+            .insert("e", vec![2usize.into(), 3usize.into()])
+            .unwrap();
+        runtime.poll();
+
+        // Pending insertions are visible to queries before any poll.
+        runtime
+            .insert("e", vec![3usize.into(), 4usize.into()])
+            .unwrap();
+        assert!(runtime
+            .contains("tc", &vec![1usize.into(), 4usize.into()])
+            .unwrap());
+
+        // And so are pending deletions, along with everything derived through them.
+        runtime.remove(&build_query!(e(1usize, 2usize)));
+        assert!(!runtime
+            .contains("tc", &vec![1usize.into(), 3usize.into()])
+            .unwrap());
+        let mut actual: Vec<_> = runtime
+            .query(&build_query!(tc(_, 4usize)))
+            .unwrap()
+            .collect();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec![2usize.into(), 4usize.into()],
+            vec![3usize.into(), 4usize.into()],
+        ];
+        assert_eq!(expected, actual);
+
+        // Answering on demand leaves the changes pending, and the poll agrees with it.
+        assert!(!runtime.safe());
+        runtime.poll();
+        let mut actual: Vec<_> = runtime
+            .query(&build_query!(tc(_, 4usize)))
+            .unwrap()
+            .collect();
+        actual.sort();
+        assert_eq!(expected, actual);
+
+        // Without the option, queries still wait for the poll.
+        let mut runtime = MicroRuntime::new(tc_program);
+        runtime
+            .insert("e", vec![1usize.into(), 2usize.into()])
+            .unwrap();
+        assert!(runtime
+            .contains("tc", &vec![1usize.into(), 2usize.into()])
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_budgeted_queries() {
+        let mut runtime = MicroRuntime::new(program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
         });
+        (0..50usize).for_each(|node| {
+            runtime
+                .insert("e", vec![node.into(), (node + 1).into()])
+                .unwrap();
+        });
+        let query = build_query!(tc(0usize, _));
+
+        // Budgets apply whether or not the changes are pending.
+        let first = QueryBudget {
+            max_answers: Some(5),
+            ..Default::default()
+        };
+        let answers = runtime.query_within(&query, &first).unwrap();
+        assert_eq!(5, answers.facts.len());
+        assert!(answers.truncated);
 
         runtime.poll();
+        let near = QueryBudget {
+            max_depth: Some(9),
+            ..Default::default()
+        };
+        let answers = runtime.query_within(&query, &near).unwrap();
+        assert_eq!(10, answers.facts.len());
+        assert!(answers.truncated);
+        assert!(answers
+            .facts
+            .iter()
+            .all(|fact| runtime.contains("tc", fact).unwrap()));
 
-        // This query reads as: "Get all in tc with any values in any positions"
-        let all = build_query!(tc(_, _));
-        // And this one as: "Get all in tc with the first term being a"
-        // There also is a QueryBuilder, if you do not want to use a macro.
-        let all_from_a = build_query!(tc("a", _));
+        let all = runtime
+            .query_within(&query, &QueryBudget::default())
+            .unwrap();
+        assert_eq!(50, all.facts.len());
+        assert!(!all.truncated);
+    }
 
-        let actual_all: HashSet<AnonymousGroundAtom> = runtime.query(&all).unwrap().collect();
-        let expected_all: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
-            // Second iter
-            vec!["a".into(), "c".into()],
-            vec!["b".into(), "d".into()],
-            // Third iter
-            vec!["a".into(), "d".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all, actual_all);
+    #[test]
+    fn integration_test_self_joins() {
+        let mut linear = MicroRuntime::new(program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        });
+        let mut nonlinear = MicroRuntime::new(program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+        });
+        let sorted = |runtime: &MicroRuntime| {
+            let mut facts: Vec<_> = runtime.query(&build_query!(tc(_, _))).unwrap().collect();
+            facts.sort();
+
+            facts
+        };
+
+        for runtime in [&mut linear, &mut nonlinear] {
+            (0usize..8).for_each(|node| {
+                runtime
+                    .insert("e", vec![node.into(), (node + 1).into()])
+                    .unwrap();
+            });
+            runtime
+                .insert("e", vec![8usize.into(), 3usize.into()])
+                .unwrap();
+            runtime.poll();
+        }
+        assert_eq!(sorted(&linear), sorted(&nonlinear));
+
+        for runtime in [&mut linear, &mut nonlinear] {
+            runtime.remove(&build_query!(e(4usize, 5usize)));
+            runtime
+                .insert("e", vec![2usize.into(), 6usize.into()])
+                .unwrap();
+            runtime.poll();
+        }
+        assert_eq!(sorted(&linear), sorted(&nonlinear));
+        assert!(nonlinear.exists(&build_query!(tc(0usize, 8usize))).unwrap());
+        assert!(!nonlinear.exists(&build_query!(tc(4usize, 5usize))).unwrap());
+    }
+
+    #[test]
+    fn integration_test_generic_joins() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            triangle(?x, ?y, ?z) <- [e(?x, ?y), e(?y, ?z), e(?z, ?x)],
+            closing(?x, ?y, ?z) <- [tc(?x, ?y), e(?y, ?z), tc(?z, ?x), !blocked(?x)],
+        };
+        let mut binary = MicroRuntime::new(program.clone());
+        let options = RuntimeOptions {
+            generic_joins: true,
+            ..Default::default()
+        };
+        let mut generic = MicroRuntime::with_options(program, options);
+
+        let sorted = |runtime: &MicroRuntime, query: &Query| {
+            let mut facts: Vec<_> = runtime.query(query).unwrap().collect();
+            facts.sort();
+
+            facts
+        };
+        let compare = |binary: &MicroRuntime, generic: &MicroRuntime| {
+            for query in [
+                build_query!(triangle(_, _, _)),
+                build_query!(closing(_, _, _)),
+            ] {
+                assert_eq!(sorted(binary, &query), sorted(generic, &query));
+            }
+        };
+
+        for runtime in [&mut binary, &mut generic] {
+            vec![(1usize, 2usize), (2, 3), (3, 1), (3, 4), (4, 5), (5, 3)]
+                .into_iter()
+                .for_each(|(from, to)| {
+                    runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+                });
+            runtime.insert("blocked", vec![4usize.into()]).unwrap();
+            runtime.poll();
+        }
+        compare(&binary, &generic);
+        assert_eq!(
+            6,
+            generic
+                .query(&build_query!(triangle(_, _, _)))
+                .unwrap()
+                .count()
+        );
+
+        for runtime in [&mut binary, &mut generic] {
+            runtime.remove(&build_query!(e(3usize, 1usize)));
+            runtime
+                .insert("e", vec![2usize.into(), 4usize.into()])
+                .unwrap();
+            runtime.poll();
+        }
+        compare(&binary, &generic);
+        assert_eq!(
+            3,
+            generic
+                .query(&build_query!(triangle(_, _, _)))
+                .unwrap()
+                .count()
+        );
+    }
+
+    #[test]
+    fn integration_test_plan_hints() {
+        let program = program! {
+            #[no_reorder]
+            triangle(?x, ?y, ?z) <- [e(?x, ?y), e(?y, ?z), e(?z, ?x)],
+            #[no_index]
+            #[index(f(1))]
+            reach(?x, ?z) <- [f(?x, ?y), f(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            generic_joins: true,
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(program, options.clone());
+
+        let triangle_rule = runtime
+            .program
+            .inner
+            .iter()
+            .find(|rule| rule.head.symbol == "triangle")
+            .unwrap();
+        assert!(!runtime
+            .stacks
+            .get(triangle_rule)
+            .unwrap()
+            .inner
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::GenericJoin(_))));
+        assert!(runtime.processed.get_index("f", &[1]).is_some());
+        assert!(runtime.processed.get_index("f", &[0]).is_none());
+
+        for (from, to) in [(1usize, 2usize), (2, 3), (3, 1)] {
+            runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            runtime.insert("f", vec![from.into(), to.into()]).unwrap();
+        }
+        runtime.poll();
+        assert_eq!(
+            3,
+            runtime
+                .query(&build_query!(triangle(_, _, _)))
+                .unwrap()
+                .count()
+        );
+        assert!(runtime
+            .exists(&build_query!(reach(1usize, 3usize)))
+            .unwrap());
+
+        let out_of_range = program! {
+            #[index(e(2))]
+            tc(?x, ?y) <- [e(?x, ?y)],
+        };
+        assert!(MicroRuntime::try_with_options(out_of_range, options)
+            .err()
+            .unwrap()
+            .contains("asks for an index over column 2 of e, which has 2 terms"));
+    }
+
+    #[test]
+    #[should_panic(expected = "can not keep its join order, as b shares no variable")]
+    fn test_plan_hints_with_disconnected_order() {
+        let program = program! {
+            #[no_reorder]
+            p(?x, ?y) <- [a(?x), b(?y), c(?x, ?y)],
+        };
+
+        MicroRuntime::new(program);
+    }
+
+    #[test]
+    fn integration_test_exists() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            on_demand: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+
+        // Before the poll, the answer is found on demand.
+        assert!(runtime.exists(&build_query!(tc(1usize, 4usize))).unwrap());
+        assert!(!runtime.exists(&build_query!(tc(4usize, _))).unwrap());
+
+        runtime.poll();
+        assert!(runtime.exists(&build_query!(tc(1usize, _))).unwrap());
+        assert!(!runtime.exists(&build_query!(tc(_, 1usize))).unwrap());
+    }
+
+    #[test]
+    fn integration_test_exclusion_and_range_matchers() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            on_demand: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime
+            .insert("e", vec![4usize.into(), "end".into()])
+            .unwrap();
+        let sorted = |runtime: &MicroRuntime, query: &Query| {
+            let mut actual: Vec<_> = runtime.query(query).unwrap().collect();
+            actual.sort();
+            actual
+        };
+
+        // Before the poll, on demand, and after it.
+        for _ in 0..2 {
+            let expected: Vec<AnonymousGroundAtom> = vec![
+                vec![1usize.into(), "end".into()],
+                vec![1usize.into(), 3usize.into()],
+                vec![1usize.into(), 4usize.into()],
+            ];
+            assert_eq!(
+                expected,
+                sorted(&runtime, &build_query!(tc(1usize, !2usize)))
+            );
+
+            // Ranges leave out values of other types.
+            let expected: Vec<AnonymousGroundAtom> = vec![
+                vec![2usize.into(), 3usize.into()],
+                vec![2usize.into(), 4usize.into()],
+                vec![3usize.into(), 4usize.into()],
+            ];
+            assert_eq!(
+                expected,
+                sorted(&runtime, &build_query!(tc(>= 2usize, <= 4usize)))
+            );
+            assert!(!runtime
+                .exists(&build_query!(tc(>= 2usize, <= 2usize)))
+                .unwrap());
+            assert!(runtime.exists(&build_query!(tc(4usize, !4usize))).unwrap());
+
+            runtime.poll();
+        }
+
+        assert!(runtime
+            .create_view("not_from_one", &build_query!(tc(!1usize, _)))
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_derivation_depths() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            record_depths: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program.clone(), options.clone());
+        vec![(0usize, 1usize), (1, 2), (2, 3)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        assert!(runtime.query_with_depth(&build_query!(tc(_, _))).is_err());
+        runtime.poll();
+
+        // In a closure, the depth of a path is its length.
+        let depths = |runtime: &MicroRuntime, query: &Query| {
+            let mut actual = runtime.query_with_depth(query).unwrap();
+            actual.sort();
+            actual
+        };
+        let paths = vec![
+            (vec![0usize.into(), 1usize.into()], 1),
+            (vec![0usize.into(), 2usize.into()], 2),
+            (vec![0usize.into(), 3usize.into()], 3),
+        ];
+        assert_eq!(paths, depths(&runtime, &build_query!(tc(0usize, _))));
+        let expected = vec![(vec![1usize.into(), 2usize.into()], 0)];
+        assert_eq!(expected, depths(&runtime, &build_query!(e(1usize, _))));
+
+        // Deleted facts are forgotten, and derived again from the changes that bring them back.
+        runtime.remove(&build_query!(e(1usize, 2usize)));
+        runtime.poll();
+        assert_eq!(1, depths(&runtime, &build_query!(tc(0usize, _))).len());
+        runtime
+            .insert("e", vec![1usize.into(), 2usize.into()])
+            .unwrap();
+        runtime.poll();
+        assert_eq!(paths, depths(&runtime, &build_query!(tc(0usize, _))));
+
+        assert!(MicroRuntime::new(tc_program.clone())
+            .query_with_depth(&build_query!(tc(_, _)))
+            .is_err());
+        let options = RuntimeOptions {
+            count_derivations: true,
+            ..options
+        };
+        assert!(MicroRuntime::try_with_options(tc_program, options).is_err());
+    }
+
+    #[test]
+    fn integration_test_query_many() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        let options = RuntimeOptions {
+            on_demand: true,
+            ..Default::default()
+        };
+
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        let queries = vec![
+            build_query!(tc(1usize, _)),
+            build_query!(e(_, 4usize)),
+            build_query!(tc(2usize, _)),
+            build_query!(tc(_, 1usize)),
+        ];
+        let sorted_answers = |runtime: &MicroRuntime| {
+            let mut answers = runtime.query_many(&queries).unwrap();
+            answers.iter_mut().for_each(|facts| facts.sort());
+            answers
+        };
+
+        // Every query gets the answers it would get on its own, whether evaluated on demand or not.
+        let expected: Vec<Vec<AnonymousGroundAtom>> = queries
+            .iter()
+            .map(|query| {
+                let mut facts: Vec<_> = runtime.query(query).unwrap().collect();
+                facts.sort();
+                facts
+            })
+            .collect();
+        assert_eq!(expected, sorted_answers(&runtime));
+        assert_eq!(3, expected[0].len());
+
+        runtime.poll();
+        assert_eq!(expected, sorted_answers(&runtime));
+        assert!(runtime
+            .query_many(&[build_query!(tc(1usize, 2usize, 3usize))])
+            .is_err());
+    }
+
+    #[test]
+    fn integration_test_components() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            reachable(?y) <- [tc(1usize, ?y)],
+            even(?x) <- [zero(?x)],
+            odd(?y) <- [even(?x), succ(?x, ?y)],
+            even(?y) <- [odd(?x), succ(?x, ?y)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+                runtime
+                    .insert("succ", vec![from.into(), to.into()])
+                    .unwrap();
+            });
+        runtime.insert("zero", vec![1usize.into()]).unwrap();
+        runtime.poll();
+
+        // A rule that reads a recursive relation sees all of it, not only its first iteration.
+        let mut actual: Vec<_> = runtime
+            .query(&build_query!(reachable(_)))
+            .unwrap()
+            .collect();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec![2usize.into()],
+            vec![3usize.into()],
+            vec![4usize.into()],
+        ];
+        assert_eq!(expected, actual);
+
+        // And mutually recursive relations are evaluated together to a fixpoint.
+        let mut actual: Vec<_> = runtime.query(&build_query!(even(_))).unwrap().collect();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![vec![1usize.into()], vec![3usize.into()]];
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn integration_test_prepared_queries() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime.poll();
+
+        let reachable_from = prepare_query!(tc(?1, _));
+        runtime.prepare(&reachable_from).unwrap();
+
+        let mut actual = runtime.execute(&reachable_from, &[2usize.into()]).unwrap();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec![2usize.into(), 3usize.into()],
+            vec![2usize.into(), 4usize.into()],
+        ];
+        assert_eq!(expected, actual);
+
+        // The index follows the relation as it changes.
+        runtime
+            .insert("e", vec![4usize.into(), 5usize.into()])
+            .unwrap();
+        runtime.poll();
+        assert_eq!(
+            4,
+            runtime
+                .execute(&reachable_from, &[1usize.into()])
+                .unwrap()
+                .len()
+        );
+
+        let edge = prepare_query!(tc(?2, ?1));
+        assert_eq!(
+            vec![vec![TypedValue::from(3usize), 5usize.into()]],
+            runtime
+                .execute(&edge, &[5usize.into(), 3usize.into()])
+                .unwrap()
+        );
+
+        assert!(runtime.execute(&reachable_from, &[]).is_err());
+        assert!(runtime.prepare(&prepare_query!(tc(?1))).is_err());
+
+        // The index outlives the program it was prepared under.
+        runtime
+            .replace_program(program! { tc(?x, ?y) <- [e(?x, ?y)] })
+            .unwrap();
+        runtime.poll();
+        assert!(runtime.processed.get_index("tc", &[0]).is_some());
+        assert_eq!(
+            1,
+            runtime
+                .execute(&reachable_from, &[2usize.into()])
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn integration_test_add_and_remove_rules() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            source(?x) <- [tc(?x, _)],
+            named(?x) <- [name(?x, _)],
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        let deltas = Rc::new(RefCell::new(0));
+        let subscriber_deltas = deltas.clone();
+        runtime.subscribe("named", move |_| *subscriber_deltas.borrow_mut() += 1);
+        runtime
+            .insert("e", vec![1usize.into(), 2usize.into()])
+            .unwrap();
+        runtime
+            .insert("e", vec![2usize.into(), 3usize.into()])
+            .unwrap();
+        runtime
+            .insert("link", vec![3usize.into(), 4usize.into()])
+            .unwrap();
+        runtime
+            .insert("name", vec![1usize.into(), "a".into()])
+            .unwrap();
+        runtime.poll();
+        assert_eq!(1, *deltas.borrow());
+
+        let id = runtime
+            .add_rule(rule! { tc(?x, ?y) <- [link(?x, ?y)] })
+            .unwrap();
+        runtime.poll();
 
-        let actual_all_from_a: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all_from_a).unwrap().collect();
-        let expected_all_from_a: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["a".into(), "c".into()],
-            vec!["a".into(), "d".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all_from_a, actual_all_from_a);
+        // Everything downstream of the new rule sees its facts.
+        assert!(runtime
+            .contains("tc", &vec![1usize.into(), 4usize.into()])
+            .unwrap());
+        assert!(runtime.contains("source", &vec![3usize.into()]).unwrap());
+        // While relations that do not depend on it are left untouched.
+        assert_eq!(1, *deltas.borrow());
+        assert!(runtime
+            .add_rule(rule! { tc(?x, ?y) <- [e(?x, ?y), link(?y, _)] })
+            .is_err());
 
-        expected_all.iter().for_each(|fact| {
-            assert!(runtime.contains("tc", fact).unwrap());
-        });
+        let removed = runtime.remove_rule(id).unwrap();
+        let mut expected_removed = rule! { tc(?x, ?y) <- [link(?x, ?y)] };
+        expected_removed.id = id;
+        assert_eq!(expected_removed, removed);
+        runtime.poll();
+        assert!(!runtime
+            .contains("tc", &vec![1usize.into(), 4usize.into()])
+            .unwrap());
+        assert!(!runtime.contains("source", &vec![3usize.into()]).unwrap());
+        assert!(runtime
+            .contains("tc", &vec![1usize.into(), 3usize.into()])
+            .unwrap());
+        assert_eq!(1, *deltas.borrow());
 
-        expected_all_from_a.iter().for_each(|fact| {
-            assert!(runtime.contains("tc", fact).unwrap());
-        });
+        assert!(runtime.remove_rule(runtime.program().inner.len()).is_err());
+    }
 
-        // Update
-        runtime.insert("e", vec!["d".into(), "e".into()]);
-        assert!(!runtime.safe());
+    #[test]
+    fn integration_test_rule_ids_survive_program_edits() {
+        let mut runtime = MicroRuntime::new(program! { z(?x) <- [e(?x)] });
+        runtime.insert("e", vec![1usize.into()]).unwrap();
+        runtime.insert("f", vec![2usize.into()]).unwrap();
+
+        let id = runtime.add_rule(rule! { z(?x) <- [f(?x)] }).unwrap();
+        // Sorts before both rules above, which shifts their positions.
+        let other_id = runtime.add_rule(rule! { a(?x) <- [e(?x)] }).unwrap();
+        assert_ne!(id, other_id);
+
+        let removed = runtime.remove_rule(id).unwrap();
+        let mut expected_removed = rule! { z(?x) <- [f(?x)] };
+        expected_removed.id = id;
+        assert_eq!(expected_removed, removed);
         runtime.poll();
-        assert!(runtime.safe());
+        assert!(runtime.contains("z", &vec![1usize.into()]).unwrap());
+        assert!(!runtime.contains("z", &vec![2usize.into()]).unwrap());
+        assert!(runtime.contains("a", &vec![1usize.into()]).unwrap());
 
-        let actual_all_after_update: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all).unwrap().collect();
-        let expected_all_after_update: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
-            // Second iter
-            vec!["a".into(), "c".into()],
-            vec!["b".into(), "d".into()],
-            // Third iter
-            vec!["a".into(), "d".into()],
-            // Update
-            vec!["d".into(), "e".into()],
-            vec!["c".into(), "e".into()],
-            vec!["b".into(), "e".into()],
-            vec!["a".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all_after_update, actual_all_after_update);
+        // Ids are not handed out twice, even once their rule is gone.
+        assert!(runtime.remove_rule(id).is_err());
+        let view_id = runtime
+            .create_view("ones", &build_query!(e(1usize)))
+            .unwrap();
+        assert!(view_id != id && view_id != other_id);
+        assert!(runtime.remove_rule(other_id).is_ok());
+        assert!(runtime.remove_rule(view_id).is_ok());
+    }
+
+    #[test]
+    fn integration_test_negation_under_incremental_insertion() {
+        let program = stratified_program! {
+            reach(?y) <- [start(?x), e(?x, ?y)],
+            reach(?z) <- [reach(?y), e(?y, ?z)],
+            unreached(?x) <- [node(?x), !reach(?x)],
+            not_from_one(?x) <- [node(?x), !e(1usize, ?x)]
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        vec![1usize, 2, 3, 4].into_iter().for_each(|node| {
+            runtime.insert("node", vec![node.into()]).unwrap();
+        });
+        runtime.insert("start", vec![1usize.into()]).unwrap();
+        runtime.poll();
+
+        let sorted = |runtime: &MicroRuntime, query: &Query| {
+            let mut facts: Vec<_> = runtime.query(query).unwrap().collect();
+            facts.sort();
+            facts
+        };
+        let nodes = |values: Vec<usize>| -> Vec<AnonymousGroundAtom> {
+            values.into_iter().map(|value| vec![value.into()]).collect()
+        };
 
-        let actual_all_from_a_after_update: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all_from_a).unwrap().collect();
-        let expected_all_from_a_after_update: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["a".into(), "c".into()],
-            vec!["a".into(), "d".into()],
-            vec!["a".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
         assert_eq!(
-            expected_all_from_a_after_update,
-            actual_all_from_a_after_update
+            nodes(vec![1, 2, 3, 4]),
+            sorted(&runtime, &build_query!(unreached(_)))
+        );
+
+        // Every poll grows the negated relations, and what they now rule out is gone.
+        vec![(1usize, 2usize), (2, 3), (3, 4)]
+            .into_iter()
+            .zip([vec![1, 3, 4], vec![1, 4], vec![1]])
+            .for_each(|((from, to), expected)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+                runtime.poll();
+
+                assert_eq!(
+                    nodes(expected),
+                    sorted(&runtime, &build_query!(unreached(_)))
+                );
+            });
+        assert_eq!(
+            nodes(vec![1, 3, 4]),
+            sorted(&runtime, &build_query!(not_from_one(_)))
         );
     }
+
     #[test]
-    fn integration_test_deletions() {
-        // Queries. The explanation is in the test above
-        let all = build_query!(tc(_, _));
-        let all_from_a = build_query!(tc("a", _));
+    fn integration_test_negation_keeps_inserted_facts() {
+        let program = stratified_program! {
+            p(?x) <- [a(?x), !b(?x)]
+        };
+
+        let mut runtime = MicroRuntime::new(program);
+        runtime.insert("a", vec![1usize.into()]).unwrap();
+        runtime.insert("p", vec![5usize.into()]).unwrap();
+        runtime.poll();
+        runtime.insert("b", vec![1usize.into()]).unwrap();
+        runtime.poll();
+
+        // No rule derived p(5), so b growing takes away p(1) alone.
+        let actual: Vec<_> = runtime.query(&build_query!(p(_))).unwrap().collect();
+        assert_eq!(vec![vec![TypedValue::from(5usize)]], actual);
+
+        // Once removed, it is not kept from being taken away by negations anymore.
+        runtime.remove(&build_query!(p(5usize)));
+        runtime.insert("a", vec![5usize.into()]).unwrap();
+        runtime.poll();
+        runtime.insert("b", vec![5usize.into()]).unwrap();
+        runtime.poll();
+        assert_eq!(0, runtime.query(&build_query!(p(_))).unwrap().count());
+    }
 
+    #[test]
+    fn integration_test_relations_outside_the_program() {
         let tc_program = program! {
             tc(?x, ?y) <- [e(?x, ?y)],
-            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
         };
 
-        let mut runtime = MicroRuntime::new(tc_program);
-        vec![
-            vec!["a".into(), "b".into()],
-            // this extra atom will help with testing that rederivation works
-            vec!["a".into(), "e".into()],
-            vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
-            vec!["d".into(), "e".into()],
-        ]
-        .into_iter()
-        .for_each(|edge| {
-            runtime.insert("e", edge);
-        });
+        let mut runtime = MicroRuntime::new(tc_program.clone());
+        // Relations that nothing mentions hold nothing.
+        assert_eq!(
+            0,
+            runtime.query(&build_query!(label(_, _))).unwrap().count()
+        );
+        runtime.remove(&build_query!(label(1usize, _)));
 
+        runtime
+            .insert("label", vec![1usize.into(), "one".into()])
+            .unwrap();
         runtime.poll();
+        let actual: Vec<_> = runtime
+            .query(&build_query!(label(1usize, _)))
+            .unwrap()
+            .collect();
+        let expected: Vec<AnonymousGroundAtom> = vec![vec![1usize.into(), "one".into()]];
+        assert_eq!(expected, actual);
 
-        let actual_all: HashSet<AnonymousGroundAtom> = runtime.query(&all).unwrap().collect();
-        let expected_all: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["a".into(), "e".into()],
-            vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
-            // Second iter
-            vec!["a".into(), "c".into()],
-            vec!["b".into(), "d".into()],
-            // Third iter
-            vec!["a".into(), "d".into()],
-            // Fourth iter
-            vec!["d".into(), "e".into()],
-            vec!["c".into(), "e".into()],
-            vec!["b".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all, actual_all);
-
-        let actual_all_from_a: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all_from_a).unwrap().collect();
-        let expected_all_from_a: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["a".into(), "c".into()],
-            vec!["a".into(), "d".into()],
-            vec!["a".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all_from_a, actual_all_from_a);
-
-        // Update
-        // Point removals are a bit annoying, since they incur creating a query.
-        let d_to_e = build_query!(e("d", "e"));
-        runtime.remove(&d_to_e);
-        assert!(!runtime.safe());
+        runtime.remove(&build_query!(label(1usize, _)));
         runtime.poll();
-        assert!(runtime.safe());
+        assert_eq!(
+            0,
+            runtime.query(&build_query!(label(_, _))).unwrap().count()
+        );
 
-        let actual_all_after_update: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all).unwrap().collect();
-        let expected_all_after_update: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["b".into(), "c".into()],
-            vec!["c".into(), "d".into()],
-            // Second iter
-            vec!["a".into(), "c".into()],
-            vec!["b".into(), "d".into()],
-            // Third iter
-            vec!["a".into(), "d".into()],
-            // This remains
-            vec!["a".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_all_after_update, actual_all_after_update);
+        // Unless they are asked to be strict about them.
+        let options = RuntimeOptions {
+            strict_relations: true,
+            ..Default::default()
+        };
+        let mut runtime = MicroRuntime::with_options(tc_program, options);
+        assert!(runtime.query(&build_query!(label(_, _))).is_err());
+        assert_eq!(0, runtime.query(&build_query!(e(_, _))).unwrap().count());
 
-        let actual_all_from_a_after_update: HashSet<AnonymousGroundAtom> =
-            runtime.query(&all_from_a).unwrap().collect();
-        let expected_all_from_a_after_update: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["a".into(), "c".into()],
-            vec!["a".into(), "d".into()],
-            vec!["a".into(), "e".into()],
-        ]
-        .into_iter()
-        .collect();
+        runtime
+            .insert("label", vec![1usize.into(), "one".into()])
+            .unwrap();
+        runtime.poll();
         assert_eq!(
-            expected_all_from_a_after_update,
-            actual_all_from_a_after_update
+            1,
+            runtime.query(&build_query!(label(_, _))).unwrap().count()
         );
     }
 
     #[test]
-    fn integration_test_stratified_evaluation() {
-        let stratified_program = program! {
-            // Stratum 1: Base rule
-            base(?x, ?y) <- [edge(?x, ?y)],
-
-            // Stratum 2: Derived rule depends on Stratum 1
-            derived(?x, ?y) <- [base(?x, ?y)],
-            derived(?x, ?z) <- [base(?x, ?y), derived(?y, ?z)],
-
-            // Stratum 3: Another level of derivation
-            top(?x, ?z) <- [derived(?x, ?y), base(?y, ?z)],
+    fn integration_test_views() {
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
         };
 
-        let mut runtime = MicroRuntime::new(stratified_program);
-
-        // Insert facts into the base layer (Stratum 1)
-        vec![vec!["a".into(), "b".into()], vec!["b".into(), "c".into()]]
+        let mut runtime = MicroRuntime::new(tc_program);
+        vec![(1usize, 2usize), (2, 3), (4, 5)]
             .into_iter()
-            .for_each(|edge| {
-                runtime.insert("edge", edge);
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
             });
-
         runtime.poll();
 
-        // Query and assert expectations for each stratum
-        // Expected results for Stratum 1: `base`
-        let base_query = build_query!(base(_, _));
-        let actual_base: HashSet<AnonymousGroundAtom> =
-            runtime.query(&base_query).unwrap().collect();
-        let expected_base: HashSet<AnonymousGroundAtom> =
-            vec![vec!["a".into(), "b".into()], vec!["b".into(), "c".into()]]
-                .into_iter()
+        let id = runtime
+            .create_view("reachable_from_one", &build_query!(tc(1usize, _)))
+            .unwrap();
+        runtime.poll();
+        let reachable = |runtime: &MicroRuntime| {
+            let mut actual: Vec<_> = runtime
+                .query(&build_query!(reachable_from_one(_)))
+                .unwrap()
                 .collect();
-        assert_eq!(expected_base, actual_base);
+            actual.sort();
+            actual
+        };
+        let expected: Vec<AnonymousGroundAtom> = vec![vec![2usize.into()], vec![3usize.into()]];
+        assert_eq!(expected, reachable(&runtime));
 
-        // Expected results for Stratum 2: `derived`
-        let derived_query = build_query!(derived(_, _));
-        let actual_derived: HashSet<AnonymousGroundAtom> =
-            runtime.query(&derived_query).unwrap().collect();
-        let expected_derived: HashSet<AnonymousGroundAtom> = vec![
-            vec!["a".into(), "b".into()],
-            vec!["b".into(), "c".into()],
-            vec!["a".into(), "c".into()],
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(expected_derived, actual_derived);
+        // The view is kept up to date like any other derived relation.
+        runtime
+            .insert("e", vec![3usize.into(), 4usize.into()])
+            .unwrap();
+        runtime.remove(&build_query!(e(1usize, 2usize)));
+        runtime.poll();
+        assert!(reachable(&runtime).is_empty());
+        runtime
+            .insert("e", vec![1usize.into(), 4usize.into()])
+            .unwrap();
+        runtime.poll();
+        let expected: Vec<AnonymousGroundAtom> = vec![vec![4usize.into()], vec![5usize.into()]];
+        assert_eq!(expected, reachable(&runtime));
 
-        // Expected results for Stratum 3: `top`
-        let top_query = build_query!(top(_, _));
-        let actual_top: HashSet<AnonymousGroundAtom> = runtime.query(&top_query).unwrap().collect();
-        let expected_top: HashSet<AnonymousGroundAtom> =
-            vec![vec!["a".into(), "c".into()]].into_iter().collect();
-        assert_eq!(expected_top, actual_top);
+        // Names can not be taken twice.
+        assert!(runtime
+            .create_view("tc", &build_query!(e(_, 1usize)))
+            .is_err());
+        assert!(runtime
+            .create_view("reachable_from_one", &build_query!(e(_, 1usize)))
+            .is_err());
 
-        // Test deletions to check if stratified rederivation works correctly
-        let edge_b_to_c = build_query!(edge("b", "c"));
-        runtime.remove(&edge_b_to_c);
+        runtime.remove_rule(id).unwrap();
         runtime.poll();
+        assert_eq!(
+            0,
+            runtime
+                .query(&build_query!(reachable_from_one(_)))
+                .unwrap()
+                .count()
+        );
+    }
 
-        // After deletion, only certain derived facts should remain
-        let actual_derived_after_delete: HashSet<AnonymousGroundAtom> =
-            runtime.query(&derived_query).unwrap().collect();
-        let expected_derived_after_delete: HashSet<AnonymousGroundAtom> =
-            vec![vec!["a".into(), "b".into()]].into_iter().collect();
-        assert_eq!(expected_derived_after_delete, actual_derived_after_delete);
+    #[test]
+    fn integration_test_specialized_queries() {
+        let program = program! {
+            path(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+            labelled(?x, ?l) <- [path(?x, ?y), label(?y, ?l)],
+            labelled(?x, ?l) <- [name(?x, ?l)],
+        };
+        let options = RuntimeOptions {
+            on_demand: true,
+            ..Default::default()
+        };
 
-        let actual_top_after_delete: HashSet<AnonymousGroundAtom> =
-            runtime.query(&top_query).unwrap().collect();
-        let expected_top_after_delete: HashSet<AnonymousGroundAtom> = HashSet::new();
-        assert_eq!(expected_top_after_delete, actual_top_after_delete);
+        let mut runtime = MicroRuntime::with_options(program, options);
+        vec![(1usize, 2usize), (2, 3), (2, 4), (5, 2)]
+            .into_iter()
+            .for_each(|(from, to)| {
+                runtime.insert("e", vec![from.into(), to.into()]).unwrap();
+            });
+        runtime
+            .insert("label", vec![3usize.into(), "c".into()])
+            .unwrap();
+        runtime
+            .insert("label", vec![4usize.into(), "d".into()])
+            .unwrap();
+        runtime
+            .insert("name", vec![1usize.into(), "a".into()])
+            .unwrap();
+        runtime.poll();
+        runtime.remove(&build_query!(e(2usize, 4usize)));
+        runtime
+            .insert("name", vec![5usize.into(), "e".into()])
+            .unwrap();
+
+        // Before the poll, the answers are derived from the pending changes for the constants
+        // that the query binds.
+        let labelled = |runtime: &MicroRuntime| {
+            let mut actual: Vec<_> = runtime
+                .query(&build_query!(labelled(1usize, _)))
+                .unwrap()
+                .collect();
+            actual.sort();
+            actual
+        };
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec![1usize.into(), "a".into()],
+            vec![1usize.into(), "c".into()],
+        ];
+        assert_eq!(expected, labelled(&runtime));
+        let actual: Vec<_> = runtime
+            .query(&build_query!(labelled(_, "e")))
+            .unwrap()
+            .collect();
+        let expected_e: Vec<AnonymousGroundAtom> = vec![vec![5usize.into(), "e".into()]];
+        assert_eq!(expected_e, actual);
+
+        runtime.poll();
+        assert_eq!(expected, labelled(&runtime));
     }
 }
@@ -0,0 +1,161 @@
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+use datalog_syntax::{AnonymousGroundAtom, OrderedFloat, TypedValue};
+
+// The type that the values of a column of a delimited file are read as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Str,
+    Int,
+    Bool,
+    SignedInt,
+    Float,
+}
+
+impl ColumnType {
+    pub fn of(value: &TypedValue) -> Self {
+        match value {
+            TypedValue::Str(_) => ColumnType::Str,
+            TypedValue::Int(_) => ColumnType::Int,
+            TypedValue::Bool(_) => ColumnType::Bool,
+            TypedValue::SignedInt(_) => ColumnType::SignedInt,
+            TypedValue::Float(_) => ColumnType::Float,
+        }
+    }
+    pub fn parse(&self, field: &str) -> Result<TypedValue, String> {
+        let invalid = || format!("{:?} is not a valid {:?}", field, self);
+
+        match self {
+            ColumnType::Str => Ok(TypedValue::from(field)),
+            ColumnType::Int => field
+                .trim()
+                .parse()
+                .map(TypedValue::Int)
+                .map_err(|_| invalid()),
+            ColumnType::Bool => field
+                .trim()
+                .parse()
+                .map(TypedValue::Bool)
+                .map_err(|_| invalid()),
+            ColumnType::SignedInt => field
+                .trim()
+                .parse()
+                .map(TypedValue::SignedInt)
+                .map_err(|_| invalid()),
+            ColumnType::Float => field
+                .trim()
+                .parse()
+                .map(|float| TypedValue::Float(OrderedFloat(float)))
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+// Fields may be enclosed in double quotes, within which the delimiter has no special meaning and
+// a doubled quote stands for a single one.
+fn split_line(line: &str, delimiter: char) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut characters = line.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '"' if quoted => {
+                if characters.peek() == Some(&'"') {
+                    characters.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            character if character == delimiter && !quoted => {
+                fields.push(std::mem::take(&mut field));
+            }
+            character => field.push(character),
+        }
+    }
+
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+
+    Ok(fields)
+}
+
+pub fn read_delimited(
+    reader: impl BufRead,
+    schema: &[ColumnType],
+    delimiter: char,
+) -> Result<Vec<AnonymousGroundAtom>, String> {
+    let mut facts = vec![];
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+
+        let at_line = |error: String| format!("line {}: {}", line_idx + 1, error);
+        let fields = split_line(line, delimiter).map_err(at_line)?;
+        if fields.len() != schema.len() {
+            return Err(at_line(format!(
+                "expected {} fields, found {}",
+                schema.len(),
+                fields.len()
+            )));
+        }
+
+        let fact = fields
+            .iter()
+            .zip(schema)
+            .map(|(field, column_type)| column_type.parse(field))
+            .collect::<Result<_, _>>()
+            .map_err(at_line)?;
+        facts.push(fact);
+    }
+
+    Ok(facts)
+}
+
+fn write_field(writer: &mut impl Write, value: &TypedValue, delimiter: char) -> Result<(), String> {
+    let field = match value {
+        TypedValue::Str(inner) => {
+            if inner.contains([delimiter, '"', '\n', '\r']) {
+                format!("\"{}\"", inner.replace('"', "\"\""))
+            } else {
+                inner.to_string()
+            }
+        }
+        TypedValue::Int(inner) => inner.to_string(),
+        TypedValue::Bool(inner) => inner.to_string(),
+        TypedValue::SignedInt(inner) => inner.to_string(),
+        TypedValue::Float(inner) => inner.0.to_string(),
+    };
+
+    writer
+        .write_all(field.as_bytes())
+        .map_err(|error| error.to_string())
+}
+
+pub fn write_delimited(
+    writer: &mut impl Write,
+    facts: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
+    delimiter: char,
+) -> Result<(), String> {
+    for fact in facts {
+        for (idx, value) in fact.iter().enumerate() {
+            if idx > 0 {
+                write!(writer, "{}", delimiter).map_err(|error| error.to_string())?;
+            }
+            write_field(writer, value, delimiter)?;
+        }
+        writeln!(writer).map_err(|error| error.to_string())?;
+    }
+
+    Ok(())
+}
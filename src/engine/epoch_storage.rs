@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use ahash::HashMap;
+use datalog_syntax::AnonymousGroundAtom;
+
+use super::journal::Journal;
+
+pub type Epoch = usize;
+
+// Remembers the poll epoch in which every fact currently in the processed storage first appeared.
+#[derive(Clone, Default)]
+pub struct EpochStorage {
+    pub(crate) inner: HashMap<String, HashMap<Arc<AnonymousGroundAtom>, Epoch>>,
+    pub(crate) journal: Journal<Arc<AnonymousGroundAtom>, Epoch>,
+}
+
+impl EpochStorage {
+    pub fn tag<'a>(
+        &mut self,
+        relation_symbol: &str,
+        facts: impl Iterator<Item = &'a Arc<AnonymousGroundAtom>>,
+        epoch: Epoch,
+    ) {
+        let relation = self.inner.entry(relation_symbol.to_string()).or_default();
+
+        facts.for_each(|fact| {
+            if !relation.contains_key(fact) {
+                relation.insert(fact.clone(), epoch);
+                self.journal.record(relation_symbol, fact, None);
+            }
+        });
+    }
+    pub fn forget<'a>(
+        &mut self,
+        relation_symbol: &str,
+        facts: impl Iterator<Item = &'a Arc<AnonymousGroundAtom>>,
+    ) {
+        if let Some(relation) = self.inner.get_mut(relation_symbol) {
+            facts.for_each(|fact| {
+                if let Some(epoch) = relation.remove(fact) {
+                    self.journal.record(relation_symbol, fact, Some(epoch));
+                }
+            });
+        }
+    }
+    pub fn roll_back(&mut self) {
+        self.journal.roll_back(&mut self.inner);
+    }
+    pub fn get(&self, relation_symbol: &str, fact: &AnonymousGroundAtom) -> Option<Epoch> {
+        self.inner
+            .get(relation_symbol)
+            .and_then(|relation| relation.get(fact))
+            .copied()
+    }
+}
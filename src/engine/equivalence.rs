@@ -0,0 +1,158 @@
+use ahash::HashMap;
+use datalog_syntax::{AnonymousGroundAtom, TypedValue};
+use indexmap::IndexSet;
+
+// A binary relation that holds the reflexive, symmetric and transitive closure of the pairs put in
+// it. The closure is kept as the classes of a union-find, so merging two classes costs as much as
+// the facts it adds, rather than the joins of the rules that would spell the closure out.
+#[derive(Clone, Default)]
+pub struct Equivalence {
+    pairs: IndexSet<(TypedValue, TypedValue)>,
+    parents: HashMap<TypedValue, TypedValue>,
+    // The members of the class of every root.
+    members: HashMap<TypedValue, Vec<TypedValue>>,
+}
+
+impl Equivalence {
+    fn find<'a>(&'a self, mut value: &'a TypedValue) -> Option<&'a TypedValue> {
+        let mut parent = self.parents.get(value)?;
+        while parent != value {
+            value = parent;
+            parent = &self.parents[value];
+        }
+
+        Some(value)
+    }
+    pub fn equivalent(&self, left: &TypedValue, right: &TypedValue) -> bool {
+        match (self.find(left), self.find(right)) {
+            (Some(left_root), Some(right_root)) => left_root == right_root,
+            _ => false,
+        }
+    }
+    // Every value that is equivalent to the given one, itself included.
+    pub fn class(&self, value: &TypedValue) -> &[TypedValue] {
+        self.find(value)
+            .map_or(&[], |root| self.members[root].as_slice())
+    }
+    // Returns the root of the class of the value.
+    fn add_value(
+        &mut self,
+        value: &TypedValue,
+        added_facts: &mut Vec<AnonymousGroundAtom>,
+    ) -> TypedValue {
+        if let Some(root) = self.find(value) {
+            return root.clone();
+        }
+
+        self.parents.insert(value.clone(), value.clone());
+        self.members.insert(value.clone(), vec![value.clone()]);
+        added_facts.push(vec![value.clone(), value.clone()]);
+
+        value.clone()
+    }
+    // The smaller class goes under the root of the larger one.
+    fn union(
+        &mut self,
+        left_root: TypedValue,
+        right_root: TypedValue,
+        added_facts: &mut Vec<AnonymousGroundAtom>,
+    ) {
+        if left_root == right_root {
+            return;
+        }
+
+        let size = |root: &TypedValue| self.members.get(root).map_or(1, Vec::len);
+        let (mut root, mut child) = (left_root, right_root);
+        if size(&root) < size(&child) {
+            std::mem::swap(&mut root, &mut child);
+        }
+
+        let child_members = self
+            .members
+            .remove(&child)
+            .unwrap_or_else(|| vec![child.clone()]);
+        let root_members = self
+            .members
+            .entry(root.clone())
+            .or_insert_with(|| vec![root.clone()]);
+        root_members.iter().for_each(|root_member| {
+            child_members.iter().for_each(|child_member| {
+                added_facts.push(vec![root_member.clone(), child_member.clone()]);
+                added_facts.push(vec![child_member.clone(), root_member.clone()]);
+            })
+        });
+        root_members.extend(child_members);
+        self.parents.insert(child, root);
+    }
+    // Puts the pair in, returning the facts that the closure gains.
+    pub fn insert(&mut self, left: &TypedValue, right: &TypedValue) -> Vec<AnonymousGroundAtom> {
+        let mut added_facts = vec![];
+        if !self.pairs.insert((left.clone(), right.clone())) {
+            return added_facts;
+        }
+
+        let left_root = self.add_value(left, &mut added_facts);
+        let right_root = self.add_value(right, &mut added_facts);
+        self.union(left_root, right_root, &mut added_facts);
+
+        added_facts
+    }
+    // Takes the pair out, returning the facts that the closure loses. A union-find can not split
+    // a class, hence the classes are built again from the pairs that are left. Taking out a pair
+    // that was not put in takes nothing away, since the pairs that were put in still imply it.
+    pub fn remove(&mut self, left: &TypedValue, right: &TypedValue) -> Vec<AnonymousGroundAtom> {
+        if !self.pairs.swap_remove(&(left.clone(), right.clone())) {
+            return vec![];
+        }
+
+        let class = self.class(left).to_vec();
+        let pairs = std::mem::take(&mut self.pairs);
+        self.parents.clear();
+        self.members.clear();
+        pairs.iter().for_each(|(left, right)| {
+            self.insert(left, right);
+        });
+
+        class
+            .iter()
+            .flat_map(|member| class.iter().map(move |other| (member, other)))
+            .filter(|(member, other)| !self.equivalent(member, other))
+            .map(|(member, other)| vec![member.clone(), other.clone()])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::equivalence::Equivalence;
+    use datalog_syntax::{AnonymousGroundAtom, TypedValue};
+    use std::collections::HashSet;
+
+    fn value(name: &str) -> TypedValue {
+        TypedValue::from(name)
+    }
+
+    #[test]
+    fn test_insert_and_remove_pairs() {
+        let mut equivalence = Equivalence::default();
+
+        assert_eq!(4, equivalence.insert(&value("a"), &value("b")).len());
+        assert!(equivalence.insert(&value("a"), &value("b")).is_empty());
+        // Merging {a, b} with {c, d} adds every pair across them, both ways.
+        equivalence.insert(&value("c"), &value("d"));
+        assert_eq!(8, equivalence.insert(&value("b"), &value("c")).len());
+        assert!(equivalence.equivalent(&value("a"), &value("d")));
+        assert_eq!(4, equivalence.class(&value("d")).len());
+        assert!(!equivalence.equivalent(&value("a"), &value("e")));
+
+        assert!(equivalence.remove(&value("c"), &value("b")).is_empty());
+        let removed: HashSet<AnonymousGroundAtom> = equivalence
+            .remove(&value("b"), &value("c"))
+            .into_iter()
+            .collect();
+        assert_eq!(8, removed.len());
+        assert!(removed.contains(&vec![value("d"), value("a")]));
+        assert!(equivalence.equivalent(&value("a"), &value("b")));
+        assert!(!equivalence.equivalent(&value("a"), &value("c")));
+    }
+}
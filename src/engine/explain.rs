@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use ahash::{HashMap, HashMapExt, HashSet};
+use datalog_syntax::{AnonymousGroundAtom, Rule, Term, TypedValue};
+
+use super::index_storage::IndexStorage;
+use super::storage::{FactStorage, RelationStorage};
+use crate::evaluation::spj_processor::{Instruction, RuleEvaluator, Stack};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelationStatistics {
+    pub size: usize,
+    // How many distinct values there are in every column.
+    pub distinct_values: Vec<usize>,
+}
+
+// What the runtime holds of a relation, as tooling would show it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelationSchema {
+    pub arity: Option<usize>,
+    // Whether rules derive the relation, rather than it only being inserted into.
+    pub derived: bool,
+    pub size: usize,
+    // The columns of every hash index that is kept over the relation.
+    pub indices: Vec<Vec<usize>>,
+}
+
+fn count_distinct(facts: &FactStorage, columns: &[usize]) -> usize {
+    facts
+        .iter()
+        .map(|fact| {
+            columns
+                .iter()
+                .map(|column| &fact[*column])
+                .collect::<Vec<&TypedValue>>()
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+pub(crate) fn relation_statistics(facts: &FactStorage) -> RelationStatistics {
+    let arity = facts.first().map_or(0, |fact| fact.len());
+
+    RelationStatistics {
+        size: facts.len(),
+        distinct_values: (0..arity)
+            .map(|column| count_distinct(facts, &[column]))
+            .collect(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OperationPlan {
+    pub instruction: String,
+    pub estimated_size: usize,
+    pub actual_size: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct RulePlan {
+    pub rule: Rule,
+    pub operations: Vec<OperationPlan>,
+    // How many times the rule was evaluated during the last poll, and how many new facts it
+    // derived in total.
+    pub evaluations: usize,
+    pub new_facts: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Explanation {
+    pub relation: String,
+    pub rules: Vec<RulePlan>,
+    // Over all fixpoints that the last poll went through.
+    pub iterations: usize,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {} iterations during the last poll",
+            self.relation, self.iterations
+        )?;
+
+        for rule_plan in &self.rules {
+            writeln!(
+                f,
+                "  {:?}: evaluated {} times, {} new facts",
+                rule_plan.rule, rule_plan.evaluations, rule_plan.new_facts
+            )?;
+            for operation in &rule_plan.operations {
+                writeln!(
+                    f,
+                    "    {}: estimated {}, actual {}",
+                    operation.instruction, operation.estimated_size, operation.actual_size
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// How a query can be answered besides reading what the last poll derived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // Top-down from the base relations, as queries are answered on demand.
+    TopDown,
+    // Bottom-up from the base relations, by the rules that the query depends on specialized to its
+    // constants.
+    Specialized,
+    // Bottom-up from the base relations, by every rule of the program, as a new runtime would
+    // derive them on its first poll.
+    Rematerialized,
+}
+
+// What a strategy answered that the last poll did not derive, and what it missed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub strategy: Strategy,
+    pub missing: Vec<AnonymousGroundAtom>,
+    pub unexpected: Vec<AnonymousGroundAtom>,
+    // The least fact that the answers disagree on, to start looking into them from.
+    pub witness: AnonymousGroundAtom,
+}
+
+// How a strategy's answers differ from the expected ones, if they do.
+pub(crate) fn discrepancy(
+    strategy: Strategy,
+    expected: &BTreeSet<AnonymousGroundAtom>,
+    actual: &BTreeSet<AnonymousGroundAtom>,
+) -> Option<Discrepancy> {
+    let missing: Vec<_> = expected.difference(actual).cloned().collect();
+    let unexpected: Vec<_> = actual.difference(expected).cloned().collect();
+    let witness = missing
+        .first()
+        .into_iter()
+        .chain(unexpected.first())
+        .min()?
+        .clone();
+
+    Some(Discrepancy {
+        strategy,
+        witness,
+        missing,
+        unexpected,
+    })
+}
+
+// How long a strategy took to answer a query, and how its answers differ from what the last poll
+// derived, if they do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrategyComparison {
+    pub strategy: Strategy,
+    pub time: Duration,
+    pub discrepancy: Option<Discrepancy>,
+}
+
+// Every operation of the rule, with the size of its result as estimated from the stored relations
+// and as it is when the rule is evaluated over all of them. Estimates assume that every fact on the
+// left of a join matches as many facts on the right as there are per distinct join key, and that a
+// generic join yields as many results as it possibly could for the sizes of its atoms.
+pub(crate) fn plan_rule(
+    storage: &RelationStorage,
+    rule: &Rule,
+    generic_joins: bool,
+) -> Result<Vec<OperationPlan>, String> {
+    let stack = Stack::new(rule.clone(), generic_joins);
+    let mut index_storage = IndexStorage::default();
+    let produced = RuleEvaluator::new(storage, rule, &stack)
+        .step(&mut index_storage)?
+        .count();
+    let actual_size = |name: &str| index_storage.diff.get(name).map_or(0, Vec::len);
+
+    let mut estimates: HashMap<String, f64> = HashMap::new();
+    // The stored relation that every selection reads from.
+    let mut sources: HashMap<String, &str> = HashMap::new();
+    let (mut last_estimate, mut last_actual_size) = (0.0, 0);
+
+    let plans = stack
+        .inner
+        .iter()
+        .map(|instruction| {
+            let name = instruction.output_name();
+            let (estimate, actual) = match instruction {
+                Instruction::Move(symbol) => {
+                    let size = storage.get_relation(symbol).len();
+                    sources.insert(symbol.clone(), symbol);
+
+                    (size as f64, size)
+                }
+                Instruction::Select(symbol, conditions) => {
+                    let facts = storage.get_relation(symbol);
+                    let name = name.clone().unwrap();
+                    let columns: Vec<_> = conditions.iter().map(|(column, _)| *column).collect();
+                    let distinct = count_distinct(facts, &columns).max(1);
+                    let estimate = facts.len() as f64 / distinct as f64;
+                    let actual = actual_size(&name);
+                    sources.insert(name, symbol);
+
+                    (estimate, actual)
+                }
+                Instruction::Join(left_symbol, right_symbol, join_keys) => {
+                    let right_columns: Vec<_> = join_keys
+                        .iter()
+                        .map(|(_, right_column)| *right_column)
+                        .collect();
+                    let right_source = sources.get(right_symbol).copied().unwrap_or(right_symbol);
+                    let distinct =
+                        count_distinct(storage.get_relation(right_source), &right_columns).max(1);
+                    let estimate =
+                        estimates[left_symbol] * estimates[right_symbol] / distinct as f64;
+
+                    (estimate, actual_size(name.as_ref().unwrap()))
+                }
+                // Every atom is given the largest share, over its variables, of the atoms that
+                // have each variable, which bounds the results by the product of its size to that
+                // share.
+                Instruction::GenericJoin(inputs) => {
+                    let variables: Vec<HashSet<_>> = inputs
+                        .iter()
+                        .map(|(_, terms)| {
+                            terms
+                                .iter()
+                                .filter_map(|term| match term {
+                                    Term::Variable(name) => Some(name),
+                                    _ => None,
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    let estimate = inputs
+                        .iter()
+                        .zip(&variables)
+                        .map(|((symbol, _), atom_variables)| {
+                            let share = atom_variables
+                                .iter()
+                                .map(|variable| {
+                                    let atoms = variables
+                                        .iter()
+                                        .filter(|other| other.contains(variable))
+                                        .count();
+
+                                    1.0 / atoms as f64
+                                })
+                                .fold(0.0, f64::max);
+
+                            estimates[symbol].powf(share)
+                        })
+                        .product();
+
+                    (estimate, actual_size(name.as_ref().unwrap()))
+                }
+                Instruction::Antijoin(left_symbol, _, _) => {
+                    (estimates[left_symbol], actual_size(name.as_ref().unwrap()))
+                }
+                // One fact is projected out of every fact of the relation before.
+                Instruction::Project(_, _) => (last_estimate, last_actual_size),
+                Instruction::Aggregate(_, _) => (last_estimate, produced),
+            };
+
+            if let Some(name) = name {
+                estimates.insert(name, estimate);
+            }
+            (last_estimate, last_actual_size) = (estimate, actual);
+
+            OperationPlan {
+                instruction: format!("{:?}", instruction),
+                estimated_size: estimate.round() as usize,
+                actual_size: actual,
+            }
+        })
+        .collect();
+
+    Ok(plans)
+}
@@ -0,0 +1,54 @@
+use ahash::HashSet;
+use datalog_syntax::AnonymousGroundAtom;
+use std::sync::Arc;
+
+// Hands out one allocation for all facts that are equal, whichever relations they are stored in,
+// such as the facts that a closure copies over from the relation it closes. Facts stay interned
+// for as long as anything else holds them, and are let go of by sweeping.
+#[derive(Clone, Default)]
+pub struct FactInterner {
+    facts: HashSet<Arc<AnonymousGroundAtom>>,
+}
+
+impl FactInterner {
+    pub fn intern(&mut self, fact: Arc<AnonymousGroundAtom>) -> Arc<AnonymousGroundAtom> {
+        if let Some(interned) = self.facts.get(&fact) {
+            return interned.clone();
+        }
+        self.facts.insert(fact.clone());
+
+        fact
+    }
+    // Forgets the facts that nothing but the interner holds anymore.
+    pub fn sweep(&mut self) {
+        self.facts.retain(|fact| Arc::strong_count(fact) > 1);
+    }
+    pub fn facts(&self) -> impl Iterator<Item = &Arc<AnonymousGroundAtom>> {
+        self.facts.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::fact_interner::FactInterner;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = FactInterner::default();
+
+        let first = interner.intern(Arc::new(vec![1usize.into(), 2usize.into()]));
+        let second = interner.intern(Arc::new(vec![1usize.into(), 2usize.into()]));
+        assert!(Arc::ptr_eq(&first, &second));
+        interner.intern(Arc::new(vec![2usize.into()]));
+        assert_eq!(2, interner.facts().count());
+
+        // Only the facts that are still held elsewhere survive sweeping.
+        interner.sweep();
+        assert_eq!(1, interner.facts().count());
+        drop(first);
+        drop(second);
+        interner.sweep();
+        assert_eq!(0, interner.facts().count());
+    }
+}
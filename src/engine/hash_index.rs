@@ -0,0 +1,123 @@
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use ahash::{HashMap, RandomState};
+use datalog_syntax::{AnonymousGroundAtom, TypedValue};
+
+use super::storage::COMPACTION_FACTOR;
+
+// Every index hashes its keys the same way, so a key borrowed from the other side of a join can be
+// looked up without being cloned.
+const KEY_HASHER: RandomState = RandomState::with_seeds(
+    0x243f6a8885a308d3,
+    0x13198a2e03707344,
+    0xa4093822299f31d0,
+    0x082efa98ec4e6c89,
+);
+
+fn hash_key<'b>(values: impl Iterator<Item = &'b TypedValue>) -> u64 {
+    let mut hasher = KEY_HASHER.build_hasher();
+    values.for_each(|value| value.hash(&mut hasher));
+
+    hasher.finish()
+}
+
+// Facts bucketed by the hash of their values at some columns. Buckets may hold facts whose keys
+// merely collide, which lookups filter out.
+#[derive(Clone)]
+pub struct HashIndex {
+    pub(crate) columns: Vec<usize>,
+    pub(crate) buckets: HashMap<u64, Vec<Arc<AnonymousGroundAtom>>>,
+}
+
+impl HashIndex {
+    pub fn new(columns: Vec<usize>) -> Self {
+        Self {
+            columns,
+            buckets: Default::default(),
+        }
+    }
+    pub fn from_facts(
+        columns: Vec<usize>,
+        facts: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
+    ) -> Self {
+        let mut index = Self::new(columns);
+        facts.for_each(|fact| index.insert(fact));
+
+        index
+    }
+    // Roughly what the buckets take, leaving out the facts, which the relation owns.
+    pub fn bytes(&self) -> usize {
+        self.buckets
+            .values()
+            .map(|bucket| {
+                size_of::<(u64, Vec<Arc<AnonymousGroundAtom>>)>()
+                    + bucket.capacity() * size_of::<Arc<AnonymousGroundAtom>>()
+            })
+            .sum()
+    }
+    fn key_hash(&self, fact: &AnonymousGroundAtom) -> u64 {
+        hash_key(self.columns.iter().map(|column| &fact[*column]))
+    }
+    pub fn insert(&mut self, fact: Arc<AnonymousGroundAtom>) {
+        let key_hash = self.key_hash(&fact);
+
+        self.buckets.entry(key_hash).or_default().push(fact);
+    }
+    pub fn remove(&mut self, fact: &AnonymousGroundAtom) {
+        let key_hash = self.key_hash(fact);
+
+        if let Some(bucket) = self.buckets.get_mut(&key_hash) {
+            if let Some(position) = bucket.iter().position(|indexed| **indexed == *fact) {
+                bucket.swap_remove(position);
+            }
+            if bucket.is_empty() {
+                self.buckets.remove(&key_hash);
+            } else if bucket.capacity() > bucket.len() * COMPACTION_FACTOR {
+                bucket.shrink_to_fit();
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+    // Buckets shrink as facts are removed from them, while the map of them keeps the capacity of
+    // the most keys it ever held until it is compacted.
+    pub fn compact(&mut self) {
+        if self.buckets.capacity() > self.buckets.len() * COMPACTION_FACTOR {
+            self.buckets.shrink_to_fit();
+        }
+    }
+    // The facts of every key, telling apart the keys that share a bucket.
+    pub fn groups(
+        &self,
+    ) -> impl Iterator<Item = (Vec<&TypedValue>, Vec<&Arc<AnonymousGroundAtom>>)> + '_ {
+        self.buckets.values().flat_map(move |bucket| {
+            let mut groups: Vec<(Vec<&TypedValue>, Vec<_>)> = vec![];
+            for fact in bucket {
+                let key: Vec<_> = self.columns.iter().map(|column| &fact[*column]).collect();
+                match groups.iter_mut().find(|(other, _)| *other == key) {
+                    Some((_, facts)) => facts.push(fact),
+                    None => groups.push((key, vec![fact])),
+                }
+            }
+
+            groups
+        })
+    }
+    pub fn get<'a>(
+        &'a self,
+        key: &'a [&'a TypedValue],
+    ) -> impl Iterator<Item = &'a Arc<AnonymousGroundAtom>> + 'a {
+        self.buckets
+            .get(&hash_key(key.iter().copied()))
+            .into_iter()
+            .flatten()
+            .filter(move |fact| {
+                self.columns
+                    .iter()
+                    .zip(key)
+                    .all(|(column, value)| fact[*column] == **value)
+            })
+    }
+}
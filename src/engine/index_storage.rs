@@ -1,34 +1,185 @@
 use std::sync::Arc;
 
-use ahash::HashMap;
+use ahash::{HashMap, HashSet};
 use datalog_syntax::AnonymousGroundAtom;
 
+use super::storage::COMPACTION_FACTOR;
+
 #[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum EphemeralValue {
     FactRef(Arc<AnonymousGroundAtom>),
     JoinResult(Vec<Arc<AnonymousGroundAtom>>),
 }
 
+impl EphemeralValue {
+    // Facts are shared with the relations they come from, so only what refers to them counts.
+    pub fn bytes(&self) -> usize {
+        let products = match self {
+            EphemeralValue::FactRef(_) => 0,
+            EphemeralValue::JoinResult(product) => {
+                product.capacity() * size_of::<Arc<AnonymousGroundAtom>>()
+            }
+        };
+
+        size_of::<EphemeralValue>() + products
+    }
+    // Relations read from storage hold facts, while only joins yield join results.
+    pub fn fact(&self) -> Result<&Arc<AnonymousGroundAtom>, String> {
+        match self {
+            EphemeralValue::FactRef(fact) => Ok(fact),
+            EphemeralValue::JoinResult(_) => {
+                Err("a join result was read where a stored fact was expected".to_string())
+            }
+        }
+    }
+}
+
+// Facts read from the relations are shared with them, so they do not add to what evaluation holds
+// on to, unlike join results.
+fn join_result_bytes<'a>(values: impl IntoIterator<Item = &'a EphemeralValue>) -> usize {
+    values
+        .into_iter()
+        .filter(|value| matches!(value, EphemeralValue::JoinResult(_)))
+        .map(EphemeralValue::bytes)
+        .sum()
+}
+
 #[derive(Default)]
 pub struct IndexStorage {
     pub inner: HashMap<String, Vec<EphemeralValue>>,
     pub diff: HashMap<String, Vec<EphemeralValue>>,
+    // About how many bytes the join results and the facts derived by the last iteration take, the
+    // most they took at once, and the most they may take before evaluation gives up.
+    bytes: usize,
+    new_bytes: usize,
+    pub(crate) peak_bytes: usize,
+    limit: Option<usize>,
 }
 
 impl IndexStorage {
+    pub fn with_limit(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+    fn account(&mut self, bytes: usize) {
+        self.bytes += bytes;
+        self.peak_bytes = self.peak_bytes.max(self.bytes);
+    }
+    fn check_limit(&self) -> Result<(), String> {
+        match self.limit {
+            Some(limit) if self.bytes > limit => Err(format!(
+                "intermediate results take more than the {} bytes that they may",
+                limit
+            )),
+            _ => Ok(()),
+        }
+    }
+    // Starts over from the given known and new values, dropping everything else.
+    pub fn replace(
+        &mut self,
+        inner: HashMap<String, Vec<EphemeralValue>>,
+        diff: HashMap<String, Vec<EphemeralValue>>,
+    ) {
+        self.inner = inner;
+        self.diff = diff;
+        self.new_bytes = self
+            .diff
+            .values()
+            .flatten()
+            .map(EphemeralValue::bytes)
+            .sum();
+        self.bytes = 0;
+        self.account(join_result_bytes(self.inner.values().flatten()) + self.new_bytes);
+    }
+    pub fn insert_known(&mut self, relation_symbol: &str, values: Vec<EphemeralValue>) {
+        self.account(join_result_bytes(&values));
+        self.inner.insert(relation_symbol.to_string(), values);
+    }
+    // Everything in the diff is what became known during the last iteration, hence it is appended
+    // to what was already known before the next iteration's deltas take its place.
+    pub fn advance(&mut self, new_diff: HashMap<String, Vec<EphemeralValue>>) {
+        let previous_diff = std::mem::replace(&mut self.diff, new_diff);
+        // The facts derived by the last iteration are known from now on, like any other.
+        self.bytes -= self.new_bytes;
+        self.new_bytes = self
+            .diff
+            .values()
+            .flatten()
+            .map(EphemeralValue::bytes)
+            .sum();
+        self.account(self.new_bytes);
+
+        previous_diff
+            .into_iter()
+            .for_each(|(relation_symbol, delta)| {
+                self.inner.entry(relation_symbol).or_default().extend(delta);
+            });
+    }
+    // Takes the given facts of the relation, which holds them, to be new rather than known.
+    pub fn renew<'a>(
+        &mut self,
+        relation_symbol: &str,
+        relation: impl Iterator<Item = &'a Arc<AnonymousGroundAtom>>,
+        facts: &HashSet<&AnonymousGroundAtom>,
+    ) -> Result<(), String> {
+        let known = match self.inner.remove(relation_symbol) {
+            Some(known) => known,
+            None => relation.cloned().map(EphemeralValue::FactRef).collect(),
+        };
+        let mut renewed = vec![];
+        let mut kept = vec![];
+        for allocation in known {
+            if facts.contains(allocation.fact()?.as_ref()) {
+                renewed.push(allocation);
+            } else {
+                kept.push(allocation);
+            }
+        }
+
+        self.inner.insert(relation_symbol.to_string(), kept);
+        self.diff
+            .entry(relation_symbol.to_string())
+            .or_default()
+            .extend(renewed);
+
+        Ok(())
+    }
+    // Forgets the relations that the last iteration derived nothing new for, which are known
+    // already, and releases the capacity that what is kept no longer needs.
+    pub fn compact(&mut self) {
+        let inner = &self.inner;
+        self.diff.retain(|relation_symbol, delta| {
+            !delta.is_empty() || !inner.contains_key(relation_symbol)
+        });
+        [&mut self.inner, &mut self.diff]
+            .into_iter()
+            .flat_map(|relations| relations.values_mut())
+            .for_each(|values| {
+                if values.capacity() > values.len() * COMPACTION_FACTOR {
+                    values.shrink_to_fit();
+                }
+            });
+    }
     pub fn borrow_all(
         &mut self,
         relation_symbol: &str,
         facts: impl Iterator<Item = EphemeralValue>,
-    ) {
+    ) -> Result<(), String> {
+        let mut bytes = 0;
+        let facts = facts.inspect(|fact| bytes += join_result_bytes([fact]));
         if let Some(ephemeral_relation) = self.diff.get_mut(relation_symbol) {
             ephemeral_relation.extend(facts);
         } else {
             self.diff
                 .insert(relation_symbol.to_string(), Vec::from_iter(facts));
-            if self.inner.get(relation_symbol).is_none() {
+            if !self.inner.contains_key(relation_symbol) {
                 self.inner.insert(relation_symbol.to_string(), Vec::new());
             }
         }
+        self.account(bytes);
+
+        self.check_limit()
     }
 }
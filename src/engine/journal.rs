@@ -0,0 +1,76 @@
+use std::hash::Hash;
+
+use ahash::HashMap;
+
+// What the entries of relations held before they were changed, in the order they were changed in,
+// so that going back to how they were costs as much as the changes, rather than a copy of all of
+// them. Nothing is kept unless the journal is open.
+#[derive(Clone)]
+pub(crate) struct Journal<K, V> {
+    changes: Option<Vec<(String, K, Option<V>)>>,
+}
+
+impl<K, V> Default for Journal<K, V> {
+    fn default() -> Self {
+        Self { changes: None }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Journal<K, V> {
+    pub(crate) fn open(&mut self) {
+        self.changes = Some(vec![]);
+    }
+    pub(crate) fn close(&mut self) {
+        self.changes = None;
+    }
+    pub(crate) fn is_open(&self) -> bool {
+        self.changes.is_some()
+    }
+    // The entry held the previous value, if any, before it was changed.
+    pub(crate) fn record(&mut self, relation_symbol: &str, key: &K, previous: Option<V>) {
+        if let Some(changes) = &mut self.changes {
+            changes.push((relation_symbol.to_string(), key.clone(), previous));
+        }
+    }
+    // Closes the journal, returning the changes latest first.
+    pub(crate) fn take(&mut self) -> impl Iterator<Item = (String, K, Option<V>)> {
+        self.changes.take().into_iter().flatten().rev()
+    }
+    // Puts back what every changed entry held when the journal was opened, and closes it.
+    pub(crate) fn roll_back(&mut self, relations: &mut HashMap<String, HashMap<K, V>>) {
+        self.take().for_each(|(relation_symbol, key, previous)| {
+            let relation = relations.entry(relation_symbol).or_default();
+            match previous {
+                Some(value) => relation.insert(key, value),
+                None => relation.remove(&key),
+            };
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::journal::Journal;
+    use ahash::{HashMap, HashMapExt};
+
+    #[test]
+    fn test_roll_back() {
+        let mut relations: HashMap<String, HashMap<usize, usize>> = HashMap::new();
+        relations.entry("r".to_string()).or_default().insert(1, 10);
+
+        let mut journal = Journal::default();
+        // Nothing is kept while the journal is closed.
+        journal.record("r", &1, Some(10));
+        journal.open();
+
+        let relation = relations.get_mut("r").unwrap();
+        journal.record("r", &1, relation.insert(1, 11));
+        journal.record("r", &1, relation.insert(1, 12));
+        journal.record("r", &2, relation.insert(2, 20));
+        journal.roll_back(&mut relations);
+
+        assert!(!journal.is_open());
+        assert_eq!(Some(&10), relations["r"].get(&1));
+        assert_eq!(None, relations["r"].get(&2));
+    }
+}
@@ -0,0 +1,121 @@
+use ahash::HashMap;
+use datalog_syntax::{AnonymousGroundAtom, Query, Timestamp};
+
+use super::journal::Journal;
+
+// What is known about where an inserted fact came from. Timestamps are whatever the caller wants
+// them to be, such as the time an event happened, as long as they grow with time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FactMetadata {
+    pub timestamp: Timestamp,
+    pub source: Option<String>,
+    // When the fact stops holding, in the same unit as the timestamp.
+    pub expires_at: Option<Timestamp>,
+}
+
+impl FactMetadata {
+    pub fn new(timestamp: Timestamp) -> Self {
+        Self {
+            timestamp,
+            source: None,
+            expires_at: None,
+        }
+    }
+    // Milliseconds since the unix epoch. There is no clock to read on wasm32-unknown-unknown.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn now() -> Self {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as Timestamp);
+
+        Self::new(timestamp)
+    }
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+    // The fact stops holding once the time to live has passed since its timestamp.
+    pub fn with_ttl(mut self, ttl: Timestamp) -> Self {
+        self.expires_at = Some(self.timestamp.saturating_add(ttl));
+        self
+    }
+    pub fn is_due(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+    pub fn satisfies(&self, query: &Query) -> bool {
+        query.since.is_none_or(|since| self.timestamp >= since)
+            && query
+                .source
+                .is_none_or(|source| self.source.as_deref() == Some(source))
+    }
+}
+
+// The metadata of the facts that were inserted along with some, and are still there.
+#[derive(Clone, Default)]
+pub struct MetadataStorage {
+    pub(crate) inner: HashMap<String, HashMap<AnonymousGroundAtom, FactMetadata>>,
+    pub(crate) journal: Journal<AnonymousGroundAtom, FactMetadata>,
+}
+
+impl MetadataStorage {
+    // Inserting a fact again refreshes its metadata.
+    pub fn set(
+        &mut self,
+        relation_symbol: &str,
+        fact: AnonymousGroundAtom,
+        metadata: FactMetadata,
+    ) {
+        let previous = self
+            .inner
+            .entry(relation_symbol.to_string())
+            .or_default()
+            .insert(fact.clone(), metadata);
+        self.journal.record(relation_symbol, &fact, previous);
+    }
+    pub fn forget<'a>(
+        &mut self,
+        relation_symbol: &str,
+        facts: impl Iterator<Item = &'a AnonymousGroundAtom>,
+    ) {
+        if let Some(relation) = self.inner.get_mut(relation_symbol) {
+            facts.for_each(|fact| {
+                if let Some(metadata) = relation.remove(fact) {
+                    self.journal.record(relation_symbol, fact, Some(metadata));
+                }
+            });
+        }
+    }
+    pub fn roll_back(&mut self) {
+        self.journal.roll_back(&mut self.inner);
+    }
+    pub fn get(&self, relation_symbol: &str, fact: &AnonymousGroundAtom) -> Option<&FactMetadata> {
+        self.inner
+            .get(relation_symbol)
+            .and_then(|relation| relation.get(fact))
+    }
+    pub fn older_than(
+        &self,
+        timestamp: Timestamp,
+    ) -> impl Iterator<Item = (&String, &AnonymousGroundAtom)> {
+        self.inner
+            .iter()
+            .flat_map(move |(relation_symbol, relation)| {
+                relation
+                    .iter()
+                    .filter(move |(_, metadata)| metadata.timestamp < timestamp)
+                    .map(move |(fact, _)| (relation_symbol, fact))
+            })
+    }
+    pub fn due(&self, now: Timestamp) -> impl Iterator<Item = (&String, &AnonymousGroundAtom)> {
+        self.inner
+            .iter()
+            .flat_map(move |(relation_symbol, relation)| {
+                relation
+                    .iter()
+                    .filter(move |(_, metadata)| metadata.is_due(now))
+                    .map(move |(fact, _)| (relation_symbol, fact))
+            })
+    }
+}
@@ -0,0 +1,236 @@
+use crate::engine::journal::Journal;
+use crate::helpers::helpers::is_internal;
+use ahash::HashMap;
+use datalog_syntax::{AnonymousGroundAtom, Rule};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Gets told what the evaluation is doing, without the evaluation having to print anything. Every
+// method does nothing by default, hence observers only implement what they care about.
+pub trait EvaluationObserver {
+    // A rule was evaluated once, deriving this many facts that were not known yet.
+    fn rule_evaluated(&mut self, _rule: &Rule, _new_facts: usize) {}
+    // How long an evaluation of the rule took, joins included.
+    fn rule_timed(&mut self, _rule: &Rule, _time: Duration) {}
+    // The new facts that an evaluation of the rule derived.
+    fn facts_derived(
+        &mut self,
+        _rule: &Rule,
+        _facts: &mut dyn Iterator<Item = &Arc<AnonymousGroundAtom>>,
+    ) {
+    }
+    // A fixpoint iteration has finished. The zeroth one is the pass over the nonrecursive rules.
+    fn iteration_finished(&mut self, _iteration: usize, _new_facts: usize) {}
+    // The most bytes that intermediate results took at once while reaching the fixpoint.
+    fn intermediate_bytes(&mut self, _bytes: usize) {}
+}
+
+impl EvaluationObserver for () {}
+
+// Passes every event on to both observers.
+pub(crate) struct Tee<'a>(
+    pub &'a mut dyn EvaluationObserver,
+    pub &'a mut dyn EvaluationObserver,
+);
+
+impl EvaluationObserver for Tee<'_> {
+    fn rule_evaluated(&mut self, rule: &Rule, new_facts: usize) {
+        self.0.rule_evaluated(rule, new_facts);
+        self.1.rule_evaluated(rule, new_facts);
+    }
+    fn rule_timed(&mut self, rule: &Rule, time: Duration) {
+        self.0.rule_timed(rule, time);
+        self.1.rule_timed(rule, time);
+    }
+    fn facts_derived(
+        &mut self,
+        rule: &Rule,
+        facts: &mut dyn Iterator<Item = &Arc<AnonymousGroundAtom>>,
+    ) {
+        let facts: Vec<_> = facts.collect();
+        self.0.facts_derived(rule, &mut facts.iter().copied());
+        self.1.facts_derived(rule, &mut facts.into_iter());
+    }
+    fn iteration_finished(&mut self, iteration: usize, new_facts: usize) {
+        self.0.iteration_finished(iteration, new_facts);
+        self.1.iteration_finished(iteration, new_facts);
+    }
+    fn intermediate_bytes(&mut self, bytes: usize) {
+        self.0.intermediate_bytes(bytes);
+        self.1.intermediate_bytes(bytes);
+    }
+}
+
+// What the evaluation did during the last poll, as kept by the runtime to explain queries with.
+#[derive(Clone, Default)]
+pub(crate) struct PollStatistics {
+    pub(crate) iterations: usize,
+    // How many times every rule was evaluated, how many new facts it derived and how long it took
+    // in total. Rules are told apart by how they read, as their ids change when programs get split
+    // up.
+    pub(crate) rules: HashMap<String, (usize, usize, Duration)>,
+    // The most bytes that intermediate results took at once.
+    pub(crate) intermediate_peak: usize,
+}
+
+impl EvaluationObserver for PollStatistics {
+    fn rule_evaluated(&mut self, rule: &Rule, new_facts: usize) {
+        let (evaluations, total_new_facts, _) =
+            self.rules.entry(format!("{:?}", rule)).or_default();
+        *evaluations += 1;
+        *total_new_facts += new_facts;
+    }
+    fn rule_timed(&mut self, rule: &Rule, time: Duration) {
+        self.rules.entry(format!("{:?}", rule)).or_default().2 += time;
+    }
+    fn iteration_finished(&mut self, _iteration: usize, _new_facts: usize) {
+        self.iterations += 1;
+    }
+    fn intermediate_bytes(&mut self, bytes: usize) {
+        self.intermediate_peak = self.intermediate_peak.max(bytes);
+    }
+}
+
+// Forwards every event to the observer, if there is one.
+impl<T: EvaluationObserver> EvaluationObserver for Option<T> {
+    fn rule_evaluated(&mut self, rule: &Rule, new_facts: usize) {
+        if let Some(observer) = self {
+            observer.rule_evaluated(rule, new_facts);
+        }
+    }
+    fn iteration_finished(&mut self, iteration: usize, new_facts: usize) {
+        if let Some(observer) = self {
+            observer.iteration_finished(iteration, new_facts);
+        }
+    }
+    fn rule_timed(&mut self, rule: &Rule, time: Duration) {
+        if let Some(observer) = self {
+            observer.rule_timed(rule, time);
+        }
+    }
+    fn facts_derived(
+        &mut self,
+        rule: &Rule,
+        facts: &mut dyn Iterator<Item = &Arc<AnonymousGroundAtom>>,
+    ) {
+        if let Some(observer) = self {
+            observer.facts_derived(rule, facts);
+        }
+    }
+    fn intermediate_bytes(&mut self, bytes: usize) {
+        if let Some(observer) = self {
+            observer.intermediate_bytes(bytes);
+        }
+    }
+}
+
+// The iteration of the fixpoint that first derived every fact, counting the pass over the
+// nonrecursive rules as the first, so that in a closure it is the length of the shortest path
+// that the fact stands for. Iterations are counted anew for every stratum and every poll.
+#[derive(Clone, Default)]
+pub(crate) struct DepthRecorder {
+    depths: HashMap<String, HashMap<Arc<AnonymousGroundAtom>, usize>>,
+    // The facts derived during the current iteration.
+    pending: Vec<(String, Arc<AnonymousGroundAtom>)>,
+    pub(crate) journal: Journal<Arc<AnonymousGroundAtom>, usize>,
+}
+
+impl DepthRecorder {
+    pub(crate) fn get(&self, relation_symbol: &str, fact: &AnonymousGroundAtom) -> Option<usize> {
+        self.depths
+            .get(relation_symbol)
+            .and_then(|relation| relation.get(fact))
+            .copied()
+    }
+    pub(crate) fn forget<'a>(
+        &mut self,
+        relation_symbol: &str,
+        facts: impl Iterator<Item = &'a Arc<AnonymousGroundAtom>>,
+    ) {
+        if let Some(relation) = self.depths.get_mut(relation_symbol) {
+            facts.for_each(|fact| {
+                if let Some(depth) = relation.remove(fact) {
+                    self.journal.record(relation_symbol, fact, Some(depth));
+                }
+            });
+        }
+    }
+    pub(crate) fn roll_back(&mut self) {
+        self.pending.clear();
+        self.journal.roll_back(&mut self.depths);
+    }
+}
+
+impl EvaluationObserver for DepthRecorder {
+    fn facts_derived(
+        &mut self,
+        rule: &Rule,
+        facts: &mut dyn Iterator<Item = &Arc<AnonymousGroundAtom>>,
+    ) {
+        let relation_symbol = &rule.head.symbol;
+        if is_internal(relation_symbol) {
+            return;
+        }
+
+        self.pending
+            .extend(facts.map(|fact| (relation_symbol.clone(), fact.clone())));
+    }
+    fn iteration_finished(&mut self, iteration: usize, _new_facts: usize) {
+        self.pending.drain(..).for_each(|(relation_symbol, fact)| {
+            let relation = self.depths.entry(relation_symbol.clone()).or_default();
+            if !relation.contains_key(&fact) {
+                relation.insert(fact.clone(), iteration + 1);
+                self.journal.record(&relation_symbol, &fact, None);
+            }
+        });
+    }
+}
+
+// What evaluating a rule of the program took over the last poll.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleStatistics {
+    pub rule: Rule,
+    pub evaluations: usize,
+    pub new_facts: usize,
+    // Always zero on wasm32-unknown-unknown, which has no clock to read.
+    pub time: Duration,
+}
+
+// Roughly how many bytes the runtime takes, as estimated from what it holds rather than measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    // Every fact, whether processed or waiting for the next poll.
+    pub facts: usize,
+    pub indices: usize,
+    // The most that intermediate results took at once during the last poll.
+    pub intermediate_peak: usize,
+}
+
+// Measures how long something takes, wherever there is a clock to read.
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: std::time::Instant::now(),
+        }
+    }
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        return self.start.elapsed();
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        return Duration::ZERO;
+    }
+}
+
+// How a relation changed over a poll. A fact that was both deleted and inserted again is in
+// neither.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelationDelta {
+    pub insertions: Vec<Arc<AnonymousGroundAtom>>,
+    pub deletions: Vec<Arc<AnonymousGroundAtom>>,
+}
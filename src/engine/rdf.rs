@@ -0,0 +1,440 @@
+use std::io::Read;
+
+use ahash::{HashMap, HashMapExt};
+use datalog_syntax::{AnonymousGroundAtom, Atom, Program, Rule, Term, TypedValue};
+
+// The relation that triples are loaded into, and the one that the RDFS rules entail them in.
+pub const TRIPLE: &str = "triple";
+pub const ENTAILED: &str = "entailed";
+
+pub const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+pub const RDFS_SUB_CLASS_OF: &str = "http://www.w3.org/2000/01/rdf-schema#subClassOf";
+pub const RDFS_SUB_PROPERTY_OF: &str = "http://www.w3.org/2000/01/rdf-schema#subPropertyOf";
+pub const RDFS_DOMAIN: &str = "http://www.w3.org/2000/01/rdf-schema#domain";
+pub const RDFS_RANGE: &str = "http://www.w3.org/2000/01/rdf-schema#range";
+
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_DECIMAL: &str = "http://www.w3.org/2001/XMLSchema#decimal";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+// Reads N-Triples, and Turtle as far as prefixes, `a`, and lists of predicates and objects go, as
+// facts of a ternary relation. IRIs are kept without their angle brackets and with their prefixes
+// expanded, blank nodes as _:label, and literals as they are written in N-Triples, quotes included,
+// so that they are told apart from IRIs.
+pub fn read_triples(mut reader: impl Read) -> Result<Vec<AnonymousGroundAtom>, String> {
+    let mut input = String::new();
+    reader
+        .read_to_string(&mut input)
+        .map_err(|error| error.to_string())?;
+
+    let mut parser = Parser {
+        input: &input,
+        position: 0,
+        prefixes: HashMap::new(),
+    };
+    let mut facts = vec![];
+    while parser.skip_space() {
+        parser
+            .statement(&mut facts)
+            .map_err(|error| format!("line {}: {}", parser.line(), error))?;
+    }
+
+    Ok(facts)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+    prefixes: HashMap<String, String>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+    fn line(&self) -> usize {
+        self.input[..self.position].matches('\n').count() + 1
+    }
+    // Skips whitespace and comments, telling whether anything is left.
+    fn skip_space(&mut self) -> bool {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if !trimmed.starts_with('#') {
+                return !trimmed.is_empty();
+            }
+            self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let is_next = self.rest().starts_with(token);
+        if is_next {
+            self.position += token.len();
+        }
+
+        is_next
+    }
+    // A keyword only counts as one when the name does not go on after it.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_space();
+        let is_next = self.rest().strip_prefix(keyword).is_some_and(|after| {
+            after
+                .chars()
+                .next()
+                .is_none_or(|next| !is_name_character(next))
+        });
+        if is_next {
+            self.position += keyword.len();
+        }
+
+        is_next
+    }
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if !self.eat(token) {
+            return Err(format!("expected {}", token));
+        }
+
+        Ok(())
+    }
+    fn statement(&mut self, facts: &mut Vec<AnonymousGroundAtom>) -> Result<(), String> {
+        if self.eat_keyword("@prefix") {
+            self.prefix()?;
+            return self.expect(".");
+        }
+        if self.eat_keyword("PREFIX") {
+            return self.prefix();
+        }
+
+        let subject = self.term()?;
+        if subject.starts_with('"') {
+            return Err(format!("literal {} can not be a subject", subject));
+        }
+        loop {
+            let predicate = if self.eat_keyword("a") {
+                RDF_TYPE.to_string()
+            } else {
+                self.term()?
+            };
+            loop {
+                let object = self.term()?;
+                facts.push(vec![
+                    subject.as_str().into(),
+                    predicate.as_str().into(),
+                    object.into(),
+                ]);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            // The last predicate may be followed by a semicolon all the same.
+            if !self.eat(";") || self.rest().trim_start().starts_with('.') {
+                break;
+            }
+        }
+
+        self.expect(".")
+    }
+    fn prefix(&mut self) -> Result<(), String> {
+        self.skip_space();
+        let Some(prefix) = self.name().strip_suffix(':') else {
+            return Err("expected a prefix that ends with a colon".to_string());
+        };
+        self.skip_space();
+        let iri = self.iri()?;
+
+        self.prefixes.insert(prefix.to_string(), iri);
+        Ok(())
+    }
+    fn term(&mut self) -> Result<String, String> {
+        self.skip_space();
+        let rest = self.rest();
+
+        match rest.chars().next() {
+            Some('<') => self.iri(),
+            Some('"' | '\'') => self.literal(),
+            Some('_') if rest.starts_with("_:") => {
+                self.position += 2;
+                Ok(format!("_:{}", self.name()))
+            }
+            Some(next) if next.is_ascii_digit() || next == '+' || next == '-' => self.number(),
+            Some(_) if self.eat_keyword("true") => Ok(typed_literal("true", XSD_BOOLEAN)),
+            Some(_) if self.eat_keyword("false") => Ok(typed_literal("false", XSD_BOOLEAN)),
+            Some(next) => {
+                let name = self.name();
+                let Some((prefix, local)) = name.split_once(':') else {
+                    let unexpected = if name.is_empty() {
+                        &rest[..next.len_utf8()]
+                    } else {
+                        name
+                    };
+                    return Err(format!("unexpected {:?}", unexpected));
+                };
+                let namespace = self
+                    .prefixes
+                    .get(prefix)
+                    .ok_or_else(|| format!("unknown prefix {}", prefix))?;
+
+                Ok(format!("{}{}", namespace, local))
+            }
+            None => Err("unexpected end of input".to_string()),
+        }
+    }
+    fn iri(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        if !rest.starts_with('<') {
+            return Err("expected an IRI".to_string());
+        }
+        let end = rest.find('>').ok_or("unterminated IRI")?;
+
+        self.position += end + 1;
+        Ok(rest[1..end].to_string())
+    }
+    // Names run up to the first character that can not be in them, and do not end with a dot.
+    fn name(&mut self) -> &'a str {
+        let rest = self.rest();
+        let mut end = rest
+            .find(|character| !is_name_character(character))
+            .unwrap_or(rest.len());
+        while rest[..end].ends_with('.') {
+            end -= 1;
+        }
+
+        self.position += end;
+        &rest[..end]
+    }
+    fn literal(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let quote = rest.chars().next().unwrap();
+        if rest.starts_with(&quote.to_string().repeat(3)) {
+            return Err("long strings are not supported".to_string());
+        }
+
+        let mut lexical = String::new();
+        let mut characters = rest.char_indices().skip(1);
+        let end = loop {
+            let Some((index, character)) = characters.next() else {
+                return Err("unterminated string".to_string());
+            };
+            match character {
+                '\n' | '\r' => return Err("unterminated string".to_string()),
+                '\\' => {
+                    let escaped = match characters.next().map(|(_, escaped)| escaped) {
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('f') => '\u{c}',
+                        Some(escaped @ ('"' | '\'' | '\\')) => escaped,
+                        Some(unicode @ ('u' | 'U')) => {
+                            let digits: String = characters
+                                .by_ref()
+                                .take(if unicode == 'u' { 4 } else { 8 })
+                                .map(|(_, digit)| digit)
+                                .collect();
+                            u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid escape \\{}{}", unicode, digits))?
+                        }
+                        other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+                    };
+                    lexical.push(escaped);
+                }
+                character if character == quote => break index + 1,
+                character => lexical.push(character),
+            }
+        };
+        self.position += end;
+
+        let quoted = format!(
+            "\"{}\"",
+            lexical
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        );
+        if self.rest().starts_with('@') {
+            self.position += 1;
+            return Ok(format!("{}@{}", quoted, self.name()));
+        }
+        if self.rest().starts_with("^^") {
+            self.position += 2;
+            return Ok(format!("{}^^<{}>", quoted, self.term()?));
+        }
+
+        Ok(quoted)
+    }
+    fn number(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let digits = |from: usize| {
+            rest[from..]
+                .find(|character: char| !character.is_ascii_digit())
+                .map_or(rest.len(), |end| from + end)
+        };
+
+        let mut end = digits(usize::from(rest.starts_with(['+', '-'])));
+        let mut datatype = XSD_INTEGER;
+        if rest[end..].starts_with('.') && rest[end + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            end = digits(end + 1);
+            datatype = XSD_DECIMAL;
+        }
+        if rest[end..].starts_with(['e', 'E']) {
+            end = digits(end + 1 + usize::from(rest[end + 1..].starts_with(['+', '-'])));
+            datatype = XSD_DOUBLE;
+        }
+        if !rest[..end].contains(|c: char| c.is_ascii_digit()) {
+            return Err(format!("invalid number {:?}", &rest[..end]));
+        }
+
+        self.position += end;
+        Ok(typed_literal(&rest[..end], datatype))
+    }
+}
+
+fn is_name_character(character: char) -> bool {
+    character.is_alphanumeric() || "_-.:%".contains(character)
+}
+
+fn typed_literal(lexical: &str, datatype: &str) -> String {
+    format!("\"{}\"^^<{}>", lexical, datatype)
+}
+
+// An atom of the given relation, in which terms that start with a question mark are variables.
+fn triple_atom(relation: &str, terms: [&str; 3]) -> Atom {
+    Atom {
+        terms: terms
+            .iter()
+            .map(|term| match term.strip_prefix('?') {
+                Some(variable) => Term::Variable(variable.to_string()),
+                None => Term::Constant(TypedValue::from(*term)),
+            })
+            .collect(),
+        symbol: relation.to_string(),
+        sign: true,
+    }
+}
+
+// The RDFS entailment rules for classes, properties, domains and ranges, by which the entailed
+// relation holds the triples of the triple relation and those that they entail.
+pub fn rdfs() -> Program {
+    let rule = |head: [&str; 3], body: &[(&str, [&str; 3])]| Rule {
+        head: triple_atom(ENTAILED, head),
+        body: body
+            .iter()
+            .map(|(relation, terms)| triple_atom(relation, *terms))
+            .collect(),
+        id: 0,
+        hints: Default::default(),
+    };
+
+    Program::from(vec![
+        rule(["?s", "?p", "?o"], &[(TRIPLE, ["?s", "?p", "?o"])]),
+        rule(
+            ["?x", RDF_TYPE, "?c"],
+            &[
+                (ENTAILED, ["?p", RDFS_DOMAIN, "?c"]),
+                (ENTAILED, ["?x", "?p", "?y"]),
+            ],
+        ),
+        rule(
+            ["?y", RDF_TYPE, "?c"],
+            &[
+                (ENTAILED, ["?p", RDFS_RANGE, "?c"]),
+                (ENTAILED, ["?x", "?p", "?y"]),
+            ],
+        ),
+        rule(
+            ["?p", RDFS_SUB_PROPERTY_OF, "?r"],
+            &[
+                (ENTAILED, ["?p", RDFS_SUB_PROPERTY_OF, "?q"]),
+                (ENTAILED, ["?q", RDFS_SUB_PROPERTY_OF, "?r"]),
+            ],
+        ),
+        rule(
+            ["?x", "?q", "?y"],
+            &[
+                (ENTAILED, ["?p", RDFS_SUB_PROPERTY_OF, "?q"]),
+                (ENTAILED, ["?x", "?p", "?y"]),
+            ],
+        ),
+        rule(
+            ["?c", RDFS_SUB_CLASS_OF, "?e"],
+            &[
+                (ENTAILED, ["?c", RDFS_SUB_CLASS_OF, "?d"]),
+                (ENTAILED, ["?d", RDFS_SUB_CLASS_OF, "?e"]),
+            ],
+        ),
+        rule(
+            ["?x", RDF_TYPE, "?d"],
+            &[
+                (ENTAILED, ["?c", RDFS_SUB_CLASS_OF, "?d"]),
+                (ENTAILED, ["?x", RDF_TYPE, "?c"]),
+            ],
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::rdf::{read_triples, RDF_TYPE};
+    use datalog_syntax::AnonymousGroundAtom;
+
+    #[test]
+    fn test_read_triples() {
+        let turtle = r#"
+            # People, and what they know.
+            @prefix ex: <http://example.org/> .
+            PREFIX foaf: <http://xmlns.com/foaf/0.1/>
+            <http://example.org/ada> a foaf:Person ;
+                foaf:name "Ada \"A\"é"@en , 'Lovelace' ;
+                ex:age 36 ; ex:height 1.65 ; ex:alive false ;
+                ex:knows _:b1.
+            _:b1 ex:score "2"^^ex:count .
+        "#;
+
+        let actual = read_triples(turtle.as_bytes()).unwrap();
+
+        let triple = |s: &str, p: &str, o: &str| -> AnonymousGroundAtom {
+            vec![s.into(), p.into(), o.into()]
+        };
+        let ada = "http://example.org/ada";
+        let name = "http://xmlns.com/foaf/0.1/name";
+        assert_eq!(
+            vec![
+                triple(ada, RDF_TYPE, "http://xmlns.com/foaf/0.1/Person"),
+                triple(ada, name, "\"Ada \\\"A\\\"é\"@en"),
+                triple(ada, name, "\"Lovelace\""),
+                triple(
+                    ada,
+                    "http://example.org/age",
+                    "\"36\"^^<http://www.w3.org/2001/XMLSchema#integer>"
+                ),
+                triple(
+                    ada,
+                    "http://example.org/height",
+                    "\"1.65\"^^<http://www.w3.org/2001/XMLSchema#decimal>"
+                ),
+                triple(
+                    ada,
+                    "http://example.org/alive",
+                    "\"false\"^^<http://www.w3.org/2001/XMLSchema#boolean>"
+                ),
+                triple(ada, "http://example.org/knows", "_:b1"),
+                triple(
+                    "_:b1",
+                    "http://example.org/score",
+                    "\"2\"^^<http://example.org/count>"
+                ),
+            ],
+            actual
+        );
+
+        let error = read_triples("<a> <b> <c> .\n<a> nope:b <c> .".as_bytes()).unwrap_err();
+        assert_eq!("line 2: unknown prefix nope", error);
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{Arc, RwLock};
+
+use ahash::HashMap;
+use datalog_syntax::{AnonymousGroundAtom, Query};
+
+use super::epoch_storage::Epoch;
+use super::storage::FactStorage;
+use crate::evaluation::query::pattern_match;
+
+// The relations as they were at the end of a poll.
+#[derive(Default)]
+pub(crate) struct Published {
+    pub(crate) epoch: Epoch,
+    pub(crate) relations: HashMap<String, Arc<FactStorage>>,
+    pub(crate) arities: HashMap<String, usize>,
+}
+
+// A handle that other threads can query while the runtime polls. It answers with what the runtime
+// held at the end of its last poll, which the runtime swaps in once the poll is over, so readers
+// never see a poll half done.
+#[derive(Clone, Default)]
+pub struct Reader {
+    pub(crate) published: Arc<RwLock<Arc<Published>>>,
+}
+
+impl Reader {
+    pub(crate) fn publish(&self, published: Published) {
+        *self.published.write().unwrap() = Arc::new(published);
+    }
+    fn current(&self) -> Arc<Published> {
+        self.published.read().unwrap().clone()
+    }
+    // The epoch of the poll that the answers are from.
+    pub fn epoch(&self) -> Epoch {
+        self.current().epoch
+    }
+    pub fn query(&self, query: &Query) -> Result<Vec<AnonymousGroundAtom>, String> {
+        if query.since.is_some() || query.source.is_some() {
+            return Err("readers do not keep the metadata of facts".to_string());
+        }
+
+        let published = self.current();
+        if let Some(arity) = published.arities.get(query.symbol) {
+            if *arity != query.matchers.len() {
+                return Err(format!(
+                    "{} has arity {}, but the query has {} matchers",
+                    query.symbol,
+                    arity,
+                    query.matchers.len()
+                ));
+            }
+        }
+
+        Ok(published
+            .relations
+            .get(query.symbol)
+            .into_iter()
+            .flat_map(|facts| facts.iter())
+            .filter(|fact| pattern_match(query, fact))
+            .map(|fact| (**fact).clone())
+            .collect())
+    }
+    pub fn contains(&self, relation: &str, ground_atom: &AnonymousGroundAtom) -> bool {
+        self.current()
+            .relations
+            .get(relation)
+            .is_some_and(|facts| facts.contains(ground_atom))
+    }
+}
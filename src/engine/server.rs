@@ -0,0 +1,474 @@
+// A front-end to a runtime over a byte stream, such as a TCP connection, for clients that are not
+// written in Rust. Every request is an opcode followed by its arguments, and every response is OK
+// followed by the result, or ERROR followed by the error message. Integers are little-endian u64s,
+// strings are their length followed by their UTF-8 bytes, values are laid out as in snapshots and
+// facts are their arity followed by their values.
+//
+//   LOAD_PROGRAM rules           -> nothing
+//   INSERT relation facts        -> how many facts were new
+//   REMOVE relation matchers     -> nothing
+//   POLL                         -> the epoch that the poll ended
+//   QUERY relation matchers      -> the facts matching the matchers
+//
+// Rules, facts and matchers are all preceded by how many there are. A rule is its head followed by
+// its body atoms, an atom is its sign as a byte, its symbol and its terms, and a term or matcher is
+// a tag followed by what it holds.
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::TcpListener;
+
+use datalog_syntax::{
+    Aggregation, AnonymousGroundAtom, Atom, Expression, Matcher, Operator, Program, Query, Rule,
+    Term,
+};
+
+use super::datalog::MicroRuntime;
+use super::epoch_storage::Epoch;
+use super::snapshot::{read_string, read_u64, read_value, write_bytes, write_u64, write_value};
+
+pub const LOAD_PROGRAM: u8 = 0;
+pub const INSERT: u8 = 1;
+pub const REMOVE: u8 = 2;
+pub const POLL: u8 = 3;
+pub const QUERY: u8 = 4;
+
+pub const OK: u8 = 0;
+pub const ERROR: u8 = 1;
+
+const ANY_MATCHER: u8 = 0;
+const CONSTANT_MATCHER: u8 = 1;
+const NOT_MATCHER: u8 = 2;
+const GE_MATCHER: u8 = 3;
+const LE_MATCHER: u8 = 4;
+
+const VARIABLE_TERM: u8 = 0;
+const CONSTANT_TERM: u8 = 1;
+const AGGREGATE_TERM: u8 = 2;
+const EXPRESSION_TERM: u8 = 3;
+
+// Expressions are written operator first, and their leaves are tagged as terms are.
+const BINARY_EXPRESSION: u8 = 2;
+const OPERATORS: [Operator; 3] = [Operator::Add, Operator::Subtract, Operator::Multiply];
+
+const AGGREGATIONS: [Aggregation; 5] = [
+    Aggregation::Count,
+    Aggregation::Sum,
+    Aggregation::Min,
+    Aggregation::Max,
+    Aggregation::Choice,
+];
+
+// Serves one connection after the other, for as long as the listener accepts them. A connection
+// that breaks is dropped, while the runtime stays as the requests before left it.
+pub fn serve(runtime: &mut MicroRuntime, listener: &TcpListener) -> Result<(), String> {
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|error| error.to_string())?;
+        let reader = stream.try_clone().map_err(|error| error.to_string())?;
+
+        let _ = handle_connection(runtime, BufReader::new(reader), BufWriter::new(stream));
+    }
+
+    Ok(())
+}
+
+// Answers requests until the other side closes the stream.
+pub fn handle_connection(
+    runtime: &mut MicroRuntime,
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<(), String> {
+    loop {
+        let mut opcode = [0; 1];
+        match reader.read(&mut opcode) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(error) => return Err(error.to_string()),
+        }
+
+        let mut response = vec![OK];
+        let outcome = match opcode[0] {
+            LOAD_PROGRAM => {
+                read_program(&mut reader).and_then(|program| runtime.replace_program(program))
+            }
+            INSERT => {
+                let relation = read_string(&mut reader)?;
+                let facts = read_facts(&mut reader)?;
+
+                facts
+                    .into_iter()
+                    .try_fold(0, |inserted, fact| {
+                        Ok::<_, String>(inserted + runtime.insert(&relation, fact)? as u64)
+                    })
+                    .and_then(|inserted| write_u64(&mut response, inserted))
+            }
+            REMOVE => {
+                let relation = read_string(&mut reader)?;
+                let matchers = read_matchers(&mut reader)?;
+
+                runtime.remove(&query(&relation, matchers));
+                Ok(())
+            }
+            POLL => runtime
+                .try_poll()
+                .and_then(|_| write_u64(&mut response, runtime.epoch() as u64)),
+            QUERY => {
+                let relation = read_string(&mut reader)?;
+                let matchers = read_matchers(&mut reader)?;
+                let query = query(&relation, matchers);
+
+                runtime
+                    .query(&query)
+                    .map(|facts| facts.collect::<Vec<_>>())
+                    .and_then(|facts| write_facts(&mut response, &facts))
+            }
+            unknown => Err(format!("unknown opcode {}", unknown)),
+        };
+
+        if let Err(error) = outcome {
+            response = vec![ERROR];
+            write_bytes(&mut response, error.as_bytes())?;
+        }
+        writer
+            .write_all(&response)
+            .and_then(|_| writer.flush())
+            .map_err(|error| error.to_string())?;
+    }
+}
+
+fn query(relation: &str, matchers: Vec<Matcher>) -> Query<'_> {
+    Query {
+        matchers,
+        symbol: relation,
+        since: None,
+        source: None,
+    }
+}
+
+// Sends requests to a server and reads its responses, which is all there is to a client.
+pub struct Client<S: Read + Write> {
+    stream: S,
+}
+
+impl<S: Read + Write> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+    fn request(&mut self, request: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(request)
+            .and_then(|_| self.stream.flush())
+            .map_err(|error| error.to_string())?;
+
+        let mut status = [0; 1];
+        self.stream
+            .read_exact(&mut status)
+            .map_err(|error| error.to_string())?;
+        match status[0] {
+            OK => Ok(()),
+            ERROR => Err(read_string(&mut self.stream)?),
+            unknown => Err(format!("unknown status {}", unknown)),
+        }
+    }
+    pub fn load_program(&mut self, program: &Program) -> Result<(), String> {
+        let mut request = vec![LOAD_PROGRAM];
+        write_program(&mut request, program)?;
+
+        self.request(&request)
+    }
+    pub fn insert(&mut self, relation: &str, facts: &[AnonymousGroundAtom]) -> Result<u64, String> {
+        let mut request = vec![INSERT];
+        write_bytes(&mut request, relation.as_bytes())?;
+        write_facts(&mut request, facts)?;
+
+        self.request(&request)?;
+        read_u64(&mut self.stream)
+    }
+    pub fn remove(&mut self, query: &Query) -> Result<(), String> {
+        let mut request = vec![REMOVE];
+        write_query(&mut request, query)?;
+
+        self.request(&request)
+    }
+    pub fn poll(&mut self) -> Result<Epoch, String> {
+        self.request(&[POLL])?;
+
+        Ok(read_u64(&mut self.stream)? as Epoch)
+    }
+    pub fn query(&mut self, query: &Query) -> Result<Vec<AnonymousGroundAtom>, String> {
+        let mut request = vec![QUERY];
+        write_query(&mut request, query)?;
+
+        self.request(&request)?;
+        read_facts(&mut self.stream)
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8, String> {
+    let mut byte = [0; 1];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|error| error.to_string())?;
+
+    Ok(byte[0])
+}
+
+fn read_many<T>(
+    reader: &mut impl Read,
+    read_one: impl Fn(&mut dyn Read) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    let count = read_u64(reader)?;
+
+    (0..count).map(|_| read_one(reader)).collect()
+}
+
+fn write_facts(writer: &mut impl Write, facts: &[AnonymousGroundAtom]) -> Result<(), String> {
+    write_u64(writer, facts.len() as u64)?;
+    facts.iter().try_for_each(|fact| {
+        write_u64(writer, fact.len() as u64)?;
+        fact.iter().try_for_each(|value| write_value(writer, value))
+    })
+}
+
+fn read_facts(reader: &mut impl Read) -> Result<Vec<AnonymousGroundAtom>, String> {
+    read_many(reader, |mut reader| {
+        read_many(&mut reader, |mut reader| read_value(&mut reader))
+    })
+}
+
+fn write_query(writer: &mut impl Write, query: &Query) -> Result<(), String> {
+    if query.since.is_some() || query.source.is_some() {
+        return Err("the server does not filter by metadata".to_string());
+    }
+
+    write_bytes(writer, query.symbol.as_bytes())?;
+    write_u64(writer, query.matchers.len() as u64)?;
+    query.matchers.iter().try_for_each(|matcher| match matcher {
+        Matcher::Any => write_byte(writer, ANY_MATCHER),
+        Matcher::Constant(value) => {
+            write_byte(writer, CONSTANT_MATCHER)?;
+            write_value(writer, value)
+        }
+        Matcher::Not(value) => {
+            write_byte(writer, NOT_MATCHER)?;
+            write_value(writer, value)
+        }
+        Matcher::Ge(value) => {
+            write_byte(writer, GE_MATCHER)?;
+            write_value(writer, value)
+        }
+        Matcher::Le(value) => {
+            write_byte(writer, LE_MATCHER)?;
+            write_value(writer, value)
+        }
+    })
+}
+
+fn read_matchers(reader: &mut impl Read) -> Result<Vec<Matcher>, String> {
+    read_many(reader, |mut reader| match read_byte(&mut reader)? {
+        ANY_MATCHER => Ok(Matcher::Any),
+        CONSTANT_MATCHER => Ok(Matcher::Constant(read_value(&mut reader)?)),
+        NOT_MATCHER => Ok(Matcher::Not(read_value(&mut reader)?)),
+        GE_MATCHER => Ok(Matcher::Ge(read_value(&mut reader)?)),
+        LE_MATCHER => Ok(Matcher::Le(read_value(&mut reader)?)),
+        unknown => Err(format!("unknown matcher tag {}", unknown)),
+    })
+}
+
+fn write_byte(writer: &mut impl Write, byte: u8) -> Result<(), String> {
+    writer.write_all(&[byte]).map_err(|error| error.to_string())
+}
+
+fn write_atom(writer: &mut impl Write, atom: &Atom) -> Result<(), String> {
+    write_byte(writer, atom.sign as u8)?;
+    write_bytes(writer, atom.symbol.as_bytes())?;
+    write_u64(writer, atom.terms.len() as u64)?;
+    atom.terms.iter().try_for_each(|term| match term {
+        Term::Variable(variable) => {
+            write_byte(writer, VARIABLE_TERM)?;
+            write_bytes(writer, variable.as_bytes())
+        }
+        Term::Constant(value) => {
+            write_byte(writer, CONSTANT_TERM)?;
+            write_value(writer, value)
+        }
+        Term::Aggregate(aggregation, variable) => {
+            write_byte(writer, AGGREGATE_TERM)?;
+            let position = AGGREGATIONS.iter().position(|known| known == aggregation);
+            write_byte(writer, position.unwrap() as u8)?;
+            write_bytes(writer, variable.as_bytes())
+        }
+        Term::Expression(expression) => {
+            write_byte(writer, EXPRESSION_TERM)?;
+            write_expression(writer, expression)
+        }
+    })
+}
+
+fn write_expression(writer: &mut impl Write, expression: &Expression) -> Result<(), String> {
+    match expression {
+        Expression::Variable(variable) => {
+            write_byte(writer, VARIABLE_TERM)?;
+            write_bytes(writer, variable.as_bytes())
+        }
+        Expression::Constant(value) => {
+            write_byte(writer, CONSTANT_TERM)?;
+            write_value(writer, value)
+        }
+        Expression::Binary(operator, left, right) => {
+            write_byte(writer, BINARY_EXPRESSION)?;
+            let position = OPERATORS.iter().position(|known| known == operator);
+            write_byte(writer, position.unwrap() as u8)?;
+            write_expression(writer, left)?;
+            write_expression(writer, right)
+        }
+    }
+}
+
+fn read_expression(reader: &mut impl Read) -> Result<Expression, String> {
+    match read_byte(reader)? {
+        VARIABLE_TERM => Ok(Expression::Variable(read_string(reader)?)),
+        CONSTANT_TERM => Ok(Expression::Constant(read_value(reader)?)),
+        BINARY_EXPRESSION => {
+            let operator = read_byte(reader)?;
+            let Some(operator) = OPERATORS.get(operator as usize) else {
+                return Err(format!("unknown operator {}", operator));
+            };
+            let left = read_expression(reader)?;
+            let right = read_expression(reader)?;
+
+            Ok(Expression::Binary(
+                *operator,
+                Box::new(left),
+                Box::new(right),
+            ))
+        }
+        unknown => Err(format!("unknown expression tag {}", unknown)),
+    }
+}
+
+fn read_atom(reader: &mut impl Read) -> Result<Atom, String> {
+    let sign = read_byte(reader)? != 0;
+    let symbol = read_string(reader)?;
+    let terms = read_many(reader, |mut reader| match read_byte(&mut reader)? {
+        VARIABLE_TERM => Ok(Term::Variable(read_string(&mut reader)?)),
+        CONSTANT_TERM => Ok(Term::Constant(read_value(&mut reader)?)),
+        AGGREGATE_TERM => {
+            let aggregation = read_byte(&mut reader)?;
+            let Some(aggregation) = AGGREGATIONS.get(aggregation as usize) else {
+                return Err(format!("unknown aggregation {}", aggregation));
+            };
+
+            Ok(Term::Aggregate(*aggregation, read_string(&mut reader)?))
+        }
+        EXPRESSION_TERM => Ok(Term::Expression(read_expression(&mut reader)?)),
+        unknown => Err(format!("unknown term tag {}", unknown)),
+    })?;
+
+    Ok(Atom {
+        terms,
+        symbol,
+        sign,
+    })
+}
+
+fn write_program(writer: &mut impl Write, program: &Program) -> Result<(), String> {
+    write_u64(writer, program.inner.len() as u64)?;
+    program.inner.iter().try_for_each(|rule| {
+        write_atom(writer, &rule.head)?;
+        write_u64(writer, rule.body.len() as u64)?;
+        rule.body
+            .iter()
+            .try_for_each(|body_atom| write_atom(writer, body_atom))
+    })
+}
+
+fn read_program(reader: &mut impl Read) -> Result<Program, String> {
+    let rules = read_many(reader, |mut reader| {
+        let head = read_atom(&mut reader)?;
+        let body = read_many(&mut reader, |mut reader| read_atom(&mut reader))?;
+
+        Ok(Rule {
+            head,
+            body,
+            id: 0,
+            hints: Default::default(),
+        })
+    })?;
+
+    Ok(Program::from(rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::datalog::MicroRuntime;
+    use crate::engine::server::{handle_connection, Client};
+    use datalog_rule_macro::program;
+    use datalog_syntax::*;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut client = Client::new(TcpStream::connect(address).unwrap());
+            client
+                .load_program(&program! {
+                    tc(?x, ?y) <- [e(?x, ?y)],
+                    tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+                    degree(?x, count(?y)) <- [e(?x, ?y)],
+                })
+                .unwrap();
+
+            let edges: Vec<AnonymousGroundAtom> = vec![
+                vec!["a".into(), "b".into()],
+                vec!["b".into(), "c".into()],
+                vec!["a".into(), "b".into()],
+            ];
+            assert_eq!(2, client.insert("e", &edges).unwrap());
+            assert_eq!(1, client.poll().unwrap());
+
+            let mut reachable = client.query(&build_query!(tc("a", _))).unwrap();
+            reachable.sort();
+            let expected: Vec<AnonymousGroundAtom> =
+                vec![vec!["a".into(), "b".into()], vec!["a".into(), "c".into()]];
+            assert_eq!(expected, reachable);
+            let expected: Vec<AnonymousGroundAtom> = vec![vec!["a".into(), 1usize.into()]];
+            assert_eq!(
+                expected,
+                client.query(&build_query!(degree("a", _))).unwrap()
+            );
+
+            client.remove(&build_query!(e("b", _))).unwrap();
+            client.poll().unwrap();
+            assert_eq!(1, client.query(&build_query!(tc(_, _))).unwrap().len());
+
+            // Errors come back as they are, and the connection goes on.
+            assert!(client.query(&build_query!(tc(_))).is_err());
+            let atom = |symbol: &str, variables: &[&str]| Atom {
+                terms: variables
+                    .iter()
+                    .map(|variable| Term::Variable(variable.to_string()))
+                    .collect(),
+                symbol: symbol.to_string(),
+                sign: true,
+            };
+            let unsafe_rule = Rule {
+                head: atom("unsafe", &["x", "y"]),
+                body: vec![atom("e", &["x", "x"])],
+                id: 0,
+                hints: Default::default(),
+            };
+            assert!(client
+                .load_program(&Program::from(vec![unsafe_rule]))
+                .is_err());
+            assert_eq!(1, client.query(&build_query!(tc(_, _))).unwrap().len());
+        });
+
+        let mut runtime = MicroRuntime::new(Program::from(vec![]));
+        let connection = listener.incoming().next().unwrap().unwrap();
+        let reader = connection.try_clone().unwrap();
+        handle_connection(&mut runtime, reader, connection).unwrap();
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use datalog_syntax::{AnonymousGroundAtom, OrderedFloat, Program, TypedValue};
+
+use super::epoch_storage::{Epoch, EpochStorage};
+use super::storage::RelationStorage;
+
+const MAGIC: &[u8; 4] = b"MDLG";
+const FORMAT_VERSION: u32 = 1;
+
+const STR_TAG: u8 = 0;
+const INT_TAG: u8 = 1;
+const BOOL_TAG: u8 = 2;
+const SIGNED_INT_TAG: u8 = 3;
+const FLOAT_TAG: u8 = 4;
+
+// Epochs start at one, so this marks facts that were never tagged, such as pending insertions.
+const UNTAGGED: u64 = 0;
+
+// FNV-1a over the program's debug representation, which unlike the std hashers is guaranteed to
+// stay the same across builds.
+pub fn program_hash(program: &Program) -> u64 {
+    format!("{:?}", program)
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+pub fn write_header(writer: &mut impl Write, program: &Program) -> Result<(), String> {
+    writer.write_all(MAGIC).map_err(|error| error.to_string())?;
+    writer
+        .write_all(&FORMAT_VERSION.to_le_bytes())
+        .map_err(|error| error.to_string())?;
+    write_u64(writer, program_hash(program))
+}
+
+pub fn read_header(reader: &mut impl Read, program: &Program) -> Result<(), String> {
+    let mut magic = [0; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|error| error.to_string())?;
+    if &magic != MAGIC {
+        return Err("not a snapshot".to_string());
+    }
+
+    let mut version = [0; 4];
+    reader
+        .read_exact(&mut version)
+        .map_err(|error| error.to_string())?;
+    if u32::from_le_bytes(version) != FORMAT_VERSION {
+        return Err("unsupported snapshot version".to_string());
+    }
+
+    if read_u64(reader)? != program_hash(program) {
+        return Err("snapshot was taken with a different program".to_string());
+    }
+
+    Ok(())
+}
+
+pub fn write_u64(writer: &mut impl Write, value: u64) -> Result<(), String> {
+    writer
+        .write_all(&value.to_le_bytes())
+        .map_err(|error| error.to_string())
+}
+
+pub fn read_u64(reader: &mut impl Read) -> Result<u64, String> {
+    let mut bytes = [0; 8];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|error| error.to_string())?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<(), String> {
+    write_u64(writer, bytes.len() as u64)?;
+    writer.write_all(bytes).map_err(|error| error.to_string())
+}
+
+pub fn read_string(reader: &mut impl Read) -> Result<String, String> {
+    let mut bytes = vec![0; read_u64(reader)? as usize];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|error| error.to_string())?;
+
+    String::from_utf8(bytes).map_err(|error| error.to_string())
+}
+
+pub fn write_value(writer: &mut impl Write, value: &TypedValue) -> Result<(), String> {
+    let (tag, payload) = match value {
+        TypedValue::Str(inner) => {
+            writer
+                .write_all(&[STR_TAG])
+                .map_err(|error| error.to_string())?;
+            return write_bytes(writer, inner.as_bytes());
+        }
+        TypedValue::Int(inner) => (INT_TAG, *inner as u64),
+        TypedValue::Bool(inner) => (BOOL_TAG, *inner as u64),
+        TypedValue::SignedInt(inner) => (SIGNED_INT_TAG, *inner as u64),
+        TypedValue::Float(inner) => (FLOAT_TAG, inner.0.to_bits()),
+    };
+
+    writer
+        .write_all(&[tag])
+        .map_err(|error| error.to_string())?;
+    write_u64(writer, payload)
+}
+
+pub fn read_value(reader: &mut impl Read) -> Result<TypedValue, String> {
+    let mut tag = [0; 1];
+    reader
+        .read_exact(&mut tag)
+        .map_err(|error| error.to_string())?;
+
+    match tag[0] {
+        STR_TAG => Ok(TypedValue::from(read_string(reader)?)),
+        INT_TAG => Ok(TypedValue::Int(read_u64(reader)? as usize)),
+        BOOL_TAG => Ok(TypedValue::Bool(read_u64(reader)? != 0)),
+        SIGNED_INT_TAG => Ok(TypedValue::SignedInt(read_u64(reader)? as i64)),
+        FLOAT_TAG => Ok(TypedValue::Float(OrderedFloat(f64::from_bits(read_u64(
+            reader,
+        )?)))),
+        unknown => Err(format!("unknown value tag {}", unknown)),
+    }
+}
+
+// Every relation is laid out as its symbol and its facts, each fact followed by its epoch.
+pub fn write_storage(
+    writer: &mut impl Write,
+    storage: &RelationStorage,
+    epochs: &EpochStorage,
+) -> Result<(), String> {
+    write_u64(writer, storage.inner.len() as u64)?;
+
+    for (relation_symbol, facts) in &storage.inner {
+        write_bytes(writer, relation_symbol.as_bytes())?;
+        write_u64(writer, facts.len() as u64)?;
+
+        for fact in facts {
+            write_u64(writer, fact.len() as u64)?;
+            for value in fact.iter() {
+                write_value(writer, value)?;
+            }
+
+            let epoch = epochs
+                .get(relation_symbol, fact)
+                .map_or(UNTAGGED, |epoch| epoch as u64);
+            write_u64(writer, epoch)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_storage(
+    reader: &mut impl Read,
+    storage: &mut RelationStorage,
+    epochs: &mut EpochStorage,
+) -> Result<(), String> {
+    let relation_count = read_u64(reader)?;
+
+    for _ in 0..relation_count {
+        let relation_symbol = read_string(reader)?;
+        let fact_count = read_u64(reader)? as usize;
+
+        let mut facts = Vec::with_capacity(fact_count);
+        for _ in 0..fact_count {
+            let arity = read_u64(reader)? as usize;
+            let fact: AnonymousGroundAtom = (0..arity)
+                .map(|_| read_value(reader))
+                .collect::<Result<_, _>>()?;
+            let fact = Arc::new(fact);
+
+            let epoch = read_u64(reader)?;
+            if epoch != UNTAGGED {
+                epochs.tag(&relation_symbol, [&fact].into_iter(), epoch as Epoch);
+            }
+
+            facts.push(fact);
+        }
+
+        storage.insert_all(&relation_symbol, facts.into_iter());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use datalog_syntax::{AnonymousGroundAtom, OrderedFloat, TypedValue};
+use rusqlite::types::{Value, ValueRef};
+
+use crate::engine::delimited::ColumnType;
+
+pub use rusqlite::Connection;
+
+// Converts a value of a row to the type of its column, if it has one. SQLite has no booleans, and
+// stores integers as signed, which is what they are read as otherwise.
+fn read_value(value: ValueRef, column_type: Option<ColumnType>) -> Result<TypedValue, String> {
+    let invalid = || format!("{:?} is not a valid {:?}", value, column_type.unwrap());
+
+    match (value, column_type) {
+        (ValueRef::Null, _) => Err("NULL can not be a value of a fact".to_string()),
+        (ValueRef::Blob(_), _) => Err("blobs can not be values of facts".to_string()),
+        (ValueRef::Integer(integer), Some(ColumnType::Int)) => usize::try_from(integer)
+            .map(TypedValue::Int)
+            .map_err(|_| invalid()),
+        (ValueRef::Integer(integer), Some(ColumnType::Bool)) => Ok(TypedValue::Bool(integer != 0)),
+        (ValueRef::Integer(integer), Some(ColumnType::Float)) => {
+            Ok(TypedValue::Float(OrderedFloat(integer as f64)))
+        }
+        (ValueRef::Integer(integer), Some(ColumnType::Str)) => Ok(integer.to_string().into()),
+        (ValueRef::Integer(integer), _) => Ok(TypedValue::SignedInt(integer)),
+        (ValueRef::Real(real), Some(ColumnType::Str)) => Ok(real.to_string().into()),
+        (ValueRef::Real(real), None | Some(ColumnType::Float)) => {
+            Ok(TypedValue::Float(OrderedFloat(real)))
+        }
+        (ValueRef::Real(_), _) => Err(invalid()),
+        (ValueRef::Text(text), column_type) => {
+            let text = std::str::from_utf8(text).map_err(|error| error.to_string())?;
+
+            column_type.unwrap_or(ColumnType::Str).parse(text)
+        }
+    }
+}
+
+fn write_value(value: &TypedValue) -> Result<Value, String> {
+    Ok(match value {
+        TypedValue::Str(inner) => Value::Text(inner.to_string()),
+        TypedValue::Int(inner) => Value::Integer(
+            i64::try_from(*inner).map_err(|_| format!("{} is too large for SQLite", inner))?,
+        ),
+        TypedValue::Bool(inner) => Value::Integer(*inner as i64),
+        TypedValue::SignedInt(inner) => Value::Integer(*inner),
+        TypedValue::Float(inner) => Value::Real(inner.0),
+    })
+}
+
+// Every row that the query returns is a fact, with its values converted to the types of the columns
+// that they are read into.
+pub fn read_rows(
+    connection: &Connection,
+    query: &str,
+    column_type: impl Fn(usize) -> Option<ColumnType>,
+) -> Result<Vec<AnonymousGroundAtom>, String> {
+    let mut statement = connection
+        .prepare(query)
+        .map_err(|error| error.to_string())?;
+    let column_count = statement.column_count();
+    let mut rows = statement.query([]).map_err(|error| error.to_string())?;
+
+    let mut facts = vec![];
+    while let Some(row) = rows.next().map_err(|error| error.to_string())? {
+        let fact = (0..column_count)
+            .map(|column| {
+                let value = row.get_ref(column).map_err(|error| error.to_string())?;
+
+                read_value(value, column_type(column))
+                    .map_err(|error| format!("row {}: {}", facts.len() + 1, error))
+            })
+            .collect::<Result<_, _>>()?;
+        facts.push(fact);
+    }
+
+    Ok(facts)
+}
+
+// Replaces the rows of the table with the facts, in one transaction. A table that does not exist is
+// created, with columns named c0, c1 and so on.
+pub fn write_rows(
+    connection: &Connection,
+    table: &str,
+    arity: usize,
+    facts: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
+) -> Result<(), String> {
+    let table = format!("\"{}\"", table.replace('"', "\"\""));
+    let columns: Vec<_> = (0..arity).map(|column| format!("c{}", column)).collect();
+    let placeholders = vec!["?"; arity].join(", ");
+
+    let transaction = connection
+        .unchecked_transaction()
+        .map_err(|error| error.to_string())?;
+    transaction
+        .execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}); DELETE FROM {};",
+            table,
+            columns.join(", "),
+            table
+        ))
+        .map_err(|error| error.to_string())?;
+    {
+        let mut statement = transaction
+            .prepare(&format!("INSERT INTO {} VALUES ({})", table, placeholders))
+            .map_err(|error| error.to_string())?;
+        for fact in facts {
+            let values = fact
+                .iter()
+                .map(write_value)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            statement
+                .execute(rusqlite::params_from_iter(values))
+                .map_err(|error| error.to_string())?;
+        }
+    }
+
+    transaction.commit().map_err(|error| error.to_string())
+}
@@ -1,44 +1,174 @@
-use crate::evaluation::spj_processor::RuleEvaluator;
+use crate::evaluation::spj_processor::{RuleEvaluator, StackCache};
 use crate::helpers::helpers::{OVERDELETION_PREFIX, REDERIVATION_PREFIX};
 use ahash::{HashMap, HashMapExt};
-use datalog_syntax::{AnonymousGroundAtom, Program};
-use indexmap::IndexSet;
+use datalog_syntax::{AnonymousGroundAtom, Program, Rule, TypedValue};
+use indexmap::{IndexMap, IndexSet};
 use std::sync::Arc;
 
+use super::equivalence::Equivalence;
+use super::fact_interner::FactInterner;
+use super::hash_index::HashIndex;
 use super::index_storage::{EphemeralValue, IndexStorage};
+use super::journal::Journal;
+use super::observer::{EvaluationObserver, Stopwatch};
 pub type FactStorage = IndexSet<Arc<AnonymousGroundAtom>, ahash::RandomState>;
-#[derive(Default)]
+
+// A relation whose capacity exceeds this many times its length gets shrunk on compaction.
+pub(crate) const COMPACTION_FACTOR: usize = 2;
+
+#[derive(Clone, Default)]
 pub struct RelationStorage {
-    pub(crate) inner: HashMap<String, FactStorage>,
+    // Relations are kept in the order they were first seen, so that going through them does not
+    // depend on how their names hash.
+    pub(crate) inner: IndexMap<String, FactStorage, ahash::RandomState>,
+    // Hash indices over the join columns of relations, kept in step with every change to them.
+    pub(crate) indices: HashMap<String, Vec<HashIndex>>,
+    // The relations that hold the closure of an equivalence, which is kept alongside their facts.
+    pub(crate) equivalences: HashMap<String, Equivalence>,
+    // Shares one allocation between equal facts, when they are asked to be shared.
+    pub(crate) interner: Option<FactInterner>,
+    // The facts inserted and removed while the journal is open, which were held before if they
+    // were removed.
+    pub(crate) journal: Journal<Arc<AnonymousGroundAtom>, ()>,
+}
+
+// Roughly what a fact takes: its values, and the vector and reference counts around them.
+fn fact_bytes(fact: &AnonymousGroundAtom) -> usize {
+    2 * size_of::<usize>()
+        + size_of::<AnonymousGroundAtom>()
+        + fact.capacity() * size_of::<TypedValue>()
 }
 
+// The entry of a fact in the set of its relation.
+const ENTRY_BYTES: usize = size_of::<(u64, Arc<AnonymousGroundAtom>)>();
+
 impl RelationStorage {
+    // Roughly how many bytes the facts and the indices over them take.
+    pub fn bytes(&self) -> (usize, usize) {
+        let entries = self.len() * ENTRY_BYTES;
+        // Shared facts are only counted once.
+        let facts: usize = match &self.interner {
+            Some(interner) => interner.facts().map(|fact| fact_bytes(fact)).sum(),
+            None => self
+                .inner
+                .values()
+                .flat_map(|facts| facts.iter())
+                .map(|fact| fact_bytes(fact))
+                .sum(),
+        };
+        let indices = self
+            .indices
+            .values()
+            .flat_map(|indices| indices.iter())
+            .map(HashIndex::bytes)
+            .sum();
+
+        (entries + facts, indices)
+    }
+    fn share(&mut self, fact: Arc<AnonymousGroundAtom>) -> Arc<AnonymousGroundAtom> {
+        match &mut self.interner {
+            Some(interner) => interner.intern(fact),
+            None => fact,
+        }
+    }
     pub fn get_relation(&self, relation_symbol: &str) -> &FactStorage {
-        return self.inner.get(relation_symbol).unwrap();
+        self.inner.get(relation_symbol).unwrap()
+    }
+    pub fn relation(&self, relation_symbol: &str) -> Result<&FactStorage, String> {
+        self.inner
+            .get(relation_symbol)
+            .ok_or_else(|| format!("{} is not a relation of the program", relation_symbol))
+    }
+    // The facts of a relation, of which there are none if it was never seen.
+    pub fn facts(&self, relation_symbol: &str) -> impl Iterator<Item = &Arc<AnonymousGroundAtom>> {
+        self.inner.get(relation_symbol).into_iter().flatten()
+    }
+    pub fn register_index(&mut self, relation_symbol: &str, columns: Vec<usize>) {
+        if self.get_index(relation_symbol, &columns).is_some() {
+            return;
+        }
+
+        let facts = self
+            .inner
+            .get(relation_symbol)
+            .into_iter()
+            .flatten()
+            .cloned();
+        let index = HashIndex::from_facts(columns, facts);
+
+        self.indices
+            .entry(relation_symbol.to_string())
+            .or_default()
+            .push(index);
+    }
+    pub fn drop_index(&mut self, relation_symbol: &str, columns: &[usize]) -> bool {
+        let Some(indices) = self.indices.get_mut(relation_symbol) else {
+            return false;
+        };
+        let previous_length = indices.len();
+        indices.retain(|index| index.columns != columns);
+
+        indices.len() < previous_length
+    }
+    pub fn get_equivalence(&self, relation_symbol: &str) -> Option<&Equivalence> {
+        self.equivalences.get(relation_symbol)
+    }
+    pub fn get_index(&self, relation_symbol: &str, columns: &[usize]) -> Option<&HashIndex> {
+        self.indices
+            .get(relation_symbol)?
+            .iter()
+            .find(|index| index.columns == columns)
+    }
+    fn index_fact(&mut self, relation_symbol: &str, fact: &Arc<AnonymousGroundAtom>) {
+        if let Some(indices) = self.indices.get_mut(relation_symbol) {
+            indices
+                .iter_mut()
+                .for_each(|index| index.insert(fact.clone()));
+        }
+    }
+    fn unindex_fact(&mut self, relation_symbol: &str, fact: &AnonymousGroundAtom) {
+        if let Some(indices) = self.indices.get_mut(relation_symbol) {
+            indices.iter_mut().for_each(|index| index.remove(fact));
+        }
+    }
+    fn clear_indices(&mut self, relation_symbol: &str) {
+        if let Some(indices) = self.indices.get_mut(relation_symbol) {
+            indices.iter_mut().for_each(HashIndex::clear);
+        }
     }
     pub fn drain_relation(&mut self, relation_symbol: &str) -> Vec<Arc<AnonymousGroundAtom>> {
-        let rel = self.inner.get_mut(relation_symbol).unwrap();
+        self.clear_indices(relation_symbol);
 
-        return rel.drain(..).collect();
+        let facts: Vec<_> = self
+            .inner
+            .get_mut(relation_symbol)
+            .map_or(vec![], |relation| relation.drain(..).collect());
+        facts
+            .iter()
+            .for_each(|fact| self.journal.record(relation_symbol, fact, Some(())));
+
+        facts
     }
     pub fn drain_all_relations(
         &mut self,
     ) -> impl Iterator<Item = (String, Vec<Arc<AnonymousGroundAtom>>)> + '_ {
-        let relations_to_be_drained: Vec<_> = self
-            .inner
+        let relations_to_be_drained: Vec<_> = self.inner.keys().cloned().collect();
+        relations_to_be_drained
             .iter()
-            .map(|(symbol, _)| symbol.clone())
-            .collect();
+            .for_each(|relation_symbol| self.clear_indices(relation_symbol));
 
         relations_to_be_drained.into_iter().map(|relation_symbol| {
-            (
-                relation_symbol.clone(),
-                self.inner
-                    .get_mut(&relation_symbol)
-                    .unwrap()
-                    .drain(..)
-                    .collect::<Vec<_>>(),
-            )
+            let facts: Vec<_> = self
+                .inner
+                .get_mut(&relation_symbol)
+                .unwrap()
+                .drain(..)
+                .collect();
+            facts
+                .iter()
+                .for_each(|fact| self.journal.record(&relation_symbol, fact, Some(())));
+
+            (relation_symbol, facts)
         })
     }
     pub fn overdelete(&mut self) {
@@ -50,7 +180,7 @@ impl RelationStorage {
                 (
                     symbol.clone(),
                     symbol
-                        .strip_prefix(&OVERDELETION_PREFIX)
+                        .strip_prefix(OVERDELETION_PREFIX)
                         .unwrap()
                         .to_string(),
                 )
@@ -59,20 +189,25 @@ impl RelationStorage {
 
         overdeletion_relations.into_iter().for_each(
             |(overdeletion_symbol, actual_relation_symbol)| {
-                let overdeletion_relation = self.inner.remove(&overdeletion_symbol).unwrap();
-                let actual_relation = self.inner.get_mut(&actual_relation_symbol).unwrap();
+                let overdeletion_relation =
+                    std::mem::take(self.inner.get_mut(&overdeletion_symbol).unwrap());
 
                 overdeletion_relation.iter().for_each(|atom| {
-                    actual_relation.remove(atom);
+                    let actual_relation = self.inner.get_mut(&actual_relation_symbol).unwrap();
+                    if actual_relation.swap_remove(atom) {
+                        self.unindex_fact(&actual_relation_symbol, atom);
+                        self.journal.record(&actual_relation_symbol, atom, Some(()));
+                    }
                 });
 
-                // We insert it back because it is necessary for rederivation.
-                self.inner
-                    .insert(overdeletion_symbol, overdeletion_relation);
+                // We put it back because it is necessary for rederivation.
+                *self.inner.get_mut(&overdeletion_symbol).unwrap() = overdeletion_relation;
             },
         );
     }
-    pub fn rederive(&mut self) {
+    // Returns the facts that were put back into every relation.
+    pub fn rederive(&mut self) -> HashMap<String, FactStorage> {
+        let mut rederived: HashMap<String, FactStorage> = HashMap::new();
         let rederivation_relations: Vec<_> = self
             .inner
             .iter()
@@ -81,7 +216,7 @@ impl RelationStorage {
                 (
                     symbol.clone(),
                     symbol
-                        .strip_prefix(&REDERIVATION_PREFIX)
+                        .strip_prefix(REDERIVATION_PREFIX)
                         .unwrap()
                         .to_string(),
                 )
@@ -90,26 +225,51 @@ impl RelationStorage {
 
         rederivation_relations.into_iter().for_each(
             |(rederivation_symbol, actual_relation_symbol)| {
-                let rederivation_relation = self.inner.remove(&rederivation_symbol).unwrap();
-                let actual_relation = self.inner.get_mut(&actual_relation_symbol).unwrap();
-
-                rederivation_relation.into_iter().for_each(|atom| {
-                    actual_relation.insert(atom);
+                let mut rederivation_relation =
+                    std::mem::take(self.inner.get_mut(&rederivation_symbol).unwrap());
+
+                rederivation_relation.drain(..).for_each(|atom| {
+                    let actual_relation = self.inner.get_mut(&actual_relation_symbol).unwrap();
+                    if actual_relation.insert(atom.clone()) {
+                        self.index_fact(&actual_relation_symbol, &atom);
+                        self.journal.record(&actual_relation_symbol, &atom, None);
+                        rederived
+                            .entry(actual_relation_symbol.clone())
+                            .or_default()
+                            .insert(atom);
+                    }
                 });
+
+                // Same as with overdeletion, the rederivation program of the next poll needs it.
+                *self.inner.get_mut(&rederivation_symbol).unwrap() = rederivation_relation;
             },
         );
+
+        rederived
     }
+    #[allow(dead_code)]
     pub fn clear_relation(&mut self, relation_symbol: &str) {
-        self.inner.get_mut(relation_symbol).unwrap().clear();
+        self.drain_relation(relation_symbol);
     }
     pub fn clear_prefix(&mut self, prefix: &str) {
+        let journal = &mut self.journal;
         self.inner.iter_mut().for_each(|ref_mult| {
             let (symbol, relation) = ref_mult;
 
             if symbol.starts_with(prefix) {
+                if journal.is_open() {
+                    relation
+                        .iter()
+                        .for_each(|fact| journal.record(symbol, fact, Some(())));
+                }
                 relation.clear()
             }
         });
+        self.indices.iter_mut().for_each(|(symbol, indices)| {
+            if symbol.starts_with(prefix) {
+                indices.iter_mut().for_each(HashIndex::clear)
+            }
+        });
     }
 
     pub fn insert_registered(
@@ -117,58 +277,100 @@ impl RelationStorage {
         relation_symbol: &str,
         registrations: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
     ) {
-        let mut hashes = vec![];
+        self.insert_all(relation_symbol, registrations)
+    }
 
-        registrations.into_iter().for_each(|fact| {
-            hashes.push(fact);
+    pub fn insert_all(
+        &mut self,
+        relation_symbol: &str,
+        facts: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
+    ) {
+        if !self.inner.contains_key(relation_symbol) {
+            self.inner
+                .insert(relation_symbol.to_string(), FactStorage::default());
+        }
+        let relation = self.inner.get_mut(relation_symbol).unwrap();
+        let facts = facts.map(|fact| match &mut self.interner {
+            Some(interner) => interner.intern(fact),
+            None => fact,
         });
 
+        let journal = &mut self.journal;
+        match self.indices.get_mut(relation_symbol) {
+            None if !journal.is_open() => relation.extend(facts),
+            mut indices => facts.for_each(|fact| {
+                if relation.insert(fact.clone()) {
+                    indices
+                        .iter_mut()
+                        .flat_map(|indices| indices.iter_mut())
+                        .for_each(|index| index.insert(fact.clone()));
+                    journal.record(relation_symbol, &fact, None);
+                }
+            }),
+        }
+    }
+    pub fn reserve(&mut self, relation_symbol: &str, additional: usize) {
         if let Some(relation) = self.inner.get_mut(relation_symbol) {
-            relation.extend(hashes)
+            relation.reserve(additional);
         } else {
             let mut fresh_fact_storage = FactStorage::default();
-            fresh_fact_storage.extend(hashes);
+            fresh_fact_storage.reserve(additional);
 
             self.inner
                 .insert(relation_symbol.to_string(), fresh_fact_storage);
         }
     }
+    pub fn insert(&mut self, relation_symbol: &str, ground_atom: AnonymousGroundAtom) -> bool {
+        let fact = self.share(Arc::new(ground_atom));
 
-    pub fn insert_all(
-        &mut self,
-        relation_symbol: &str,
-        facts: impl Iterator<Item = Arc<AnonymousGroundAtom>>,
-    ) {
         if let Some(relation) = self.inner.get_mut(relation_symbol) {
-            relation.extend(facts.into_iter())
+            if !relation.insert(fact.clone()) {
+                return false;
+            }
         } else {
             let mut fresh_fact_storage = FactStorage::default();
-            fresh_fact_storage.extend(facts.into_iter());
+            fresh_fact_storage.insert(fact.clone());
 
             self.inner
                 .insert(relation_symbol.to_string(), fresh_fact_storage);
         }
-    }
-    pub fn insert(&mut self, relation_symbol: &str, ground_atom: AnonymousGroundAtom) -> bool {
-        if let Some(relation) = self.inner.get_mut(relation_symbol) {
-            return relation.insert(Arc::new(ground_atom));
-        }
-
-        let mut fresh_fact_storage = FactStorage::default();
-        fresh_fact_storage.insert(Arc::new(ground_atom));
-
-        self.inner
-            .insert(relation_symbol.to_string(), fresh_fact_storage);
+        self.index_fact(relation_symbol, &fact);
+        self.journal.record(relation_symbol, &fact, None);
 
         true
     }
-    pub fn remove(&mut self, relation_symbol: &str, ground_atom: AnonymousGroundAtom) -> bool {
+    pub fn remove(&mut self, relation_symbol: &str, ground_atom: &AnonymousGroundAtom) -> bool {
         if let Some(relation) = self.inner.get_mut(relation_symbol) {
-            return relation.remove(&ground_atom);
+            if let Some(fact) = relation.swap_take(ground_atom) {
+                self.unindex_fact(relation_symbol, ground_atom);
+                self.journal.record(relation_symbol, &fact, Some(()));
+
+                return true;
+            }
         }
 
         false
     }
+    // From then on, every change to the relations can be taken back.
+    pub(crate) fn open_journal(&mut self) {
+        self.journal.open();
+    }
+    pub(crate) fn close_journal(&mut self) {
+        self.journal.close();
+    }
+    // Takes back every change since the journal was opened, latest first. The facts are held as
+    // they were, though not necessarily in the same order.
+    pub(crate) fn roll_back(&mut self) {
+        let changes: Vec<_> = self.journal.take().collect();
+        changes
+            .into_iter()
+            .for_each(|(relation_symbol, fact, previous)| match previous {
+                Some(()) => self.insert_all(&relation_symbol, std::iter::once(fact)),
+                None => {
+                    self.remove(&relation_symbol, &fact);
+                }
+            });
+    }
     pub fn contains(&self, relation_symbol: &str, ground_atom: &AnonymousGroundAtom) -> bool {
         if let Some(relation) = self.inner.get(relation_symbol) {
             return relation.contains(ground_atom);
@@ -178,89 +380,163 @@ impl RelationStorage {
     }
 
     // Nonrecursive materialisation can be done sequentially in one pass.
-    pub fn materialize_nonrecursive_delta_program<'a>(
+    pub fn materialize_nonrecursive_delta_program(
         &mut self,
         nonrecursive_program: &Program,
+        stacks: &StackCache,
         index_storage: &mut IndexStorage,
-    ) {
+        observer: &mut dyn EvaluationObserver,
+    ) -> Result<(), String> {
         let mut new_diff: HashMap<String, Vec<EphemeralValue>> = HashMap::new();
+        let mut previous_facts: HashMap<String, Vec<EphemeralValue>> = HashMap::new();
 
-        for (_idx, rule) in nonrecursive_program.inner.iter().enumerate() {
-            let evaluator = RuleEvaluator::new(self, rule);
+        for rule in nonrecursive_program.inner.iter() {
+            let stopwatch = Stopwatch::start();
+            let evaluator = RuleEvaluator::new(self, rule, stacks.get(rule)?);
 
-            let evaluation = evaluator.step(index_storage);
+            let evaluation = evaluator.step(index_storage)?;
 
             let delta_relation_symbol = rule.head.symbol.clone();
 
-            let current_relation = self.get_relation(&delta_relation_symbol);
+            let current_relation = self.relation(&delta_relation_symbol)?;
+
+            // What the head held before this pass is what the recursive iterations start from.
+            previous_facts
+                .entry(delta_relation_symbol.clone())
+                .or_insert_with(|| {
+                    current_relation
+                        .iter()
+                        .cloned()
+                        .map(EphemeralValue::FactRef)
+                        .collect()
+                });
 
             let diff: FactStorage = evaluation
                 .into_iter()
                 .filter(|fact| !current_relation.contains(fact))
-                .map(|fact| Arc::new(fact))
+                .map(Arc::new)
                 .collect();
 
+            observer.rule_timed(rule, stopwatch.elapsed());
+            observer.rule_evaluated(rule, diff.len());
+            observer.facts_derived(rule, &mut diff.iter());
             self.insert_all(&delta_relation_symbol, diff.clone().into_iter());
-            new_diff.entry(delta_relation_symbol).or_default().extend(
-                diff.into_iter()
-                    .map(|x| super::index_storage::EphemeralValue::FactRef(x)),
-            );
+            new_diff
+                .entry(delta_relation_symbol)
+                .or_default()
+                .extend(diff.into_iter().map(EphemeralValue::FactRef));
         }
 
-        index_storage.inner.extend(index_storage.diff.drain());
-        index_storage.diff = new_diff;
+        // Every relation that was read in full during this pass has to be read in full once more
+        // by the first recursive iteration, since which of its facts are new is not known.
+        index_storage.replace(previous_facts, new_diff);
+
+        Ok(())
     }
-    pub fn materialize_recursive_delta_program<'a>(
+    pub fn materialize_recursive_delta_program(
         &mut self,
         recursive_program: &Program,
+        stacks: &StackCache,
         index_storage: &mut IndexStorage,
-    ) {
+        observer: &mut dyn EvaluationObserver,
+    ) -> Result<(), String> {
         let mut new_diff: HashMap<String, Vec<EphemeralValue>> = HashMap::new();
 
         let evaluation_setup: Vec<_> = recursive_program
             .inner
             .iter()
-            .map(|rule| (&rule.head.symbol, RuleEvaluator::new(self, rule)))
-            .collect();
+            .filter(|rule| may_derive(rule, index_storage))
+            .map(|rule| Ok((rule, RuleEvaluator::new(self, rule, stacks.get(rule)?))))
+            .collect::<Result<_, String>>()?;
 
         let evaluation = evaluation_setup
             .into_iter()
-            .map(|(delta_relation_symbol, rule)| {
-                let out = rule.step(index_storage).collect::<Vec<_>>();
-                (delta_relation_symbol, out)
+            .map(|(rule, evaluator)| {
+                let stopwatch = Stopwatch::start();
+                let out = evaluator.step(index_storage)?.collect::<Vec<_>>();
+                observer.rule_timed(rule, stopwatch.elapsed());
+                Ok((rule, out))
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // Heads that have not been seen yet start out from the facts they already hold.
+        for (rule, _) in &evaluation {
+            let delta_relation_symbol = &rule.head.symbol;
+            if !index_storage.inner.contains_key(delta_relation_symbol)
+                && !index_storage.diff.contains_key(delta_relation_symbol)
+            {
+                let current_relation = self.relation(delta_relation_symbol)?;
+
+                index_storage.insert_known(
+                    delta_relation_symbol,
+                    current_relation
+                        .iter()
+                        .cloned()
+                        .map(EphemeralValue::FactRef)
+                        .collect(),
+                );
+            }
+        }
 
-        evaluation.into_iter().enumerate().for_each(
-            |(_idx, (delta_relation_symbol, current_delta_evaluation))| {
+        evaluation
+            .into_iter()
+            .for_each(|(rule, current_delta_evaluation)| {
+                let delta_relation_symbol = &rule.head.symbol;
                 let curr = self.get_relation(delta_relation_symbol);
 
                 let diff: FactStorage = current_delta_evaluation
                     .into_iter()
                     .filter(|fact| !curr.contains(fact))
-                    .map(|fact| Arc::new(fact))
+                    .map(Arc::new)
                     .collect();
 
+                observer.rule_evaluated(rule, diff.len());
+                observer.facts_derived(rule, &mut diff.iter());
                 self.insert_all(delta_relation_symbol, diff.clone().into_iter());
                 new_diff
                     .entry(delta_relation_symbol.clone())
                     .or_default()
-                    .extend(
-                        diff.into_iter()
-                            .map(|x| super::index_storage::EphemeralValue::FactRef(x)),
-                    );
-            },
-        );
+                    .extend(diff.into_iter().map(EphemeralValue::FactRef));
+            });
+
+        index_storage.advance(new_diff);
 
-        index_storage.inner.extend(index_storage.diff.drain());
-        index_storage.diff = new_diff;
+        Ok(())
+    }
+
+    // Drained and cleared relations keep the capacity of the largest batch they ever held, so
+    // long-lived runtimes release it between polls.
+    pub fn compact(&mut self) {
+        self.inner.values_mut().for_each(|relation| {
+            if relation.capacity() > relation.len() * COMPACTION_FACTOR {
+                relation.shrink_to_fit();
+            }
+        });
+        self.indices
+            .values_mut()
+            .flatten()
+            .for_each(HashIndex::compact);
+        if let Some(interner) = &mut self.interner {
+            interner.sweep();
+        }
     }
 
     pub fn len(&self) -> usize {
-        return self.inner.iter().map(|(_symbol, facts)| facts.len()).sum();
+        self.inner.values().map(|facts| facts.len()).sum()
     }
 
     pub fn is_empty(&self) -> bool {
-        return self.len() == 0;
+        self.len() == 0
     }
 }
+
+// A rule can only derive something new if a relation that it reads has not been read yet, or has
+// changed in the last iteration. Negated relations are of a lower stratum, hence never change.
+fn may_derive(rule: &Rule, index_storage: &IndexStorage) -> bool {
+    rule.body.iter().filter(|atom| atom.sign).any(|atom| {
+        let delta = index_storage.diff.get(&atom.symbol);
+        let seen = delta.is_some() || index_storage.inner.contains_key(&atom.symbol);
+
+        !seen || delta.is_some_and(|delta| !delta.is_empty())
+    })
+}
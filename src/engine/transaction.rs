@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use ahash::HashMap;
+use datalog_syntax::{AnonymousGroundAtom, Query};
+
+use super::datalog::MicroRuntime;
+use crate::evaluation::query::pattern_match;
+
+// Changes that reach the runtime all at once when committed, and not at all when rolled back or
+// dropped. Until then, the runtime is left as it was.
+pub struct Transaction<'a> {
+    runtime: &'a mut MicroRuntime,
+    insertions: Vec<(String, AnonymousGroundAtom)>,
+    deletions: Vec<(String, Arc<AnonymousGroundAtom>)>,
+    // The arities of relations that the runtime knows nothing about yet, as set by staged facts.
+    arities: HashMap<String, usize>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(runtime: &'a mut MicroRuntime) -> Self {
+        Self {
+            runtime,
+            insertions: vec![],
+            deletions: vec![],
+            arities: Default::default(),
+        }
+    }
+    pub fn insert(
+        &mut self,
+        relation: &str,
+        ground_atom: AnonymousGroundAtom,
+    ) -> Result<(), String> {
+        self.runtime.check_counted_insertion(relation)?;
+        let expected_arity = match self.runtime.arity(relation) {
+            Some(arity) => arity,
+            None => *self
+                .arities
+                .entry(relation.to_string())
+                .or_insert(ground_atom.len()),
+        };
+        if ground_atom.len() != expected_arity {
+            return Err(format!(
+                "{} has arity {}, but a fact with {} terms was given",
+                relation,
+                expected_arity,
+                ground_atom.len()
+            ));
+        }
+        self.runtime.types().check(relation, &ground_atom)?;
+
+        self.insertions.push((relation.to_string(), ground_atom));
+
+        Ok(())
+    }
+    // Removes the facts matching the query, including those that this transaction has inserted.
+    // Those have no metadata, so queries that filter by it leave them be.
+    pub fn remove(&mut self, query: &Query) {
+        let filters_metadata = query.since.is_some() || query.source.is_some();
+        self.insertions.retain(|(relation, fact)| {
+            relation != query.symbol || filters_metadata || !pattern_match(query, fact)
+        });
+
+        let deletion_targets = self.runtime.matching_facts(query);
+        self.deletions.extend(
+            deletion_targets
+                .into_iter()
+                .map(|fact| (query.symbol.to_string(), fact)),
+        );
+    }
+    // Applies the staged changes and polls once. Should the poll fail, as when its intermediate
+    // results outgrow the memory limit, the runtime goes back to how it was before the commit, and
+    // the changes are dropped.
+    pub fn commit(self) -> Result<(), String> {
+        let state = self.runtime.save_state();
+        self.deletions.into_iter().for_each(|(relation, fact)| {
+            self.runtime.remove_facts(&relation, vec![fact]);
+        });
+        let inserted = self
+            .insertions
+            .into_iter()
+            .try_for_each(|(relation, fact)| self.runtime.insert(&relation, fact).map(|_| ()));
+
+        let result = inserted.and_then(|_| self.runtime.try_poll());
+        match result {
+            Ok(()) => self.runtime.keep_state(),
+            Err(_) => self.runtime.restore_state(state),
+        }
+
+        result
+    }
+    pub fn rollback(self) {}
+}
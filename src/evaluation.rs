@@ -1,3 +1,6 @@
+pub(crate) mod counting;
+pub(crate) mod lattice;
 pub(crate) mod query;
 pub(crate) mod semi_naive;
 pub(crate) mod spj_processor;
+pub(crate) mod top_down;
@@ -0,0 +1,334 @@
+use crate::engine::hash_index::HashIndex;
+use crate::engine::storage::RelationStorage;
+use crate::evaluation::spj_processor::get_existential_variables;
+use crate::program_transformations::analysis::ProgramAnalysis;
+use ahash::{HashMap, HashMapExt};
+use datalog_syntax::*;
+use indexmap::{IndexMap, IndexSet};
+use std::sync::Arc;
+
+type Fact = Arc<AnonymousGroundAtom>;
+type Binding = HashMap<String, TypedValue>;
+
+// What a poll changes about a relation. Insertions are facts that it did not hold, and deletions
+// are facts that it did.
+#[derive(Default)]
+pub(crate) struct Delta {
+    pub(crate) insertions: IndexSet<Fact>,
+    pub(crate) deletions: IndexSet<Fact>,
+}
+
+// How many ways every derived fact can be derived in, which is all it takes to tell whether a
+// change to the base relations takes a derived fact away. Only nonrecursive programs can be
+// counted, as a fact that derives itself would never run out of derivations.
+#[derive(Clone)]
+pub(crate) struct DerivationCounts {
+    // Every rule comes after those that derive the relations it reads.
+    rules: Vec<Rule>,
+    // Derived facts are kept in the order they were first derived in.
+    counts: HashMap<String, IndexMap<Fact, usize>>,
+    // Whether the counts are those of the facts that the runtime holds.
+    complete: bool,
+}
+
+impl DerivationCounts {
+    pub(crate) fn new(program: &Program) -> Result<Self, String> {
+        let analysis = ProgramAnalysis::new(program);
+        for rule in &program.inner {
+            if analysis.is_recursive(&rule.head.symbol) {
+                return Err(format!(
+                    "{} is recursive, hence its derivations can not be counted",
+                    rule.head.symbol
+                ));
+            }
+            if rule.is_aggregate() || rule.body.iter().any(|body_atom| !body_atom.sign) {
+                return Err(format!(
+                    "{:?} aggregates or negates, hence its derivations can not be counted",
+                    rule
+                ));
+            }
+            if let Some(variable) = get_existential_variables(rule).into_iter().next() {
+                return Err(format!(
+                    "{:?} has head variable {} that its body does not bind",
+                    rule, variable
+                ));
+            }
+        }
+
+        let position = |relation_symbol: &str| {
+            analysis
+                .components
+                .iter()
+                .position(|component| component.iter().any(|relation| relation == relation_symbol))
+        };
+        let mut rules = program.inner.clone();
+        rules.sort_by_key(|rule| position(&rule.head.symbol));
+
+        Ok(Self {
+            rules,
+            counts: HashMap::new(),
+            complete: true,
+        })
+    }
+    pub(crate) fn derives(&self, relation_symbol: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.head.symbol == relation_symbol)
+    }
+    pub(crate) fn is_complete(&self) -> bool {
+        self.complete
+    }
+    // Forgets every count, until they are all counted again.
+    pub(crate) fn clear(&mut self) {
+        self.counts.clear();
+        self.complete = false;
+    }
+    // The facts of the relation that have at least one derivation.
+    pub(crate) fn derived_facts(&self, relation_symbol: &str) -> impl Iterator<Item = &Fact> {
+        self.counts
+            .get(relation_symbol)
+            .into_iter()
+            .flat_map(|counts| counts.keys())
+    }
+    pub(crate) fn is_derived(&self, relation_symbol: &str, fact: &AnonymousGroundAtom) -> bool {
+        self.counts
+            .get(relation_symbol)
+            .is_some_and(|counts| counts.contains_key(fact))
+    }
+    // The columns that propagation looks facts up by, for the runtime to index.
+    pub(crate) fn lookups(&self) -> Vec<(String, Vec<usize>)> {
+        let mut lookups = vec![];
+        self.rules.iter().for_each(|rule| {
+            (0..rule.body.len()).for_each(|delta_position| {
+                let mut bound: IndexSet<&str> = variables(&rule.body[delta_position]).collect();
+                rule.body
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| *position != delta_position)
+                    .for_each(|(_, body_atom)| {
+                        let columns = bound_columns(body_atom, |variable| bound.contains(variable));
+                        if !columns.is_empty() {
+                            lookups.push((body_atom.symbol.clone(), columns));
+                        }
+                        bound.extend(variables(body_atom));
+                    });
+            })
+        });
+
+        lookups
+    }
+    // Carries the deltas of the base relations over to the relations that the rules derive, from
+    // the relations as they were before, adding the deltas of the derived relations to the others.
+    // Every rule is evaluated once for every body atom, joining its delta with the atoms before it
+    // as they are after the change and those after it as they were before, which counts every
+    // derivation that appears or disappears exactly once.
+    pub(crate) fn propagate(&mut self, old: &RelationStorage, deltas: &mut HashMap<String, Delta>) {
+        self.complete = true;
+
+        let mut inserted_indices = HashMap::new();
+        let mut start = 0;
+        while start < self.rules.len() {
+            let relation_symbol = self.rules[start].head.symbol.clone();
+            let end = start
+                + self.rules[start..]
+                    .iter()
+                    .take_while(|rule| rule.head.symbol == relation_symbol)
+                    .count();
+
+            let mut changes: IndexMap<AnonymousGroundAtom, isize> = IndexMap::new();
+            let mut views = Views {
+                old,
+                deltas,
+                inserted_indices: &mut inserted_indices,
+            };
+            self.rules[start..end].iter().for_each(|rule| {
+                rule.body
+                    .iter()
+                    .enumerate()
+                    .for_each(|(delta_position, body_atom)| {
+                        let Some(delta) = views.deltas.get(&body_atom.symbol) else {
+                            return;
+                        };
+                        let signed_facts = delta
+                            .insertions
+                            .iter()
+                            .map(|fact| (fact.clone(), 1))
+                            .chain(delta.deletions.iter().map(|fact| (fact.clone(), -1)));
+                        let signed_facts: Vec<_> = signed_facts.collect();
+
+                        signed_facts.into_iter().for_each(|(fact, sign)| {
+                            let Some(binding) = unify(&body_atom.terms, &fact, &Binding::new())
+                            else {
+                                return;
+                            };
+                            views.extend(rule, delta_position, 0, binding, &mut |binding| {
+                                if let Some(fact) = ground(&rule.head, binding) {
+                                    *changes.entry(fact).or_insert(0) += sign;
+                                }
+                            });
+                        });
+                    });
+            });
+
+            let counts = self.counts.entry(relation_symbol.clone()).or_default();
+            let mut delta = Delta::default();
+            changes
+                .into_iter()
+                .filter(|(_, change)| *change != 0)
+                .for_each(|(fact, change)| {
+                    let fact = Arc::new(fact);
+                    let before = counts.get(&fact).copied().unwrap_or(0);
+                    let after = before as isize + change;
+                    debug_assert!(after >= 0, "{:?} lost more derivations than it had", fact);
+
+                    if after > 0 {
+                        counts.insert(fact.clone(), after as usize);
+                    } else {
+                        counts.swap_remove(&fact);
+                    }
+                    if before == 0 && after > 0 {
+                        delta.insertions.insert(fact);
+                    } else if before > 0 && after <= 0 {
+                        delta.deletions.insert(fact);
+                    }
+                });
+            deltas.insert(relation_symbol, delta);
+
+            start = end;
+        }
+    }
+}
+
+// The relations both as they were before the change and as they are after it.
+struct Views<'a> {
+    old: &'a RelationStorage,
+    deltas: &'a HashMap<String, Delta>,
+    inserted_indices: &'a mut HashMap<(String, Vec<usize>), HashIndex>,
+}
+
+impl Views<'_> {
+    fn candidates(&mut self, body_atom: &Atom, binding: &Binding, new: bool) -> Vec<Fact> {
+        let columns = bound_columns(body_atom, |variable| binding.contains_key(variable));
+        let key: Vec<&TypedValue> = columns
+            .iter()
+            .filter_map(|column| match &body_atom.terms[*column] {
+                Term::Constant(constant) => Some(constant),
+                Term::Variable(variable) => binding.get(variable),
+                Term::Aggregate(_, _) | Term::Expression(_) => None,
+            })
+            .collect();
+
+        let old_facts: Vec<Fact> = match self.old.get_index(&body_atom.symbol, &columns) {
+            Some(index) if !columns.is_empty() => index.get(&key).cloned().collect(),
+            _ => self
+                .old
+                .inner
+                .get(&body_atom.symbol)
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        };
+        let Some(delta) = self.deltas.get(&body_atom.symbol).filter(|_| new) else {
+            return old_facts;
+        };
+
+        let mut facts: Vec<Fact> = old_facts
+            .into_iter()
+            .filter(|fact| !delta.deletions.contains(fact))
+            .collect();
+        if columns.is_empty() {
+            facts.extend(delta.insertions.iter().cloned());
+        } else {
+            let index = self
+                .inserted_indices
+                .entry((body_atom.symbol.clone(), columns.clone()))
+                .or_insert_with(|| {
+                    HashIndex::from_facts(columns, delta.insertions.iter().cloned())
+                });
+            facts.extend(index.get(&key).cloned());
+        }
+
+        facts
+    }
+    // Goes through the body atoms past the given position, other than the delta one, handing every
+    // complete binding to the sink.
+    fn extend(
+        &mut self,
+        rule: &Rule,
+        delta_position: usize,
+        position: usize,
+        binding: Binding,
+        sink: &mut dyn FnMut(&Binding),
+    ) {
+        if position == rule.body.len() {
+            return sink(&binding);
+        }
+        if position == delta_position {
+            return self.extend(rule, delta_position, position + 1, binding, sink);
+        }
+
+        let body_atom = &rule.body[position];
+        self.candidates(body_atom, &binding, position < delta_position)
+            .into_iter()
+            .for_each(|fact| {
+                if let Some(binding) = unify(&body_atom.terms, &fact, &binding) {
+                    self.extend(rule, delta_position, position + 1, binding, sink);
+                }
+            });
+    }
+}
+
+fn variables(atom: &Atom) -> impl Iterator<Item = &str> {
+    atom.terms.iter().filter_map(|term| match term {
+        Term::Variable(variable) => Some(variable.as_str()),
+        _ => None,
+    })
+}
+
+fn bound_columns(atom: &Atom, is_bound: impl Fn(&str) -> bool) -> Vec<usize> {
+    atom.terms
+        .iter()
+        .enumerate()
+        .filter(|(_, term)| match term {
+            Term::Constant(_) => true,
+            Term::Variable(variable) => is_bound(variable),
+            Term::Aggregate(_, _) | Term::Expression(_) => false,
+        })
+        .map(|(column, _)| column)
+        .collect()
+}
+
+fn unify(terms: &[Term], fact: &AnonymousGroundAtom, binding: &Binding) -> Option<Binding> {
+    let mut binding = binding.clone();
+    for (term, value) in terms.iter().zip(fact) {
+        match term {
+            Term::Constant(constant) if constant != value => return None,
+            Term::Variable(variable) => match binding.get(variable) {
+                Some(bound) if bound != value => return None,
+                Some(_) => {}
+                None => {
+                    binding.insert(variable.clone(), value.clone());
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Some(binding)
+}
+
+// Nothing is derived when an expression of the head has no value for the binding.
+fn ground(head: &Atom, binding: &Binding) -> Option<AnonymousGroundAtom> {
+    head.terms
+        .iter()
+        .map(|term| match term {
+            Term::Constant(constant) => Some(constant.clone()),
+            Term::Variable(variable) => binding.get(variable).cloned(),
+            Term::Expression(expression) => {
+                expression.evaluate(&|variable| binding.get(variable).cloned())
+            }
+            Term::Aggregate(_, _) => None,
+        })
+        .collect()
+}
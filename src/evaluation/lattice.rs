@@ -0,0 +1,367 @@
+use crate::engine::index_storage::IndexStorage;
+use crate::engine::storage::RelationStorage;
+use crate::evaluation::semi_naive::semi_naive_evaluation;
+use crate::evaluation::spj_processor::{RuleEvaluator, StackCache};
+use crate::program_transformations::analysis::ProgramAnalysis;
+use crate::program_transformations::dependency_graph::split_components;
+use ahash::{HashMap, HashMapExt};
+use datalog_syntax::*;
+use indexmap::{IndexMap, IndexSet};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+const IMPROVEMENT_PREFIX: &str = "improved_";
+
+type Aggregations = Vec<Option<Aggregation>>;
+
+// Min and max only ever get better as the facts that they range over grow, hence they can be taken
+// over relations that depend on them, as in shortest paths. The relations of such a recursion are
+// evaluated together from scratch, keeping the best fact of every group, and every round after the
+// first only evaluates the rules over what the previous one derived or improved. Recursions that
+// keep improving, such as shortest paths through a negative cycle, do not terminate.
+pub(crate) struct Lattice {
+    // The rules of every component of relations that aggregate over themselves.
+    program: Program,
+    // The same rules, once for every body atom of the component, which reads the facts that the
+    // previous round derived or improved instead.
+    delta_program: Program,
+    // The rules that do not aggregate, by the components that they derive among themselves.
+    plain_components: Vec<(Program, Program)>,
+    // The relations of the components, with the aggregations of those that aggregate.
+    relations: IndexMap<String, Option<Aggregations>>,
+}
+
+fn aggregations(rule: &Rule) -> Option<Aggregations> {
+    rule.is_aggregate().then(|| {
+        rule.head
+            .terms
+            .iter()
+            .map(|term| match term {
+                Term::Aggregate(aggregation, _) => Some(*aggregation),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+// The column that decides which fact of a group is best, and whether lower is better.
+fn extremum(aggregations: &Aggregations) -> Option<(usize, bool)> {
+    let mut extrema = aggregations
+        .iter()
+        .enumerate()
+        .filter_map(|(column, aggregation)| match aggregation {
+            Some(Aggregation::Min) => Some((column, true)),
+            Some(Aggregation::Max) => Some((column, false)),
+            _ => None,
+        });
+    let extremum = extrema.next();
+    let is_monotonic = aggregations
+        .iter()
+        .all(|aggregation| !matches!(aggregation, Some(Aggregation::Count | Aggregation::Sum)));
+
+    extremum.filter(|_| is_monotonic && extrema.next().is_none())
+}
+
+fn improvement_symbol(relation_symbol: &str) -> String {
+    format!("{}{}", IMPROVEMENT_PREFIX, relation_symbol)
+}
+
+impl Lattice {
+    pub(crate) fn new(program: &Program) -> Result<Self, String> {
+        let analysis = ProgramAnalysis::new(program);
+        let mut rules = vec![];
+        let mut relations = IndexMap::new();
+
+        for component in &analysis.components {
+            let component_rules: Vec<&Rule> = program
+                .inner
+                .iter()
+                .filter(|rule| component.contains(&rule.head.symbol))
+                .collect();
+            if !component_rules.iter().any(|rule| rule.is_aggregate())
+                || !analysis.is_recursive(&component[0])
+            {
+                continue;
+            }
+
+            for rule in component_rules {
+                let rule_aggregations = aggregations(rule);
+                if rule_aggregations
+                    .as_ref()
+                    .is_some_and(|rule_aggregations| extremum(rule_aggregations).is_none())
+                {
+                    return Err(format!(
+                        "{:?} aggregates over relations that depend on its head, which only a \
+                         single min or max can",
+                        rule
+                    ));
+                }
+                if *relations
+                    .entry(rule.head.symbol.clone())
+                    .or_insert(rule_aggregations.clone())
+                    != rule_aggregations
+                {
+                    return Err(format!(
+                        "{:?} aggregates unlike the other rules of {}",
+                        rule, rule.head.symbol
+                    ));
+                }
+                if rule
+                    .body
+                    .iter()
+                    .any(|body_atom| !body_atom.sign && component.contains(&body_atom.symbol))
+                {
+                    return Err("negation must be stratified".to_string());
+                }
+
+                rules.push(rule.clone());
+            }
+        }
+
+        let delta_rules = rules
+            .iter()
+            .flat_map(|rule| {
+                rule.body
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, body_atom)| relations.contains_key(&body_atom.symbol))
+                    .map(move |(position, body_atom)| {
+                        let mut delta_rule = rule.clone();
+                        delta_rule.body[position].symbol = improvement_symbol(&body_atom.symbol);
+
+                        delta_rule
+                    })
+            })
+            .collect::<Vec<_>>();
+        let plain_rules: Vec<_> = rules
+            .iter()
+            .filter(|rule| !rule.is_aggregate())
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            program: Program::from(rules),
+            delta_program: Program::from(delta_rules),
+            plain_components: split_components(&Program::from(plain_rules)),
+            relations,
+        })
+    }
+    pub(crate) fn derives(&self, relation_symbol: &str) -> bool {
+        self.relations.contains_key(relation_symbol)
+    }
+    pub(crate) fn programs(&self) -> impl Iterator<Item = &Program> {
+        [&self.program, &self.delta_program].into_iter().chain(
+            self.plain_components
+                .iter()
+                .flat_map(|(nonrecursive_program, recursive_program)| {
+                    [nonrecursive_program, recursive_program]
+                }),
+        )
+    }
+    // The facts that the relations of the components have, given those of the relations that they
+    // read from the storage.
+    pub(crate) fn evaluate(
+        &self,
+        storage: &RelationStorage,
+        stacks: &StackCache,
+        memory_limit: Option<usize>,
+    ) -> Result<IndexMap<String, IndexSet<AnonymousGroundAtom>>, String> {
+        let mut scratch = RelationStorage::default();
+        for body_atom in self.program.inner.iter().flat_map(|rule| &rule.body) {
+            if !self.derives(&body_atom.symbol) && !scratch.inner.contains_key(&body_atom.symbol) {
+                scratch.insert_all(&body_atom.symbol, storage.facts(&body_atom.symbol).cloned());
+            }
+        }
+        for relation_symbol in self.relations.keys() {
+            scratch
+                .inner
+                .insert(relation_symbol.clone(), Default::default());
+        }
+
+        let mut best: HashMap<&str, HashMap<Vec<TypedValue>, AnonymousGroundAtom>> = HashMap::new();
+        let mut program = &self.program;
+        loop {
+            let mut derived: IndexMap<&str, Vec<AnonymousGroundAtom>> = IndexMap::new();
+            for rule in &program.inner {
+                let facts = RuleEvaluator::new(&scratch, rule, stacks.get(rule)?)
+                    .step(&mut IndexStorage::with_limit(memory_limit))?;
+
+                derived
+                    .entry(rule.head.symbol.as_str())
+                    .or_default()
+                    .extend(facts);
+            }
+
+            let mut changed = false;
+            for (relation_symbol, aggregations) in &self.relations {
+                let facts = derived
+                    .swap_remove(relation_symbol.as_str())
+                    .unwrap_or_default();
+                let (stale, fresh) = match aggregations {
+                    None => (
+                        vec![],
+                        facts
+                            .into_iter()
+                            .filter(|fact| !scratch.contains(relation_symbol, fact))
+                            .collect::<IndexSet<_>>(),
+                    ),
+                    Some(aggregations) => {
+                        let extremum = extremum(aggregations).ok_or_else(|| {
+                            format!("{} aggregates over no min or max", relation_symbol)
+                        })?;
+
+                        improve(
+                            best.entry(relation_symbol).or_default(),
+                            aggregations,
+                            extremum,
+                            facts,
+                        )
+                    }
+                };
+
+                changed |= !fresh.is_empty();
+                stale.iter().for_each(|fact| {
+                    scratch.remove(relation_symbol, fact);
+                });
+                scratch.inner.insert(
+                    improvement_symbol(relation_symbol),
+                    fresh.iter().cloned().map(Arc::new).collect(),
+                );
+                scratch.insert_all(relation_symbol, fresh.into_iter().map(Arc::new));
+            }
+
+            if !changed {
+                break;
+            }
+            program = &self.delta_program;
+        }
+
+        // The relations that do not aggregate hold whatever was derived from groups before they
+        // were at their best, so they are derived again from the best ones.
+        for (relation_symbol, aggregations) in &self.relations {
+            if aggregations.is_none() {
+                scratch
+                    .inner
+                    .insert(relation_symbol.clone(), Default::default());
+            }
+        }
+        for (nonrecursive_program, recursive_program) in &self.plain_components {
+            semi_naive_evaluation(
+                &mut scratch,
+                stacks,
+                nonrecursive_program,
+                recursive_program,
+                memory_limit,
+                &mut (),
+            )?;
+        }
+
+        Ok(self
+            .relations
+            .keys()
+            .map(|relation_symbol| {
+                let facts = scratch.facts(relation_symbol).map(|fact| (**fact).clone());
+
+                (relation_symbol.clone(), facts.collect())
+            })
+            .collect())
+    }
+}
+
+// Keeps the best fact of every group, returning those that were replaced and those that replaced
+// them. Of facts that are as good, the least one is kept, so that the outcome does not depend on
+// the order that they were derived in.
+fn improve(
+    best: &mut HashMap<Vec<TypedValue>, AnonymousGroundAtom>,
+    aggregations: &Aggregations,
+    (column, is_min): (usize, bool),
+    facts: Vec<AnonymousGroundAtom>,
+) -> (Vec<AnonymousGroundAtom>, IndexSet<AnonymousGroundAtom>) {
+    let mut improved = IndexMap::new();
+    for fact in facts {
+        let group: Vec<_> = fact
+            .iter()
+            .zip(aggregations)
+            .filter(|(_, aggregation)| aggregation.is_none())
+            .map(|(value, _)| value.clone())
+            .collect();
+        let is_better = |current: &AnonymousGroundAtom| {
+            let ordering = fact[column].cmp(&current[column]);
+            let ordering = if is_min { ordering } else { ordering.reverse() };
+
+            ordering.then_with(|| fact.cmp(current)) == Ordering::Less
+        };
+
+        if best.get(&group).is_none_or(is_better) {
+            let previous = best.insert(group.clone(), fact.clone());
+            improved.entry(group).or_insert(previous);
+        }
+    }
+
+    let mut stale = vec![];
+    let mut fresh = IndexSet::new();
+    for (group, previous) in improved {
+        stale.extend(previous);
+        fresh.insert(best[&group].clone());
+    }
+
+    (stale, fresh)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::storage::RelationStorage;
+    use crate::evaluation::lattice::Lattice;
+    use crate::evaluation::spj_processor::StackCache;
+    use datalog_rule_macro::{program, rule};
+    use datalog_syntax::*;
+    use indexmap::IndexSet;
+
+    #[test]
+    fn test_lattice() {
+        let program = program! {
+            shortest(?x, min(?d)) <- [source(?x, ?d)],
+            shortest(?y, min(?d)) <- [reachable(?y, ?d)],
+            reachable(?y, ?e) <- [shortest(?x, ?d), e(?x, ?y, ?w)], ?e = ?d + ?w,
+            hops(?x, count(?y)) <- [e(?x, ?y, _)],
+        };
+        let lattice = Lattice::new(&program).unwrap();
+        assert!(lattice.derives("shortest") && lattice.derives("reachable"));
+        assert!(!lattice.derives("hops"));
+
+        let mut storage = RelationStorage::default();
+        storage.insert("source", vec!["a".into(), 0usize.into()]);
+        for (from, to, weight) in [
+            ("a", "b", 5usize),
+            ("a", "c", 1),
+            ("c", "b", 1),
+            ("b", "a", 1),
+        ] {
+            storage.insert("e", vec![from.into(), to.into(), weight.into()]);
+        }
+        let stacks = StackCache::from_programs(lattice.programs());
+
+        let relations = lattice.evaluate(&storage, &stacks, None).unwrap();
+
+        let expected: IndexSet<AnonymousGroundAtom> = vec![
+            vec!["a".into(), 0usize.into()],
+            vec!["c".into(), 1usize.into()],
+            vec!["b".into(), 2usize.into()],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, relations["shortest"]);
+        // What was reachable from b before its shortest path was found is not in the end.
+        assert!(!relations["reachable"].contains(&vec!["a".into(), 6usize.into()]));
+        assert!(relations["reachable"].contains(&vec!["a".into(), 3usize.into()]));
+
+        let counting = Program::from(vec![
+            rule! { tc(?x, ?y) <- [e(?x, ?y)] },
+            rule! { tc(?x, count(?y)) <- [tc(?x, ?y)] },
+        ]);
+        assert!(Lattice::new(&counting)
+            .err()
+            .unwrap()
+            .contains("which only a single min or max can"));
+    }
+}
@@ -1,14 +1,22 @@
-use datalog_syntax::{AnonymousGroundAtom, Matcher, Query};
+use datalog_syntax::{AnonymousGroundAtom, Matcher, Query, TypedValue};
 
 pub fn pattern_match(query: &Query, fact: &AnonymousGroundAtom) -> bool {
-    return fact.iter().enumerate().all(|(index, term)| {
+    fact.iter().enumerate().all(|(index, term)| {
         if let Some(matcher) = query.matchers.get(index) {
             return match (matcher, term) {
                 (Matcher::Any, _) => true,
                 (Matcher::Constant(target), term) => target == term,
+                (Matcher::Not(target), term) => target != term,
+                (Matcher::Ge(bound), term) => is_comparable(bound, term) && term >= bound,
+                (Matcher::Le(bound), term) => is_comparable(bound, term) && term <= bound,
             };
         }
 
         true
-    });
+    })
+}
+
+// Values of different types are ordered by their type first, which ranges should not go by.
+fn is_comparable(bound: &TypedValue, value: &TypedValue) -> bool {
+    std::mem::discriminant(bound) == std::mem::discriminant(value)
 }
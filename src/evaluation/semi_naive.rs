@@ -1,35 +1,124 @@
-use crate::engine::{index_storage::IndexStorage, storage::RelationStorage};
+use crate::engine::index_storage::IndexStorage;
+use crate::engine::observer::EvaluationObserver;
+use crate::engine::storage::{FactStorage, RelationStorage};
+use crate::evaluation::spj_processor::StackCache;
+use ahash::{HashMap, HashSet};
 use datalog_syntax::Program;
 
 pub fn semi_naive_evaluation(
     relation_storage: &mut RelationStorage,
+    stacks: &StackCache,
     nonrecursive_program: &Program,
     recursive_program: &Program,
-) {
-    let mut index_storage = IndexStorage::default();
-    relation_storage
-        .materialize_nonrecursive_delta_program(nonrecursive_program, &mut index_storage);
+    memory_limit: Option<usize>,
+    observer: &mut dyn EvaluationObserver,
+) -> Result<(), String> {
+    semi_naive_evaluation_with_delta(
+        relation_storage,
+        stacks,
+        nonrecursive_program,
+        recursive_program,
+        &HashMap::default(),
+        memory_limit,
+        observer,
+    )
+}
+
+// Facts that the relations of the program were given since they were last evaluated, such as
+// rederived ones, are taken to be new rather than known, so that derivations that go through
+// several of them are found as well. Evaluation fails once its intermediate results take more
+// bytes than the memory limit.
+pub fn semi_naive_evaluation_with_delta(
+    relation_storage: &mut RelationStorage,
+    stacks: &StackCache,
+    nonrecursive_program: &Program,
+    recursive_program: &Program,
+    delta: &HashMap<String, FactStorage>,
+    memory_limit: Option<usize>,
+    observer: &mut dyn EvaluationObserver,
+) -> Result<(), String> {
+    let mut index_storage = IndexStorage::with_limit(memory_limit);
+    let result = reach_fixpoint(
+        relation_storage,
+        stacks,
+        nonrecursive_program,
+        recursive_program,
+        delta,
+        &mut index_storage,
+        observer,
+    );
+    // Reported even when the limit was hit, to tell by how much.
+    observer.intermediate_bytes(index_storage.peak_bytes);
+
+    result
+}
+
+fn reach_fixpoint(
+    relation_storage: &mut RelationStorage,
+    stacks: &StackCache,
+    nonrecursive_program: &Program,
+    recursive_program: &Program,
+    delta: &HashMap<String, FactStorage>,
+    index_storage: &mut IndexStorage,
+    observer: &mut dyn EvaluationObserver,
+) -> Result<(), String> {
+    let previous_non_delta_fact_count = relation_storage.len();
+    relation_storage.materialize_nonrecursive_delta_program(
+        nonrecursive_program,
+        stacks,
+        index_storage,
+        observer,
+    )?;
+    // Only the relations that the program derives, as those of lower strata may have gained
+    // other facts since, which are read in full anyway.
+    let heads: HashSet<&String> = nonrecursive_program
+        .inner
+        .iter()
+        .chain(&recursive_program.inner)
+        .map(|rule| &rule.head.symbol)
+        .collect();
+    for relation_symbol in heads {
+        if let Some(facts) = delta.get(relation_symbol) {
+            index_storage.renew(
+                relation_symbol,
+                relation_storage.relation(relation_symbol)?.iter(),
+                &facts.iter().map(|fact| fact.as_ref()).collect(),
+            )?;
+        }
+    }
+    observer.iteration_finished(0, relation_storage.len() - previous_non_delta_fact_count);
 
+    let mut iteration = 0;
     loop {
+        iteration += 1;
         let previous_non_delta_fact_count = relation_storage.len();
 
-        relation_storage.materialize_recursive_delta_program(recursive_program, &mut index_storage);
+        relation_storage.materialize_recursive_delta_program(
+            recursive_program,
+            stacks,
+            index_storage,
+            observer,
+        )?;
         let current_non_delta_fact_count = relation_storage.len();
+        index_storage.compact();
 
         let new_fact_count = current_non_delta_fact_count - previous_non_delta_fact_count;
+        observer.iteration_finished(iteration, new_fact_count);
 
         if new_fact_count == 0 {
-            return;
+            return Ok(());
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::engine::observer::PollStatistics;
     use crate::engine::storage::RelationStorage;
     use crate::evaluation::semi_naive::semi_naive_evaluation;
+    use crate::evaluation::spj_processor::StackCache;
     use crate::helpers::helpers::split_program;
-    use datalog_rule_macro::program;
+    use datalog_rule_macro::{program, rule};
     use datalog_syntax::*;
     use std::collections::HashSet;
     use std::sync::Arc;
@@ -64,11 +153,60 @@ mod test {
 
         let expected: HashSet<AnonymousGroundAtom> =
             vec![vec!["a".into(), "c".into()]].into_iter().collect();
+        let stacks =
+            StackCache::from_programs([&nonrecursive_delta_program, &recursive_delta_program]);
         semi_naive_evaluation(
             &mut storage,
+            &stacks,
             &nonrecursive_delta_program,
             &recursive_delta_program,
+            None,
+            &mut (),
+        )
+        .unwrap();
+        let actual: HashSet<_> = storage
+            .get_relation("hop")
+            .into_iter()
+            .map(|x| (**x).clone())
+            .collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_two_hop() {
+        let mut storage: RelationStorage = Default::default();
+        storage.inner.insert("e".to_string(), Default::default());
+        storage.inner.insert("hop".to_string(), Default::default());
+        insert_into(
+            &mut storage,
+            "e",
+            vec![
+                vec!["a".into(), "b".into()],
+                vec!["b".into(), "c".into()],
+                vec!["c".into(), "d".into()],
+                vec!["d".into(), "e".into()],
+            ],
         );
+
+        let two_hop = program! { hop(?x, ?w) <- [e(?x, ?y), e(?y, ?z), e(?z, ?w)] };
+        let (nonrecursive_delta_program, recursive_delta_program) = split_program(two_hop);
+
+        let expected: HashSet<AnonymousGroundAtom> =
+            vec![vec!["a".into(), "d".into()], vec!["b".into(), "e".into()]]
+                .into_iter()
+                .collect();
+        let stacks =
+            StackCache::from_programs([&nonrecursive_delta_program, &recursive_delta_program]);
+        semi_naive_evaluation(
+            &mut storage,
+            &stacks,
+            &nonrecursive_delta_program,
+            &recursive_delta_program,
+            None,
+            &mut (),
+        )
+        .unwrap();
         let actual: HashSet<_> = storage
             .get_relation("hop")
             .into_iter()
@@ -114,11 +252,17 @@ mod test {
         .into_iter()
         .collect();
 
+        let stacks =
+            StackCache::from_programs([&nonrecursive_delta_program, &recursive_delta_program]);
         semi_naive_evaluation(
             &mut storage,
+            &stacks,
             &nonrecursive_delta_program,
             &recursive_delta_program,
-        );
+            None,
+            &mut (),
+        )
+        .unwrap();
 
         let actual: HashSet<_> = storage
             .get_relation("tc")
@@ -164,11 +308,17 @@ mod test {
         ]
         .into_iter()
         .collect();
+        let stacks =
+            StackCache::from_programs([&nonrecursive_delta_program, &recursive_delta_program]);
         semi_naive_evaluation(
             &mut storage,
+            &stacks,
             &nonrecursive_delta_program,
             &recursive_delta_program,
-        );
+            None,
+            &mut (),
+        )
+        .unwrap();
 
         let actual: HashSet<_> = storage
             .get_relation("tc")
@@ -178,4 +328,52 @@ mod test {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_converged_relations_are_skipped() {
+        let mut storage: RelationStorage = Default::default();
+        for relation_symbol in ["e", "f", "long", "short"] {
+            storage
+                .inner
+                .insert(relation_symbol.to_string(), Default::default());
+        }
+        insert_into(
+            &mut storage,
+            "e",
+            (0..10usize)
+                .map(|node| vec![node.into(), (node + 1).into()])
+                .collect(),
+        );
+        insert_into(&mut storage, "f", vec![vec![0usize.into(), 1usize.into()]]);
+
+        let program = program! {
+            long(?x, ?y) <- [e(?x, ?y)],
+            long(?x, ?z) <- [e(?x, ?y), long(?y, ?z)],
+            short(?x, ?y) <- [f(?x, ?y)],
+            short(?x, ?z) <- [f(?x, ?y), short(?y, ?z)],
+        };
+        let (nonrecursive_delta_program, recursive_delta_program) = split_program(program);
+        let stacks =
+            StackCache::from_programs([&nonrecursive_delta_program, &recursive_delta_program]);
+        let mut statistics = PollStatistics::default();
+        semi_naive_evaluation(
+            &mut storage,
+            &stacks,
+            &nonrecursive_delta_program,
+            &recursive_delta_program,
+            None,
+            &mut statistics,
+        )
+        .unwrap();
+
+        assert_eq!(55, storage.get_relation("long").len());
+        assert_eq!(1, storage.get_relation("short").len());
+        // Once short stops growing, its rule is no longer evaluated, while long keeps going.
+        let evaluations = |rule: Rule| statistics.rules[&format!("{:?}", rule)].0;
+        assert_eq!(
+            1,
+            evaluations(rule! { short(?x, ?z) <- [f(?x, ?y), short(?y, ?z)] })
+        );
+        assert!(evaluations(rule! { long(?x, ?z) <- [e(?x, ?y), long(?y, ?z)] }) >= 9);
+    }
 }
@@ -1,30 +1,69 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use crate::engine::hash_index::HashIndex;
 use crate::engine::index_storage::{EphemeralValue, IndexStorage};
 use crate::engine::storage::RelationStorage;
-use crate::evaluation::spj_processor::Instruction::{Antijoin, Join, Project};
-use datalog_syntax::{AnonymousGroundAtom, Rule, Term, TypedValue, Variable};
+use crate::evaluation::spj_processor::Instruction::{Aggregate, Antijoin, Join, Project};
+use datalog_syntax::{
+    Aggregation, AnonymousGroundAtom, Atom, Expression, OrderedFloat, Program, Rule, Term,
+    TypedValue, Variable,
+};
 use indexmap::{IndexMap, IndexSet};
 // This implements a minimal SPJ (Select, Project, Join) processor
 
 pub type Column = usize;
 pub type Value = TypedValue;
 pub type Symbol = String;
-pub type Sign = bool;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ProjectionInput {
     Column(Column),
     Value(Value),
+    // A head variable that the body does not bind becomes a constant named after the rule, the
+    // variable and the values of the columns, i.e. a Skolem term over the head variables that it
+    // does bind.
+    Skolem(String, Vec<Column>),
+    // Computed from the values at the columns that its variables are bound to.
+    Expr(Expression, Vec<(Variable, Column)>),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Instruction {
     Move(Symbol),
-    Select(Symbol, Sign, Column, Value),
+    // Keeps the facts holding every value at its column. For a negated atom these are the facts to
+    // be ruled out, which the antijoin that follows takes care of.
+    Select(Symbol, Vec<(Column, Value)>),
     Project(Symbol, Vec<ProjectionInput>),
     Join(Symbol, Symbol, Vec<(usize, usize)>),
+    // Joins all of the positive atoms at once, binding one variable after the other to the values
+    // that every atom with it allows. Its results are laid out as those of a chain of joins.
+    GenericJoin(Vec<(Symbol, Vec<Term>)>),
     Antijoin(Symbol, Symbol, Vec<(usize, usize)>),
+    // Groups the projection by the columns without an aggregation. It is only correct over the
+    // whole body, never over a delta.
+    Aggregate(Symbol, Vec<Option<Aggregation>>),
+}
+
+impl Instruction {
+    // The name under which the result is kept while the rule is evaluated, if it is kept at all.
+    pub(crate) fn output_name(&self) -> Option<String> {
+        match self {
+            Instruction::Move(symbol) => Some(symbol.clone()),
+            Instruction::Select(symbol, conditions) => {
+                Some(stringify_selection(symbol, conditions))
+            }
+            Instruction::Join(left_symbol, right_symbol, join_keys) => {
+                Some(stringify_join(left_symbol, right_symbol, join_keys, false))
+            }
+            Instruction::Antijoin(left_symbol, right_symbol, join_keys) => {
+                Some(stringify_join(left_symbol, right_symbol, join_keys, true))
+            }
+            Instruction::GenericJoin(inputs) => Some(stringify_generic_join(inputs)),
+            Instruction::Project(_, _) | Instruction::Aggregate(_, _) => None,
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -32,92 +71,96 @@ pub struct Stack {
     pub(crate) inner: Vec<Instruction>,
 }
 
-fn stringify_selection(selection: &Instruction) -> String {
-    match selection {
-        Instruction::Select(symbol, sign, column, value) => {
-            if *sign {
-                format!("{}_{}={:?}", symbol, column, value)
-            } else {
-                format!("!{}_{}={:?}", symbol, column, value)
-            }
-        }
-        _ => unreachable!(),
-    }
+fn stringify_selection(symbol: &str, conditions: &[(Column, Value)]) -> String {
+    let conditions_format = conditions
+        .iter()
+        .map(|(column, value)| format!("{}={:?}", column, value))
+        .collect::<Vec<_>>()
+        .join("_");
+
+    format!("{}_{}", symbol, conditions_format)
 }
 
-fn stringify_join(join: &Instruction) -> String {
-    let equality = match join {
-        Instruction::Join(_, _, _) => "=",
-        Instruction::Antijoin(_, _, _) => "!=",
-        _ => unreachable!(),
-    };
+fn stringify_join(
+    left_symbol: &str,
+    right_symbol: &str,
+    join_keys: &[(usize, usize)],
+    anti: bool,
+) -> String {
+    let equality = if anti { "!=" } else { "=" };
+    let join_keys_format = join_keys
+        .iter()
+        .map(|(left_column, right_column)| format!("{}{}{}", left_column, equality, right_column))
+        .collect::<Vec<_>>()
+        .join("_");
+
+    format!("{}_{}_{}", left_symbol, right_symbol, join_keys_format)
+}
 
-    return match join {
-        Instruction::Join(left_symbol, right_symbol, join_keys)
-        | Instruction::Antijoin(left_symbol, right_symbol, join_keys) => {
-            let join_keys_format = join_keys
+fn stringify_generic_join(inputs: &[(Symbol, Vec<Term>)]) -> String {
+    inputs
+        .iter()
+        .map(|(symbol, terms)| {
+            let terms_format = terms
                 .iter()
-                .map(|(left_column, right_column)| {
-                    format!("{}{}{}", left_column, equality, right_column)
+                .map(|term| match term {
+                    Term::Variable(name) => format!("?{}", name),
+                    Term::Constant(value) => format!("{:?}", value),
+                    Term::Aggregate(_, _) | Term::Expression(_) => format!("{:?}", term),
                 })
                 .collect::<Vec<_>>()
-                .join("_");
+                .join(",");
 
-            format!("{}_{}_{}", left_symbol, right_symbol, join_keys_format)
-        }
-        _ => unreachable!(),
-    };
+            format!("{}({})", symbol, terms_format)
+        })
+        .collect::<Vec<_>>()
+        .join("_")
 }
 
-fn get_selection(symbol: &str, sign: &bool, terms: &Vec<Term>) -> Option<Instruction> {
-    let selection: Vec<Instruction> = terms
+// Every constant of the atom narrows it down before it takes part in any join. The selection is
+// returned along with the name that its result is kept under.
+fn get_selection(symbol: &str, terms: &[Term]) -> Option<(Symbol, Instruction)> {
+    let conditions: Vec<(Column, Value)> = terms
         .iter()
         .enumerate()
-        .filter(|(_, term)| match term {
-            Term::Variable(_) => false,
-            Term::Constant(_) => true,
-        })
-        .map(|(idx, constant)| {
-            let constant_value = match constant {
-                Term::Constant(inner) => inner,
-                _ => unreachable!(),
-            };
-
-            return Instruction::Select(
-                symbol.to_string(),
-                sign.clone(),
-                idx,
-                constant_value.clone(),
-            );
+        .filter_map(|(idx, term)| match term {
+            Term::Constant(inner) => Some((idx, inner.clone())),
+            Term::Variable(_) | Term::Aggregate(_, _) | Term::Expression(_) => None,
         })
         .collect();
 
-    return selection.get(0).cloned();
+    if conditions.is_empty() {
+        return None;
+    }
+
+    Some((
+        stringify_selection(symbol, &conditions),
+        Instruction::Select(symbol.to_string(), conditions),
+    ))
 }
 
-fn get_variables(terms: &Vec<Term>) -> IndexMap<Variable, usize> {
+fn satisfies(fact: &AnonymousGroundAtom, conditions: &[(Column, Value)]) -> bool {
+    conditions
+        .iter()
+        .all(|(column, value)| fact[*column] == *value)
+}
+
+fn get_variables(terms: &[Term]) -> IndexMap<Variable, usize> {
     terms
-        .into_iter()
+        .iter()
         .cloned()
         .enumerate()
-        .filter(|(_, term)| match term {
-            Term::Variable(_) => true,
-            Term::Constant(_) => false,
-        })
-        .map(|(idx, term)| match term {
-            Term::Variable(name) => (name, idx),
-            Term::Constant(_) => unreachable!(),
+        .filter_map(|(idx, term)| match term {
+            // Wildcards never join, not even with each other.
+            Term::Variable(name) if name != "_" => Some((name, idx)),
+            Term::Variable(_) | Term::Constant(_) | Term::Aggregate(_, _) | Term::Expression(_) => {
+                None
+            }
         })
         .collect()
 }
 
-fn get_join(
-    left_terms: &Vec<Term>,
-    right_terms: &Vec<Term>,
-    left_symbol: &str,
-    right_symbol: &str,
-    anti: bool,
-) -> Option<Instruction> {
+fn get_join_keys(left_terms: &[Term], right_terms: &[Term]) -> Vec<(usize, usize)> {
     let left_variable_map = get_variables(left_terms);
     let right_variable_map = get_variables(right_terms);
 
@@ -129,23 +172,40 @@ fn get_join(
         }
     }
 
-    if !join_keys.is_empty() {
-        return if anti {
-            Some(Antijoin(
-                left_symbol.to_string(),
-                right_symbol.to_string(),
-                join_keys,
-            ))
-        } else {
-            Some(Join(
-                left_symbol.to_string(),
-                right_symbol.to_string(),
-                join_keys,
-            ))
-        };
+    join_keys
+}
+
+// Without shared variables, a negated atom rules out everything as soon as it holds at all.
+fn get_antijoin(
+    left_terms: &[Term],
+    right_terms: &[Term],
+    left_symbol: &str,
+    right_symbol: &str,
+) -> (Symbol, Instruction) {
+    let join_keys = get_join_keys(left_terms, right_terms);
+
+    (
+        stringify_join(left_symbol, right_symbol, &join_keys, true),
+        Antijoin(left_symbol.to_string(), right_symbol.to_string(), join_keys),
+    )
+}
+
+fn get_join(
+    left_terms: &[Term],
+    right_terms: &[Term],
+    left_symbol: &str,
+    right_symbol: &str,
+) -> Option<(Symbol, Instruction)> {
+    let join_keys = get_join_keys(left_terms, right_terms);
+
+    if join_keys.is_empty() {
+        return None;
     }
 
-    return None;
+    Some((
+        stringify_join(left_symbol, right_symbol, &join_keys, false),
+        Join(left_symbol.to_string(), right_symbol.to_string(), join_keys),
+    ))
 }
 
 fn get_projection(rule: &Rule) -> Instruction {
@@ -153,13 +213,10 @@ fn get_projection(rule: &Rule) -> Instruction {
         .head
         .terms
         .iter()
-        .filter(|term| match term {
-            Term::Variable(_) => true,
-            Term::Constant(_) => false,
-        })
-        .map(|term| match term {
-            Term::Variable(name) => name.clone(),
-            Term::Constant(_) => unreachable!(),
+        .flat_map(|term| match term {
+            Term::Variable(name) | Term::Aggregate(_, name) => vec![name.clone()],
+            Term::Expression(expression) => expression.variables().into_iter().cloned().collect(),
+            Term::Constant(_) => vec![],
         })
         .collect();
 
@@ -172,7 +229,8 @@ fn get_projection(rule: &Rule) -> Instruction {
     rule.body.iter().for_each(|body_atom| {
         body_atom.terms.iter().for_each(|term| {
             match term {
-                Term::Variable(name) => {
+                // Negated atoms come last and bind nothing.
+                Term::Variable(name) if body_atom.sign => {
                     if !seen.contains(name) {
                         seen.insert(name.clone());
 
@@ -182,90 +240,272 @@ fn get_projection(rule: &Rule) -> Instruction {
                         }
                     }
                 }
-                Term::Constant(_) => {}
+                Term::Variable(_)
+                | Term::Constant(_)
+                | Term::Aggregate(_, _)
+                | Term::Expression(_) => {}
             }
 
             position_assuming_joins_are_natural += 1;
         });
     });
 
+    let frontier: Vec<Column> = variable_location_assuming_joins_are_natural
+        .values()
+        .copied()
+        .collect();
     let projection = rule
         .head
         .terms
         .iter()
         .map(|term| match term {
-            Term::Variable(name) => ProjectionInput::Column(
-                *variable_location_assuming_joins_are_natural
-                    .get(name)
-                    .unwrap(),
-            ),
+            Term::Variable(name) | Term::Aggregate(_, name) => {
+                match variable_location_assuming_joins_are_natural.get(name) {
+                    Some(column) => ProjectionInput::Column(*column),
+                    None => ProjectionInput::Skolem(
+                        format!("{}_{}_{}", rule.head.symbol, rule.id, name),
+                        frontier.clone(),
+                    ),
+                }
+            }
             Term::Constant(value) => ProjectionInput::Value(value.clone()),
+            Term::Expression(expression) => ProjectionInput::Expr(
+                expression.clone(),
+                expression
+                    .variables()
+                    .into_iter()
+                    .filter_map(|name| {
+                        variable_location_assuming_joins_are_natural
+                            .get(name)
+                            .map(|column| (name.clone(), *column))
+                    })
+                    .collect(),
+            ),
         })
         .collect();
 
     Project(rule.head.symbol.clone(), projection)
 }
 
+fn get_aggregation(rule: &Rule) -> Instruction {
+    let aggregations = rule
+        .head
+        .terms
+        .iter()
+        .map(|term| match term {
+            Term::Aggregate(aggregation, _) => Some(*aggregation),
+            Term::Variable(_) | Term::Constant(_) | Term::Expression(_) => None,
+        })
+        .collect();
+
+    Aggregate(rule.head.symbol.clone(), aggregations)
+}
+
+// Joins are only ever taken between the atoms seen so far and the next one, and without shared
+// variables there is nothing to join on. Hence every positive atom is moved after the first one
+// that it shares a variable with, keeping the written order otherwise.
+fn order_by_connectivity(body: &mut [Atom]) {
+    let positive_count = body.iter().filter(|atom| atom.sign).count();
+    let mut bound: HashSet<Variable> = HashSet::new();
+
+    for position in 0..positive_count {
+        let connected = (position..positive_count)
+            .find(|&candidate| {
+                get_variables(&body[candidate].terms)
+                    .keys()
+                    .any(|variable| bound.contains(variable))
+            })
+            .unwrap_or(position);
+        body[position..=connected].rotate_right(1);
+
+        bound.extend(get_variables(&body[position].terms).into_keys());
+    }
+}
+
+// Whether the positive atoms join in a cycle, as told by GYO reduction: variables that only one atom
+// has are dropped, and so are atoms whose variables another atom has as well, which leaves nothing
+// of an acyclic body.
+pub fn is_cyclic(body: &[Atom]) -> bool {
+    let mut atoms: Vec<HashSet<Variable>> = body
+        .iter()
+        .filter(|atom| atom.sign)
+        .map(|atom| get_variables(&atom.terms).into_keys().collect())
+        .collect();
+
+    loop {
+        let mut occurrences: HashMap<Variable, usize> = HashMap::new();
+        atoms.iter().flatten().for_each(|variable| {
+            *occurrences.entry(variable.clone()).or_default() += 1;
+        });
+        atoms
+            .iter_mut()
+            .for_each(|variables| variables.retain(|variable| occurrences[variable] > 1));
+
+        let contained = (0..atoms.len()).find(|&idx| {
+            atoms[idx].is_empty()
+                || (0..atoms.len()).any(|other| {
+                    other != idx
+                        && atoms[idx].is_subset(&atoms[other])
+                        && (atoms[idx] != atoms[other] || other < idx)
+                })
+        });
+        match contained {
+            Some(idx) => {
+                atoms.swap_remove(idx);
+            }
+            None => return !atoms.is_empty(),
+        }
+    }
+}
+
+impl Stack {
+    // Rules whose positive atoms join in a cycle, such as triangles, are evaluated by a generic
+    // join when asked to, since a chain of binary joins may go through far more intermediate
+    // results than the rule has answers. Rules pinned to their join order never are.
+    pub fn new(rule: Rule, generic_joins: bool) -> Self {
+        if !generic_joins || rule.hints.no_reorder || !is_cyclic(&rule.body) {
+            return Stack::from(rule);
+        }
+
+        let mut operations = vec![];
+        let mut rule = rule;
+        rule.body.sort_by_key(|atom| !atom.sign);
+
+        let mut push_input = |atom: &Atom| match get_selection(&atom.symbol, &atom.terms) {
+            Some((symbol, selection)) => {
+                operations.push(selection);
+
+                symbol
+            }
+            None => {
+                operations.push(Instruction::Move(atom.symbol.clone()));
+
+                atom.symbol.clone()
+            }
+        };
+        let (positive_atoms, negated_atoms): (Vec<_>, Vec<_>) =
+            rule.body.iter().partition(|atom| atom.sign);
+        let inputs: Vec<_> = positive_atoms
+            .iter()
+            .map(|atom| (push_input(atom), atom.terms.clone()))
+            .collect();
+        let negated_inputs: Vec<_> = negated_atoms
+            .iter()
+            .map(|atom| (push_input(atom), atom.terms.clone()))
+            .collect();
+
+        let mut last_join_result_name = stringify_generic_join(&inputs);
+        let generic_join = Instruction::GenericJoin(inputs);
+        let last_join_terms: Vec<Term> = positive_atoms
+            .iter()
+            .flat_map(|atom| atom.terms.clone())
+            .collect();
+        operations.push(generic_join);
+
+        negated_inputs
+            .iter()
+            .for_each(|(right_symbol, right_terms)| {
+                let (antijoin_name, antijoin) = get_antijoin(
+                    &last_join_terms,
+                    right_terms,
+                    &last_join_result_name,
+                    right_symbol,
+                );
+                last_join_result_name = antijoin_name;
+                operations.push(antijoin);
+            });
+
+        operations.push(get_projection(&rule));
+        if rule.is_aggregate() {
+            operations.push(get_aggregation(&rule));
+        }
+
+        Stack { inner: operations }
+    }
+}
+
 impl From<Rule> for Stack {
     // convert a logical Rule into a sequence of operations represented by an Instruction enum
     fn from(rule: Rule) -> Self {
         let mut operations = vec![];
 
+        // Negated atoms only filter what the positive ones bind, so they go last.
+        let mut rule = rule;
+        // Bodies that are connected in the order they are written keep it, which those of rules
+        // pinned to their join order are checked to be.
+        rule.body.sort_by_key(|atom| !atom.sign);
+        order_by_connectivity(&mut rule.body);
+
         let mut body_iter = rule.body.iter().peekable();
-        let mut last_join_result_name = None;
+        let mut last_join_result_name: Option<String> = None;
         let mut last_join_terms: Vec<Term> = vec![];
         while let Some(current_atom) = body_iter.next() {
             if let Some(next_atom) = body_iter.peek() {
                 let mut left_symbol = current_atom.symbol.clone();
                 let mut left_terms = current_atom.terms.clone();
-                let left_sign = current_atom.sign.clone();
                 let mut right_symbol = next_atom.symbol.clone();
-                let right_sign = next_atom.sign.clone();
+                let right_sign = next_atom.sign;
                 let right_terms = &next_atom.terms;
 
-                if last_join_result_name.is_none() {
-                    if let Some(selection) =
-                        get_selection(&left_symbol, &left_sign, &current_atom.terms)
-                    {
-                        left_symbol = stringify_selection(&selection);
-                        operations.push(selection);
-                    } else {
-                        operations.push(Instruction::Move(left_symbol.clone()));
-                    }
-                } else if let Some(_) = last_join_result_name {
-                    left_symbol = last_join_result_name.clone().unwrap();
+                if let Some(last_join_result_name) = &last_join_result_name {
+                    left_symbol = last_join_result_name.clone();
                     left_terms = last_join_terms.clone();
+                } else if let Some((selection_name, selection)) =
+                    get_selection(&left_symbol, &current_atom.terms)
+                {
+                    left_symbol = selection_name;
+                    operations.push(selection);
+                } else {
+                    operations.push(Instruction::Move(left_symbol.clone()));
                 }
 
-                if let Some(selection) = get_selection(&right_symbol, &right_sign, right_terms) {
-                    right_symbol = stringify_selection(&selection);
+                if let Some((selection_name, selection)) = get_selection(&right_symbol, right_terms)
+                {
+                    right_symbol = selection_name;
                     operations.push(selection);
                 } else {
                     operations.push(Instruction::Move(right_symbol.clone()));
                 }
 
-                let is_anti_join = !left_sign || !right_sign;
-                if let Some(binary_join) = get_join(
-                    &left_terms,
-                    right_terms,
-                    &left_symbol,
-                    &right_symbol,
-                    is_anti_join,
-                ) {
-                    last_join_result_name = Some(stringify_join(&binary_join));
+                let is_anti_join = !right_sign;
+                let binary_join = if is_anti_join {
+                    Some(get_antijoin(
+                        &left_terms,
+                        right_terms,
+                        &left_symbol,
+                        &right_symbol,
+                    ))
+                } else {
+                    get_join(&left_terms, right_terms, &left_symbol, &right_symbol)
+                };
+                if let Some((binary_join_name, binary_join)) = binary_join {
+                    last_join_result_name = Some(binary_join_name);
                     last_join_terms = left_terms.clone();
-                    last_join_terms.extend(right_terms.clone());
+                    // An antijoin passes on the left side alone.
+                    if !is_anti_join {
+                        last_join_terms.extend(right_terms.clone());
+                    }
 
                     operations.push(binary_join);
                 }
             } else {
                 if operations.is_empty() {
-                    operations.push(Instruction::Move(current_atom.symbol.clone()));
+                    if let Some((_, selection)) =
+                        get_selection(&current_atom.symbol, &current_atom.terms)
+                    {
+                        operations.push(selection);
+                    } else {
+                        operations.push(Instruction::Move(current_atom.symbol.clone()));
+                    }
                 }
 
                 let projection = get_projection(&rule);
 
                 operations.push(projection);
+
+                if rule.is_aggregate() {
+                    operations.push(get_aggregation(&rule));
+                }
             }
         }
 
@@ -273,86 +513,721 @@ impl From<Rule> for Stack {
     }
 }
 
+// The relations that joins read straight from storage, along with the columns they are joined on,
+// which are worth keeping a persistent index over, unless the rule is not to probe them. Those
+// that the rule asks for are kept regardless.
+pub fn get_join_indices(rule: &Rule) -> Vec<(Symbol, Vec<Column>)> {
+    if rule.hints.no_index {
+        return rule.hints.indices.clone();
+    }
+
+    let stack = Stack::from(rule.clone());
+    let moved_symbols: HashSet<&Symbol> = stack
+        .inner
+        .iter()
+        .filter_map(|operation| match operation {
+            Instruction::Move(symbol) => Some(symbol),
+            _ => None,
+        })
+        .collect();
+
+    stack
+        .inner
+        .iter()
+        .filter_map(|operation| match operation {
+            Join(_, right_symbol, join_keys) | Antijoin(_, right_symbol, join_keys)
+                if moved_symbols.contains(right_symbol) =>
+            {
+                Some((
+                    right_symbol.clone(),
+                    join_keys
+                        .iter()
+                        .map(|(_, right_column)| *right_column)
+                        .collect(),
+                ))
+            }
+            _ => None,
+        })
+        .chain(rule.hints.indices.iter().cloned())
+        .collect()
+}
+
+// The relations that selections read straight from storage, along with the columns they select
+// on, which an index would spare them scanning.
+pub fn get_selection_indices(rule: &Rule) -> Vec<(Symbol, Vec<Column>)> {
+    Stack::from(rule.clone())
+        .inner
+        .into_iter()
+        .filter_map(|operation| match operation {
+            Instruction::Select(symbol, conditions) => Some((
+                symbol,
+                conditions.into_iter().map(|(column, _)| column).collect(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+// A stack only depends on its rule, so it is built once per program rather than every time the
+// rule is evaluated.
+#[derive(Default)]
+pub struct StackCache {
+    inner: HashMap<Rule, Stack>,
+}
+
+impl StackCache {
+    #[cfg(test)]
+    pub fn from_programs<'b>(programs: impl IntoIterator<Item = &'b Program>) -> Self {
+        Self::new(programs, false)
+    }
+    pub fn new<'b>(programs: impl IntoIterator<Item = &'b Program>, generic_joins: bool) -> Self {
+        Self {
+            inner: programs
+                .into_iter()
+                .flat_map(|program| program.inner.iter())
+                .map(|rule| (rule.clone(), Stack::new(rule.clone(), generic_joins)))
+                .collect(),
+        }
+    }
+    pub fn get(&self, rule: &Rule) -> Result<&Stack, String> {
+        self.inner
+            .get(rule)
+            .ok_or_else(|| format!("no stack was built for {:?}", rule))
+    }
+}
+
 pub struct RuleEvaluator<'a> {
     rule: &'a Rule,
+    stack: &'a Stack,
     facts_storage: &'a RelationStorage,
 }
 
 impl<'a> RuleEvaluator<'a> {
-    pub(crate) fn new(facts_storage: &'a RelationStorage, rule: &'a Rule) -> Self {
+    pub(crate) fn new(
+        facts_storage: &'a RelationStorage,
+        rule: &'a Rule,
+        stack: &'a Stack,
+    ) -> Self {
         Self {
             rule,
+            stack,
             facts_storage,
         }
     }
+    // The persistent index over the columns of a stored relation, unless the rule is not to probe
+    // any.
+    fn persistent_index(&self, symbol: &str, columns: &[usize]) -> Option<&'a HashIndex> {
+        if self.rule.hints.no_index {
+            return None;
+        }
+
+        self.facts_storage.get_index(symbol, columns)
+    }
+    // An index over the whole of a relation, which is the persistent one whenever the ephemeral
+    // relation holds all of what is stored.
+    fn index_whole_relation(
+        &self,
+        symbol: &str,
+        columns: Vec<usize>,
+        old: Option<&Vec<EphemeralValue>>,
+        delta: &[EphemeralValue],
+    ) -> Result<Cow<'a, HashIndex>, String> {
+        let length = old.map_or(0, Vec::len) + delta.len();
+        let persistent_index = self.persistent_index(symbol, &columns).filter(|_| {
+            self.facts_storage
+                .inner
+                .get(symbol)
+                .is_some_and(|relation| relation.len() == length)
+        });
+
+        Ok(match persistent_index {
+            Some(index) => Cow::Borrowed(index),
+            None => Cow::Owned(index_ephemeral_relation(
+                columns,
+                old.into_iter().flatten().chain(delta),
+            )?),
+        })
+    }
+    // Both sides of a self-join are the same relation, so what is new is the new facts on the left
+    // against all of them on the right, plus the old facts on the left against the new ones on the
+    // right. The latter are found by probing with the new facts as well, rather than with the old
+    // ones, and pairs of new facts are only taken from the former.
+    fn do_self_join(
+        &self,
+        symbol: &str,
+        join_keys: &[(usize, usize)],
+        old: Option<&Vec<EphemeralValue>>,
+        delta: &[EphemeralValue],
+    ) -> Result<Vec<EphemeralValue>, String> {
+        let (left_columns, right_columns): (Vec<usize>, Vec<usize>) =
+            join_keys.iter().copied().unzip();
+        let right_index = self.index_whole_relation(symbol, right_columns.clone(), old, delta)?;
+        let mut join_result = do_join(
+            join_keys,
+            delta,
+            &JoinRight::Indexed(Cow::Borrowed(&right_index)),
+        )?;
+
+        let left_index = if left_columns == right_columns {
+            right_index
+        } else {
+            self.index_whole_relation(symbol, left_columns, old, delta)?
+        };
+        let delta_facts: Vec<&Arc<AnonymousGroundAtom>> = delta
+            .iter()
+            .map(EphemeralValue::fact)
+            .collect::<Result<_, _>>()?;
+        let delta_set: HashSet<&AnonymousGroundAtom> =
+            delta_facts.iter().map(|fact| fact.as_ref()).collect();
+        delta_facts.iter().for_each(|right_fact| {
+            let key: Vec<&TypedValue> = right_columns
+                .iter()
+                .map(|right_column| &right_fact[*right_column])
+                .collect();
+            left_index
+                .get(&key)
+                .filter(|left_fact| !delta_set.contains(left_fact.as_ref()))
+                .for_each(|left_fact| {
+                    join_result.push(EphemeralValue::JoinResult(vec![
+                        left_fact.clone(),
+                        (*right_fact).clone(),
+                    ]));
+                });
+        });
+
+        Ok(join_result)
+    }
 }
 
-fn do_join(
-    penultimate_operation: usize,
-    relation_symbol_to_be_projected: &mut String,
-    idx: usize,
-    join_keys: &Vec<(usize, usize)>,
-    left_relation: &Vec<EphemeralValue>,
-    right_relation: &Vec<EphemeralValue>,
-    join_result_name: &String,
-    join_key_positions: Option<&Vec<((usize, usize), usize)>>,
-) -> Vec<EphemeralValue> {
-    if idx == penultimate_operation {
-        *relation_symbol_to_be_projected = join_result_name.clone();
+// The fact of a join result that every left join key column lies in, the column within that fact,
+// and the right column it is matched with.
+type JoinKeyPositions = Vec<((usize, usize), usize)>;
+
+// Join results are flat concatenations of their facts, so a column of the left side has to be
+// located within the fact it came from.
+fn get_join_key_positions(
+    join_keys: &[(usize, usize)],
+    product: &[Arc<AnonymousGroundAtom>],
+) -> Result<JoinKeyPositions, String> {
+    join_keys
+        .iter()
+        .map(|(left_column, right_column)| {
+            let mut offset = 0;
+
+            for (left_idx, fact) in product.iter().enumerate() {
+                if *left_column < offset + fact.len() {
+                    return Ok(((left_idx, left_column - offset), *right_column));
+                }
+
+                offset += fact.len();
+            }
+
+            Err(format!(
+                "join key column {} lies past the {} columns of the join result",
+                left_column, offset
+            ))
+        })
+        .collect()
+}
+
+// Below this many facts on either side of a join, scanning the right side for every fact on the
+// left is cheaper than hashing the right side first.
+const NESTED_LOOP_THRESHOLD: usize = 10;
+
+// The right side of a join, which is either probed through an index over it, or scanned in full
+// for every fact on the left.
+enum JoinRight<'b> {
+    Indexed(Cow<'b, HashIndex>),
+    Scanned(Vec<&'b Arc<AnonymousGroundAtom>>),
+}
+
+impl<'b> JoinRight<'b> {
+    // Sides are picked anew for every join of every iteration, since deltas shrink as the
+    // evaluation goes on.
+    fn new(
+        columns: Vec<usize>,
+        left_length: usize,
+        relation: impl Iterator<Item = &'b EphemeralValue>,
+    ) -> Result<Self, String> {
+        let facts: Vec<_> = relation
+            .map(EphemeralValue::fact)
+            .collect::<Result<_, _>>()?;
+        if left_length.min(facts.len()) < NESTED_LOOP_THRESHOLD {
+            return Ok(JoinRight::Scanned(facts));
+        }
+
+        Ok(JoinRight::Indexed(Cow::Owned(HashIndex::from_facts(
+            columns,
+            facts.into_iter().cloned(),
+        ))))
     }
+}
 
+fn do_join(
+    join_keys: &[(usize, usize)],
+    left_relation: &[EphemeralValue],
+    right: &JoinRight,
+) -> Result<Vec<EphemeralValue>, String> {
     let mut join_result = vec![];
 
-    left_relation.into_iter().for_each(|left_allocation| {
-        right_relation.into_iter().for_each(|right_allocation| {
-            let right_fact = match right_allocation {
-                EphemeralValue::FactRef(fact) => fact,
-                EphemeralValue::JoinResult(_) => unreachable!(),
-            };
+    let join_key_positions = match left_relation.first() {
+        Some(EphemeralValue::JoinResult(product)) => {
+            Some(get_join_key_positions(join_keys, product)?)
+        }
+        _ => None,
+    };
 
-            match left_allocation {
-                EphemeralValue::FactRef(left_fact) => {
-                    if join_keys.iter().all(|(left_column, right_column)| {
-                        left_fact[*left_column] == right_fact[*right_column]
-                    }) {
-                        {
-                            join_result.push(EphemeralValue::JoinResult(vec![
-                                left_fact.clone(),
-                                right_fact.clone(),
-                            ]));
-                        }
-                    }
-                }
+    left_relation.iter().for_each(|left_allocation| {
+        let left_key: Vec<&TypedValue> = match left_allocation {
+            EphemeralValue::FactRef(left_fact) => join_keys
+                .iter()
+                .map(|(left_column, _)| &left_fact[*left_column])
+                .collect(),
+            EphemeralValue::JoinResult(product) => join_key_positions
+                .iter()
+                .flatten()
+                .map(|((left_fact_idx, left_column), _)| &product[*left_fact_idx][*left_column])
+                .collect(),
+        };
+
+        let mut push = |right_fact: &Arc<AnonymousGroundAtom>| {
+            let product = match left_allocation {
+                EphemeralValue::FactRef(left_fact) => vec![left_fact.clone(), right_fact.clone()],
                 EphemeralValue::JoinResult(product) => {
-                    if let Some(join_key_positions) = join_key_positions {
-                        if join_key_positions.into_iter().all(
-                            |((left_fact_idx, left_column), right_column)| {
-                                product[*left_fact_idx][*left_column] == right_fact[*right_column]
-                            },
-                        ) {
-                            let mut new_product = product.clone();
-                            new_product.push(right_fact.clone());
-
-                            join_result.push(EphemeralValue::JoinResult(new_product));
-                        }
-                    }
+                    let mut new_product = product.clone();
+                    new_product.push(right_fact.clone());
+
+                    new_product
                 }
+            };
+
+            join_result.push(EphemeralValue::JoinResult(product));
+        };
+
+        match right {
+            JoinRight::Indexed(right_index) => right_index.get(&left_key).for_each(push),
+            JoinRight::Scanned(right_facts) => right_facts
+                .iter()
+                .filter(|right_fact| {
+                    join_keys
+                        .iter()
+                        .zip(&left_key)
+                        .all(|((_, right_column), value)| right_fact[*right_column] == **value)
+                })
+                .for_each(|right_fact| push(right_fact)),
+        }
+    });
+
+    Ok(join_result)
+}
+
+fn index_ephemeral_relation<'b>(
+    columns: Vec<usize>,
+    relation: impl Iterator<Item = &'b EphemeralValue>,
+) -> Result<HashIndex, String> {
+    let facts: Vec<_> = relation
+        .map(|allocation| allocation.fact().cloned())
+        .collect::<Result<_, _>>()?;
+
+    Ok(HashIndex::from_facts(columns, facts.into_iter()))
+}
+
+// The facts of one atom of a generic join, arranged by the values of its variables in the order
+// that they are bound in.
+struct Trie<'a> {
+    // The position in the binding order of every variable of the atom, in that order, along with
+    // the columns that hold it.
+    variables: Vec<(usize, Vec<Column>)>,
+    // The values that every variable takes for the values of those before it.
+    levels: Vec<HashMap<Vec<&'a TypedValue>, IndexSet<&'a TypedValue>>>,
+    facts: HashMap<Vec<&'a TypedValue>, Vec<&'a Arc<AnonymousGroundAtom>>>,
+}
+
+impl<'a> Trie<'a> {
+    fn new(
+        terms: &[Term],
+        order: &IndexSet<Variable>,
+        facts: impl Iterator<Item = &'a Arc<AnonymousGroundAtom>>,
+    ) -> Self {
+        let mut variables: Vec<(usize, Vec<Column>)> = vec![];
+        terms.iter().enumerate().for_each(|(column, term)| {
+            let Term::Variable(name) = term else {
+                return;
+            };
+            let Some(position) = order.get_index_of(name) else {
+                return;
+            };
+            match variables.iter_mut().find(|(known, _)| *known == position) {
+                Some((_, columns)) => columns.push(column),
+                None => variables.push((position, vec![column])),
+            }
+        });
+        variables.sort();
+
+        let mut trie = Self {
+            levels: vec![HashMap::new(); variables.len()],
+            variables,
+            facts: HashMap::new(),
+        };
+        facts.for_each(|fact| {
+            // An atom with a variable at several columns only holds facts with the same value at
+            // all of them.
+            let values: Option<Vec<&TypedValue>> = trie
+                .variables
+                .iter()
+                .map(|(_, columns)| {
+                    let value = &fact[columns[0]];
+                    columns
+                        .iter()
+                        .all(|column| fact[*column] == *value)
+                        .then_some(value)
+                })
+                .collect();
+            let Some(values) = values else {
+                return;
+            };
+
+            (0..values.len()).for_each(|level| {
+                trie.levels[level]
+                    .entry(values[..level].to_vec())
+                    .or_default()
+                    .insert(values[level]);
+            });
+            trie.facts.entry(values).or_default().push(fact);
+        });
+
+        trie
+    }
+    // The values that the atom allows for the variable at the position, if it has that variable.
+    fn extensions(
+        &self,
+        position: usize,
+        binding: &[&'a TypedValue],
+    ) -> Option<Option<&IndexSet<&'a TypedValue>>> {
+        let level = self
+            .variables
+            .iter()
+            .position(|(variable, _)| *variable == position)?;
+        let prefix: Vec<_> = self.variables[..level]
+            .iter()
+            .map(|(variable, _)| binding[*variable])
+            .collect();
+
+        Some(self.levels[level].get(&prefix))
+    }
+}
+
+fn extend_generic_join<'a>(
+    tries: &[Trie<'a>],
+    variable_count: usize,
+    binding: &mut Vec<&'a TypedValue>,
+    join_result: &mut Vec<EphemeralValue>,
+) {
+    let position = binding.len();
+    if position == variable_count {
+        let mut products: Vec<Vec<Arc<AnonymousGroundAtom>>> = vec![vec![]];
+        for trie in tries {
+            let key: Vec<_> = trie
+                .variables
+                .iter()
+                .map(|(variable, _)| binding[*variable])
+                .collect();
+            let Some(facts) = trie.facts.get(&key) else {
+                return;
+            };
+            products = products
+                .into_iter()
+                .flat_map(|product| {
+                    facts.iter().map(move |fact| {
+                        let mut product = product.clone();
+                        product.push((*fact).clone());
+
+                        product
+                    })
+                })
+                .collect();
+        }
+
+        join_result.extend(products.into_iter().map(EphemeralValue::JoinResult));
+        return;
+    }
+
+    let Some(candidates) = tries
+        .iter()
+        .filter_map(|trie| trie.extensions(position, binding))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    let Some(smallest) = candidates.iter().min_by_key(|values| values.len()) else {
+        return;
+    };
+
+    smallest
+        .iter()
+        .filter(|value| candidates.iter().all(|values| values.contains(*value)))
+        .for_each(|value| {
+            binding.push(value);
+            extend_generic_join(tries, variable_count, binding, join_result);
+            binding.pop();
+        });
+}
+
+// What is new is, for every atom, its delta joined with the atoms before it as they are now and
+// those after it as they were, which holds every new result exactly once.
+fn do_generic_join(
+    inputs: &[(Symbol, Vec<Term>)],
+    index_storage: &IndexStorage,
+) -> Result<Option<Vec<EphemeralValue>>, String> {
+    let order: IndexSet<Variable> = inputs
+        .iter()
+        .flat_map(|(_, terms)| get_variables(terms).into_keys())
+        .collect();
+    let facts = |symbol: &Symbol, old: bool, new: bool| {
+        let old = index_storage.inner.get(symbol).filter(|_| old);
+        let new = index_storage.diff.get(symbol).filter(|_| new);
+
+        old.into_iter()
+            .chain(new)
+            .flatten()
+            .map(EphemeralValue::fact)
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let mut join_result = None;
+    for (delta_position, (delta_symbol, _)) in inputs.iter().enumerate() {
+        if index_storage
+            .diff
+            .get(delta_symbol)
+            .is_none_or(|delta| delta.is_empty())
+        {
+            continue;
+        }
+
+        let tries: Vec<Trie> = inputs
+            .iter()
+            .enumerate()
+            .map(|(position, (symbol, terms))| {
+                let (old, new) = match position.cmp(&delta_position) {
+                    std::cmp::Ordering::Less => (true, true),
+                    std::cmp::Ordering::Equal => (false, true),
+                    std::cmp::Ordering::Greater => (true, false),
+                };
+
+                Ok(Trie::new(
+                    terms,
+                    &order,
+                    facts(symbol, old, new)?.into_iter(),
+                ))
+            })
+            .collect::<Result<_, String>>()?;
+        extend_generic_join(
+            &tries,
+            order.len(),
+            &mut vec![],
+            join_result.get_or_insert_with(Vec::new),
+        );
+    }
+
+    Ok(join_result)
+}
+
+// Whatever on the left has no match on the right is passed on as it is.
+fn do_antijoin(
+    join_keys: &[(usize, usize)],
+    left_relation: &[EphemeralValue],
+    has_match: impl Fn(&[&TypedValue]) -> bool,
+) -> Result<Vec<EphemeralValue>, String> {
+    let join_key_positions = match left_relation.first() {
+        Some(EphemeralValue::JoinResult(product)) => {
+            Some(get_join_key_positions(join_keys, product)?)
+        }
+        _ => None,
+    };
+
+    Ok(left_relation
+        .iter()
+        .filter(|left_allocation| {
+            let left_key: Vec<&TypedValue> = match left_allocation {
+                EphemeralValue::FactRef(left_fact) => join_keys
+                    .iter()
+                    .map(|(left_column, _)| &left_fact[*left_column])
+                    .collect(),
+                EphemeralValue::JoinResult(product) => join_key_positions
+                    .iter()
+                    .flatten()
+                    .map(|((left_fact_idx, left_column), _)| &product[*left_fact_idx][*left_column])
+                    .collect(),
+            };
+
+            !has_match(&left_key)
+        })
+        .cloned()
+        .collect())
+}
+
+// The stored relation that the right side of an antijoin stands for, along with the selection
+// that narrows it down, if any.
+fn negated_relation<'b>(
+    stack: &'b Stack,
+    right_symbol: &'b str,
+) -> (&'b str, Option<&'b [(Column, Value)]>) {
+    stack
+        .inner
+        .iter()
+        .find_map(|operation| match operation {
+            Instruction::Select(symbol, conditions)
+                if stringify_selection(symbol, conditions) == right_symbol =>
+            {
+                Some((symbol.as_str(), Some(conditions.as_slice())))
             }
+            _ => None,
         })
+        .unwrap_or((right_symbol, None))
+}
+
+fn skolem_constant(name: &str, columns: &[Column], fact: &AnonymousGroundAtom) -> TypedValue {
+    let arguments = columns
+        .iter()
+        .map(|column| format!("{:?}", fact[*column]))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    TypedValue::from(format!("_:{}({})", name, arguments))
+}
+
+// Head variables that no positive body atom binds.
+pub fn get_existential_variables(rule: &Rule) -> Vec<&Variable> {
+    rule.head
+        .terms
+        .iter()
+        .flat_map(|term| match term {
+            Term::Variable(name) | Term::Aggregate(_, name) => vec![name],
+            Term::Expression(expression) => expression.variables(),
+            Term::Constant(_) => vec![],
+        })
+        .filter(|name| {
+            !rule
+                .body
+                .iter()
+                .filter(|body_atom| body_atom.sign)
+                .flat_map(|body_atom| body_atom.terms.iter())
+                .any(|term| matches!(term, Term::Variable(bound) if bound == *name))
+        })
+        .collect()
+}
+
+// Sums stay unsigned for as long as every summand is, and widen to signed integers and then to
+// floats otherwise. Values that are not numbers are skipped.
+fn add(left: TypedValue, right: &TypedValue) -> TypedValue {
+    match (left, right) {
+        (TypedValue::Int(l), TypedValue::Int(r)) => TypedValue::Int(l + r),
+        (TypedValue::Int(l), TypedValue::SignedInt(r)) => TypedValue::SignedInt(l as i64 + r),
+        (TypedValue::Int(l), TypedValue::Float(r)) => {
+            TypedValue::Float(OrderedFloat(l as f64 + r.0))
+        }
+        (TypedValue::SignedInt(l), TypedValue::Int(r)) => TypedValue::SignedInt(l + *r as i64),
+        (TypedValue::SignedInt(l), TypedValue::SignedInt(r)) => TypedValue::SignedInt(l + r),
+        (TypedValue::SignedInt(l), TypedValue::Float(r)) => {
+            TypedValue::Float(OrderedFloat(l as f64 + r.0))
+        }
+        (TypedValue::Float(l), TypedValue::Int(r)) => {
+            TypedValue::Float(OrderedFloat(l.0 + *r as f64))
+        }
+        (TypedValue::Float(l), TypedValue::SignedInt(r)) => {
+            TypedValue::Float(OrderedFloat(l.0 + *r as f64))
+        }
+        (TypedValue::Float(l), TypedValue::Float(r)) => TypedValue::Float(OrderedFloat(l.0 + r.0)),
+        (left, _) => left,
+    }
+}
+
+// Every projected fact stands for one distinct way of satisfying the body, hence aggregations
+// count and sum over all of them rather than over distinct values.
+fn do_aggregate(
+    aggregations: &[Option<Aggregation>],
+    projected_facts: Vec<AnonymousGroundAtom>,
+) -> Vec<AnonymousGroundAtom> {
+    let mut groups: IndexMap<AnonymousGroundAtom, Vec<AnonymousGroundAtom>> = IndexMap::new();
+
+    projected_facts.into_iter().for_each(|fact| {
+        let group_key = fact
+            .iter()
+            .zip(aggregations)
+            .filter(|(_, aggregation)| aggregation.is_none())
+            .map(|(value, _)| value.clone())
+            .collect();
+
+        groups.entry(group_key).or_default().push(fact);
     });
 
-    join_result
+    groups
+        .into_iter()
+        .filter_map(|(group_key, facts)| {
+            let mut group_key_values = group_key.into_iter();
+            let chosen = choose(aggregations, &facts)?;
+
+            aggregations
+                .iter()
+                .enumerate()
+                .map(|(column, aggregation)| {
+                    let values = facts.iter().map(|fact| &fact[column]);
+
+                    match aggregation {
+                        None => group_key_values.next(),
+                        Some(Aggregation::Count) => Some(TypedValue::Int(facts.len())),
+                        Some(Aggregation::Sum) => Some(values.fold(TypedValue::Int(0), add)),
+                        Some(Aggregation::Min) => values.min().cloned(),
+                        Some(Aggregation::Max) => values.max().cloned(),
+                        Some(Aggregation::Choice) => Some(chosen[column].clone()),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// The fact of a group that choices are taken from: the least of those with the first min or max, if
+// there is one, otherwise the least of all.
+fn choose<'a>(
+    aggregations: &[Option<Aggregation>],
+    facts: &'a [AnonymousGroundAtom],
+) -> Option<&'a AnonymousGroundAtom> {
+    let extremum = aggregations
+        .iter()
+        .enumerate()
+        .find_map(|(column, aggregation)| match aggregation {
+            Some(Aggregation::Min) => Some((column, facts.iter().map(|f| &f[column]).min())),
+            Some(Aggregation::Max) => Some((column, facts.iter().map(|f| &f[column]).max())),
+            _ => None,
+        });
+
+    facts
+        .iter()
+        .filter(|fact| match extremum {
+            Some((column, value)) => Some(&fact[column]) == value,
+            None => true,
+        })
+        .min()
 }
 
 impl<'a> RuleEvaluator<'a> {
+    // Fails on rules that read relations the storage does not hold, rather than taking them to be
+    // empty, since those are not relations of the program that the rule was built for.
     pub fn step(
         &self,
         index_storage: &mut IndexStorage,
-    ) -> impl Iterator<Item = AnonymousGroundAtom> + 'a {
-        let stack = Stack::from(self.rule.clone());
-
-        // There will always be at least two elements on the stack. Move or Select, and then Projection.
-        let penultimate_operation = stack.inner.len() - 2;
+    ) -> Result<impl Iterator<Item = AnonymousGroundAtom> + 'a, String> {
+        let stack = self.stack;
+
+        // There will always be at least one Move or Select before the Projection.
+        let penultimate_operation = stack
+            .inner
+            .iter()
+            .position(|operation| matches!(operation, Project(_, _)))
+            .filter(|position| *position > 0)
+            .ok_or_else(|| format!("{:?} projects nothing that its body reads", self.rule))?
+            - 1;
         let mut relation_symbol_to_be_projected = self.rule.head.symbol.clone();
         let mut grounded_facts: Vec<AnonymousGroundAtom> = vec![];
 
@@ -362,189 +1237,313 @@ impl<'a> RuleEvaluator<'a> {
                     if idx == penultimate_operation {
                         relation_symbol_to_be_projected = symbol.clone();
                     }
-                    let moved = index_storage.diff.get(symbol).is_some();
-                    if !moved {
-                        let fact_refs = self.facts_storage.get_relation(symbol);
+                    // A relation that has been seen before only changes through its delta, which
+                    // is already in the diff.
+                    let seen = index_storage.diff.contains_key(symbol)
+                        || index_storage.inner.contains_key(symbol);
+                    if !seen {
+                        let fact_refs = self.facts_storage.relation(symbol)?;
 
                         index_storage.borrow_all(
                             symbol,
                             fact_refs
                                 .into_iter()
                                 .map(|fact| EphemeralValue::FactRef(fact.clone())),
-                        );
+                        )?;
                     }
                 }
-                Instruction::Select(symbol, sign, column, value) => {
-                    let index_name = stringify_selection(&operation);
+                Instruction::Select(symbol, conditions) => {
+                    let index_name = stringify_selection(symbol, conditions);
                     if idx == penultimate_operation {
                         relation_symbol_to_be_projected = index_name.clone();
                     }
+                    let select = |fact: &AnonymousGroundAtom| satisfies(fact, conditions);
                     // If the index already exists, then this is a NOOP.
-                    if index_storage.diff.get(&index_name).is_none() {
-                        let target_relation = self.facts_storage.get_relation(symbol);
+                    if !index_storage.diff.contains_key(&index_name) {
+                        if index_storage.inner.contains_key(&index_name) {
+                            // Only the delta of the target relation can add to an existing index.
+                            let selection: Vec<_> = index_storage
+                                .diff
+                                .get(symbol)
+                                .into_iter()
+                                .flatten()
+                                .filter_map(|allocation| match allocation.fact() {
+                                    Ok(fact) => select(fact).then(|| Ok(allocation.clone())),
+                                    Err(error) => Some(Err(error)),
+                                })
+                                .collect::<Result<_, String>>()?;
+
+                            index_storage.borrow_all(&index_name, selection.into_iter())?;
+                        } else {
+                            let columns: Vec<Column> =
+                                conditions.iter().map(|(column, _)| *column).collect();
+                            let key: Vec<&Value> =
+                                conditions.iter().map(|(_, value)| value).collect();
+
+                            let equivalence = self.facts_storage.get_equivalence(symbol);
+                            match self.persistent_index(symbol, &columns) {
+                                // Selecting both columns of an equivalence asks whether the
+                                // values are equivalent, which is a single lookup.
+                                _ if equivalence.is_some() && columns.len() == 2 => {
+                                    let fact: AnonymousGroundAtom =
+                                        key.into_iter().cloned().collect();
+                                    let target_relation = self.facts_storage.relation(symbol)?;
+
+                                    index_storage.borrow_all(
+                                        &index_name,
+                                        target_relation
+                                            .get(&fact)
+                                            .map(|fact| EphemeralValue::FactRef(fact.clone()))
+                                            .into_iter(),
+                                    )?;
+                                }
+                                Some(index) => index_storage.borrow_all(
+                                    &index_name,
+                                    index
+                                        .get(&key)
+                                        .map(|fact| EphemeralValue::FactRef(fact.clone())),
+                                )?,
+                                None => {
+                                    let target_relation = self.facts_storage.relation(symbol)?;
+
+                                    let selection = target_relation
+                                        .iter()
+                                        .filter(|fact| select(fact))
+                                        .map(|fact| EphemeralValue::FactRef(fact.clone()));
+
+                                    index_storage.borrow_all(&index_name, selection)?;
+                                }
+                            }
+                        }
+                    }
+                }
 
-                        // Apply the selection based on the `sign`
-                        let selection = target_relation
+                Instruction::Antijoin(left_symbol, right_symbol, join_keys) => {
+                    let join_result_name =
+                        stringify_join(left_symbol, right_symbol, join_keys, true);
+                    if idx == penultimate_operation {
+                        relation_symbol_to_be_projected = join_result_name.clone();
+                    }
+                    if index_storage.diff.contains_key(&join_result_name) {
+                        continue;
+                    }
+
+                    // The negated relation belongs to a lower stratum, hence it does not change
+                    // anymore and only what is new on the left can be new in the result. It is
+                    // probed as it is stored, rather than as much of it as this evaluation has
+                    // read so far, so that no fact of it can be missed.
+                    let left_delta = index_storage.diff.get(left_symbol);
+
+                    if let Some(left_delta) = left_delta {
+                        let (negated_symbol, selection) = negated_relation(stack, right_symbol);
+                        let right_columns: Vec<usize> = join_keys
                             .iter()
-                            .filter(|fact| {
-                                if *sign {
-                                    fact[*column] == *value // Positive selection: column == value
-                                } else {
-                                    fact[*column] != *value // Negated selection: column != value
-                                }
-                            })
-                            .map(|fact| EphemeralValue::FactRef(fact.clone()));
+                            .map(|(_, right_column)| *right_column)
+                            .collect();
+                        let persistent_index = match selection {
+                            None => self.persistent_index(negated_symbol, &right_columns),
+                            Some(_) => None,
+                        };
+
+                        let antijoin_result = match persistent_index {
+                            Some(right_index) => do_antijoin(join_keys, left_delta, |key| {
+                                right_index.get(key).next().is_some()
+                            })?,
+                            None => {
+                                let right_keys: HashSet<Vec<&TypedValue>> = self
+                                    .facts_storage
+                                    .inner
+                                    .get(negated_symbol)
+                                    .into_iter()
+                                    .flatten()
+                                    .filter(|fact| {
+                                        selection
+                                            .is_none_or(|conditions| satisfies(fact, conditions))
+                                    })
+                                    .map(|fact| {
+                                        right_columns.iter().map(|column| &fact[*column]).collect()
+                                    })
+                                    .collect();
+
+                                do_antijoin(join_keys, left_delta, |key| right_keys.contains(key))?
+                            }
+                        };
 
-                        index_storage.borrow_all(&index_name, selection);
+                        index_storage.borrow_all(&join_result_name, antijoin_result.into_iter())?;
                     }
                 }
 
-                Instruction::Join(left_symbol, right_symbol, join_keys)
-                | Instruction::Antijoin(left_symbol, right_symbol, join_keys) => {
+                Instruction::Join(left_symbol, right_symbol, join_keys) => {
+                    let join_result_name =
+                        stringify_join(left_symbol, right_symbol, join_keys, false);
+                    if idx == penultimate_operation {
+                        relation_symbol_to_be_projected = join_result_name.clone();
+                    }
+                    // Join results are named after what they join rather than after the rule, so
+                    // another rule with the same body prefix has already computed this one in this
+                    // iteration.
+                    if index_storage.diff.contains_key(&join_result_name) {
+                        continue;
+                    }
+
                     let left = index_storage.inner.get(left_symbol);
-                    let right = index_storage.inner.get(right_symbol);
                     let left_delta = index_storage.diff.get(left_symbol);
+                    let right = index_storage.inner.get(right_symbol);
                     let right_delta = index_storage.diff.get(right_symbol);
+                    let right_columns: Vec<usize> = join_keys
+                        .iter()
+                        .map(|(_, right_column)| *right_column)
+                        .collect();
+
+                    if left_symbol == right_symbol {
+                        if let Some(delta) = left_delta {
+                            let join_result =
+                                self.do_self_join(right_symbol, join_keys, right, delta)?;
+                            index_storage.borrow_all(&join_result_name, join_result.into_iter())?;
+                        }
+                        continue;
+                    }
 
-                    let join_result_name = stringify_join(operation);
-                    let mut join_key_positions = None;
-                    if let Some(left_relation) = left {
-                        if let Some(left_allocation) = left_relation.get(0) {
-                            match left_allocation {
-                                EphemeralValue::JoinResult(product) => {
-                                    join_key_positions = Some(
-                                        join_keys
-                                            .iter()
-                                            .map(|(left_column, right_column)| {
-                                                let mut cumsum = 0;
-
-                                                let arities = product.iter().map(|fact| fact.len());
-
-                                                let mut left_idx = 0;
-
-                                                for (idx, arity) in arities.enumerate() {
-                                                    cumsum += arity;
-
-                                                    if *left_column < cumsum {
-                                                        left_idx = idx;
-                                                        break;
-                                                    }
-                                                }
-
-                                                ((left_idx, cumsum - left_column), *right_column)
-                                            })
-                                            .collect::<Vec<_>>(),
-                                    );
-                                }
-                                EphemeralValue::FactRef(_) => {}
-                            }
+                    // What is new is the new left against the whole right, plus the old left
+                    // against the new right. Whenever the whole right is all of a stored relation,
+                    // its persistent index stands in for it.
+                    let mut join_result = None;
+                    if let Some(left_delta) = left_delta {
+                        let right_length: usize =
+                            right.into_iter().chain(right_delta).map(Vec::len).sum();
+                        let persistent_index = self
+                            .persistent_index(right_symbol, &right_columns)
+                            .filter(|_| {
+                                self.facts_storage
+                                    .inner
+                                    .get(right_symbol)
+                                    .is_some_and(|relation| relation.len() == right_length)
+                            });
+
+                        let join_right = match persistent_index {
+                            Some(right_index) => JoinRight::Indexed(Cow::Borrowed(right_index)),
+                            None => JoinRight::new(
+                                right_columns.clone(),
+                                left_delta.len(),
+                                right.into_iter().chain(right_delta).flatten(),
+                            )?,
                         };
+                        let delta = do_join(join_keys, left_delta, &join_right)?;
+                        join_result.get_or_insert_with(Vec::new).extend(delta);
+                    }
+                    if let (Some(left), Some(right_delta)) = (left, right_delta) {
+                        let join_right =
+                            JoinRight::new(right_columns, left.len(), right_delta.iter())?;
+
+                        join_result.get_or_insert_with(Vec::new).extend(do_join(
+                            join_keys,
+                            left,
+                            &join_right,
+                        )?);
                     }
 
-                    let left_right_delta = {
-                        if left.is_some() && right_delta.is_some() {
-                            Some(do_join(
-                                penultimate_operation,
-                                &mut relation_symbol_to_be_projected,
-                                idx,
-                                join_keys,
-                                left.as_ref().unwrap(),
-                                right_delta.as_ref().unwrap(),
-                                &join_result_name,
-                                join_key_positions.as_ref(),
-                            ))
-                        } else {
-                            None
-                        }
-                    };
-                    let right_left_delta = {
-                        if right.is_some() && left_delta.is_some() {
-                            Some(do_join(
-                                penultimate_operation,
-                                &mut relation_symbol_to_be_projected,
-                                idx,
-                                join_keys,
-                                left_delta.as_ref().unwrap(),
-                                right.as_ref().unwrap(),
-                                &join_result_name,
-                                join_key_positions.as_ref(),
-                            ))
-                        } else {
-                            None
-                        }
-                    };
-                    let left_delta_right_delta = {
-                        if left_delta.is_some() && right_delta.is_some() {
-                            Some(do_join(
-                                penultimate_operation,
-                                &mut relation_symbol_to_be_projected,
-                                idx,
-                                join_keys,
-                                left_delta.as_ref().unwrap(),
-                                right_delta.as_ref().unwrap(),
-                                &join_result_name,
-                                join_key_positions.as_ref(),
-                            ))
-                        } else {
-                            None
-                        }
-                    };
+                    if let Some(join_result) = join_result {
+                        index_storage.borrow_all(&join_result_name, join_result.into_iter())?;
+                    }
+                }
 
-                    if let Some(left_right_delta) = left_right_delta {
-                        index_storage.borrow_all(&join_result_name, left_right_delta.into_iter());
+                Instruction::GenericJoin(inputs) => {
+                    let join_result_name = stringify_generic_join(inputs);
+                    if idx == penultimate_operation {
+                        relation_symbol_to_be_projected = join_result_name.clone();
                     }
-                    if let Some(right_left_delta) = right_left_delta {
-                        index_storage.borrow_all(&join_result_name, right_left_delta.into_iter());
+                    if index_storage.diff.contains_key(&join_result_name) {
+                        continue;
                     }
-                    if let Some(left_delta_right_delta) = left_delta_right_delta {
-                        index_storage
-                            .borrow_all(&join_result_name, left_delta_right_delta.into_iter());
+
+                    if let Some(join_result) = do_generic_join(inputs, index_storage)? {
+                        index_storage.borrow_all(&join_result_name, join_result.into_iter())?;
                     }
                 }
 
                 Instruction::Project(_symbol, projection_inputs) => {
+                    // Nothing to project if nothing changed since the last iteration.
                     let ephemeral_relation_to_be_projected = index_storage
                         .diff
-                        .get(relation_symbol_to_be_projected.as_str())
-                        .unwrap();
+                        .get(relation_symbol_to_be_projected.as_str());
                     ephemeral_relation_to_be_projected
                         .into_iter()
+                        .flatten()
                         .for_each(|allocation| {
                             let fact = match allocation {
                                 EphemeralValue::FactRef(fact) => fact.clone(),
                                 EphemeralValue::JoinResult(facts) => Arc::new(
                                     facts
-                                        .into_iter()
+                                        .iter()
                                         .flat_map(|fact| fact.iter().cloned().collect::<Vec<_>>())
                                         .collect(),
                                 ),
                             };
-                            let mut projection = vec![];
-
-                            projection_inputs.iter().for_each(|projection_input| {
-                                match projection_input {
-                                    ProjectionInput::Column(column) => {
-                                        projection.push(fact[*column].clone())
+                            let projection: Option<Vec<_>> = projection_inputs
+                                .iter()
+                                .map(|projection_input| match projection_input {
+                                    ProjectionInput::Column(column) => Some(fact[*column].clone()),
+                                    ProjectionInput::Value(value) => Some(value.clone()),
+                                    ProjectionInput::Skolem(name, columns) => {
+                                        Some(skolem_constant(name, columns, &fact))
                                     }
-                                    ProjectionInput::Value(value) => projection.push(value.clone()),
-                                }
-                            });
-
-                            grounded_facts.push(projection)
+                                    ProjectionInput::Expr(expression, columns) => expression
+                                        .evaluate(&|name| {
+                                            columns
+                                                .iter()
+                                                .find(|(variable, _)| variable == name)
+                                                .map(|(_, column)| fact[*column].clone())
+                                        }),
+                                })
+                                .collect();
+
+                            grounded_facts.extend(projection)
                         });
                 }
+
+                Instruction::Aggregate(_symbol, aggregations) => {
+                    grounded_facts = do_aggregate(aggregations, grounded_facts);
+                }
             }
         }
 
-        grounded_facts.into_iter()
+        Ok(grounded_facts.into_iter())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::evaluation::spj_processor::{Instruction, ProjectionInput, Stack};
+    use crate::engine::index_storage::{EphemeralValue, IndexStorage};
+    use crate::engine::storage::RelationStorage;
+    use crate::evaluation::spj_processor::{
+        do_join, is_cyclic, Instruction, JoinRight, ProjectionInput, RuleEvaluator, Stack,
+    };
     use datalog_rule_macro::rule;
     use datalog_syntax::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    #[test]
+    fn from_cyclic_rule_into_generic_join_stack() {
+        let rule = rule! { triangle(?x, ?y, ?z) <- [e(?x, ?y), e(?y, ?z), e(?z, ?x)] };
+        assert!(is_cyclic(&rule.body));
+
+        let stack = Stack::new(rule.clone(), true);
+        assert_eq!(
+            1,
+            stack
+                .inner
+                .iter()
+                .filter(|instruction| matches!(instruction, Instruction::GenericJoin(_)))
+                .count()
+        );
+        assert_eq!(Stack::from(rule.clone()), Stack::new(rule, false));
+
+        // A path joins no cycle, and keeps its binary joins.
+        let path = rule! { path(?x, ?z) <- [e(?x, ?y), e(?y, ?z)] };
+        assert!(!is_cyclic(&path.body));
+        assert_eq!(Stack::from(path.clone()), Stack::new(path, true));
+    }
 
     #[test]
     fn from_unary_rule_into_stack() {
@@ -588,8 +1587,8 @@ mod test {
 
         let expected_stack = Stack {
             inner: vec![
-                Instruction::Select("T".to_string(), true, 1, TypedValue::Int(2)),
-                Instruction::Select("T".to_string(), true, 1, TypedValue::Int(2)),
+                Instruction::Select("T".to_string(), vec![(1, TypedValue::Int(2))]),
+                Instruction::Select("T".to_string(), vec![(1, TypedValue::Int(2))]),
                 Instruction::Join("T_1=2".to_string(), "T_1=2".to_string(), vec![(2, 0)]),
                 Instruction::Project(
                     "T".to_string(),
@@ -624,16 +1623,53 @@ mod test {
         assert_eq!(expected_stack, Stack::from(rule))
     }
 
+    #[test]
+    fn from_rule_with_wildcards_into_stack() {
+        let rule = rule! { R(?x, ?z) <- [T(?x, _, ?y), T(?y, _, ?z)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Move("T".to_string()),
+                Instruction::Move("T".to_string()),
+                Instruction::Join("T".to_string(), "T".to_string(), vec![(2, 0)]),
+                Instruction::Project(
+                    "R".to_string(),
+                    vec![ProjectionInput::Column(0), ProjectionInput::Column(5)],
+                ),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn from_rule_with_disconnected_neighbours_into_stack() {
+        let rule = rule! { R(?y) <- [D(?y), S(?x), E(?x, ?y)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Move("D".to_string()),
+                Instruction::Move("E".to_string()),
+                Instruction::Join("D".to_string(), "E".to_string(), vec![(0, 1)]),
+                Instruction::Move("S".to_string()),
+                Instruction::Join("D_E_0=1".to_string(), "S".to_string(), vec![(1, 0)]),
+                Instruction::Project("R".to_string(), vec![ProjectionInput::Column(0)]),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
     #[test]
     fn from_ternary_rule_into_operations() {
         let rule = rule! { T(?y, 0, ?w) <- [T(?x, 2, ?y), T(?y, 2, ?z), T(3, ?z, ?w)] };
 
         let expected_stack = Stack {
             inner: vec![
-                Instruction::Select("T".to_string(), true, 1, TypedValue::Int(2)),
-                Instruction::Select("T".to_string(), true, 1, TypedValue::Int(2)),
+                Instruction::Select("T".to_string(), vec![(1, TypedValue::Int(2))]),
+                Instruction::Select("T".to_string(), vec![(1, TypedValue::Int(2))]),
                 Instruction::Join("T_1=2".to_string(), "T_1=2".to_string(), vec![(2, 0)]),
-                Instruction::Select("T".to_string(), true, 0, TypedValue::Int(3)),
+                Instruction::Select("T".to_string(), vec![(0, TypedValue::Int(3))]),
                 Instruction::Join(
                     "T_1=2_T_1=2_2=0".to_string(),
                     "T_0=3".to_string(),
@@ -652,4 +1688,232 @@ mod test {
 
         assert_eq!(expected_stack, Stack::from(rule))
     }
+
+    #[test]
+    fn from_aggregate_rule_into_stack() {
+        let rule = rule! { C(?x, count(?y)) <- [T(?x, ?y)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Move("T".to_string()),
+                Instruction::Project(
+                    "C".to_string(),
+                    vec![ProjectionInput::Column(0), ProjectionInput::Column(1)],
+                ),
+                Instruction::Aggregate("C".to_string(), vec![None, Some(Aggregation::Count)]),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn from_rule_with_leading_negation_into_stack() {
+        let rule = rule! { Y(?x) <- [!E(?x), T(?x)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Move("T".to_string()),
+                Instruction::Move("E".to_string()),
+                Instruction::Antijoin("T".to_string(), "E".to_string(), vec![(0, 0)]),
+                Instruction::Project("Y".to_string(), vec![ProjectionInput::Column(0)]),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn from_rule_with_constant_in_single_atom_into_stack() {
+        let rule = rule! { Y(?x) <- [T(?x, 2)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Select("T".to_string(), vec![(1, TypedValue::Int(2))]),
+                Instruction::Project("Y".to_string(), vec![ProjectionInput::Column(0)]),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn from_rule_with_constants_in_every_atom_into_stack() {
+        let rule = rule! { Y(?x, ?y) <- [T(3, ?x, 5), U(?x, 4, ?y, 4)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Select(
+                    "T".to_string(),
+                    vec![(0, TypedValue::Int(3)), (2, TypedValue::Int(5))],
+                ),
+                Instruction::Select(
+                    "U".to_string(),
+                    vec![(1, TypedValue::Int(4)), (3, TypedValue::Int(4))],
+                ),
+                Instruction::Join(
+                    "T_0=3_2=5".to_string(),
+                    "U_1=4_3=4".to_string(),
+                    vec![(1, 0)],
+                ),
+                Instruction::Project(
+                    "Y".to_string(),
+                    vec![ProjectionInput::Column(1), ProjectionInput::Column(5)],
+                ),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn selection_holds_every_constant() {
+        let rule = rule! { Y(?x) <- [T(3usize, ?x, 5usize), !N(?x, 1usize, 2usize)] };
+        let stack = Stack::from(rule.clone());
+
+        let mut storage = RelationStorage::default();
+        [
+            (3usize, 1usize, 5usize),
+            (3, 2, 5),
+            (3, 3, 6),
+            (4, 4, 5),
+            (3, 5, 5),
+        ]
+        .into_iter()
+        .for_each(|(first, second, third)| {
+            storage.insert("T", vec![first.into(), second.into(), third.into()]);
+        });
+        storage.insert("N", vec![2usize.into(), 1usize.into(), 2usize.into()]);
+        storage.insert("N", vec![5usize.into(), 1usize.into(), 3usize.into()]);
+
+        let actual: HashSet<_> = RuleEvaluator::new(&storage, &rule, &stack)
+            .step(&mut IndexStorage::default())
+            .unwrap()
+            .collect();
+        let expected: HashSet<AnonymousGroundAtom> = vec![vec![1usize.into()], vec![5usize.into()]]
+            .into_iter()
+            .collect();
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn from_rule_with_constant_in_negated_atom_into_stack() {
+        let rule = rule! { Y(?x) <- [N(?x), !T(2, ?x)] };
+
+        let expected_stack = Stack {
+            inner: vec![
+                Instruction::Move("N".to_string()),
+                Instruction::Select("T".to_string(), vec![(0, TypedValue::Int(2))]),
+                Instruction::Antijoin("N".to_string(), "T_0=2".to_string(), vec![(0, 1)]),
+                Instruction::Project("Y".to_string(), vec![ProjectionInput::Column(0)]),
+            ],
+        };
+
+        assert_eq!(expected_stack, Stack::from(rule))
+    }
+
+    #[test]
+    fn rules_with_the_same_prefix_share_its_join() {
+        let first_rule = rule! { P(?x, ?z) <- [E(?x, ?y), E(?y, ?z)] };
+        let second_rule = rule! { Q(?z, ?x) <- [E(?x, ?y), E(?y, ?z)] };
+        let first_stack = Stack::from(first_rule.clone());
+        let second_stack = Stack::from(second_rule.clone());
+
+        let mut storage = RelationStorage::default();
+        storage.insert("E", vec![1usize.into(), 2usize.into()]);
+        storage.insert("E", vec![2usize.into(), 3usize.into()]);
+
+        let mut index_storage = IndexStorage::default();
+        let first: Vec<_> = RuleEvaluator::new(&storage, &first_rule, &first_stack)
+            .step(&mut index_storage)
+            .unwrap()
+            .collect();
+        assert_eq!(vec![vec![TypedValue::from(1usize), 3usize.into()]], first);
+
+        // The second rule reads the join that the first left behind, rather than the relation.
+        let empty_storage = {
+            let mut storage = RelationStorage::default();
+            storage.inner.insert("E".to_string(), Default::default());
+            storage
+        };
+        let second: Vec<_> = RuleEvaluator::new(&empty_storage, &second_rule, &second_stack)
+            .step(&mut index_storage)
+            .unwrap()
+            .collect();
+        assert_eq!(vec![vec![TypedValue::from(3usize), 1usize.into()]], second);
+    }
+
+    #[test]
+    fn reading_an_unknown_relation_fails() {
+        let rule = rule! { P(?x) <- [Q(?x)] };
+        let stack = Stack::from(rule.clone());
+
+        let error = RuleEvaluator::new(&RelationStorage::default(), &rule, &stack)
+            .step(&mut IndexStorage::default())
+            .err()
+            .unwrap();
+        assert_eq!("Q is not a relation of the program", error);
+    }
+
+    #[test]
+    fn antijoin_probes_the_stored_relation() {
+        let rule = rule! { P(?x) <- [Q(?x), !R(?x)] };
+        let stack = Stack::from(rule.clone());
+
+        let mut storage = RelationStorage::default();
+        vec![1usize, 2, 3].into_iter().for_each(|value| {
+            storage.insert("Q", vec![value.into()]);
+        });
+        storage.insert("R", vec![1usize.into()]);
+        storage.insert("R", vec![2usize.into()]);
+
+        // What the evaluation has read of the negated relation lags behind what is stored.
+        let mut index_storage = IndexStorage::default();
+        index_storage.borrow_all("R", vec![].into_iter()).unwrap();
+
+        let actual: HashSet<_> = RuleEvaluator::new(&storage, &rule, &stack)
+            .step(&mut index_storage)
+            .unwrap()
+            .collect();
+        let expected: HashSet<AnonymousGroundAtom> =
+            vec![vec![3usize.into()]].into_iter().collect();
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn tiny_sides_are_joined_by_nested_loops() {
+        let facts = |count: usize| -> Vec<EphemeralValue> {
+            (0..count)
+                .map(|value| {
+                    let fact: AnonymousGroundAtom = vec![(value % 4).into(), value.into()];
+                    EphemeralValue::FactRef(Arc::new(fact))
+                })
+                .collect()
+        };
+        let (small, large) = (facts(3), facts(40));
+        let join_keys = [(0, 0)];
+
+        let scanned = JoinRight::new(vec![0], small.len(), large.iter()).unwrap();
+        assert!(matches!(scanned, JoinRight::Scanned(_)));
+        let indexed = JoinRight::new(vec![0], large.len(), large.iter()).unwrap();
+        assert!(matches!(indexed, JoinRight::Indexed(_)));
+
+        let joined = |right: &JoinRight| {
+            let mut products: Vec<_> = do_join(&join_keys, &large, right)
+                .unwrap()
+                .into_iter()
+                .map(|allocation| match allocation {
+                    EphemeralValue::JoinResult(product) => product,
+                    EphemeralValue::FactRef(_) => unreachable!(),
+                })
+                .collect();
+            products.sort();
+
+            products
+        };
+        assert_eq!(400, joined(&indexed).len());
+        assert_eq!(joined(&indexed), joined(&scanned));
+    }
 }
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::engine::datalog::QueryBudget;
+use crate::evaluation::query::pattern_match;
+use datalog_syntax::{AnonymousGroundAtom, Atom, Matcher, Program, Query, Rule, Term, TypedValue};
+use indexmap::{IndexMap, IndexSet};
+
+// The values that the answers to a goal must have at each column, if any.
+type Pattern = Vec<Option<TypedValue>>;
+type Bindings = HashMap<String, TypedValue>;
+type Goal = (String, Pattern);
+type Base<'a> = Rc<dyn Fn(&str) -> Vec<Arc<AnonymousGroundAtom>> + 'a>;
+
+// Answers a single goal by only evaluating the rules it depends on, specialised to the constants
+// it is asked with. Every goal met on the way is tabled, and the table is grown until none of its
+// answers change, so that recursion terminates.
+pub(crate) struct TopDown<'a> {
+    rules: HashMap<&'a str, Vec<&'a Rule>>,
+    base: Base<'a>,
+    base_cache: HashMap<String, Vec<Arc<AnonymousGroundAtom>>>,
+    table: IndexMap<Goal, IndexSet<Arc<AnonymousGroundAtom>>>,
+    // How many goals deep every tabled goal was first met at, the query itself being none deep.
+    depths: Vec<usize>,
+    // What goals may be tabled within, how many besides the query were, along with those of the
+    // negations solved on the way, and whether the budget kept any from being tabled.
+    budget: QueryBudget,
+    subqueries: usize,
+    truncated: bool,
+    // The tabled goal to be evaluated next, and whether any goal changed since the table was last
+    // gone through from the start.
+    cursor: usize,
+    changed: bool,
+    complete: bool,
+}
+
+// The answers to a query, handed out as soon as they are derived, so that taking only a few of them
+// does not evaluate the goal to completion.
+pub(crate) struct Answers<'a> {
+    solver: TopDown<'a>,
+    goal: Goal,
+    yielded: usize,
+}
+
+impl Iterator for Answers<'_> {
+    type Item = Arc<AnonymousGroundAtom>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let answer = self.solver.answer(&self.goal, self.yielded)?;
+        self.yielded += 1;
+
+        Some(answer)
+    }
+}
+
+impl Answers<'_> {
+    // Whether the budget kept any goal from being evaluated so far, in which case some answers may
+    // be missing.
+    pub fn truncated(&self) -> bool {
+        self.solver.truncated
+    }
+}
+
+impl<'a> TopDown<'a> {
+    // The base facts of a relation are the ones that base hands out, and rules add to them.
+    pub fn new(
+        program: &'a Program,
+        base: impl Fn(&str) -> Vec<Arc<AnonymousGroundAtom>> + 'a,
+    ) -> Result<Self, String> {
+        let mut rules: HashMap<&str, Vec<&Rule>> = HashMap::new();
+        for rule in &program.inner {
+            if rule.is_aggregate() {
+                return Err(format!("{:?} can not be evaluated on demand", rule));
+            }
+            rules.entry(&rule.head.symbol).or_default().push(rule);
+        }
+
+        Ok(Self::with_base(rules, Rc::new(base), HashMap::new()))
+    }
+    fn with_base(
+        rules: HashMap<&'a str, Vec<&'a Rule>>,
+        base: Base<'a>,
+        base_cache: HashMap<String, Vec<Arc<AnonymousGroundAtom>>>,
+    ) -> Self {
+        Self {
+            rules,
+            base,
+            base_cache,
+            table: IndexMap::new(),
+            depths: vec![],
+            budget: QueryBudget::default(),
+            subqueries: 0,
+            truncated: false,
+            cursor: 0,
+            changed: false,
+            complete: true,
+        }
+    }
+    // Goals past the budget are taken to have no answers, so that what is answered still holds.
+    pub fn with_budget(mut self, budget: &QueryBudget) -> Self {
+        self.budget = budget.clone();
+
+        self
+    }
+    pub fn query(mut self, query: &Query) -> Answers<'a> {
+        let goal = self.goal(query);
+
+        Answers {
+            solver: self,
+            goal,
+            yielded: 0,
+        }
+    }
+    // Every answer to the query, keeping the goals tabled on the way for later queries to reuse.
+    pub fn answers(&mut self, query: &Query) -> Vec<Arc<AnonymousGroundAtom>> {
+        let goal = self.goal(query);
+
+        (0..).map_while(|idx| self.answer(&goal, idx)).collect()
+    }
+    // Whether the query has any answer, giving up on the rest as soon as one is found.
+    pub fn exists(&mut self, query: &Query) -> bool {
+        let goal = self.goal(query);
+
+        (0..)
+            .map_while(|idx| self.answer(&goal, idx))
+            .any(|answer| pattern_match(query, &answer))
+    }
+    fn goal(&mut self, query: &Query) -> Goal {
+        let arity = self.arity(query.symbol).unwrap_or(query.matchers.len());
+        let pattern = (0..arity)
+            .map(|column| match query.matchers.get(column) {
+                Some(Matcher::Constant(value)) => Some(value.clone()),
+                _ => None,
+            })
+            .collect();
+        let goal = (query.symbol.to_string(), pattern);
+        self.table_goal(&goal, 0);
+
+        goal
+    }
+    // Whether the goal is tabled, tabling it if the budget allows for one more at that depth.
+    fn table_goal(&mut self, goal: &Goal, depth: usize) -> bool {
+        if self.table.contains_key(goal) {
+            return true;
+        }
+        if depth > 0 {
+            let too_deep = self.budget.max_depth.is_some_and(|max| depth > max);
+            let too_many = self
+                .budget
+                .max_subqueries
+                .is_some_and(|max| self.subqueries >= max);
+            if too_deep || too_many {
+                self.truncated = true;
+                return false;
+            }
+            self.subqueries += 1;
+        }
+
+        self.table.insert(goal.clone(), IndexSet::new());
+        self.depths.push(depth);
+        self.complete = false;
+
+        true
+    }
+    fn arity(&self, relation_symbol: &str) -> Option<usize> {
+        self.rules
+            .get(relation_symbol)
+            .and_then(|rules| rules.first())
+            .map(|rule| rule.head.terms.len())
+    }
+    // The answer to the goal that comes after the given number of them, evaluating no further than
+    // it takes to find it.
+    fn answer(&mut self, goal: &Goal, idx: usize) -> Option<Arc<AnonymousGroundAtom>> {
+        loop {
+            if let Some(answer) = self.table[goal].get_index(idx) {
+                return Some(answer.clone());
+            }
+            if self.complete {
+                return None;
+            }
+            self.step();
+        }
+    }
+    // Evaluates the next tabled goal. Evaluating a goal may table new ones, which are then
+    // evaluated in the same round, and the table is complete once a round changes nothing.
+    fn step(&mut self) {
+        let (subgoal, answers) = self.table.get_index(self.cursor).unwrap();
+        let (subgoal, known) = (subgoal.clone(), answers.len());
+        let tabled = self.table.len();
+
+        let derived = self.evaluate(&subgoal, self.depths[self.cursor]);
+        let answers = &mut self.table[self.cursor];
+        derived.into_iter().for_each(|fact| {
+            answers.insert(fact);
+        });
+
+        self.changed |= answers.len() != known || self.table.len() != tabled;
+        self.cursor += 1;
+        if self.cursor == self.table.len() {
+            self.cursor = 0;
+            self.complete = !std::mem::take(&mut self.changed);
+        }
+    }
+    fn base_facts(&mut self, relation_symbol: &str) -> &[Arc<AnonymousGroundAtom>] {
+        let base = &self.base;
+
+        self.base_cache
+            .entry(relation_symbol.to_string())
+            .or_insert_with(|| base(relation_symbol))
+    }
+    // The answers to a goal that can be found with what the table currently holds.
+    fn evaluate(&mut self, goal: &Goal, depth: usize) -> Vec<Arc<AnonymousGroundAtom>> {
+        let (relation_symbol, pattern) = goal;
+        let mut answers: Vec<_> = self
+            .base_facts(relation_symbol)
+            .iter()
+            .filter(|fact| fits(pattern, fact))
+            .cloned()
+            .collect();
+
+        let rules = self.rules.get(relation_symbol.as_str()).cloned();
+        for rule in rules.into_iter().flatten() {
+            let Some(bindings) = unify(&rule.head.terms, pattern) else {
+                continue;
+            };
+
+            // Negated atoms go last, so that every variable of theirs is bound by then.
+            let mut body: Vec<_> = rule.body.iter().collect();
+            body.sort_by_key(|atom| !atom.sign);
+
+            let mut all_bindings = vec![bindings];
+            for atom in body {
+                all_bindings = all_bindings
+                    .into_iter()
+                    .flat_map(|bindings| self.extend(atom, bindings, depth + 1))
+                    .collect();
+            }
+
+            answers.extend(all_bindings.into_iter().filter_map(|bindings| {
+                rule.head
+                    .terms
+                    .iter()
+                    .map(|term| match term {
+                        Term::Variable(name) => bindings.get(name).cloned(),
+                        Term::Constant(value) => Some(value.clone()),
+                        Term::Expression(expression) => {
+                            expression.evaluate(&|name| bindings.get(name).cloned())
+                        }
+                        Term::Aggregate(_, _) => None,
+                    })
+                    .collect::<Option<AnonymousGroundAtom>>()
+                    // Expressions are not unified with the pattern, hence what they compute may
+                    // not fit it.
+                    .filter(|fact| fits(pattern, fact))
+                    .map(Arc::new)
+            }));
+        }
+
+        answers
+    }
+    // Every way of binding the atom's variables on top of the given bindings, where the atom is a
+    // goal of the given depth.
+    fn extend(&mut self, atom: &Atom, bindings: Bindings, depth: usize) -> Vec<Bindings> {
+        let pattern: Pattern = atom
+            .terms
+            .iter()
+            .map(|term| match term {
+                Term::Variable(name) => bindings.get(name).cloned(),
+                Term::Constant(value) => Some(value.clone()),
+                Term::Aggregate(_, _) | Term::Expression(_) => None,
+            })
+            .collect();
+
+        let facts: Vec<_> = if self.rules.contains_key(atom.symbol.as_str()) {
+            let goal = (atom.symbol.clone(), pattern.clone());
+            if !atom.sign {
+                // Negated relations are of a lower stratum, so they are solved apart from the goals
+                // that depend on them, up to their first answer. Whatever they are solved with
+                // counts against the budget, and a negation that was cut short does not hold.
+                let mut solver = TopDown::with_base(
+                    self.rules.clone(),
+                    self.base.clone(),
+                    std::mem::take(&mut self.base_cache),
+                )
+                .with_budget(&self.budget);
+                solver.subqueries = self.subqueries;
+                let found = solver.table_goal(&goal, depth) && solver.answer(&goal, 0).is_some();
+                self.base_cache = solver.base_cache;
+                self.subqueries = solver.subqueries;
+                self.truncated |= solver.truncated && !found;
+
+                return if found || solver.truncated {
+                    vec![]
+                } else {
+                    vec![bindings]
+                };
+            }
+
+            if !self.table_goal(&goal, depth) {
+                return vec![];
+            }
+            self.table[&goal].iter().cloned().collect()
+        } else {
+            self.base_facts(&atom.symbol)
+                .iter()
+                .filter(|fact| fits(&pattern, fact))
+                .cloned()
+                .collect()
+        };
+
+        if !atom.sign {
+            return if facts.is_empty() {
+                vec![bindings]
+            } else {
+                vec![]
+            };
+        }
+
+        facts
+            .iter()
+            .filter_map(|fact| bind(&atom.terms, fact, bindings.clone()))
+            .collect()
+    }
+}
+
+// The bindings extended with the values that the fact gives the terms, if it fits them.
+fn bind(terms: &[Term], fact: &AnonymousGroundAtom, mut bindings: Bindings) -> Option<Bindings> {
+    for (term, value) in terms.iter().zip(fact.iter()) {
+        match term {
+            Term::Variable(name) if name == "_" => {}
+            Term::Variable(name) => match bindings.get(name) {
+                Some(bound) if bound != value => return None,
+                Some(_) => {}
+                None => {
+                    bindings.insert(name.clone(), value.clone());
+                }
+            },
+            Term::Constant(constant) if constant != value => return None,
+            Term::Constant(_) => {}
+            Term::Aggregate(_, _) | Term::Expression(_) => return None,
+        }
+    }
+
+    Some(bindings)
+}
+
+fn fits(pattern: &Pattern, fact: &AnonymousGroundAtom) -> bool {
+    pattern.len() == fact.len()
+        && pattern
+            .iter()
+            .zip(fact.iter())
+            .all(|(expected, value)| expected.as_ref().is_none_or(|expected| expected == value))
+}
+
+// The bindings under which the head can have the constants of the pattern, if there are any.
+fn unify(head: &[Term], pattern: &Pattern) -> Option<Bindings> {
+    if head.len() != pattern.len() {
+        return None;
+    }
+
+    let mut bindings = Bindings::new();
+    for (term, expected) in head.iter().zip(pattern) {
+        let Some(expected) = expected else {
+            continue;
+        };
+        match term {
+            Term::Variable(name) => match bindings.get(name) {
+                Some(bound) if bound != expected => return None,
+                Some(_) => {}
+                None => {
+                    bindings.insert(name.clone(), expected.clone());
+                }
+            },
+            Term::Constant(constant) if constant != expected => return None,
+            _ => {}
+        }
+    }
+
+    Some(bindings)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::datalog::QueryBudget;
+    use crate::evaluation::top_down::TopDown;
+    use datalog_rule_macro::{program, stratified_program};
+    use datalog_syntax::*;
+    use std::sync::Arc;
+
+    fn edges(relation_symbol: &str) -> Vec<Arc<AnonymousGroundAtom>> {
+        if relation_symbol != "e" {
+            return vec![];
+        }
+
+        vec![(1usize, 2usize), (2, 3), (3, 4), (5, 6)]
+            .into_iter()
+            .map(|(from, to)| Arc::new(vec![from.into(), to.into()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_recursive_goal() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)]
+        };
+        let solver = TopDown::new(&program, edges).unwrap();
+
+        let mut actual: Vec<_> = solver
+            .query(&build_query!(tc(2usize, _)))
+            .map(|fact| (*fact).clone())
+            .collect();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![
+            vec![2usize.into(), 3usize.into()],
+            vec![2usize.into(), 4usize.into()],
+        ];
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_answers_are_streamed() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)]
+        };
+        let chain = |relation_symbol: &str| {
+            if relation_symbol != "e" {
+                return vec![];
+            }
+
+            (0..30usize)
+                .map(|node| Arc::new(vec![node.into(), (node + 1).into()]))
+                .collect()
+        };
+        let solver = TopDown::new(&program, chain).unwrap();
+
+        let mut answers = solver.query(&build_query!(tc(0usize, _)));
+        assert!(answers.next().is_some());
+        assert!(!answers.solver.complete);
+        assert!(answers.solver.table.len() < 30);
+
+        assert_eq!(29, answers.count());
+    }
+
+    #[test]
+    fn test_budgeted_goal() {
+        let program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            unreached(?x) <- [e(?x, ?y), !tc(1usize, ?x)]
+        };
+        let answers = |query: &Query, budget: QueryBudget| {
+            let mut answers = TopDown::new(&program, edges)
+                .unwrap()
+                .with_budget(&budget)
+                .query(query);
+            let mut facts: Vec<_> = answers.by_ref().map(|fact| (*fact).clone()).collect();
+            facts.sort();
+
+            (facts, answers.truncated())
+        };
+        let tc = |to: &[usize]| -> Vec<AnonymousGroundAtom> {
+            to.iter()
+                .map(|to| vec![1usize.into(), (*to).into()])
+                .collect()
+        };
+
+        let unlimited = answers(&build_query!(tc(1usize, _)), QueryBudget::default());
+        assert_eq!((tc(&[2, 3, 4]), false), unlimited);
+
+        // Going through tc(2, _) to tc(3, _) is one goal too deep.
+        let shallow = QueryBudget {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            (tc(&[2, 3]), true),
+            answers(&build_query!(tc(1usize, _)), shallow)
+        );
+
+        let narrow = QueryBudget {
+            max_subqueries: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            (tc(&[2]), true),
+            answers(&build_query!(tc(1usize, _)), narrow.clone())
+        );
+
+        // A negation that could not be solved within the budget does not hold.
+        assert_eq!((vec![], true), answers(&build_query!(unreached(_)), narrow));
+        assert_eq!(
+            (vec![vec![1usize.into()], vec![5usize.into()]], false),
+            answers(&build_query!(unreached(_)), QueryBudget::default())
+        );
+    }
+
+    #[test]
+    fn test_existence_of_goal() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [tc(?x, ?y), tc(?y, ?z)]
+        };
+        let mut solver = TopDown::new(&program, edges).unwrap();
+
+        assert!(solver.exists(&build_query!(tc(1usize, 4usize))));
+        assert!(!solver.exists(&build_query!(tc(4usize, _))));
+        assert!(!solver.exists(&build_query!(tc(1usize, 6usize))));
+    }
+
+    #[test]
+    fn test_negated_goal() {
+        let program = stratified_program! {
+            reach(?y) <- [e(1usize, ?y)],
+            reach(?z) <- [reach(?y), e(?y, ?z)],
+            unreached(?x) <- [e(?x, ?y), !reach(?x)]
+        };
+        let solver = TopDown::new(&program, edges).unwrap();
+
+        let mut actual: Vec<_> = solver
+            .query(&build_query!(unreached(_)))
+            .map(|fact| (*fact).clone())
+            .collect();
+        actual.sort();
+        let expected: Vec<AnonymousGroundAtom> = vec![vec![1usize.into()], vec![5usize.into()]];
+
+        assert_eq!(expected, actual);
+    }
+}
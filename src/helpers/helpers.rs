@@ -3,6 +3,12 @@ use datalog_syntax::Program;
 pub const OVERDELETION_PREFIX: &str = "delete_";
 pub const REDERIVATION_PREFIX: &str = "rederive_";
 
+// The relations that DRed keeps its overdeletions and rederivations in.
+pub fn is_internal(relation_symbol: &str) -> bool {
+    relation_symbol.starts_with(OVERDELETION_PREFIX)
+        || relation_symbol.starts_with(REDERIVATION_PREFIX)
+}
+
 pub fn add_prefix(symbol: &mut String, prefix: &str) {
     *symbol = format!("{}{}", prefix, symbol);
 }
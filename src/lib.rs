@@ -1,4 +1,11 @@
+#[cfg(feature = "bench")]
+pub mod bench;
 pub mod engine;
 mod evaluation;
 mod helpers;
 pub mod program_transformations;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "macros")]
+pub use datalog_rule_macro::{program, rule, semipositive_program, stratified_program, Relation};
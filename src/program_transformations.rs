@@ -1,2 +1,7 @@
+pub mod analysis;
+pub mod composition;
 pub mod dependency_graph;
 pub(crate) mod dred;
+pub(crate) mod specialization;
+pub mod transform;
+pub mod typecheck;
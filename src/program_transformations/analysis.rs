@@ -0,0 +1,173 @@
+use crate::program_transformations::dependency_graph::stratify_program;
+use datalog_syntax::Program;
+use petgraph::algo;
+use petgraph::graphmap::DiGraphMap;
+use std::collections::BTreeSet;
+
+// The head relation of some rule reads the body relation, through a negated atom if negated is
+// set.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dependency {
+    pub head: String,
+    pub body: String,
+    pub negated: bool,
+}
+
+// How the relations of a program depend on each other, as tooling would show it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramAnalysis {
+    // Every relation that the program mentions, in order of their names.
+    pub relations: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+    // The strongly connected components of the dependency graph, with every component after those
+    // that it reads from. Relations that depend on themselves share a component with nothing else.
+    pub components: Vec<Vec<String>>,
+    // The relations derived in every stratum, lowest first, unless negation is not stratified.
+    pub strata: Option<Vec<Vec<String>>>,
+}
+
+impl ProgramAnalysis {
+    pub fn new(program: &Program) -> Self {
+        let relations: BTreeSet<&str> = program
+            .inner
+            .iter()
+            .flat_map(|rule| {
+                std::iter::once(&rule.head)
+                    .chain(rule.body.iter())
+                    .map(|atom| atom.symbol.as_str())
+            })
+            .collect();
+        let dependencies: BTreeSet<Dependency> = program
+            .inner
+            .iter()
+            .flat_map(|rule| {
+                rule.body.iter().map(|body_atom| Dependency {
+                    head: rule.head.symbol.clone(),
+                    body: body_atom.symbol.clone(),
+                    negated: !body_atom.sign,
+                })
+            })
+            .collect();
+
+        let mut relation_graph: DiGraphMap<&str, ()> = DiGraphMap::new();
+        relations.iter().for_each(|relation_symbol| {
+            relation_graph.add_node(relation_symbol);
+        });
+        dependencies.iter().for_each(|dependency| {
+            relation_graph.add_edge(&dependency.body, &dependency.head, ());
+        });
+        // Components come out of kosaraju_scc in reverse topological order.
+        let components = algo::kosaraju_scc(&relation_graph)
+            .into_iter()
+            .rev()
+            .map(|component| {
+                let mut component: Vec<_> = component.into_iter().map(String::from).collect();
+                component.sort();
+                component
+            })
+            .collect();
+
+        let strata = stratify_program(program).map(|strata| {
+            strata
+                .iter()
+                .map(|stratum| {
+                    stratum
+                        .inner
+                        .iter()
+                        .map(|rule| rule.head.symbol.clone())
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect()
+                })
+                .collect()
+        });
+
+        Self {
+            relations: relations.into_iter().map(String::from).collect(),
+            dependencies: dependencies.into_iter().collect(),
+            components,
+            strata,
+        }
+    }
+    pub fn is_stratifiable(&self) -> bool {
+        self.strata.is_some()
+    }
+    // Whether the relation depends on itself, directly or through others.
+    pub fn is_recursive(&self, relation_symbol: &str) -> bool {
+        self.components.iter().any(|component| {
+            component.len() > 1 && component.iter().any(|relation| relation == relation_symbol)
+        }) || self.dependencies.iter().any(|dependency| {
+            dependency.head == relation_symbol && dependency.body == relation_symbol
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program_transformations::analysis::{Dependency, ProgramAnalysis};
+    use datalog_rule_macro::stratified_program;
+    use datalog_syntax::*;
+
+    #[test]
+    fn test_analysis() {
+        let program = stratified_program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+            even(?y) <- [odd(?x), succ(?x, ?y)],
+            odd(?y) <- [even(?x), succ(?x, ?y)],
+            unreachable(?x) <- [node(?x), !tc(1usize, ?x)]
+        };
+
+        let analysis = ProgramAnalysis::new(&program);
+
+        assert_eq!(
+            vec!["e", "even", "node", "odd", "succ", "tc", "unreachable"],
+            analysis.relations
+        );
+        assert!(analysis.dependencies.contains(&Dependency {
+            head: "unreachable".to_string(),
+            body: "tc".to_string(),
+            negated: true,
+        }));
+
+        let position = |relation_symbol: &str| {
+            analysis
+                .components
+                .iter()
+                .position(|component| component.iter().any(|relation| relation == relation_symbol))
+                .unwrap()
+        };
+        assert_eq!(position("even"), position("odd"));
+        assert!(position("e") < position("tc"));
+        assert!(position("tc") < position("unreachable"));
+
+        assert!(analysis.is_recursive("tc"));
+        assert!(analysis.is_recursive("even"));
+        assert!(!analysis.is_recursive("unreachable"));
+
+        assert_eq!(
+            Some(vec![
+                vec!["even".to_string(), "odd".to_string(), "tc".to_string()],
+                vec!["unreachable".to_string()],
+            ]),
+            analysis.strata
+        );
+    }
+
+    #[test]
+    fn test_unstratifiable_analysis() {
+        // Each half is stratifiable on its own, which the macro checks.
+        let first = stratified_program! {
+            p(?x) <- [r(?x), !q(?x)]
+        };
+        let second = stratified_program! {
+            q(?x) <- [r(?x), !p(?x)]
+        };
+        let program = Program::from([first.inner, second.inner].concat());
+
+        let analysis = ProgramAnalysis::new(&program);
+
+        assert!(!analysis.is_stratifiable());
+        assert!(analysis.is_recursive("p"));
+    }
+}
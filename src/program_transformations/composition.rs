@@ -0,0 +1,125 @@
+use crate::program_transformations::dependency_graph::stratify_program;
+use datalog_syntax::{Program, Rule};
+use std::collections::HashMap;
+
+// Puts a program together out of modules, every one of which is the only one deriving the
+// relations in its rules' heads. Modules may read each other's relations freely.
+#[derive(Default)]
+pub struct ProgramBuilder {
+    rules: Vec<Rule>,
+    owners: HashMap<String, String>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn module(mut self, name: &str, program: Program) -> Result<Self, String> {
+        for rule in &program.inner {
+            if let Some(owner) = self.owners.get(&rule.head.symbol) {
+                if owner != name {
+                    return Err(format!(
+                        "{} is derived by both {} and {}",
+                        rule.head.symbol, owner, name
+                    ));
+                }
+            }
+        }
+
+        program.inner.iter().for_each(|rule| {
+            self.owners
+                .insert(rule.head.symbol.clone(), name.to_string());
+        });
+        self.rules.extend(program.inner);
+
+        Ok(self)
+    }
+    // Modules that are stratifiable on their own may stop being so once merged, by negating
+    // each other's relations in a cycle.
+    pub fn build(self) -> Result<Program, String> {
+        let program = Program::from(self.rules);
+
+        let incremental_rules: Vec<_> = program
+            .inner
+            .iter()
+            .filter(|rule| !rule.is_aggregate())
+            .cloned()
+            .collect();
+        if stratify_program(&Program::from(incremental_rules)).is_none() {
+            return Err("negation must be stratified".to_string());
+        }
+
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::program_transformations::composition::ProgramBuilder;
+    use datalog_rule_macro::*;
+    use datalog_syntax::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_compose_modules() {
+        let ontology = program! {
+            person(?x) <- [student(?x)],
+        };
+        let tenant = stratified_program! {
+            contact(?x) <- [person(?x), !blocked(?x)]
+        };
+
+        let expected_program = stratified_program! {
+            person(?x) <- [student(?x)],
+            contact(?x) <- [person(?x), !blocked(?x)]
+        };
+        let actual_program = ProgramBuilder::new()
+            .module("ontology", ontology)
+            .unwrap()
+            .module("tenant", tenant)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(expected_program, actual_program)
+    }
+
+    #[test]
+    fn test_compose_conflicting_modules() {
+        let ontology = program! {
+            person(?x) <- [student(?x)],
+        };
+        let tenant = program! {
+            person(?x) <- [employee(?x)],
+        };
+
+        let error = ProgramBuilder::new()
+            .module("ontology", ontology)
+            .unwrap()
+            .module("tenant", tenant)
+            .err()
+            .unwrap();
+
+        assert_eq!("person is derived by both ontology and tenant", error)
+    }
+
+    #[test]
+    fn test_compose_unstratifiable_modules() {
+        let first = stratified_program! {
+            p(?x) <- [r(?x), !q(?x)]
+        };
+        let second = stratified_program! {
+            q(?x) <- [r(?x), !p(?x)]
+        };
+
+        let error = ProgramBuilder::new()
+            .module("first", first)
+            .unwrap()
+            .module("second", second)
+            .unwrap()
+            .build()
+            .unwrap_err();
+
+        assert_eq!("negation must be stratified", error)
+    }
+}
@@ -5,27 +5,30 @@ use std::collections::HashMap;
 
 type RuleGraph<'a> = GraphMap<&'a Rule, bool, Directed>;
 
-pub fn generate_rule_dependency_graph<'a>(program: &Vec<Rule>) -> RuleGraph {
+pub fn generate_rule_dependency_graph(program: &[Rule]) -> RuleGraph<'_> {
     let mut output = DiGraphMap::new();
-    let mut idb_relations = HashMap::new();
+    let mut idb_relations: HashMap<&str, Vec<&Rule>> = HashMap::new();
     for rule in program {
-        idb_relations.insert(rule.clone().head.symbol, rule);
+        idb_relations
+            .entry(rule.head.symbol.as_str())
+            .or_default()
+            .push(rule);
         output.add_node(rule);
     }
     for rule in program {
         for body_atom in &rule.body {
-            if let Some(body_atom_rule) = idb_relations.get(&body_atom.symbol) {
-                output.add_edge(body_atom_rule, rule, true);
+            if let Some(body_atom_rules) = idb_relations.get(body_atom.symbol.as_str()) {
+                for body_atom_rule in body_atom_rules {
+                    output.add_edge(body_atom_rule, rule, true);
+                }
             }
         }
     }
-    return output;
+    output
 }
 
 pub fn stratify<'a>(rule_graph: &'a RuleGraph) -> Vec<Vec<&'a Rule>> {
-    let sccs = algo::kosaraju_scc(&rule_graph);
-
-    return sccs;
+    algo::kosaraju_scc(&rule_graph)
 }
 
 pub fn sort_program(program: &Program) -> Program {
@@ -33,11 +36,98 @@ pub fn sort_program(program: &Program) -> Program {
     let stratification = stratify(&rule_graph)
         .into_iter()
         .rev()
-        .flat_map(|program| program)
+        .flatten()
         .cloned()
         .collect();
 
-    return Program {
+    Program {
         inner: stratification,
-    };
+    }
+}
+
+// The rules grouped by the strongly connected components of the relations that they derive, with
+// every component after those that it reads from. The rules of a component that read none of its
+// relations are its nonrecursive ones, and the rest have to be evaluated to a fixpoint.
+pub fn split_components(program: &Program) -> Vec<(Program, Program)> {
+    let mut relation_graph: DiGraphMap<&str, ()> = DiGraphMap::new();
+    program.inner.iter().for_each(|rule| {
+        relation_graph.add_node(rule.head.symbol.as_str());
+    });
+    for rule in &program.inner {
+        for body_atom in &rule.body {
+            if relation_graph.contains_node(body_atom.symbol.as_str()) {
+                relation_graph.add_edge(body_atom.symbol.as_str(), rule.head.symbol.as_str(), ());
+            }
+        }
+    }
+
+    // Components come out of kosaraju_scc in reverse topological order.
+    algo::kosaraju_scc(&relation_graph)
+        .into_iter()
+        .rev()
+        .map(|component| {
+            let (recursive_rules, nonrecursive_rules): (Vec<_>, Vec<_>) = program
+                .inner
+                .iter()
+                .filter(|rule| component.contains(&rule.head.symbol.as_str()))
+                .cloned()
+                .partition(|rule| {
+                    rule.body
+                        .iter()
+                        .any(|body_atom| component.contains(&body_atom.symbol.as_str()))
+                });
+
+            (
+                Program::from(nonrecursive_rules),
+                Program::from(recursive_rules),
+            )
+        })
+        .collect()
+}
+
+// Every relation sits in the lowest stratum that is not below anything it depends on, and above
+// everything it depends on negatively. A negative cycle keeps pushing its relations upwards, past
+// the highest stratum a stratifiable program could need.
+pub fn stratify_program(program: &Program) -> Option<Vec<Program>> {
+    let mut strata: HashMap<&str, usize> = program
+        .inner
+        .iter()
+        .map(|rule| (rule.head.symbol.as_str(), 0))
+        .collect();
+    let highest_stratum = strata.len();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for rule in &program.inner {
+            for body_atom in &rule.body {
+                let lowest_stratum = strata.get(body_atom.symbol.as_str()).copied().unwrap_or(0)
+                    + !body_atom.sign as usize;
+                let head_stratum = strata.get_mut(rule.head.symbol.as_str()).unwrap();
+
+                if lowest_stratum > *head_stratum {
+                    if lowest_stratum > highest_stratum {
+                        return None;
+                    }
+
+                    *head_stratum = lowest_stratum;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut stratified_rules = vec![vec![]; highest_stratum + 1];
+    program.inner.iter().for_each(|rule| {
+        stratified_rules[strata[rule.head.symbol.as_str()]].push(rule.clone());
+    });
+
+    Some(
+        stratified_rules
+            .into_iter()
+            .filter(|rules| !rules.is_empty())
+            .map(Program::from)
+            .collect(),
+    )
 }
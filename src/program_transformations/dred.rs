@@ -1,15 +1,21 @@
 use crate::helpers::helpers::{add_prefix, OVERDELETION_PREFIX, REDERIVATION_PREFIX};
-use datalog_syntax::{Program, Rule};
-use std::collections::HashSet;
+use datalog_syntax::{Program, Rule, Term};
+use indexmap::IndexSet;
 
 pub fn make_overdeletion_program(program: &Program) -> Program {
-    let mut overdeletion_rules_set = HashSet::new();
+    let mut overdeletion_rules_set = IndexSet::new();
 
     for rule in &program.inner {
         let mut overdeletion_rule = rule.clone();
         add_prefix(&mut overdeletion_rule.head.symbol, OVERDELETION_PREFIX);
 
-        for (index, _) in rule.body.iter().enumerate() {
+        // Deleting facts of a negated relation can only lead to more facts, not fewer.
+        for (index, _) in rule
+            .body
+            .iter()
+            .enumerate()
+            .filter(|(_, body_atom)| body_atom.sign)
+        {
             let mut new_rule = overdeletion_rule.clone();
             add_prefix(&mut new_rule.body[index].symbol, OVERDELETION_PREFIX);
             overdeletion_rules_set.insert(new_rule);
@@ -22,13 +28,19 @@ pub fn make_overdeletion_program(program: &Program) -> Program {
 }
 
 pub fn make_rederivation_program(program: &Program) -> Program {
-    let mut rederivation_rules_set = HashSet::new();
+    let mut rederivation_rules_set = IndexSet::new();
 
     for rule in &program.inner {
         let mut rederivaton_rule = rule.clone();
 
         let mut rederivation_head = rederivaton_rule.head.clone();
         add_prefix(&mut rederivation_head.symbol, OVERDELETION_PREFIX);
+        // Computed columns can not be matched against, and the rule recomputes them anyway.
+        for term in &mut rederivation_head.terms {
+            if matches!(term, Term::Expression(_)) {
+                *term = Term::Variable("_".to_string());
+            }
+        }
         rederivaton_rule.body.insert(0, rederivation_head);
 
         add_prefix(&mut rederivaton_rule.head.symbol, REDERIVATION_PREFIX);
@@ -81,4 +93,18 @@ mod test {
 
         assert_eq!(expected_program, actual_program)
     }
+
+    #[test]
+    fn test_make_overdeletion_program_with_negation() {
+        let program = stratified_program! {
+            unreached(?x) <- [node(?x), !reach(?x)]
+        };
+
+        let expected_program = stratified_program! {
+            delete_unreached(?x) <- [delete_node(?x), !reach(?x)]
+        };
+        let actual_program = make_overdeletion_program(&program);
+
+        assert_eq!(expected_program, actual_program)
+    }
 }
@@ -0,0 +1,237 @@
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use datalog_syntax::{AnonymousGroundAtom, Atom, Matcher, Program, Query, Rule, Term, TypedValue};
+
+const SPECIALIZATION_PREFIX: &str = "specialized_";
+
+// The constants that a relation is used with, column by column.
+pub type Binding = Vec<Option<TypedValue>>;
+
+pub struct Specialization {
+    pub program: Program,
+    // The relation that holds the answers to the query.
+    pub answers: String,
+    // The relation and binding that every derived relation of the program was specialized from.
+    pub origins: HashMap<String, (String, Binding)>,
+}
+
+pub fn is_bound_by(binding: &Binding, fact: &AnonymousGroundAtom) -> bool {
+    binding
+        .iter()
+        .zip(fact)
+        .all(|(constant, value)| constant.as_ref().is_none_or(|constant| constant == value))
+}
+
+// Substitutes the constants of the query into the rules that it depends on, down to the base
+// relations, so that evaluating them bottom-up selects by those constants before joining rather
+// than deriving the whole relation. A relation used with different constants is specialized once
+// for each of them. There is nothing to specialize when the query binds nothing, and rules that
+// recurse, negate, aggregate, compute head terms or have existential variables are left to other
+// strategies.
+pub fn specialize_program(program: &Program, query: &Query) -> Option<Specialization> {
+    let binding: Binding = query
+        .matchers
+        .iter()
+        .map(|matcher| match matcher {
+            Matcher::Constant(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    let is_derived = program
+        .inner
+        .iter()
+        .any(|rule| rule.head.symbol == query.symbol);
+    if binding.iter().all(Option::is_none) || !is_derived {
+        return None;
+    }
+
+    let mut specializer = Specializer {
+        program,
+        origins: HashMap::new(),
+        names: HashMap::new(),
+        visiting: HashSet::new(),
+        rules: vec![],
+    };
+    let answers = specializer.specialize(query.symbol, binding)?;
+
+    Some(Specialization {
+        program: Program::from(specializer.rules),
+        answers,
+        origins: specializer.origins,
+    })
+}
+
+struct Specializer<'a> {
+    program: &'a Program,
+    origins: HashMap<String, (String, Binding)>,
+    names: HashMap<(String, Binding), String>,
+    // The relations whose rules are being specialized, which reaching again means recursion.
+    visiting: HashSet<String>,
+    rules: Vec<Rule>,
+}
+
+impl Specializer<'_> {
+    // Returns the relation that holds the facts of the given one that have the given constants.
+    fn specialize(&mut self, symbol: &str, binding: Binding) -> Option<String> {
+        let rules: Vec<&Rule> = self
+            .program
+            .inner
+            .iter()
+            .filter(|rule| rule.head.symbol == symbol)
+            .collect();
+        if rules.is_empty() {
+            return Some(symbol.to_string());
+        }
+        if self.visiting.contains(symbol) {
+            return None;
+        }
+        let key = (symbol.to_string(), binding);
+        if let Some(name) = self.names.get(&key) {
+            return Some(name.clone());
+        }
+
+        let name = if key.1.iter().all(Option::is_none) {
+            symbol.to_string()
+        } else {
+            format!("{}{}_{}", SPECIALIZATION_PREFIX, symbol, self.names.len())
+        };
+        self.names.insert(key.clone(), name.clone());
+        self.origins.insert(name.clone(), key.clone());
+        self.visiting.insert(symbol.to_string());
+
+        for rule in rules {
+            let is_unsupported = rule.is_aggregate()
+                || rule.body.iter().any(|body_atom| !body_atom.sign)
+                || rule
+                    .head
+                    .terms
+                    .iter()
+                    .any(|term| matches!(term, Term::Expression(_)))
+                || !is_range_restricted(rule);
+            if is_unsupported {
+                return None;
+            }
+            let Some(substitution) = unify(&rule.head, &key.1) else {
+                continue;
+            };
+
+            let mut body = vec![];
+            for body_atom in &rule.body {
+                let terms = substitute(&body_atom.terms, &substitution);
+                let binding = terms
+                    .iter()
+                    .map(|term| match term {
+                        Term::Constant(value) => Some(value.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                body.push(Atom {
+                    symbol: self.specialize(&body_atom.symbol, binding)?,
+                    terms,
+                    sign: true,
+                });
+            }
+            let head = Atom {
+                terms: substitute(&rule.head.terms, &substitution),
+                symbol: name.clone(),
+                sign: true,
+            };
+
+            self.rules.push(Rule {
+                head,
+                body,
+                id: 0,
+                hints: rule.hints.clone(),
+            });
+        }
+        self.visiting.remove(symbol);
+
+        Some(name)
+    }
+}
+
+fn is_range_restricted(rule: &Rule) -> bool {
+    rule.head.terms.iter().all(|term| match term {
+        Term::Variable(name) => rule
+            .body
+            .iter()
+            .any(|body_atom| body_atom.terms.contains(&Term::Variable(name.clone()))),
+        _ => true,
+    })
+}
+
+// The constants that the head variables take for the head to have the given ones, if it can.
+fn unify(head: &Atom, binding: &Binding) -> Option<HashMap<String, TypedValue>> {
+    let mut substitution = HashMap::new();
+    for (term, constant) in head.terms.iter().zip(binding) {
+        let Some(constant) = constant else {
+            continue;
+        };
+
+        match term {
+            Term::Constant(value) if value != constant => return None,
+            Term::Variable(name)
+                if substitution
+                    .insert(name.clone(), constant.clone())
+                    .is_some_and(|previous| previous != *constant) =>
+            {
+                return None;
+            }
+            _ => {}
+        }
+    }
+
+    Some(substitution)
+}
+
+fn substitute(terms: &[Term], substitution: &HashMap<String, TypedValue>) -> Vec<Term> {
+    terms
+        .iter()
+        .map(|term| match term {
+            Term::Variable(name) => substitution
+                .get(name)
+                .map_or(term.clone(), |value| Term::Constant(value.clone())),
+            _ => term.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program_transformations::specialization::specialize_program;
+    use datalog_rule_macro::{program, rule};
+    use datalog_syntax::*;
+
+    #[test]
+    fn test_specialize_program() {
+        let program = program! {
+            path(?x, ?z) <- [e(?x, ?y), e(?y, ?z)],
+            labelled(?x, ?l) <- [path(?x, ?y), label(?y, ?l)],
+            labelled(?x, ?l) <- [name(?x, ?l)],
+            named(?l) <- [labelled(1usize, ?l)],
+        };
+        let query = build_query!(labelled(1usize, _));
+
+        let specialization = specialize_program(&program, &query).unwrap();
+
+        let expected = Program::from(vec![
+            rule! { specialized_path_1(1usize, ?z) <- [e(1usize, ?y), e(?y, ?z)] },
+            rule! { specialized_labelled_0(1usize, ?l) <- [specialized_path_1(1usize, ?y), label(?y, ?l)] },
+            rule! { specialized_labelled_0(1usize, ?l) <- [name(1usize, ?l)] },
+        ]);
+        assert_eq!(expected, specialization.program);
+        assert_eq!("specialized_labelled_0", specialization.answers);
+        assert_eq!(
+            Some(&("path".to_string(), vec![Some(1usize.into()), None])),
+            specialization.origins.get("specialized_path_1")
+        );
+
+        // Queries that bind nothing, or that depend on recursion, are not specialized.
+        assert!(specialize_program(&program, &build_query!(labelled(_, _))).is_none());
+        let tc_program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+        assert!(specialize_program(&tc_program, &build_query!(tc(1usize, _))).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::program_transformations::dependency_graph::sort_program;
+use crate::program_transformations::dred::{make_overdeletion_program, make_rederivation_program};
+use crate::program_transformations::specialization::specialize_program;
+use datalog_syntax::{Program, Query};
+
+// Rewrites a program into another one. Any function from programs to programs is one, so that
+// rewrites of one's own can be put in a pipeline along with those that the runtime uses.
+pub trait Transformation {
+    fn apply(&self, program: Program) -> Result<Program, String>;
+}
+
+impl<F: Fn(Program) -> Result<Program, String>> Transformation for F {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        self(program)
+    }
+}
+
+// The rules that derive the facts that deleting facts may take away, into relations of their own.
+pub struct Overdeletion;
+
+impl Transformation for Overdeletion {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        Ok(make_overdeletion_program(&program))
+    }
+}
+
+// The rules that derive the overdeleted facts that still hold, into relations of their own.
+pub struct Rederivation;
+
+impl Transformation for Rederivation {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        Ok(make_rederivation_program(&program))
+    }
+}
+
+// The rules ordered so that every one comes after those whose relations it reads, recursion aside.
+pub struct Sort;
+
+impl Transformation for Sort {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        Ok(sort_program(&program))
+    }
+}
+
+// The rules that the query depends on with its constants substituted into them, deriving its
+// answers into a relation of their own, as bound queries are answered on demand.
+pub struct Specialize<'a>(pub &'a Query<'a>);
+
+impl Transformation for Specialize<'_> {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        specialize_program(&program, self.0)
+            .map(|specialization| specialization.program)
+            .ok_or_else(|| format!("{} can not be specialized to the query", self.0.symbol))
+    }
+}
+
+// Transformations applied one after the other, each to what the one before it yields.
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    transformations: Vec<Box<dyn Transformation + 'a>>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn then(mut self, transformation: impl Transformation + 'a) -> Self {
+        self.transformations.push(Box::new(transformation));
+        self
+    }
+    pub fn overdeletion(self) -> Self {
+        self.then(Overdeletion)
+    }
+    pub fn rederivation(self) -> Self {
+        self.then(Rederivation)
+    }
+    pub fn sort(self) -> Self {
+        self.then(Sort)
+    }
+    pub fn specialize(self, query: &'a Query<'a>) -> Self {
+        self.then(Specialize(query))
+    }
+}
+
+impl Transformation for Pipeline<'_> {
+    fn apply(&self, program: Program) -> Result<Program, String> {
+        self.transformations
+            .iter()
+            .try_fold(program, |program, transformation| {
+                transformation.apply(program)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::program_transformations::dred::{
+        make_overdeletion_program, make_rederivation_program,
+    };
+    use crate::program_transformations::transform::{Pipeline, Transformation};
+    use datalog_rule_macro::*;
+    use datalog_syntax::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_pipeline() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            tc(?x, ?z) <- [e(?x, ?y), tc(?y, ?z)],
+        };
+
+        let expected = make_rederivation_program(&make_overdeletion_program(&program));
+        let actual = Pipeline::new()
+            .overdeletion()
+            .rederivation()
+            .apply(program.clone())
+            .unwrap();
+        assert_eq!(expected, actual);
+
+        // Rewrites of one's own go along with the rest.
+        let without_recursion = |program: Program| {
+            Ok(Program::from(
+                program
+                    .inner
+                    .into_iter()
+                    .filter(|rule| rule.body.len() == 1)
+                    .collect::<Vec<_>>(),
+            ))
+        };
+        let query = build_query!(tc(1usize, _));
+        let expected = program! {
+            specialized_tc_0(1usize, ?y) <- [e(1usize, ?y)],
+        };
+        let actual = Pipeline::new()
+            .then(without_recursion)
+            .specialize(&query)
+            .apply(program.clone())
+            .unwrap();
+        assert_eq!(expected, actual);
+
+        let error = Pipeline::new()
+            .specialize(&query)
+            .apply(program)
+            .unwrap_err();
+        assert_eq!("tc can not be specialized to the query", error);
+    }
+}
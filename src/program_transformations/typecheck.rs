@@ -0,0 +1,248 @@
+use crate::engine::delimited::ColumnType;
+use ahash::{HashMap, HashMapExt};
+use datalog_syntax::*;
+
+// The type of every column of every relation, as far as the constants of a program tell. Columns
+// that a rule binds to the same variable are of the same type, since a join between columns of
+// different types would never match anything. Columns that nothing ties to a constant may hold
+// values of any type, and of several at once.
+#[derive(Clone, Debug, Default)]
+pub struct TypeSignature {
+    // The columns of the same type make up a class, and every class is a tree of those that were
+    // merged into it.
+    classes: HashMap<(String, usize), usize>,
+    parents: Vec<usize>,
+    types: Vec<Option<ColumnType>>,
+}
+
+impl TypeSignature {
+    pub fn infer(program: &Program) -> Result<Self, String> {
+        let mut signature = Self::default();
+
+        for rule in &program.inner {
+            let mut variables: HashMap<&str, usize> = HashMap::new();
+            let atoms = rule
+                .body
+                .iter()
+                .chain(std::iter::once(&rule.head))
+                .flat_map(|atom| {
+                    atom.terms
+                        .iter()
+                        .enumerate()
+                        .map(move |(column, term)| (atom.symbol.as_str(), column, term))
+                });
+
+            for (relation_symbol, column, term) in atoms {
+                let class = signature.class(relation_symbol, column);
+                let variable = match term {
+                    Term::Constant(value) => {
+                        signature.assign(class, ColumnType::of(value), relation_symbol, column)?;
+                        continue;
+                    }
+                    Term::Variable(variable) if variable == "_" => continue,
+                    Term::Aggregate(Aggregation::Count, _) => {
+                        signature.assign(class, ColumnType::Int, relation_symbol, column)?;
+                        continue;
+                    }
+                    // Operands are of the type of what is computed from them.
+                    Term::Expression(expression) => {
+                        for value in constants(expression) {
+                            signature.assign(
+                                class,
+                                ColumnType::of(value),
+                                relation_symbol,
+                                column,
+                            )?;
+                        }
+                        for variable in expression.variables() {
+                            match variables.get(variable.as_str()) {
+                                Some(bound_class) => {
+                                    signature.unify(*bound_class, class, relation_symbol, column)?
+                                }
+                                None => {
+                                    variables.insert(variable, class);
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    Term::Variable(variable) | Term::Aggregate(_, variable) => variable,
+                };
+
+                match variables.get(variable.as_str()) {
+                    Some(bound_class) => {
+                        signature.unify(*bound_class, class, relation_symbol, column)?
+                    }
+                    None => {
+                        variables.insert(variable, class);
+                    }
+                }
+            }
+        }
+
+        Ok(signature)
+    }
+    pub fn column_type(&self, relation_symbol: &str, column: usize) -> Option<ColumnType> {
+        let class = self.classes.get(&(relation_symbol.to_string(), column))?;
+
+        self.types[self.find(*class)]
+    }
+    // Fails if a value of the fact is not of the type of its column, which the program would never
+    // match.
+    pub fn check(
+        &self,
+        relation_symbol: &str,
+        ground_atom: &AnonymousGroundAtom,
+    ) -> Result<(), String> {
+        let conflict = ground_atom.iter().enumerate().find_map(|(column, value)| {
+            let column_type = self.column_type(relation_symbol, column)?;
+
+            (column_type != ColumnType::of(value)).then(|| {
+                format!(
+                    "column {} of {} holds {:?} values, but {:?} was given",
+                    column, relation_symbol, column_type, value
+                )
+            })
+        });
+
+        conflict.map_or(Ok(()), Err)
+    }
+    fn class(&mut self, relation_symbol: &str, column: usize) -> usize {
+        let next_class = self.parents.len();
+        let class = *self
+            .classes
+            .entry((relation_symbol.to_string(), column))
+            .or_insert(next_class);
+        if class == next_class {
+            self.parents.push(class);
+            self.types.push(None);
+        }
+
+        class
+    }
+    fn find(&self, mut class: usize) -> usize {
+        while self.parents[class] != class {
+            class = self.parents[class];
+        }
+
+        class
+    }
+    fn assign(
+        &mut self,
+        class: usize,
+        column_type: ColumnType,
+        relation_symbol: &str,
+        column: usize,
+    ) -> Result<(), String> {
+        let root = self.find(class);
+        match self.types[root] {
+            Some(known_type) if known_type != column_type => Err(format!(
+                "column {} of {} is used as both {:?} and {:?}",
+                column, relation_symbol, known_type, column_type
+            )),
+            _ => {
+                self.types[root] = Some(column_type);
+
+                Ok(())
+            }
+        }
+    }
+    fn unify(
+        &mut self,
+        left: usize,
+        right: usize,
+        relation_symbol: &str,
+        column: usize,
+    ) -> Result<(), String> {
+        let (left, right) = (self.find(left), self.find(right));
+        if left == right {
+            return Ok(());
+        }
+
+        if let Some(right_type) = self.types[right] {
+            self.assign(left, right_type, relation_symbol, column)?;
+        }
+        self.parents[right] = left;
+
+        Ok(())
+    }
+}
+
+fn constants(expression: &Expression) -> Vec<&TypedValue> {
+    match expression {
+        Expression::Variable(_) => vec![],
+        Expression::Constant(value) => vec![value],
+        Expression::Binary(_, left, right) => {
+            let mut values = constants(left);
+            values.extend(constants(right));
+            values
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::delimited::ColumnType;
+    use crate::program_transformations::typecheck::TypeSignature;
+    use datalog_rule_macro::program;
+    use datalog_syntax::*;
+
+    #[test]
+    fn test_types_flow_through_variables() {
+        let program = program! {
+            reach(?x, ?y) <- [e(?x, ?y)],
+            reach(?x, ?z) <- [reach(?x, ?y), e(?y, ?z)],
+            from_a(?y) <- [reach("a", ?y)],
+            degree(?x, count(?y)) <- [e(?x, ?y)],
+        };
+
+        let signature = TypeSignature::infer(&program).unwrap();
+
+        for (relation, column) in [
+            ("e", 0),
+            ("e", 1),
+            ("reach", 1),
+            ("from_a", 0),
+            ("degree", 0),
+        ] {
+            assert_eq!(
+                Some(ColumnType::Str),
+                signature.column_type(relation, column),
+                "column {} of {}",
+                column,
+                relation
+            );
+        }
+        assert_eq!(Some(ColumnType::Int), signature.column_type("degree", 1));
+        assert_eq!(None, signature.column_type("missing", 0));
+    }
+
+    #[test]
+    fn test_conflicting_types() {
+        let program = program! {
+            named(?x) <- [e(?x, "a")],
+            numbered(?x) <- [e(?x, 1usize)],
+        };
+
+        let error = TypeSignature::infer(&program).unwrap_err();
+        assert!(error.contains("used as both"), "{}", error);
+    }
+
+    #[test]
+    fn test_facts_are_checked_against_typed_columns() {
+        let program = program! {
+            tc(?x, ?y) <- [e(?x, ?y)],
+            from_a(?y) <- [tc("a", ?y)],
+        };
+        let signature = TypeSignature::infer(&program).unwrap();
+        assert_eq!(None, signature.column_type("tc", 1));
+
+        assert!(signature
+            .check("e", &vec!["b".into(), 1usize.into()])
+            .is_ok());
+        assert!(signature.check("e", &vec!["b".into(), "c".into()]).is_ok());
+        assert!(signature
+            .check("e", &vec![1usize.into(), 2usize.into()])
+            .is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// Helpers for testing rule sets: comparing the facts of relations with those they should have,
+// either given inline or kept in golden files. The datasets and programs of the benchmarks double
+// as fixtures.
+use crate::engine::datalog::MicroRuntime;
+use crate::engine::delimited::write_delimited;
+use datalog_syntax::*;
+use std::path::Path;
+use std::sync::Arc;
+
+pub use crate::bench::*;
+
+// Set to have golden files written with the facts that relations have, rather than compared with
+// them.
+pub const UPDATE_GOLDEN: &str = "MICRO_DATALOG_UPDATE_GOLDEN";
+
+// The facts of a relation, sorted so that they can be compared regardless of the order they were
+// derived in.
+pub fn relation_facts(
+    runtime: &MicroRuntime,
+    relation: &str,
+) -> Result<Vec<AnonymousGroundAtom>, String> {
+    let Some(arity) = runtime.arity(relation) else {
+        return Ok(vec![]);
+    };
+    let query = Query {
+        matchers: (0..arity).map(|_| Matcher::Any).collect(),
+        symbol: relation,
+        since: None,
+        source: None,
+    };
+
+    let mut facts: Vec<_> = runtime.query(&query)?.collect();
+    facts.sort();
+
+    Ok(facts)
+}
+
+#[track_caller]
+pub fn assert_relation_eq<T: IntoFact>(
+    runtime: &MicroRuntime,
+    relation: &str,
+    expected: impl IntoIterator<Item = T>,
+) {
+    let actual = relation_facts(runtime, relation).unwrap_or_else(|error| panic!("{}", error));
+    let mut expected: Vec<_> = expected.into_iter().map(IntoFact::into_fact).collect();
+    expected.sort();
+    expected.dedup();
+
+    if actual != expected {
+        let missing: Vec<_> = expected
+            .iter()
+            .filter(|fact| actual.binary_search(fact).is_err())
+            .collect();
+        let unexpected: Vec<_> = actual
+            .iter()
+            .filter(|fact| expected.binary_search(fact).is_err())
+            .collect();
+
+        panic!(
+            "{} is missing {:?} and has {:?}, which it should not",
+            relation, missing, unexpected
+        );
+    }
+}
+
+// Asserts that a relation has exactly the given facts, which can be any tuples that facts can be
+// made of.
+#[macro_export]
+macro_rules! assert_relation_eq {
+    ($runtime:expr, $relation:expr, $facts:expr $(,)?) => {
+        $crate::testing::assert_relation_eq(&$runtime, $relation, $facts)
+    };
+}
+
+fn golden(runtime: &MicroRuntime, relation: &str) -> Result<String, String> {
+    let facts = relation_facts(runtime, relation)?;
+    let mut golden = vec![];
+    write_delimited(&mut golden, facts.into_iter().map(Arc::new), '\t')?;
+
+    String::from_utf8(golden).map_err(|error| error.to_string())
+}
+
+// Writes the facts of a relation to a golden file, one tab separated fact per line.
+pub fn write_golden(
+    runtime: &MicroRuntime,
+    relation: &str,
+    path: impl AsRef<Path>,
+) -> Result<(), String> {
+    std::fs::write(path, golden(runtime, relation)?).map_err(|error| error.to_string())
+}
+
+// Asserts that a relation has the facts of a golden file, or writes them to it when UPDATE_GOLDEN
+// is set.
+#[track_caller]
+pub fn assert_golden(runtime: &MicroRuntime, relation: &str, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN).is_some() {
+        write_golden(runtime, relation, path).unwrap_or_else(|error| panic!("{}", error));
+        return;
+    }
+
+    let actual = golden(runtime, relation).unwrap_or_else(|error| panic!("{}", error));
+    let expected = std::fs::read_to_string(path).unwrap_or_else(|error| {
+        panic!(
+            "{} could not be read, which setting {} writes: {}",
+            path.display(),
+            UPDATE_GOLDEN,
+            error
+        )
+    });
+
+    assert_eq!(
+        expected,
+        actual,
+        "{} differs from {}",
+        relation,
+        path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::datalog::MicroRuntime;
+    use crate::testing::{assert_golden, chain, transitive_closure, write_golden};
+
+    fn tc_over_chain() -> MicroRuntime {
+        let mut runtime = MicroRuntime::new(transitive_closure());
+        runtime.insert_many("e", chain(2)).unwrap();
+        runtime.poll();
+
+        runtime
+    }
+
+    #[test]
+    fn test_assert_relation_eq() {
+        let runtime = tc_over_chain();
+
+        assert_relation_eq!(
+            runtime,
+            "tc",
+            vec![(0usize, 1usize), (0, 2), (1, 2), (0, 1)]
+        );
+        assert_relation_eq!(runtime, "unknown", Vec::<(usize,)>::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "tc is missing [[2, 0]] and has [[0, 2]]")]
+    fn test_assert_relation_eq_with_other_facts() {
+        let runtime = tc_over_chain();
+
+        assert_relation_eq!(runtime, "tc", vec![(0usize, 1usize), (1, 2), (2, 0)]);
+    }
+
+    #[test]
+    fn test_assert_golden() {
+        let mut runtime = tc_over_chain();
+
+        let path = std::env::temp_dir().join(format!("tc-{}.tsv", std::process::id()));
+        write_golden(&runtime, "tc", &path).unwrap();
+        let golden = std::fs::read_to_string(&path).unwrap();
+        assert_eq!("0\t1\n0\t2\n1\t2\n", golden);
+        assert_golden(&runtime, "tc", &path);
+
+        runtime
+            .insert("e", vec![2usize.into(), 3usize.into()])
+            .unwrap();
+        runtime.poll();
+        let changed = super::golden(&runtime, "tc").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(golden, changed);
+    }
+}